@@ -0,0 +1,131 @@
+//! Level reads and order updates on books of growing queue length. `snapshot` and
+//! `bid_level` read the cached per-level aggregates; `summed_orders` recomputes the
+//! same top levels from the resting orders, as the book did before it kept aggregates.
+//! `cancel_add` and `modify` update the order at the back of a best-level queue, which
+//! a scan of the level would reach last.
+
+use std::{collections::BTreeMap, hint::black_box};
+
+use batonics::order_book::Book;
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use dbn::{
+    FlagSet, Side,
+    record::{MboMsg, RecordHeader},
+    rtype,
+};
+
+const LEVELS_PER_SIDE: i64 = 50;
+const SNAPSHOT_LEVELS: usize = 10;
+const TICK: i64 = 10_000_000;
+const MID: i64 = 65_000_000_000;
+
+fn build_book(orders_per_level: u64) -> Book {
+    let mut book = Book::new();
+    let mut order_id = 0;
+    for level in 1..=LEVELS_PER_SIDE {
+        for (side, price) in [(b'B', MID - level * TICK), (b'A', MID + level * TICK)] {
+            for _ in 0..orders_per_level {
+                order_id += 1;
+                book.apply(add(order_id, side, price))
+                    .expect("distinct order ids");
+            }
+        }
+    }
+    book
+}
+
+fn add(order_id: u64, side: u8, price: i64) -> MboMsg {
+    order(b'A', order_id, side, price, 1 + (order_id % 7) as u32)
+}
+
+fn order(action: u8, order_id: u64, side: u8, price: i64, size: u32) -> MboMsg {
+    MboMsg {
+        hd: RecordHeader::new::<MboMsg>(rtype::MBO, 1, 1, 0),
+        order_id,
+        price,
+        size,
+        flags: FlagSet::empty(),
+        channel_id: 0,
+        action: action as _,
+        side: side as _,
+        ts_recv: 0,
+        ts_in_delta: 0,
+        sequence: 0,
+    }
+}
+
+/// Price, size and order count of a level.
+type Aggregate = (i64, u32, u32);
+
+/// Top levels per side summed from every resting order.
+fn summed_orders(book: &Book, levels: usize) -> (Vec<Aggregate>, Vec<Aggregate>) {
+    let mut bids: BTreeMap<i64, (u32, u32)> = BTreeMap::new();
+    let mut asks: BTreeMap<i64, (u32, u32)> = BTreeMap::new();
+    for order in book.resting_orders() {
+        let side = match order.side() {
+            Ok(Side::Bid) => &mut bids,
+            _ => &mut asks,
+        };
+        let (size, count) = side.entry(order.price).or_default();
+        *size = size.saturating_add(order.size);
+        *count += 1;
+    }
+    (top(bids.into_iter().rev(), levels), top(asks, levels))
+}
+
+fn top(side: impl IntoIterator<Item = (i64, (u32, u32))>, levels: usize) -> Vec<Aggregate> {
+    side.into_iter()
+        .take(levels)
+        .map(|(price, (size, count))| (price, size, count))
+        .collect()
+}
+
+fn level_reads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("book_levels");
+    for orders_per_level in [1, 10, 100] {
+        let book = build_book(orders_per_level);
+        group.bench_with_input(
+            BenchmarkId::new("snapshot", orders_per_level),
+            &book,
+            |b, book| b.iter(|| black_box(book.snapshot(SNAPSHOT_LEVELS))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("bid_level", orders_per_level),
+            &book,
+            |b, book| b.iter(|| black_box(book.bid_level(SNAPSHOT_LEVELS - 1))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("summed_orders", orders_per_level),
+            &book,
+            |b, book| b.iter(|| black_box(summed_orders(book, SNAPSHOT_LEVELS))),
+        );
+    }
+    group.finish();
+}
+
+fn order_updates(c: &mut Criterion) {
+    let mut group = c.benchmark_group("book_apply");
+    for orders_per_level in [1, 10, 100, 1000] {
+        // The last bid added at the best level sits at the back of its queue
+        let order_id = orders_per_level;
+        let price = MID - TICK;
+        let resized = |action| order(action, order_id, b'B', price, 1);
+        let mut book = build_book(orders_per_level);
+        // Shrinking keeps the order's place and lets each cancel take all of it
+        book.apply(resized(b'M')).expect("resting order");
+        group.bench_function(BenchmarkId::new("cancel_add", orders_per_level), |b| {
+            b.iter(|| {
+                book.apply(resized(b'C')).expect("resting order");
+                book.apply(resized(b'A'))
+            })
+        });
+        let mut book = build_book(orders_per_level);
+        group.bench_function(BenchmarkId::new("modify", orders_per_level), |b| {
+            b.iter(|| book.apply(resized(b'M')))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, level_reads, order_updates);
+criterion_main!(benches);
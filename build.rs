@@ -1,4 +1,8 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     prost_build::compile_protos(&["src/proto/mbo.proto"], &["src/proto/"])?;
+    // The snapshot service also gets its tonic server; clients generate their own
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_protos(&["src/proto/snapshots.proto"], &["src/proto/"])?;
     Ok(())
 }
@@ -0,0 +1,124 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{Instrument, info, info_span};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// W3C trace context header, passed through so callers can join our logs to their traces.
+pub const TRACEPARENT_HEADER: HeaderName = HeaderName::from_static("traceparent");
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(0);
+
+/// Id of the current request, available to handlers as an extension.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl RequestId {
+    /// Reuses a caller-supplied `x-request-id`, otherwise generates one that is unique
+    /// across restarts by prefixing the process start time.
+    fn from_request(request: &Request) -> Self {
+        if let Some(id) = request
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= 128)
+        {
+            return Self(id.to_owned());
+        }
+        Self(format!("{:x}-{:06x}", process_start_secs(), next_request()))
+    }
+}
+
+fn next_request() -> u64 {
+    NEXT_REQUEST.fetch_add(1, Ordering::Relaxed)
+}
+
+fn process_start_secs() -> u64 {
+    static START: OnceLock<u64> = OnceLock::new();
+    *START.get_or_init(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    })
+}
+
+/// Trace id from a `traceparent` header such as `00-<32 hex trace id>-<16 hex span id>-01`.
+fn trace_id(traceparent: &str) -> Option<&str> {
+    let mut parts = traceparent.split('-');
+    let (_version, trace_id) = (parts.next()?, parts.next()?);
+    (trace_id.len() == 32 && trace_id.bytes().all(|b| b.is_ascii_hexdigit())).then_some(trace_id)
+}
+
+/// Tags each request with a request id, echoes it and any trace context on the response,
+/// and logs one `http_access` line per request once the response head is ready. The
+/// handler runs in an `http_request` span, so whatever it logs carries the request id.
+pub async fn access_log(mut request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let request_id = RequestId::from_request(&request);
+    let traceparent = request.headers().get(&TRACEPARENT_HEADER).cloned();
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.to_string())
+        .unwrap_or_else(|| String::from("-"));
+    request.extensions_mut().insert(request_id.clone());
+
+    let span = info_span!("http_request", request_id = %request_id);
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    // Streaming bodies (SSE) have no known size up front
+    let bytes = response
+        .body()
+        .size_hint()
+        .exact()
+        .map_or_else(|| String::from("-"), |n| n.to_string());
+    let trace = traceparent
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .and_then(trace_id)
+        .unwrap_or("-");
+    span.in_scope(|| {
+        info!(
+            client,
+            method = %method,
+            path,
+            status = response.status().as_u16(),
+            latency_us = start.elapsed().as_micros() as u64,
+            bytes,
+            trace_id = trace,
+            "http_access"
+        )
+    });
+
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        headers.insert(REQUEST_ID_HEADER, value);
+    }
+    if let Some(traceparent) = traceparent {
+        headers.insert(TRACEPARENT_HEADER, traceparent);
+    }
+    response
+}
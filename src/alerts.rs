@@ -0,0 +1,453 @@
+//! Alert rules evaluated against the live pipeline: a wide spread or thin book held for
+//! a while, or a feed gone quiet. Alerts are logged as `alert` lines and, when a webhook
+//! is configured, POSTed to it as JSON.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::{
+    analytics::rolling::STATS_DEPTH_LEVELS,
+    latency::wall_clock_ns,
+    shutdown::Shutdown,
+    snapshot::{LevelEntry, SnapshotRecord, TimestampSource},
+};
+
+/// Alerts waiting for the webhook; more are logged but not posted.
+const WEBHOOK_QUEUE: usize = 1_024;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const NS_PER_SEC: i64 = 1_000_000_000;
+
+/// What a rule watches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertKind {
+    /// BBO spread above `threshold` ticks.
+    Spread,
+    /// Size of the top `STATS_DEPTH_LEVELS` levels of either side below `threshold`.
+    Depth,
+    /// No records for `threshold` milliseconds of wall time.
+    Silence,
+}
+
+/// One configured rule, e.g. `spread>4/10s`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlertRule {
+    /// As configured, naming the rule in alerts.
+    pub label: String,
+    pub kind: AlertKind,
+    pub threshold: i64,
+    /// How long the condition must hold before the alert fires, in snapshot time for
+    /// spread and depth rules.
+    pub hold_ns: i64,
+}
+
+impl AlertRule {
+    /// Parses `spread>TICKS[/DURATION]`, `depth<SIZE[/DURATION]` or `silence>DURATION`,
+    /// with durations in ms, s, m or h.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        let (kind, rest) = if let Some(rest) = raw.strip_prefix("spread>") {
+            (AlertKind::Spread, rest)
+        } else if let Some(rest) = raw.strip_prefix("depth<") {
+            (AlertKind::Depth, rest)
+        } else if let Some(rest) = raw.strip_prefix("silence>") {
+            (AlertKind::Silence, rest)
+        } else {
+            bail!(
+                "alert rule {:?} must start with spread>, depth< or silence>",
+                raw
+            );
+        };
+        let (threshold, hold_ns) = match kind {
+            AlertKind::Silence => {
+                let ns = parse_duration(rest).with_context(|| format!("alert rule {:?}", raw))?;
+                (ns / 1_000_000, 0)
+            }
+            _ => {
+                let (threshold, hold) = match rest.split_once('/') {
+                    Some((threshold, hold)) => (threshold, Some(hold)),
+                    None => (rest, None),
+                };
+                let threshold: i64 = threshold
+                    .parse()
+                    .ok()
+                    .filter(|threshold| *threshold > 0)
+                    .ok_or_else(|| {
+                        anyhow!("alert rule {:?} needs a positive whole threshold", raw)
+                    })?;
+                let hold_ns = hold
+                    .map(parse_duration)
+                    .transpose()
+                    .with_context(|| format!("alert rule {:?}", raw))?
+                    .unwrap_or(0);
+                (threshold, hold_ns)
+            }
+        };
+        Ok(Self {
+            label: raw.to_owned(),
+            kind,
+            threshold,
+            hold_ns,
+        })
+    }
+
+    /// Parses a comma-separated list of rules; an empty list turns alerting off.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>> {
+        let mut rules: Vec<Self> = Vec::new();
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let rule = Self::parse(entry)?;
+            if rules.iter().any(|r| r.label == rule.label) {
+                bail!("alert rule {:?} is listed twice", rule.label);
+            }
+            rules.push(rule);
+        }
+        Ok(rules)
+    }
+}
+
+fn parse_duration(raw: &str) -> Result<i64> {
+    let split = raw
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("duration {:?} has no unit (ms, s, m or h)", raw))?;
+    let (count, unit) = raw.split_at(split);
+    let count: i64 = count
+        .parse()
+        .map_err(|_| anyhow!("duration {:?} must start with a number", raw))?;
+    let unit_ns = match unit {
+        "ms" => 1_000_000,
+        "s" => NS_PER_SEC,
+        "m" => 60 * NS_PER_SEC,
+        "h" => 3_600 * NS_PER_SEC,
+        _ => bail!("duration {:?} has unknown unit {:?}", raw, unit),
+    };
+    let ns = count.saturating_mul(unit_ns);
+    if count == 0 || ns > 86_400 * NS_PER_SEC {
+        bail!("duration {:?} must be between 1ms and 24h", raw);
+    }
+    Ok(ns)
+}
+
+/// Rules and where their alerts go.
+#[derive(Clone, Debug)]
+pub struct AlertConfig {
+    pub rules: Vec<AlertRule>,
+    /// Price increment that spread thresholds count in.
+    pub tick_size: i64,
+    /// URL every alert is POSTed to as JSON; alerts are only logged without one.
+    pub webhook: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+impl fmt::Display for AlertState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        })
+    }
+}
+
+/// A rule starting or stopping to fire, as logged and POSTed.
+#[derive(Clone, Debug, Serialize)]
+pub struct AlertEvent {
+    pub rule: String,
+    pub kind: AlertKind,
+    pub state: AlertState,
+    /// Null for silence, which watches the whole feed.
+    pub symbol: Option<String>,
+    pub instrument_id: Option<u32>,
+    /// Spread in ticks, depth in contracts or silence in milliseconds.
+    pub value: i64,
+    pub threshold: i64,
+    /// Snapshot time of the change; null for silence.
+    pub ts: Option<i64>,
+    /// Wall time, Unix nanoseconds.
+    pub raised_at: i64,
+}
+
+impl AlertEvent {
+    fn log(&self) {
+        warn!(
+            state = %self.state,
+            rule = %self.rule,
+            symbol = self.symbol.as_deref().unwrap_or("-"),
+            value = self.value,
+            threshold = self.threshold,
+            ts = self.ts,
+            "alert"
+        );
+    }
+}
+
+/// Where one rule stands for one symbol.
+#[derive(Debug, Default)]
+struct RuleState {
+    /// Snapshot time the condition started holding.
+    since: Option<i64>,
+    firing: bool,
+}
+
+/// Evaluates the rules. Ingest feeds it every published snapshot and counts every record
+/// read; a watchdog thread raises silence alerts.
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    tick_size: i64,
+    clock: TimestampSource,
+    /// Per (rule index, symbol).
+    states: Mutex<HashMap<(usize, String), RuleState>>,
+    received: AtomicU64,
+    /// Closed by `finish` so the webhook thread drains and exits.
+    webhook: Mutex<Option<crossbeam_channel::Sender<AlertEvent>>>,
+    stopped: AtomicBool,
+}
+
+/// The engine and the threads `finish` waits for.
+pub struct Alerts {
+    pub engine: Arc<AlertEngine>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Alerts {
+    /// Starts the webhook and watchdog threads the rules need.
+    pub fn start(config: &AlertConfig, clock: TimestampSource, shutdown: &Shutdown) -> Self {
+        let mut threads = Vec::new();
+        let webhook = config.webhook.clone().and_then(|url| {
+            let (tx, rx) = crossbeam_channel::bounded(WEBHOOK_QUEUE);
+            match thread::Builder::new()
+                .name("alert-webhook".to_owned())
+                .spawn(move || post_alerts(rx, url))
+            {
+                Ok(handle) => {
+                    threads.push(handle);
+                    Some(tx)
+                }
+                Err(e) => {
+                    error!(error = %e, "alert webhook failed to start, alerts are only logged");
+                    None
+                }
+            }
+        });
+        let engine = Arc::new(AlertEngine {
+            rules: config.rules.clone(),
+            tick_size: config.tick_size,
+            clock,
+            states: Mutex::new(HashMap::new()),
+            received: AtomicU64::new(0),
+            webhook: Mutex::new(webhook),
+            stopped: AtomicBool::new(false),
+        });
+        for (index, rule) in engine.rules.iter().enumerate() {
+            if rule.kind != AlertKind::Silence {
+                continue;
+            }
+            let watched = engine.clone();
+            let shutdown = shutdown.clone();
+            match thread::Builder::new()
+                .name("alert-watchdog".to_owned())
+                .spawn(move || watched.watch_silence(index, &shutdown))
+            {
+                Ok(handle) => threads.push(handle),
+                Err(e) => error!(rule = %rule.label, error = %e, "alert watchdog failed to start"),
+            }
+        }
+        info!(
+            rules = config
+                .rules
+                .iter()
+                .map(|rule| rule.label.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            webhook = config.webhook.is_some(),
+            "alerts enabled"
+        );
+        Self { engine, threads }
+    }
+
+    /// Stops watching for silence and waits for the webhook to post what is queued.
+    pub fn finish(self) {
+        self.engine.stopped.store(true, Ordering::Relaxed);
+        self.engine
+            .webhook
+            .lock()
+            .expect("alert webhook lock poisoned")
+            .take();
+        for handle in self.threads {
+            if handle.join().is_err() {
+                error!("alert thread panicked");
+            }
+        }
+    }
+}
+
+impl AlertEngine {
+    /// Counts a record read from the feed. Cheap enough to call once per record.
+    pub fn on_record(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Evaluates the spread and depth rules against a published snapshot.
+    pub fn observe(&self, record: &SnapshotRecord) {
+        let ts = self.clock.pick(record.ts_event, record.ts_recv);
+        let payload = &record.payload;
+        let mut raised = Vec::new();
+        {
+            let mut states = self.states.lock().expect("alert state lock poisoned");
+            for (index, rule) in self.rules.iter().enumerate() {
+                let (breached, value) = match rule.kind {
+                    AlertKind::Spread => match (&payload.bbo.best_bid, &payload.bbo.best_ask) {
+                        (Some(bid), Some(ask)) => {
+                            let ticks = (ask.price - bid.price) / self.tick_size;
+                            (ticks > rule.threshold, ticks)
+                        }
+                        // A one-sided book has no spread to judge
+                        _ => (false, 0),
+                    },
+                    AlertKind::Depth => {
+                        let value = depth(&payload.bids).min(depth(&payload.asks));
+                        (value < rule.threshold, value)
+                    }
+                    AlertKind::Silence => continue,
+                };
+                let state = states.entry((index, payload.symbol.clone())).or_default();
+                let change = if breached {
+                    let since = *state.since.get_or_insert(ts);
+                    (!state.firing && ts - since >= rule.hold_ns).then_some(AlertState::Firing)
+                } else {
+                    state.since = None;
+                    state.firing.then_some(AlertState::Resolved)
+                };
+                if let Some(change) = change {
+                    state.firing = change == AlertState::Firing;
+                    raised.push(AlertEvent {
+                        rule: rule.label.clone(),
+                        kind: rule.kind,
+                        state: change,
+                        symbol: Some(payload.symbol.clone()),
+                        instrument_id: Some(record.instrument_id),
+                        value,
+                        threshold: rule.threshold,
+                        ts: Some(ts),
+                        raised_at: wall_clock_ns(),
+                    });
+                }
+            }
+        }
+        for event in raised {
+            self.raise(event);
+        }
+    }
+
+    fn raise(&self, event: AlertEvent) {
+        event.log();
+        if let Some(tx) = &*self.webhook.lock().expect("alert webhook lock poisoned")
+            && tx.try_send(event).is_err()
+        {
+            warn!("alert webhook queue full, alert only logged");
+        }
+    }
+
+    /// Raises the silence rule at `index` while no record arrives for its threshold.
+    fn watch_silence(&self, index: usize, shutdown: &Shutdown) {
+        let rule = &self.rules[index];
+        let limit = Duration::from_millis(rule.threshold as u64);
+        let tick = (limit / 4).clamp(Duration::from_millis(10), Duration::from_secs(1));
+        let mut seen = self.received.load(Ordering::Relaxed);
+        let mut last_change = Instant::now();
+        let mut firing = false;
+        while !self.stopped.load(Ordering::Relaxed) && !shutdown.is_triggered() {
+            thread::sleep(tick);
+            let received = self.received.load(Ordering::Relaxed);
+            let change = if received != seen {
+                seen = received;
+                let quiet = last_change.elapsed();
+                last_change = Instant::now();
+                firing.then_some((AlertState::Resolved, quiet))
+            } else {
+                let quiet = last_change.elapsed();
+                (!firing && quiet >= limit).then_some((AlertState::Firing, quiet))
+            };
+            if let Some((state, quiet)) = change {
+                firing = state == AlertState::Firing;
+                self.raise(AlertEvent {
+                    rule: rule.label.clone(),
+                    kind: rule.kind,
+                    state,
+                    symbol: None,
+                    instrument_id: None,
+                    value: quiet.as_millis() as i64,
+                    threshold: rule.threshold,
+                    ts: None,
+                    raised_at: wall_clock_ns(),
+                });
+            }
+        }
+    }
+}
+
+/// POSTs each alert to `url`, one at a time, until the queue closes. A failed POST is
+/// logged and not retried: a later alert supersedes it.
+fn post_alerts(rx: crossbeam_channel::Receiver<AlertEvent>, url: String) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!(error = %e, "alert webhook runtime failed, alerts are only logged");
+            return;
+        }
+    };
+    let client = reqwest::Client::new();
+    for event in rx {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                error!(rule = %event.rule, error = %e, "alert webhook failed to encode");
+                continue;
+            }
+        };
+        let request = client
+            .post(&url)
+            .header("content-type", "application/json")
+            .timeout(WEBHOOK_TIMEOUT)
+            .body(body);
+        // Built inside the runtime, which the request's timeout timer needs
+        let sent = runtime.block_on(async { request.send().await });
+        match sent.and_then(|response| response.error_for_status()) {
+            Ok(_) => {}
+            Err(e) => warn!(
+                rule = %event.rule,
+                state = %event.state,
+                error = %e,
+                "alert webhook failed"
+            ),
+        }
+    }
+}
+
+fn depth(levels: &[LevelEntry]) -> i64 {
+    levels
+        .iter()
+        .take(STATS_DEPTH_LEVELS)
+        .map(|level| level.size as i64)
+        .sum()
+}
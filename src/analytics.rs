@@ -0,0 +1,350 @@
+pub mod bars;
+pub mod flow;
+pub mod iceberg;
+pub mod rolling;
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use dbn::{
+    Publisher,
+    enums::{Action, Side},
+    record::{MboMsg, Record},
+};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{
+    order_book::Market,
+    snapshot::{ContractMeta, SymbolMap},
+};
+
+/// Events kept for slow `/sse/events` clients before they start missing some.
+const EVENT_BUFFER: usize = 1024;
+
+/// Consolidated-book analytics shared between ingest and the server: the running
+/// report behind `/analytics` and the event feed behind `/sse/events`.
+pub struct Analytics {
+    report: Mutex<AnalyticsReport>,
+    events: broadcast::Sender<AnalyticsEvent>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AnalyticsReport {
+    /// Only instruments quoted by more than one publisher appear.
+    pub symbols: BTreeMap<String, SymbolAnalytics>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SymbolAnalytics {
+    pub trades: u64,
+    pub trade_size: u64,
+    pub trade_throughs: u64,
+    pub trade_through_size: u64,
+    /// Trade-throughs by the venue that printed the trade.
+    pub by_executing_venue: BTreeMap<String, VenueCount>,
+    /// Trade-throughs by the venue whose better quote was ignored.
+    pub by_protected_venue: BTreeMap<String, VenueCount>,
+    /// Times the consolidated book became locked (best bid == best ask across venues).
+    pub locked: u64,
+    /// Times the consolidated book became crossed (best bid > best ask across venues).
+    pub crossed: u64,
+    pub last_trade_through: Option<TradeThrough>,
+    /// Value of the trades and trade-throughs above, with `CONTRACT_MULTIPLIER`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notional: Option<TradeNotional>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TradeNotional {
+    pub currency: String,
+    pub trades: f64,
+    pub trade_throughs: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct VenueCount {
+    pub count: u64,
+    pub size: u64,
+}
+
+/// A trade printed at a price worse than another venue's displayed quote.
+#[derive(Clone, Debug, Serialize)]
+pub struct TradeThrough {
+    pub symbol: String,
+    pub instrument_id: u32,
+    pub ts_event: i64,
+    pub venue: String,
+    /// Aggressor side: `B` buy, `A` sell, `N` unknown.
+    pub side: char,
+    pub price: i64,
+    pub size: u32,
+    /// Consolidated best bid and ask across the other venues when the trade printed.
+    pub best_bid: Option<i64>,
+    pub best_ask: Option<i64>,
+    pub protected_venues: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnalyticsEvent {
+    TradeThrough(TradeThrough),
+    /// The consolidated book turned locked or crossed.
+    QuoteRule {
+        symbol: String,
+        instrument_id: u32,
+        ts_event: i64,
+        state: QuoteState,
+        best_bid: i64,
+        best_ask: i64,
+        bid_venue: String,
+        ask_venue: String,
+    },
+}
+
+impl AnalyticsEvent {
+    pub fn symbol(&self) -> &str {
+        match self {
+            AnalyticsEvent::TradeThrough(trade) => &trade.symbol,
+            AnalyticsEvent::QuoteRule { symbol, .. } => symbol,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuoteState {
+    #[default]
+    Normal,
+    Locked,
+    Crossed,
+}
+
+impl Default for Analytics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Analytics {
+    pub fn new() -> Self {
+        Self {
+            report: Mutex::new(AnalyticsReport::default()),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+
+    pub fn report(&self) -> AnalyticsReport {
+        self.report.lock().expect("analytics lock poisoned").clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AnalyticsEvent> {
+        self.events.subscribe()
+    }
+
+    fn update(&self, symbol: &str, f: impl FnOnce(&mut SymbolAnalytics)) {
+        let mut report = self.report.lock().expect("analytics lock poisoned");
+        f(report.symbols.entry(symbol.to_owned()).or_default());
+    }
+
+    fn publish(&self, event: AnalyticsEvent) {
+        // No subscribers is not an error
+        let _ = self.events.send(event);
+    }
+}
+
+/// Watches applied records for trade-throughs and locked or crossed consolidated
+/// books. Instruments with a single publisher are skipped: a venue cannot trade through
+/// or lock its own book. Stale books are ignored so a sequence gap does not produce
+/// phantom violations.
+pub struct TradeThroughMonitor {
+    analytics: Arc<Analytics>,
+    quote_states: HashMap<u32, QuoteState>,
+    contract: Option<ContractMeta>,
+}
+
+impl TradeThroughMonitor {
+    pub fn new(analytics: Arc<Analytics>) -> Self {
+        Self {
+            analytics,
+            quote_states: HashMap::new(),
+            contract: None,
+        }
+    }
+
+    /// Also sums the notional of trades and trade-throughs.
+    pub fn with_contract(mut self, contract: Option<ContractMeta>) -> Self {
+        self.contract = contract;
+        self
+    }
+
+    /// Called after `mbo` was applied to `market`.
+    pub fn observe(&mut self, market: &Market, mbo: &MboMsg, symbols: &SymbolMap) {
+        let instrument_id = mbo.hd.instrument_id;
+        let Some(books) = market.books_by_pub(instrument_id) else {
+            return;
+        };
+        if books.len() < 2 {
+            return;
+        }
+        let Ok(publisher) = mbo.publisher() else {
+            return;
+        };
+        match mbo.action() {
+            Ok(Action::Trade) => self.check_trade(market, mbo, publisher, symbols),
+            Ok(Action::Add | Action::Modify | Action::Cancel | Action::Clear) => {
+                self.check_quotes(market, mbo, symbols)
+            }
+            Ok(Action::Fill | Action::None) | Err(_) => {}
+        }
+    }
+
+    fn check_trade(
+        &mut self,
+        market: &Market,
+        mbo: &MboMsg,
+        publisher: Publisher,
+        symbols: &SymbolMap,
+    ) {
+        let instrument_id = mbo.hd.instrument_id;
+        let symbol = symbols.resolve(instrument_id);
+        let side = mbo.side().unwrap_or(Side::None);
+        let (mut best_bid, mut best_ask) = (None::<i64>, None::<i64>);
+        let mut protected_venues = Vec::new();
+        for (book_pub, book) in market.books_by_pub(instrument_id).unwrap_or_default() {
+            if *book_pub == publisher || book.is_stale() {
+                continue;
+            }
+            let (bid, ask) = book.best_prices();
+            best_bid = best_bid.max(bid);
+            best_ask = match (best_ask, ask) {
+                (Some(current), Some(ask)) => Some(current.min(ask)),
+                (current, ask) => current.or(ask),
+            };
+            // A buyer paid more than this venue's offer, or a seller took less than its bid
+            let through_ask = side != Side::Ask && ask.is_some_and(|ask| mbo.price > ask);
+            let through_bid = side != Side::Bid && bid.is_some_and(|bid| mbo.price < bid);
+            if through_ask || through_bid {
+                protected_venues.push(book_pub.as_str().to_owned());
+            }
+        }
+
+        let size = mbo.size as u64;
+        let contract = self.contract.as_ref();
+        let value = contract.map(|contract| contract.value(mbo.price, size));
+        let add_notional = |stats: &mut SymbolAnalytics, through: bool| {
+            let (Some(contract), Some(value)) = (contract, value) else {
+                return;
+            };
+            let notional = stats.notional.get_or_insert_with(|| TradeNotional {
+                currency: contract.currency.clone(),
+                ..TradeNotional::default()
+            });
+            notional.trades += value;
+            if through {
+                notional.trade_throughs += value;
+            }
+        };
+        if protected_venues.is_empty() {
+            self.analytics.update(symbol, |stats| {
+                stats.trades += 1;
+                stats.trade_size += size;
+                add_notional(stats, false);
+            });
+            return;
+        }
+        let trade_through = TradeThrough {
+            symbol: symbol.to_owned(),
+            instrument_id,
+            ts_event: mbo.hd.ts_event as i64,
+            venue: publisher.as_str().to_owned(),
+            side: side as u8 as char,
+            price: mbo.price,
+            size: mbo.size,
+            best_bid,
+            best_ask,
+            protected_venues,
+        };
+        self.analytics.update(symbol, |stats| {
+            stats.trades += 1;
+            stats.trade_size += size;
+            stats.trade_throughs += 1;
+            stats.trade_through_size += size;
+            add_notional(stats, true);
+            let executing = stats
+                .by_executing_venue
+                .entry(trade_through.venue.clone())
+                .or_default();
+            executing.count += 1;
+            executing.size += size;
+            for venue in &trade_through.protected_venues {
+                let protected = stats.by_protected_venue.entry(venue.clone()).or_default();
+                protected.count += 1;
+                protected.size += size;
+            }
+            stats.last_trade_through = Some(trade_through.clone());
+        });
+        self.analytics
+            .publish(AnalyticsEvent::TradeThrough(trade_through));
+    }
+
+    fn check_quotes(&mut self, market: &Market, mbo: &MboMsg, symbols: &SymbolMap) {
+        let instrument_id = mbo.hd.instrument_id;
+        let mut best_bid: Option<(i64, Publisher)> = None;
+        let mut best_ask: Option<(i64, Publisher)> = None;
+        for (book_pub, book) in market.books_by_pub(instrument_id).unwrap_or_default() {
+            if book.is_stale() {
+                continue;
+            }
+            let (bid, ask) = book.best_prices();
+            if let Some(bid) = bid
+                && best_bid.is_none_or(|(best, _)| bid > best)
+            {
+                best_bid = Some((bid, *book_pub));
+            }
+            if let Some(ask) = ask
+                && best_ask.is_none_or(|(best, _)| ask < best)
+            {
+                best_ask = Some((ask, *book_pub));
+            }
+        }
+
+        let state = match (best_bid, best_ask) {
+            // One venue's own book never stays locked; it would have matched
+            (Some((bid, bid_pub)), Some((ask, ask_pub))) if bid_pub != ask_pub => {
+                match bid.cmp(&ask) {
+                    Ordering::Less => QuoteState::Normal,
+                    Ordering::Equal => QuoteState::Locked,
+                    Ordering::Greater => QuoteState::Crossed,
+                }
+            }
+            _ => QuoteState::Normal,
+        };
+        let previous = self.quote_states.insert(instrument_id, state);
+        if state == QuoteState::Normal || previous == Some(state) {
+            return;
+        }
+        let (Some((bid, bid_pub)), Some((ask, ask_pub))) = (best_bid, best_ask) else {
+            return;
+        };
+        let symbol = symbols.resolve(instrument_id);
+        self.analytics.update(symbol, |stats| match state {
+            QuoteState::Locked => stats.locked += 1,
+            QuoteState::Crossed => stats.crossed += 1,
+            QuoteState::Normal => {}
+        });
+        self.analytics.publish(AnalyticsEvent::QuoteRule {
+            symbol: symbol.to_owned(),
+            instrument_id,
+            ts_event: mbo.hd.ts_event as i64,
+            state,
+            best_bid: bid,
+            best_ask: ask,
+            bid_venue: bid_pub.as_str().to_owned(),
+            ask_venue: ask_pub.as_str().to_owned(),
+        });
+    }
+}
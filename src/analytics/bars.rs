@@ -0,0 +1,254 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{snapshot::TimestampSource, trades::TradeRecord};
+
+/// Closed bars kept per symbol and interval for `/bars`.
+pub const BAR_HISTORY: usize = 10_000;
+
+const NS_PER_SEC: i64 = 1_000_000_000;
+
+/// Width of a time bar, e.g. `1s` or `5m`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BarInterval {
+    pub label: String,
+    pub ns: i64,
+}
+
+impl BarInterval {
+    /// Parses a whole number of `ms`, `s`, `m` or `h`, up to one day.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        let split = raw
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("bar interval {:?} has no unit (ms, s, m or h)", raw))?;
+        let (count, unit) = raw.split_at(split);
+        let count: i64 = count
+            .parse()
+            .map_err(|_| anyhow!("bar interval {:?} must start with a number", raw))?;
+        let unit_ns = match unit {
+            "ms" => 1_000_000,
+            "s" => NS_PER_SEC,
+            "m" => 60 * NS_PER_SEC,
+            "h" => 3_600 * NS_PER_SEC,
+            _ => bail!("bar interval {:?} has unknown unit {:?}", raw, unit),
+        };
+        let ns = count.saturating_mul(unit_ns);
+        if count == 0 || ns > 86_400 * NS_PER_SEC {
+            bail!("bar interval {:?} must be between 1ms and 24h", raw);
+        }
+        Ok(Self {
+            label: raw.to_owned(),
+            ns,
+        })
+    }
+
+    /// Parses a comma-separated list such as `1s,1m`; an empty list turns bars off.
+    pub fn parse_list(raw: &str) -> Result<Vec<Self>> {
+        let mut intervals: Vec<Self> = Vec::new();
+        for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let interval = Self::parse(entry)?;
+            if intervals.iter().any(|other| other.ns == interval.ns) {
+                bail!("bar interval {:?} is listed more than once", entry);
+            }
+            intervals.push(interval);
+        }
+        Ok(intervals)
+    }
+}
+
+/// OHLCV over one interval of `ts_event` time, or `ts_recv` with `TimestampSource::Recv`.
+/// Prices are fixed-point like every price in the API; `vwap` is rounded to the nearest
+/// fixed-point unit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bar {
+    pub symbol: String,
+    pub instrument_id: u32,
+    pub interval: String,
+    /// Inclusive start of the interval.
+    pub start_ts: i64,
+    /// Exclusive end of the interval.
+    pub end_ts: i64,
+    pub open: i64,
+    pub high: i64,
+    pub low: i64,
+    pub close: i64,
+    pub volume: u64,
+    pub trades: u64,
+    pub vwap: i64,
+    /// False for the bar still being built.
+    pub closed: bool,
+}
+
+pub type SharedBar = Arc<Bar>;
+
+struct OpenBar {
+    bar: Bar,
+    interval_ns: i64,
+    notional: i128,
+}
+
+impl OpenBar {
+    fn new(trade: &TradeRecord, ts: i64, interval: &BarInterval) -> Self {
+        let start_ts = ts - ts.rem_euclid(interval.ns);
+        Self {
+            bar: Bar {
+                symbol: trade.symbol.clone(),
+                instrument_id: trade.instrument_id,
+                interval: interval.label.clone(),
+                start_ts,
+                end_ts: start_ts + interval.ns,
+                open: trade.price,
+                high: trade.price,
+                low: trade.price,
+                close: trade.price,
+                volume: 0,
+                trades: 0,
+                vwap: trade.price,
+                closed: false,
+            },
+            interval_ns: interval.ns,
+            notional: 0,
+        }
+    }
+
+    fn add(&mut self, trade: &TradeRecord) {
+        let bar = &mut self.bar;
+        bar.high = bar.high.max(trade.price);
+        bar.low = bar.low.min(trade.price);
+        bar.close = trade.price;
+        bar.volume += trade.size as u64;
+        bar.trades += 1;
+        self.notional += trade.price as i128 * trade.size as i128;
+        if bar.volume > 0 {
+            let volume = bar.volume as i128;
+            bar.vwap = ((self.notional + volume / 2).div_euclid(volume)) as i64;
+        }
+    }
+}
+
+/// Recent bars per symbol and interval, shared between ingest and the server.
+pub struct BarStore {
+    intervals: Vec<BarInterval>,
+    closed: Mutex<HashMap<(String, String), VecDeque<SharedBar>>>,
+    open: Mutex<HashMap<(String, String), Bar>>,
+}
+
+impl BarStore {
+    pub fn new(intervals: Vec<BarInterval>) -> Self {
+        Self {
+            intervals,
+            closed: Mutex::new(HashMap::new()),
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn intervals(&self) -> &[BarInterval] {
+        &self.intervals
+    }
+
+    /// Adds a closed bar and forgets the open bar of its symbol and interval.
+    pub fn record(&self, bar: SharedBar) {
+        let key = (bar.symbol.clone(), bar.interval.clone());
+        self.open
+            .lock()
+            .expect("bar store lock poisoned")
+            .remove(&key);
+        let mut closed = self.closed.lock().expect("bar store lock poisoned");
+        let history = closed.entry(key).or_default();
+        if history.len() == BAR_HISTORY {
+            history.pop_front();
+        }
+        history.push_back(bar);
+    }
+
+    fn update_open(&self, bar: &Bar) {
+        self.open
+            .lock()
+            .expect("bar store lock poisoned")
+            .insert((bar.symbol.clone(), bar.interval.clone()), bar.clone());
+    }
+
+    /// Up to `limit` of the latest bars, oldest first, ending with the open bar if any.
+    pub fn recent(&self, symbol: &str, interval: &str, limit: usize) -> Vec<Bar> {
+        let key = (symbol.to_owned(), interval.to_owned());
+        let open = self
+            .open
+            .lock()
+            .expect("bar store lock poisoned")
+            .get(&key)
+            .cloned();
+        let closed = self.closed.lock().expect("bar store lock poisoned");
+        let mut bars: Vec<Bar> = closed
+            .get(&key)
+            .map(|history| history.iter().map(|bar| bar.as_ref().clone()).collect())
+            .unwrap_or_default();
+        bars.extend(open);
+        bars.split_off(bars.len().saturating_sub(limit))
+    }
+}
+
+/// Folds trades into time bars for every configured interval. A bar closes when a
+/// trade of its instrument lands in a later interval, or when ingest ends; intervals
+/// without trades produce no bar.
+pub struct BarAggregator {
+    store: Arc<BarStore>,
+    clock: TimestampSource,
+    open: HashMap<(u32, i64), OpenBar>,
+}
+
+impl BarAggregator {
+    pub fn new(store: Arc<BarStore>, clock: TimestampSource) -> Self {
+        Self {
+            store,
+            clock,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Adds `trade` to its bars and returns the bars it closed.
+    pub fn on_trade(&mut self, trade: &TradeRecord) -> Vec<SharedBar> {
+        let ts = self.clock.pick(trade.ts_event, trade.ts_recv);
+        let mut closed = Vec::new();
+        for interval in self.store.intervals() {
+            let key = (trade.instrument_id, interval.ns);
+            let bar = match self.open.remove(&key) {
+                Some(open) if ts < open.bar.end_ts => open,
+                Some(open) => {
+                    // Recorded before the new bar opens, which would otherwise be dropped
+                    let bar = close(open);
+                    self.store.record(bar.clone());
+                    closed.push(bar);
+                    OpenBar::new(trade, ts, interval)
+                }
+                None => OpenBar::new(trade, ts, interval),
+            };
+            let bar = self.open.entry(key).or_insert(bar);
+            bar.add(trade);
+            self.store.update_open(&bar.bar);
+        }
+        closed
+    }
+
+    /// Closes every open bar, e.g. when ingest ends.
+    pub fn finish(&mut self) -> Vec<SharedBar> {
+        let mut open: Vec<OpenBar> = self.open.drain().map(|(_, bar)| bar).collect();
+        open.sort_by_key(|bar| (bar.bar.instrument_id, bar.interval_ns));
+        let closed: Vec<SharedBar> = open.into_iter().map(close).collect();
+        for bar in &closed {
+            self.store.record(bar.clone());
+        }
+        closed
+    }
+}
+
+fn close(open: OpenBar) -> SharedBar {
+    let mut bar = open.bar;
+    bar.closed = true;
+    Arc::new(bar)
+}
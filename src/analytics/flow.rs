@@ -0,0 +1,144 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    order_book::Market,
+    snapshot::{Bbo, FlowSignals, SnapshotRecord},
+};
+
+/// Price and size of one side of the consolidated BBO.
+type Quote = Option<(i64, u32)>;
+
+#[derive(Default)]
+struct InstrumentFlow {
+    bbo_epoch: Option<u64>,
+    last: Option<(Quote, Quote)>,
+    /// OFI contribution of each BBO change inside the window, by `ts_event`.
+    events: VecDeque<(i64, i64)>,
+    ofi: i64,
+}
+
+impl InstrumentFlow {
+    fn observe(&mut self, ts_event: i64, bid: Quote, ask: Quote) {
+        if let Some((prev_bid, prev_ask)) = self.last.replace((bid, ask)) {
+            let e = bid_flow(prev_bid, bid) - ask_flow(prev_ask, ask);
+            if e != 0 {
+                self.events.push_back((ts_event, e));
+                self.ofi += e;
+            }
+        }
+    }
+
+    fn expire(&mut self, ts_event: i64, window_ns: i64) {
+        while let Some(&(ts, e)) = self.events.front() {
+            if ts > ts_event.saturating_sub(window_ns) {
+                break;
+            }
+            self.events.pop_front();
+            self.ofi -= e;
+        }
+    }
+}
+
+/// Bid term of a BBO change's OFI contribution (see `FlowSignals::ofi`). A side that
+/// is empty before or after the change contributes nothing.
+fn bid_flow(prev: Quote, next: Quote) -> i64 {
+    let (Some((prev_px, prev_sz)), Some((px, sz))) = (prev, next) else {
+        return 0;
+    };
+    let joined = if px >= prev_px { sz as i64 } else { 0 };
+    let left = if px <= prev_px { prev_sz as i64 } else { 0 };
+    joined - left
+}
+
+fn ask_flow(prev: Quote, next: Quote) -> i64 {
+    let (Some((prev_px, prev_sz)), Some((px, sz))) = (prev, next) else {
+        return 0;
+    };
+    let joined = if px <= prev_px { sz as i64 } else { 0 };
+    let left = if px >= prev_px { prev_sz as i64 } else { 0 };
+    joined - left
+}
+
+/// Per-instrument order-flow state behind `Snapshot::signals`. Ingest feeds it every
+/// applied record, so the OFI sees BBO changes a thinned snapshot cadence never emits.
+pub struct FlowTracker {
+    /// `None` when flow signals are off; every call is then a no-op.
+    window_ns: Option<i64>,
+    instruments: HashMap<u32, InstrumentFlow>,
+}
+
+impl FlowTracker {
+    pub fn new(window_ns: Option<i64>) -> Self {
+        Self {
+            window_ns,
+            instruments: HashMap::new(),
+        }
+    }
+
+    /// Called after a record was applied to `instrument_id`. Records that leave every
+    /// publisher's BBO alone are skipped without consolidating the book.
+    pub fn on_book(&mut self, market: &Market, instrument_id: u32, ts_event: i64) {
+        if self.window_ns.is_none() {
+            return;
+        }
+        let flow = self.instruments.entry(instrument_id).or_default();
+        let epoch = market.bbo_epoch(instrument_id);
+        if flow.bbo_epoch.replace(epoch) == Some(epoch) {
+            return;
+        }
+        let (bid, ask) = market.aggregated_bbo(instrument_id);
+        flow.observe(
+            ts_event,
+            bid.map(|level| (level.price, level.size)),
+            ask.map(|level| (level.price, level.size)),
+        );
+    }
+
+    /// Called for each replayed snapshot of a source without a book.
+    pub fn on_snapshot(&mut self, record: &SnapshotRecord) {
+        if self.window_ns.is_none() {
+            return;
+        }
+        let (bid, ask) = quotes(&record.payload.bbo);
+        self.instruments
+            .entry(record.instrument_id)
+            .or_default()
+            .observe(record.ts_event, bid, ask);
+    }
+
+    /// Adds the instrument's signals to `record`.
+    pub fn stamp(&mut self, mut record: SnapshotRecord) -> SnapshotRecord {
+        let Some(window_ns) = self.window_ns else {
+            return record;
+        };
+        let flow = self.instruments.entry(record.instrument_id).or_default();
+        flow.expire(record.ts_event, window_ns);
+        let (bid, ask) = quotes(&record.payload.bbo);
+        let (queue_imbalance, microprice) = match (bid, ask) {
+            (Some((bid_px, bid_sz)), Some((ask_px, ask_sz))) if bid_sz > 0 || ask_sz > 0 => {
+                let (bid_sz, ask_sz) = (bid_sz as f64, ask_sz as f64);
+                let total = bid_sz + ask_sz;
+                (
+                    Some((bid_sz - ask_sz) / total),
+                    Some(
+                        ((ask_px as f64 * bid_sz + bid_px as f64 * ask_sz) / total).round() as i64,
+                    ),
+                )
+            }
+            _ => (None, None),
+        };
+        record.payload.signals = Some(FlowSignals {
+            ofi: flow.ofi,
+            queue_imbalance,
+            microprice,
+        });
+        record
+    }
+}
+
+fn quotes(bbo: &Bbo) -> (Quote, Quote) {
+    (
+        bbo.best_bid.as_ref().map(|level| (level.price, level.size)),
+        bbo.best_ask.as_ref().map(|level| (level.price, level.size)),
+    )
+}
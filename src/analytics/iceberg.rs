@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use dbn::{
+    enums::{Action, Side},
+    record::{MboMsg, Record},
+};
+
+use crate::{order_book::Market, snapshot::SnapshotRecord};
+
+/// How soon after a fill new size at the filled price counts as a refill. Venues
+/// replenish an iceberg's display in the same matching event, so this is tight.
+const REFILL_WINDOW_NS: u64 = 1_000_000;
+
+/// A level as (instrument_id, side, price). Publishers quoting the same price share it.
+type LevelKey = (u32, Side, i64);
+
+#[derive(Debug, Default)]
+struct LevelHistory {
+    /// Order and ts_event of the last fill at the level not yet followed by a refill.
+    last_fill: Option<(u64, u64)>,
+    /// Refills in a row, each following a fill.
+    refills: u32,
+    /// Size shown by the latest refill, i.e. the order's display quantity.
+    peak: u32,
+}
+
+/// Flags probable iceberg orders: levels where the same size reappears at the same price
+/// right after fills, either as a new order replacing a filled one or as the filled order
+/// growing back. Once a level has refilled `min_refills` times in a row with the same
+/// size, snapshots give it an `est_hidden` of one more display quantity. Ingest feeds it every record before applying it, since trades and
+/// fills pass through the book without a trace.
+pub struct IcebergTracker {
+    /// `None` when detection is off; every call is then a no-op.
+    min_refills: Option<u32>,
+    levels: HashMap<LevelKey, LevelHistory>,
+}
+
+impl IcebergTracker {
+    pub fn new(min_refills: Option<u32>) -> Self {
+        Self {
+            min_refills,
+            levels: HashMap::new(),
+        }
+    }
+
+    /// Called before `rec` is applied to `market`, so a modify can be compared with the
+    /// order it changes.
+    pub fn before_apply(&mut self, market: &Market, rec: &MboMsg) {
+        if self.min_refills.is_none() {
+            return;
+        }
+        let (Ok(action), Ok(side)) = (rec.action(), rec.side()) else {
+            return;
+        };
+        let instrument_id = rec.hd.instrument_id;
+        if action == Action::Clear {
+            self.levels.retain(|(id, _, _), _| *id != instrument_id);
+            return;
+        }
+        if side == Side::None {
+            return;
+        }
+        let key = (instrument_id, side, rec.price);
+        let ts_event = rec.hd.ts_event;
+        let book = rec
+            .publisher()
+            .ok()
+            .and_then(|publisher| market.book(instrument_id, publisher));
+        let refill = match action {
+            Action::Fill => {
+                let level = self.levels.entry(key).or_default();
+                // Another order filled before the last one was refilled breaks the run;
+                // one order filled in pieces does not
+                if level
+                    .last_fill
+                    .is_some_and(|(order_id, _)| order_id != rec.order_id)
+                {
+                    level.refills = 0;
+                }
+                level.last_fill = Some((rec.order_id, ts_event));
+                return;
+            }
+            Action::Add => Some(rec.size),
+            // Only an order growing at its price; whether it is the filled one is checked below
+            Action::Modify => book
+                .filter(|book| book.order_level(rec.order_id) == Some((side, rec.price)))
+                .and_then(|book| book.order(rec.order_id))
+                .filter(|order| rec.size > order.size)
+                .map(|order| rec.size - order.size),
+            _ => None,
+        };
+        let Some(size) = refill else {
+            return;
+        };
+        let Some(level) = self.levels.get_mut(&key) else {
+            return;
+        };
+        let follows_fill = level.last_fill.is_some_and(|(order_id, filled_at)| {
+            let replaced = match action {
+                // A new order replaces the filled one once its display is used up
+                Action::Add => book.is_none_or(|book| book.order(order_id).is_none()),
+                _ => order_id == rec.order_id,
+            };
+            replaced && ts_event.saturating_sub(filled_at) <= REFILL_WINDOW_NS
+        });
+        if !follows_fill {
+            return;
+        }
+        level.last_fill = None;
+        // An iceberg shows the same display quantity each time
+        level.refills = if level.refills > 0 && level.peak == size {
+            level.refills + 1
+        } else {
+            1
+        };
+        level.peak = size;
+    }
+
+    /// Sets `est_hidden` on the record's levels flagged as icebergs. Forgets levels the
+    /// instrument's books no longer have.
+    pub fn stamp(&mut self, market: &Market, mut record: SnapshotRecord) -> SnapshotRecord {
+        let Some(min_refills) = self.min_refills else {
+            return record;
+        };
+        let instrument_id = record.instrument_id;
+        let books = market.books_by_pub(instrument_id).unwrap_or_default();
+        self.levels.retain(|&(id, side, price), _| {
+            id != instrument_id
+                || books
+                    .iter()
+                    .any(|(_, book)| book.level_size(side, price) > 0)
+        });
+        let payload = &mut record.payload;
+        for (side, levels) in [
+            (Side::Bid, &mut payload.bids),
+            (Side::Ask, &mut payload.asks),
+        ] {
+            for level in levels.iter_mut() {
+                level.est_hidden = self
+                    .levels
+                    .get(&(instrument_id, side, level.price))
+                    .filter(|history| history.refills >= min_refills)
+                    .map(|history| history.peak);
+            }
+        }
+        record
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::{LevelEntry, SnapshotRecord, TimestampSource};
+
+/// Levels per side summed into the depth statistics.
+pub const STATS_DEPTH_LEVELS: usize = 5;
+
+/// Statistics of one symbol's snapshots over the trailing window, as `/stats` serves
+/// them and the `book_stats` table stores them. Prices are fixed-point like every price
+/// in the API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BookStats {
+    pub symbol: String,
+    pub instrument_id: u32,
+    /// Time of the newest snapshot, where the window ends.
+    pub ts_event: i64,
+    pub window_ms: i64,
+    /// Snapshots in the window.
+    pub snapshots: u64,
+    /// Time-weighted average spread; null while no snapshot in the window had both sides.
+    pub avg_spread: Option<i64>,
+    /// Time-weighted average size of the top `STATS_DEPTH_LEVELS` levels of each side.
+    pub avg_bid_depth: f64,
+    pub avg_ask_depth: f64,
+    /// Square root of the summed squared log returns of the midprice between consecutive
+    /// snapshots; null with fewer than two mids.
+    pub realized_vol: Option<f64>,
+    /// Snapshots per second of the window.
+    pub updates_per_sec: f64,
+}
+
+pub type SharedStats = Arc<BookStats>;
+
+struct Sample {
+    ts: i64,
+    spread: Option<i64>,
+    bid_depth: u64,
+    ask_depth: u64,
+    mid: Option<f64>,
+}
+
+impl Sample {
+    fn new(record: &SnapshotRecord, ts: i64) -> Self {
+        let bbo = &record.payload.bbo;
+        let (spread, mid) = match (&bbo.best_bid, &bbo.best_ask) {
+            (Some(bid), Some(ask)) => (
+                Some(ask.price - bid.price),
+                Some((bid.price as f64 + ask.price as f64) / 2.0),
+            ),
+            _ => (None, None),
+        };
+        Self {
+            ts,
+            spread,
+            bid_depth: depth(&record.payload.bids),
+            ask_depth: depth(&record.payload.asks),
+            mid,
+        }
+    }
+}
+
+fn depth(levels: &[LevelEntry]) -> u64 {
+    levels
+        .iter()
+        .take(STATS_DEPTH_LEVELS)
+        .map(|level| level.size as u64)
+        .sum()
+}
+
+struct SymbolWindow {
+    instrument_id: u32,
+    samples: VecDeque<Sample>,
+    /// Persist interval of the newest sample.
+    bucket: Option<i64>,
+}
+
+impl SymbolWindow {
+    fn stats(&self, symbol: &str, window_ns: i64) -> Option<BookStats> {
+        let last = self.samples.back()?;
+        // Each snapshot holds until the next one; the newest has no weight yet
+        let weights: Vec<f64> = self
+            .samples
+            .iter()
+            .zip(self.samples.iter().skip(1))
+            .map(|(sample, next)| (next.ts - sample.ts) as f64)
+            .chain([0.0])
+            .collect();
+        let total: f64 = weights.iter().sum();
+        // A window whose snapshots share one timestamp weighs them equally
+        let weight = |i: usize| if total > 0.0 { weights[i] } else { 1.0 };
+        let average = |value: &dyn Fn(&Sample) -> Option<f64>| {
+            let (sum, weights) = self
+                .samples
+                .iter()
+                .enumerate()
+                .filter_map(|(i, sample)| value(sample).map(|v| (v * weight(i), weight(i))))
+                .fold((0.0, 0.0), |(sum, total), (v, w)| (sum + v, total + w));
+            (weights > 0.0).then(|| sum / weights)
+        };
+        let returns: Vec<f64> = self
+            .samples
+            .iter()
+            .filter_map(|sample| sample.mid)
+            .collect::<Vec<_>>()
+            .windows(2)
+            .map(|pair| (pair[1] / pair[0]).ln())
+            .collect();
+        Some(BookStats {
+            symbol: symbol.to_owned(),
+            instrument_id: self.instrument_id,
+            ts_event: last.ts,
+            window_ms: window_ns / 1_000_000,
+            snapshots: self.samples.len() as u64,
+            avg_spread: average(&|sample| sample.spread.map(|s| s as f64))
+                .map(|spread| spread.round() as i64),
+            avg_bid_depth: average(&|sample| Some(sample.bid_depth as f64)).unwrap_or(0.0),
+            avg_ask_depth: average(&|sample| Some(sample.ask_depth as f64)).unwrap_or(0.0),
+            realized_vol: (!returns.is_empty())
+                .then(|| returns.iter().map(|r| r * r).sum::<f64>().sqrt()),
+            updates_per_sec: self.samples.len() as f64 * 1e9 / window_ns as f64,
+        })
+    }
+}
+
+/// Rolling statistics per symbol over the trailing `window_ns` of snapshot time,
+/// shared between ingest, which feeds it every published snapshot, and the server.
+pub struct RollingStats {
+    window_ns: i64,
+    /// Interval at which `observe` hands back rows to persist; `None` persists nothing.
+    persist_ns: Option<i64>,
+    clock: TimestampSource,
+    symbols: Mutex<HashMap<String, SymbolWindow>>,
+}
+
+impl RollingStats {
+    pub fn new(window_ns: i64, persist_ns: Option<i64>, clock: TimestampSource) -> Self {
+        Self {
+            window_ns,
+            persist_ns,
+            clock,
+            symbols: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds a snapshot to its symbol's window. When it starts a new persist interval,
+    /// returns the statistics the previous interval ended with.
+    pub fn observe(&self, record: &SnapshotRecord) -> Option<SharedStats> {
+        let ts = self.clock.pick(record.ts_event, record.ts_recv);
+        let symbol = &record.payload.symbol;
+        let mut symbols = self.symbols.lock().expect("rolling stats lock poisoned");
+        let window = symbols
+            .entry(symbol.clone())
+            .or_insert_with(|| SymbolWindow {
+                instrument_id: record.instrument_id,
+                samples: VecDeque::new(),
+                bucket: None,
+            });
+        let bucket = self.persist_ns.map(|ns| ts.div_euclid(ns));
+        let closed = match (window.bucket, bucket) {
+            (Some(prev), Some(bucket)) if prev != bucket => {
+                window.stats(symbol, self.window_ns).map(Arc::new)
+            }
+            _ => None,
+        };
+        window.bucket = bucket;
+        window.samples.push_back(Sample::new(record, ts));
+        while window
+            .samples
+            .front()
+            .is_some_and(|sample| sample.ts <= ts - self.window_ns)
+        {
+            window.samples.pop_front();
+        }
+        closed
+    }
+
+    /// Every symbol's statistics as the last snapshot left them, for the rows of the
+    /// intervals still open when ingest ends.
+    pub fn finish(&self) -> Vec<SharedStats> {
+        if self.persist_ns.is_none() {
+            return Vec::new();
+        }
+        let mut stats = self.all();
+        stats.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        stats.into_iter().map(Arc::new).collect()
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<BookStats> {
+        self.symbols
+            .lock()
+            .expect("rolling stats lock poisoned")
+            .get(symbol)?
+            .stats(symbol, self.window_ns)
+    }
+
+    pub fn all(&self) -> Vec<BookStats> {
+        self.symbols
+            .lock()
+            .expect("rolling stats lock poisoned")
+            .iter()
+            .filter_map(|(symbol, window)| window.stats(symbol, self.window_ns))
+            .collect()
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use prost::Message;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::sleep,
+};
+use tracing::{error, info};
+
+use crate::{
+    config::BenchConfig,
+    proto::{MboBatch, SubscribeRequest},
+};
+
+/// Connects to `stream_tcp`, reads frames for the configured duration and reports rates.
+pub async fn run(config: BenchConfig) -> Result<()> {
+    info!(
+        server = %config.server_addr,
+        duration_secs = config.duration_secs,
+        "tcp_bench connecting"
+    );
+
+    let mut stream = TcpStream::connect(&config.server_addr)
+        .await
+        .with_context(|| format!("failed to connect to {}", config.server_addr))?;
+
+    // Subscribing explicitly spares the server's handshake timeout
+    let request = SubscribeRequest {
+        instrument_ids: config.instrument_ids.clone(),
+        start_sequence: 0,
+    };
+    let mut frame = (request.encoded_len() as u32).to_be_bytes().to_vec();
+    request.encode(&mut frame)?;
+    stream
+        .write_all(&frame)
+        .await
+        .context("failed to send subscribe request")?;
+
+    info!("connected, starting benchmark");
+
+    let msg_counter = Arc::new(AtomicU64::new(0));
+    let batch_counter = Arc::new(AtomicU64::new(0));
+    let bytes_counter = Arc::new(AtomicU64::new(0));
+
+    let msg_counter_clone = Arc::clone(&msg_counter);
+    let batch_counter_clone = Arc::clone(&batch_counter);
+    let bytes_counter_clone = Arc::clone(&bytes_counter);
+
+    // Spawn stats reporter
+    let stats_handle = tokio::spawn(async move {
+        let mut last_report = Instant::now();
+        let mut last_msgs = 0u64;
+        let mut last_batches = 0u64;
+        let mut last_bytes = 0u64;
+
+        loop {
+            sleep(Duration::from_secs(1)).await;
+
+            let total_msgs = msg_counter_clone.load(Ordering::Relaxed);
+            let total_batches = batch_counter_clone.load(Ordering::Relaxed);
+            let total_bytes = bytes_counter_clone.load(Ordering::Relaxed);
+
+            let interval = last_report.elapsed().as_secs_f64();
+            let msgs_delta = total_msgs.saturating_sub(last_msgs);
+            let batches_delta = total_batches.saturating_sub(last_batches);
+            let bytes_delta = total_bytes.saturating_sub(last_bytes);
+
+            let msg_rate = msgs_delta as f64 / interval;
+            let batch_rate = batches_delta as f64 / interval;
+            let throughput_mbps = (bytes_delta as f64 / interval) / (1024.0 * 1024.0);
+
+            info!(
+                msgs = total_msgs,
+                batches = total_batches,
+                msg_rate,
+                batch_rate,
+                throughput_mbps,
+                "bench progress"
+            );
+
+            last_msgs = total_msgs;
+            last_batches = total_batches;
+            last_bytes = total_bytes;
+            last_report = Instant::now();
+        }
+    });
+
+    // Read loop
+    let bench_start = Instant::now();
+    let mut read_buf = BytesMut::with_capacity(1024 * 1024); // 1MB buffer
+
+    while bench_start.elapsed() < Duration::from_secs(config.duration_secs) {
+        // Read length prefix (4-byte u32 big-endian)
+        let mut len_buf = [0u8; 4];
+        if let Err(e) = stream.read_exact(&mut len_buf).await {
+            error!(error = %e, "read error");
+            break;
+        }
+
+        let frame_len = u32::from_be_bytes(len_buf) as usize;
+        bytes_counter.fetch_add(4, Ordering::Relaxed);
+
+        // Read protobuf frame
+        read_buf.clear();
+        read_buf.resize(frame_len, 0);
+        if let Err(e) = stream.read_exact(&mut read_buf).await {
+            error!(error = %e, "read frame error");
+            break;
+        }
+        bytes_counter.fetch_add(frame_len as u64, Ordering::Relaxed);
+        // Decode batch
+        match MboBatch::decode(&mut read_buf.as_ref()) {
+            Ok(batch) => {
+                let msg_count = batch.msgs.len() as u64;
+                msg_counter.fetch_add(msg_count, Ordering::Relaxed);
+                batch_counter.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                error!(error = %e, "decode error");
+                break;
+            }
+        }
+    }
+
+    stats_handle.abort();
+
+    // Final stats
+    let elapsed = bench_start.elapsed().as_secs_f64();
+    let total_msgs = msg_counter.load(Ordering::Relaxed);
+    let total_batches = batch_counter.load(Ordering::Relaxed);
+    let total_bytes = bytes_counter.load(Ordering::Relaxed);
+
+    // The report is the command's output, so it is printed rather than logged
+    eprintln!("\nbench_complete");
+    eprintln!("===============");
+    eprintln!("Duration:        {:.2}s", elapsed);
+    eprintln!("Total Messages:  {}", total_msgs);
+    eprintln!("Total Batches:   {}", total_batches);
+    eprintln!(
+        "Total Bytes:     {} ({:.2}MB)",
+        total_bytes,
+        total_bytes as f64 / (1024.0 * 1024.0)
+    );
+    eprintln!();
+    eprintln!("Avg Msg Rate:    {:.0} msg/s", total_msgs as f64 / elapsed);
+    eprintln!(
+        "Avg Batch Rate:  {:.0} batch/s",
+        total_batches as f64 / elapsed
+    );
+    eprintln!(
+        "Avg Throughput:  {:.2} MB/s",
+        total_bytes as f64 / elapsed / (1024.0 * 1024.0)
+    );
+
+    Ok(())
+}
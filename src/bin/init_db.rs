@@ -1,11 +1,17 @@
-use std::env;
+use anyhow::Result;
+use tracing::info;
 
-use anyhow::{Context, Result};
+use batonics::{
+    cli::{self, InitDbArgs},
+    config::{InitDbConfig, LogConfig},
+    logging,
+};
 
 fn main() -> Result<()> {
-    let db_url = env::var("DATABASE_URL")
-        .context("DATABASE_URL env var must be set to initialize schema")?;
-    batonics::storage::init_database(&db_url)?;
-    println!("schema ensured for {}", db_url);
+    let args = cli::parse::<InitDbArgs>()?;
+    logging::init(&LogConfig::from_args(&args.log)?)?;
+    let config = InitDbConfig::from_args(&args)?;
+    batonics::storage::init_database(&config.db_url, config.timescale.as_ref())?;
+    info!(db_url = %config.db_url, "schema ensured");
     Ok(())
 }
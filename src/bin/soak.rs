@@ -0,0 +1,715 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use batonics::{
+    analytics::{Analytics, bars::BarStore},
+    cli::{self, SoakArgs},
+    compression::CompressionConfig,
+    config::{LogConfig, Problems},
+    ingest::{DbnFileSource, IngestSource},
+    instruments::InstrumentRegistry,
+    logging,
+    order_book::Market,
+    server::{Namespace, ServerConfig, ServerContext, spawn_http_server},
+    shutdown::Shutdown,
+    snapshot::{
+        PriceFormat, SharedSnapshot, SnapshotRecord, SnapshotRegistry, SnapshotSequencer,
+        SymbolMap, build_snapshot_record,
+    },
+    supervisor::{SinkHealth, SinkSwitches},
+    trades::TradeTape,
+    venue_stats::VenueStats,
+};
+use dbn::{
+    FlagSet, UNDEF_PRICE,
+    record::{MboMsg, RecordHeader},
+    rtype,
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+
+/// How long the HTTP and WebSocket views get to catch up with the book after a loop.
+const CONVERGE_TIMEOUT: Duration = Duration::from_secs(5);
+const SYNTHETIC_MID: i64 = 64_000_000_000;
+const SYNTHETIC_TICK: i64 = 10_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SoakSource {
+    File,
+    /// Deterministic random order flow, identical on every loop.
+    Synthetic,
+}
+
+#[derive(Clone, Debug)]
+struct SoakConfig {
+    source: SoakSource,
+    input_path: String,
+    symbol: String,
+    synthetic_records: u64,
+    duration: Duration,
+    max_loops: u64,
+    addr: SocketAddr,
+    depth: usize,
+    max_rss_growth_mb: f64,
+    http_interval: Duration,
+    queue_capacity: usize,
+    ws_buffer: usize,
+}
+
+impl SoakConfig {
+    fn from_args(args: &SoakArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let source = match args.soak_source.as_str() {
+            "synthetic" => SoakSource::Synthetic,
+            _ => SoakSource::File,
+        };
+        match source {
+            SoakSource::File => problems.ensure(Path::new(&args.input_path).exists(), || {
+                format!("--input-path/INPUT_PATH {} does not exist", args.input_path)
+            }),
+            SoakSource::Synthetic => {
+                problems.at_least("soak-synthetic-records", args.soak_synthetic_records, 1);
+            }
+        }
+        let config = Self {
+            source,
+            input_path: args.input_path.clone(),
+            symbol: args.symbol.clone(),
+            synthetic_records: args.soak_synthetic_records,
+            duration: Duration::from_secs(problems.at_least(
+                "soak-duration-secs",
+                args.soak_duration_secs,
+                1,
+            )),
+            max_loops: args.soak_max_loops,
+            addr: args.soak_addr,
+            depth: problems.range("snapshot-depth", args.snapshot_depth, 1, 1_000),
+            max_rss_growth_mb: problems.at_least(
+                "soak-max-rss-growth-mb",
+                args.soak_max_rss_growth_mb,
+                0.0,
+            ),
+            http_interval: Duration::from_millis(problems.at_least(
+                "soak-http-interval-ms",
+                args.soak_http_interval_ms,
+                1,
+            )),
+            queue_capacity: problems.range("queue-capacity", args.queue_capacity, 1, 100_000_000),
+            ws_buffer: problems.range("ws-buffer", args.ws_buffer, 1, 1_000_000),
+        };
+        problems.finish()?;
+        Ok(config)
+    }
+
+    fn open_source(&self) -> Result<Box<dyn IngestSource>> {
+        Ok(match self.source {
+            SoakSource::File => Box::new(DbnFileSource::open(&self.input_path)?),
+            SoakSource::Synthetic => Box::new(SyntheticSource::new(self.synthetic_records)),
+        })
+    }
+}
+
+/// Adds, modifies and cancels orders around a fixed mid from a seeded xorshift
+/// generator, starting with a Clear so each loop begins from an empty book.
+struct SyntheticSource {
+    remaining: u64,
+    rng: u64,
+    next_order_id: u64,
+    live: Vec<(u64, u8, i64, u32)>,
+    ts_event: u64,
+    sequence: u32,
+    started: bool,
+}
+
+impl SyntheticSource {
+    fn new(records: u64) -> Self {
+        Self {
+            remaining: records,
+            rng: 0x9e37_79b9_7f4a_7c15,
+            next_order_id: 1,
+            live: Vec::new(),
+            ts_event: 1_700_000_000_000_000_000,
+            sequence: 0,
+            started: false,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn msg(&mut self, action: u8, side: u8, order_id: u64, price: i64, size: u32) -> MboMsg {
+        self.ts_event += 1_000_000;
+        self.sequence += 1;
+        MboMsg {
+            // Publisher 1 is GLBX.MDP3, as in the sample data
+            hd: RecordHeader::new::<MboMsg>(rtype::MBO, 1, 1, self.ts_event),
+            order_id,
+            price,
+            size,
+            flags: FlagSet::empty(),
+            channel_id: 0,
+            action: action as _,
+            side: side as _,
+            ts_recv: self.ts_event,
+            ts_in_delta: 0,
+            sequence: self.sequence,
+        }
+    }
+}
+
+impl IngestSource for SyntheticSource {
+    fn next_record(&mut self) -> Result<Option<MboMsg>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        if !self.started {
+            self.started = true;
+            return Ok(Some(self.msg(b'R', b'N', 0, UNDEF_PRICE, 0)));
+        }
+        let roll = self.next_random();
+        if self.live.len() < 200 || roll % 10 < 6 {
+            let side = if roll.is_multiple_of(2) { b'B' } else { b'A' };
+            let ticks = 1 + (self.next_random() % 20) as i64;
+            let price = match side {
+                b'B' => SYNTHETIC_MID - ticks * SYNTHETIC_TICK,
+                _ => SYNTHETIC_MID + ticks * SYNTHETIC_TICK,
+            };
+            let size = 1 + (self.next_random() % 10) as u32;
+            let order_id = self.next_order_id;
+            self.next_order_id += 1;
+            self.live.push((order_id, side, price, size));
+            return Ok(Some(self.msg(b'A', side, order_id, price, size)));
+        }
+        let idx = (self.next_random() % self.live.len() as u64) as usize;
+        if roll.is_multiple_of(3) {
+            let size = 1 + (self.next_random() % 10) as u32;
+            self.live[idx].3 = size;
+            let (order_id, side, price, _) = self.live[idx];
+            return Ok(Some(self.msg(b'M', side, order_id, price, size)));
+        }
+        let (order_id, side, price, size) = self.live.swap_remove(idx);
+        Ok(Some(self.msg(b'C', side, order_id, price, size)))
+    }
+
+    fn describe(&self) -> String {
+        String::from("synthetic")
+    }
+}
+
+/// Book view rebuilt from snapshot or delta JSON, compared against the registry.
+/// Order and level counts are left out: the server skips deltas whose only change
+/// is below the snapshot depth, so those can lag without the view being wrong.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct BookView {
+    bids: BTreeMap<i64, (u64, u64)>,
+    asks: BTreeMap<i64, (u64, u64)>,
+    best_bid: Option<(i64, u64, u64)>,
+    best_ask: Option<(i64, u64, u64)>,
+}
+
+fn level(value: &Value) -> Option<(i64, u64, u64)> {
+    Some((
+        value.get("price")?.as_i64()?,
+        value.get("size")?.as_u64()?,
+        value.get("count")?.as_u64()?,
+    ))
+}
+
+fn levels(value: &Value) -> impl Iterator<Item = (i64, u64, u64)> + '_ {
+    value.as_array().into_iter().flatten().filter_map(level)
+}
+
+impl BookView {
+    fn from_snapshot(snapshot: &Value) -> Self {
+        let mut view = Self {
+            bids: levels(&snapshot["bids"])
+                .map(|(p, s, c)| (p, (s, c)))
+                .collect(),
+            asks: levels(&snapshot["asks"])
+                .map(|(p, s, c)| (p, (s, c)))
+                .collect(),
+            ..Self::default()
+        };
+        view.apply_bbo(snapshot);
+        view
+    }
+
+    fn apply_delta(&mut self, delta: &Value) {
+        for (side, book) in [("bids", &mut self.bids), ("asks", &mut self.asks)] {
+            let changes = &delta[side];
+            for (price, size, count) in levels(&changes["added"]).chain(levels(&changes["changed"]))
+            {
+                book.insert(price, (size, count));
+            }
+            for price in changes["removed"].as_array().into_iter().flatten() {
+                if let Some(price) = price.as_i64() {
+                    book.remove(&price);
+                }
+            }
+        }
+        self.apply_bbo(delta);
+    }
+
+    /// The BBO is only present in deltas when it changed.
+    fn apply_bbo(&mut self, value: &Value) {
+        if let Some(bbo) = value.get("bbo").filter(|bbo| !bbo.is_null()) {
+            self.best_bid = level(&bbo["best_bid"]);
+            self.best_ask = level(&bbo["best_ask"]);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    http_requests: AtomicU64,
+    http_errors: AtomicU64,
+    ws_messages: AtomicU64,
+    ws_conflated: AtomicU64,
+    ws_errors: AtomicU64,
+    queue_overflows: AtomicU64,
+    queue_consumed: AtomicU64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct SoakSummary {
+    pass: bool,
+    source: String,
+    loops: u64,
+    elapsed_secs: u64,
+    records: u64,
+    snapshots: u64,
+    checksum: String,
+    rss_baseline_mb: Option<f64>,
+    rss_max_mb: Option<f64>,
+    http_requests: u64,
+    http_errors: u64,
+    ws_messages: u64,
+    ws_conflated: u64,
+    queue_overflows: u64,
+    failures: Vec<String>,
+}
+
+struct LoopResult {
+    records: u64,
+    snapshots: u64,
+    checksum: u64,
+}
+
+fn main() -> Result<()> {
+    let args = cli::parse::<SoakArgs>()?;
+    logging::init(&LogConfig::from_args(&args.log)?)?;
+    let config = SoakConfig::from_args(&args)?;
+    info!(
+        source = ?config.source,
+        duration_secs = config.duration.as_secs(),
+        max_loops = config.max_loops,
+        addr = %config.addr,
+        max_rss_growth_mb = config.max_rss_growth_mb,
+        "soak_start"
+    );
+
+    let registry = Arc::new(SnapshotRegistry::new());
+    let (updates, _) = broadcast::channel::<SharedSnapshot>(config.ws_buffer);
+    let counters = Arc::new(Counters::default());
+    let ws_views: Arc<Mutex<HashMap<String, BookView>>> = Arc::default();
+
+    let _server = spawn_http_server(
+        ServerContext {
+            namespaces: vec![Namespace {
+                prefix: String::new(),
+                registry: registry.clone(),
+                updates: updates.clone(),
+                analytics: Arc::new(Analytics::new()),
+                simulator: None,
+                trades: Arc::new(TradeTape::default()),
+                venue: Arc::new(VenueStats::new()),
+                bars: Arc::new(BarStore::new(Vec::new())),
+                stats: None,
+                books: None,
+                replay: None,
+                instruments: Arc::new(InstrumentRegistry::new()),
+                mbp_prices: PriceFormat::for_tick(SYNTHETIC_TICK),
+            }],
+            sinks: Arc::new(SinkHealth::new()),
+            switches: Arc::new(SinkSwitches::new()),
+            db_url: None,
+            storage: None,
+            order_pool: None,
+            queues: None,
+            latency: None,
+            ingest: None,
+            shutdown: Shutdown::new(),
+        },
+        ServerConfig {
+            addr: config.addr,
+            sse_max_rate: 10.0,
+            ws_conflated_rate: 2.0,
+            compression: CompressionConfig {
+                ws_min_bytes: usize::MAX,
+                sse_gzip: false,
+            },
+        },
+    );
+
+    // Stands in for the storage writer so queue overflow is measured the same way
+    let (queue_tx, queue_rx) = crossbeam_channel::bounded::<SharedSnapshot>(config.queue_capacity);
+    let consumer_counters = counters.clone();
+    thread::spawn(move || {
+        for snapshot in queue_rx {
+            let _ = snapshot.to_json_string();
+            consumer_counters
+                .queue_consumed
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    });
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .context("failed to build tokio runtime for soak clients")?;
+    let http = reqwest::Client::new();
+    let base = format!("http://{}", config.addr);
+    runtime
+        .block_on(wait_ready(&http, &base))
+        .context("server did not become ready")?;
+    runtime.spawn(poll_http(
+        http.clone(),
+        base.clone(),
+        config.http_interval,
+        registry.clone(),
+        counters.clone(),
+    ));
+    runtime.spawn(follow_ws(
+        format!("ws://{}/ws/snapshots", config.addr),
+        ws_views.clone(),
+        counters.clone(),
+    ));
+    // Let the WebSocket subscribe before the first snapshot is published
+    thread::sleep(Duration::from_millis(200));
+
+    let started = Instant::now();
+    let mut summary = SoakSummary {
+        source: format!("{:?}", config.source).to_lowercase(),
+        ..SoakSummary::default()
+    };
+    let mut first_checksum: Option<u64> = None;
+    loop {
+        let result = run_loop(&config, &registry, &updates, &queue_tx, &counters)?;
+        summary.loops += 1;
+        summary.records += result.records;
+        summary.snapshots += result.snapshots;
+        let mut failures = Vec::new();
+
+        match first_checksum {
+            None => first_checksum = Some(result.checksum),
+            Some(expected) if expected != result.checksum => failures.push(format!(
+                "loop {} checksum {:016x} diverged from {:016x}",
+                summary.loops, result.checksum, expected
+            )),
+            Some(_) => {}
+        }
+        failures.extend(runtime.block_on(check_views(&http, &base, &registry, &ws_views)));
+
+        // The first loop warms allocator pools and caches, so it sets the baseline
+        let rss = rss_mb();
+        if summary.rss_baseline_mb.is_none() {
+            summary.rss_baseline_mb = rss;
+        }
+        summary.rss_max_mb = match (rss, summary.rss_max_mb) {
+            (Some(rss), Some(max)) => Some(rss.max(max)),
+            (rss, max) => rss.or(max),
+        };
+        if let (Some(rss), Some(baseline)) = (rss, summary.rss_baseline_mb)
+            && rss - baseline > config.max_rss_growth_mb
+        {
+            failures.push(format!(
+                "loop {} rss {:.1}MB grew more than {}MB over baseline {:.1}MB",
+                summary.loops, rss, config.max_rss_growth_mb, baseline
+            ));
+        }
+        let overflows = counters.queue_overflows.load(Ordering::Relaxed);
+        if overflows > 0 {
+            failures.push(format!("{} snapshots overflowed the queue", overflows));
+        }
+        let http_errors = counters.http_errors.load(Ordering::Relaxed);
+        if http_errors > 0 {
+            failures.push(format!("{} HTTP requests failed", http_errors));
+        }
+        if counters.ws_errors.load(Ordering::Relaxed) > 0 {
+            failures.push(String::from("WebSocket client disconnected"));
+        }
+
+        info!(
+            n = summary.loops,
+            records = result.records,
+            snapshots = result.snapshots,
+            checksum = format!("{:016x}", result.checksum),
+            rss_mb = rss,
+            http_requests = counters.http_requests.load(Ordering::Relaxed),
+            ws_messages = counters.ws_messages.load(Ordering::Relaxed),
+            ws_conflated = counters.ws_conflated.load(Ordering::Relaxed),
+            queue_consumed = counters.queue_consumed.load(Ordering::Relaxed),
+            elapsed_s = started.elapsed().as_secs(),
+            "soak_loop"
+        );
+        for failure in &failures {
+            warn!(failure = %failure, "soak_failure");
+        }
+        summary.failures.extend(failures);
+
+        let out_of_loops = config.max_loops > 0 && summary.loops >= config.max_loops;
+        // Stop at the first failing loop; later loops cannot make the run pass
+        if !summary.failures.is_empty() || out_of_loops || started.elapsed() >= config.duration {
+            break;
+        }
+    }
+
+    summary.pass = summary.failures.is_empty();
+    summary.elapsed_secs = started.elapsed().as_secs();
+    summary.checksum = format!("{:016x}", first_checksum.unwrap_or_default());
+    summary.http_requests = counters.http_requests.load(Ordering::Relaxed);
+    summary.http_errors = counters.http_errors.load(Ordering::Relaxed);
+    summary.ws_messages = counters.ws_messages.load(Ordering::Relaxed);
+    summary.ws_conflated = counters.ws_conflated.load(Ordering::Relaxed);
+    summary.queue_overflows = counters.queue_overflows.load(Ordering::Relaxed);
+    info!(soak_summary = %serde_json::to_string(&summary)?);
+    // The server thread only stops on ctrl+c
+    std::process::exit(if summary.pass { 0 } else { 1 });
+}
+
+/// Replays the source once into a fresh book, publishing like the ingest pipeline.
+fn run_loop(
+    config: &SoakConfig,
+    registry: &SnapshotRegistry,
+    updates: &broadcast::Sender<SharedSnapshot>,
+    queue_tx: &crossbeam_channel::Sender<SharedSnapshot>,
+    counters: &Counters,
+) -> Result<LoopResult> {
+    let mut source = config.open_source()?;
+    let mut symbols = SymbolMap::new(config.symbol.clone());
+    symbols.extend_missing(source.symbols());
+    let mut market = Market::new();
+    let mut hasher = DefaultHasher::new();
+    let mut records = 0u64;
+    let mut snapshots = 0u64;
+    let mut sequencer = SnapshotSequencer::new();
+
+    while let Some(rec) = source.next_record()? {
+        records += 1;
+        let instrument_id = rec.hd.instrument_id;
+        let ts_event = rec.hd.ts_event as i64;
+        if market.apply(rec).is_err() {
+            continue;
+        }
+        let snapshot = sequencer.stamp(build_snapshot_record(
+            &market,
+            instrument_id,
+            &symbols,
+            ts_event,
+            config.depth,
+        ));
+        hash_snapshot(&snapshot, &mut hasher);
+        let shared = Arc::new(snapshot);
+        registry.store(shared.clone());
+        let _ = updates.send(shared.clone());
+        if queue_tx.try_send(shared).is_err() {
+            counters.queue_overflows.fetch_add(1, Ordering::Relaxed);
+        }
+        snapshots += 1;
+    }
+    Ok(LoopResult {
+        records,
+        snapshots,
+        checksum: hasher.finish(),
+    })
+}
+
+fn hash_snapshot(snapshot: &SnapshotRecord, hasher: &mut DefaultHasher) {
+    let payload = &snapshot.payload;
+    (snapshot.instrument_id, snapshot.ts_event).hash(hasher);
+    for level in payload.bids.iter().chain(&payload.asks) {
+        (level.price, level.size, level.count).hash(hasher);
+    }
+    (payload.total_orders, payload.bid_levels, payload.ask_levels).hash(hasher);
+}
+
+/// Compares what HTTP serves and what the WebSocket client rebuilt from deltas with
+/// the registry, which is stable between loops.
+async fn check_views(
+    http: &reqwest::Client,
+    base: &str,
+    registry: &SnapshotRegistry,
+    ws_views: &Mutex<HashMap<String, BookView>>,
+) -> Vec<String> {
+    let mut failures = Vec::new();
+    for snapshot in registry.all() {
+        let symbol = &snapshot.payload.symbol;
+        let expected = match serde_json::to_value(&snapshot.payload) {
+            Ok(value) => value,
+            Err(e) => {
+                failures.push(format!("{} failed to serialize snapshot: {}", symbol, e));
+                continue;
+            }
+        };
+        let served = http
+            .get(format!("{}/snapshot/{}", base, symbol))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match served {
+            Ok(response) => match response
+                .bytes()
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|body| {
+                    serde_json::from_slice::<Value>(&body).map_err(anyhow::Error::from)
+                }) {
+                Ok(body) if body == expected => {}
+                Ok(_) => failures.push(format!("{} HTTP snapshot diverged from the book", symbol)),
+                Err(e) => failures.push(format!("{} HTTP snapshot unreadable: {}", symbol, e)),
+            },
+            Err(e) => failures.push(format!("{} HTTP snapshot failed: {}", symbol, e)),
+        }
+
+        let expected_view = BookView::from_snapshot(&expected);
+        let deadline = Instant::now() + CONVERGE_TIMEOUT;
+        loop {
+            let converged = ws_views
+                .lock()
+                .expect("ws view lock poisoned")
+                .get(symbol)
+                .is_some_and(|view| *view == expected_view);
+            if converged {
+                break;
+            }
+            if Instant::now() >= deadline {
+                failures.push(format!(
+                    "{} WebSocket view did not converge within {:?}",
+                    symbol, CONVERGE_TIMEOUT
+                ));
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    failures
+}
+
+async fn wait_ready(http: &reqwest::Client, base: &str) -> Result<()> {
+    let deadline = Instant::now() + CONVERGE_TIMEOUT;
+    loop {
+        match http.get(format!("{}/healthz", base)).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            _ if Instant::now() >= deadline => bail!("{}/healthz not ready", base),
+            _ => tokio::time::sleep(Duration::from_millis(50)).await,
+        }
+    }
+}
+
+async fn poll_http(
+    http: reqwest::Client,
+    base: String,
+    interval: Duration,
+    registry: Arc<SnapshotRegistry>,
+    counters: Arc<Counters>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    for path in ["/snapshot", "/healthz", "/metrics"].iter().cycle() {
+        ticker.tick().await;
+        // /snapshot is 404 until the first snapshot is published
+        if registry.latest().is_none() {
+            continue;
+        }
+        counters.http_requests.fetch_add(1, Ordering::Relaxed);
+        let result = http
+            .get(format!("{}{}", base, path))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            counters.http_errors.fetch_add(1, Ordering::Relaxed);
+            warn!(path = %path, error = %e, "soak_http_error");
+        }
+    }
+}
+
+async fn follow_ws(
+    url: String,
+    views: Arc<Mutex<HashMap<String, BookView>>>,
+    counters: Arc<Counters>,
+) {
+    if let Err(e) = follow_ws_inner(&url, &views, &counters).await {
+        warn!(error = format!("{:#}", e), "soak_ws_error");
+    }
+    counters.ws_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+async fn follow_ws_inner(
+    url: &str,
+    views: &Mutex<HashMap<String, BookView>>,
+    counters: &Counters,
+) -> Result<()> {
+    // No subscribe message: the default is every symbol at full depth
+    let (mut socket, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .with_context(|| format!("failed to connect to {}", url))?;
+    while let Some(message) = socket.next().await {
+        let text = match message.context("WebSocket read failed")? {
+            Message::Text(text) => text,
+            Message::Close(_) => bail!("server closed the WebSocket"),
+            _ => continue,
+        };
+        let message: Value = serde_json::from_str(&text).context("invalid WebSocket JSON")?;
+        counters.ws_messages.fetch_add(1, Ordering::Relaxed);
+        if message["tier"] == "conflated" {
+            counters.ws_conflated.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut views = views.lock().expect("ws view lock poisoned");
+        if let Some(snapshot) = message.get("snapshot") {
+            let symbol = snapshot["symbol"].as_str().unwrap_or_default().to_owned();
+            views.insert(symbol, BookView::from_snapshot(snapshot));
+        } else if let Some(delta) = message.get("delta") {
+            let symbol = delta["symbol"].as_str().unwrap_or_default();
+            match views.get_mut(symbol) {
+                Some(view) => view.apply_delta(delta),
+                None => bail!("delta for {} before any snapshot", symbol),
+            }
+        }
+    }
+    bail!("WebSocket stream ended")
+}
+
+fn rss_mb() -> Option<f64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kb: f64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb / 1024.0)
+}
@@ -0,0 +1,203 @@
+//! Order lookups answered from the live books for `/order` and `/queue_pos`.
+//!
+//! The books belong to the ingest threads, so rather than locking them on every record,
+//! the server hands each lookup to the thread owning the instrument's books, which runs
+//! it between records. When ingest ends the final books keep answering.
+
+use std::{sync::Arc, thread, time::Duration};
+
+use dbn::{Publisher, enums::Side};
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tracing::error;
+
+use crate::order_book::{Book, Market};
+
+/// How long a lookup waits for its thread. A live feed only reaches the next record,
+/// and so the next lookup, when the venue sends one.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Query = Box<dyn FnOnce(&Market) + Send>;
+
+/// The server's end: sends lookups to the thread owning the books of an instrument.
+pub struct BookQueries {
+    /// One per ingest shard, chosen by `instrument_id % shards` as records are.
+    shards: Vec<crossbeam_channel::Sender<Query>>,
+}
+
+/// An ingest thread's end, answering lookups against the market it owns.
+pub struct QueryInbox {
+    rx: crossbeam_channel::Receiver<Query>,
+}
+
+/// Why a lookup got no answer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unanswered {
+    /// Ingest stopped without leaving its books behind, e.g. after an error.
+    Closed,
+    /// The thread did not get to the lookup within `ANSWER_TIMEOUT`.
+    Busy,
+}
+
+/// Lookups for `shards` ingest threads, and the inbox each one serves.
+pub fn book_queries(shards: usize) -> (BookQueries, Vec<QueryInbox>) {
+    let (senders, inboxes) = (0..shards.max(1))
+        .map(|_| {
+            let (tx, rx) = crossbeam_channel::unbounded();
+            (tx, QueryInbox { rx })
+        })
+        .unzip();
+    (BookQueries { shards: senders }, inboxes)
+}
+
+impl QueryInbox {
+    /// Answers the lookups waiting for `market`. Called between records, so it costs a
+    /// channel poll when there are none.
+    pub fn serve(&self, market: &Market) {
+        while let Ok(query) = self.rx.try_recv() {
+            query(market);
+        }
+    }
+
+    /// Keeps answering lookups from the final `market` once ingest is done with it.
+    pub fn serve_final(self, market: Market) {
+        let spawned = thread::Builder::new()
+            .name("book-queries".to_owned())
+            .spawn(move || {
+                for query in self.rx {
+                    query(&market);
+                }
+            });
+        if let Err(e) = spawned {
+            error!(error = %e, "book queries failed to keep the final books");
+        }
+    }
+}
+
+impl BookQueries {
+    /// Runs `query` on the books of `instrument_id`, or on every shard's until one
+    /// answers when the instrument is not given.
+    pub async fn ask<T, F>(
+        &self,
+        instrument_id: Option<u32>,
+        query: F,
+    ) -> Result<Option<T>, Unanswered>
+    where
+        T: Send + 'static,
+        F: Fn(&Market) -> Option<T> + Send + Sync + 'static,
+    {
+        let query = Arc::new(query);
+        let shards: Vec<&crossbeam_channel::Sender<Query>> = match instrument_id {
+            Some(id) => vec![&self.shards[id as usize % self.shards.len()]],
+            None => self.shards.iter().collect(),
+        };
+        for shard in shards {
+            let (tx, rx) = oneshot::channel();
+            let query = query.clone();
+            shard
+                .send(Box::new(move |market: &Market| {
+                    let _ = tx.send(query(market));
+                }))
+                .map_err(|_| Unanswered::Closed)?;
+            match tokio::time::timeout(ANSWER_TIMEOUT, rx).await {
+                Ok(Ok(Some(answer))) => return Ok(Some(answer)),
+                Ok(Ok(None)) => {}
+                Ok(Err(_)) => return Err(Unanswered::Closed),
+                Err(_) => return Err(Unanswered::Busy),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A resting order as `/order` reports it.
+#[derive(Clone, Debug, Serialize)]
+pub struct OrderView {
+    pub order_id: u64,
+    pub instrument_id: u32,
+    pub publisher_id: u16,
+    pub side: char,
+    pub price: i64,
+    pub size: u32,
+    /// ts_event of the record that gave the order its place in the queue.
+    pub ts_event: u64,
+    pub flags: u8,
+}
+
+/// Where a resting order stands in its level's queue, as `/queue_pos` reports it.
+#[derive(Clone, Debug, Serialize)]
+pub struct QueuePosition {
+    pub order_id: u64,
+    pub instrument_id: u32,
+    pub publisher_id: u16,
+    pub side: char,
+    pub price: i64,
+    pub size: u32,
+    /// Size resting ahead of the order at its price.
+    pub size_ahead: u64,
+    /// Size and order count of the whole level, the order included.
+    pub level_size: u32,
+    pub level_count: u32,
+}
+
+/// Narrows a lookup to one instrument or publisher; unset fields match every book.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OrderFilter {
+    pub instrument_id: Option<u32>,
+    pub publisher_id: Option<u16>,
+}
+
+impl OrderView {
+    pub fn find(market: &Market, order_id: u64, filter: OrderFilter) -> Option<Self> {
+        let (instrument_id, publisher, book) = find_book(market, order_id, filter)?;
+        let (side, price) = book.order_level(order_id)?;
+        let order = book.order(order_id)?;
+        Some(Self {
+            order_id,
+            instrument_id,
+            publisher_id: publisher as u16,
+            side: side as u8 as char,
+            price,
+            size: order.size,
+            ts_event: order.ts_event,
+            flags: order.flags.raw(),
+        })
+    }
+}
+
+impl QueuePosition {
+    pub fn find(market: &Market, order_id: u64, filter: OrderFilter) -> Option<Self> {
+        let (instrument_id, publisher, book) = find_book(market, order_id, filter)?;
+        let (side, price) = book.order_level(order_id)?;
+        let level = match side {
+            Side::Bid => book.bid_level_by_px(price),
+            _ => book.ask_level_by_px(price),
+        }?;
+        Some(Self {
+            order_id,
+            instrument_id,
+            publisher_id: publisher as u16,
+            side: side as u8 as char,
+            price,
+            size: book.order(order_id)?.size,
+            size_ahead: book.queue_pos(order_id)?,
+            level_size: level.size,
+            level_count: level.count,
+        })
+    }
+}
+
+/// The first book matching `filter` in which `order_id` rests.
+fn find_book(
+    market: &Market,
+    order_id: u64,
+    filter: OrderFilter,
+) -> Option<(u32, Publisher, &Book)> {
+    market
+        .iter_books()
+        .find(|(instrument_id, publisher, book)| {
+            filter.instrument_id.is_none_or(|id| id == *instrument_id)
+                && filter.publisher_id.is_none_or(|id| id == *publisher as u16)
+                && book.order(order_id).is_some()
+        })
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use crate::{
+    order_book::{Market, PriceLevel},
+    snapshot::TimestampSource,
+};
+
+/// How often ingest builds a snapshot for an instrument. Building and publishing a
+/// snapshot costs far more than applying a record, so thinning them is the main lever
+/// on ingest throughput. Every record still updates the book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotCadence {
+    /// One snapshot per applied record.
+    EveryMessage,
+    /// One snapshot per N applied records of the instrument.
+    EveryN(u64),
+    /// At most one snapshot per `interval_ns` of the gate's clock per instrument.
+    Interval { interval_ns: i64 },
+    /// Only when the instrument's best bid or ask price, size or count changes. Updates
+    /// that leave every publisher's `Book::bbo_epoch` alone are skipped without
+    /// looking at the book.
+    BboChange,
+}
+
+type BboKey = Option<(i64, u32, u32)>;
+
+#[derive(Default)]
+struct InstrumentCadence {
+    applied: u64,
+    last_emit_ts: Option<i64>,
+    bbo_epoch: Option<u64>,
+    last_bbo: Option<(BboKey, BboKey)>,
+    /// `ts_event` and `ts_recv` of the latest applied record not covered by an emitted
+    /// snapshot.
+    pending_ts: Option<(i64, i64)>,
+}
+
+/// Per-instrument state behind a `SnapshotCadence`.
+pub struct CadenceGate {
+    cadence: SnapshotCadence,
+    clock: TimestampSource,
+    instruments: HashMap<u32, InstrumentCadence>,
+}
+
+impl CadenceGate {
+    pub fn new(cadence: SnapshotCadence, clock: TimestampSource) -> Self {
+        Self {
+            cadence,
+            clock,
+            instruments: HashMap::new(),
+        }
+    }
+
+    /// Called after a record was applied to `instrument_id`; true when a snapshot
+    /// should be emitted for it.
+    pub fn should_emit(
+        &mut self,
+        market: &Market,
+        instrument_id: u32,
+        ts_event: i64,
+        ts_recv: i64,
+    ) -> bool {
+        let ts = self.clock.pick(ts_event, ts_recv);
+        if self.cadence == SnapshotCadence::EveryMessage {
+            return true;
+        }
+        let state = self.instruments.entry(instrument_id).or_default();
+        state.applied += 1;
+        let emit = match self.cadence {
+            SnapshotCadence::EveryMessage => true,
+            SnapshotCadence::EveryN(n) => (state.applied - 1).is_multiple_of(n),
+            SnapshotCadence::Interval { interval_ns } => state
+                .last_emit_ts
+                .is_none_or(|last| ts.saturating_sub(last) >= interval_ns),
+            SnapshotCadence::BboChange => {
+                let epoch = market.bbo_epoch(instrument_id);
+                if state.bbo_epoch.replace(epoch) == Some(epoch) {
+                    return false;
+                }
+                // A venue's touch moved, but the consolidated BBO may not have
+                let (bid, ask) = market.aggregated_bbo(instrument_id);
+                let bbo = (bbo_key(bid), bbo_key(ask));
+                let changed = state.last_bbo != Some(bbo);
+                state.last_bbo = Some(bbo);
+                changed
+            }
+        };
+        if emit {
+            state.last_emit_ts = Some(ts);
+            state.pending_ts = None;
+        } else {
+            state.pending_ts = Some((ts_event, ts_recv));
+        }
+        emit
+    }
+
+    /// Instruments whose latest state was never emitted, with that state's `ts_event`
+    /// and `ts_recv`, so ingest can publish a closing snapshot. A BBO-only cadence has
+    /// nothing pending: the last emitted snapshot already carries the current BBO.
+    pub fn pending(&self) -> Vec<(u32, i64, i64)> {
+        if self.cadence == SnapshotCadence::BboChange {
+            return Vec::new();
+        }
+        let mut pending: Vec<(u32, i64, i64)> = self
+            .instruments
+            .iter()
+            .filter_map(|(instrument_id, state)| {
+                let (ts_event, ts_recv) = state.pending_ts?;
+                Some((*instrument_id, ts_event, ts_recv))
+            })
+            .collect();
+        pending.sort_unstable();
+        pending
+    }
+
+    /// Drops the instrument's state, returning what `pending` had for it.
+    pub fn forget(&mut self, instrument_id: u32) -> Option<(i64, i64)> {
+        let state = self.instruments.remove(&instrument_id)?;
+        if self.cadence == SnapshotCadence::BboChange {
+            return None;
+        }
+        state.pending_ts
+    }
+}
+
+fn bbo_key(level: Option<PriceLevel>) -> BboKey {
+    level.map(|level| (level.price, level.size, level.count))
+}
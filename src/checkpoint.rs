@@ -0,0 +1,209 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use dbn::{
+    Publisher,
+    decode::dbn::RecordDecoder,
+    encode::{EncodeRecord, dbn::RecordEncoder},
+    record::MboMsg,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::{self, Codec},
+    order_book::{GapReport, Market},
+};
+
+const MAGIC: &[u8; 8] = b"BTNCKPT\0";
+const VERSION: u32 = 1;
+
+/// Where in the input a checkpoint was taken.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct CheckpointPosition {
+    /// Records read from the input, counting from its start.
+    pub records: u64,
+    /// ts_event of the last record read, used to check the input on resume.
+    pub last_ts_event: i64,
+}
+
+/// A market rebuilt from a checkpoint file.
+pub struct Checkpoint {
+    pub input_path: String,
+    pub position: CheckpointPosition,
+    pub market: Market,
+    /// Last `Snapshot::seq` issued per instrument, so numbering continues on resume.
+    pub snapshot_seqs: Vec<(u32, u64)>,
+    pub orders: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    input_path: String,
+    position: CheckpointPosition,
+    sequences: Vec<SequenceState>,
+    gaps: GapReport,
+    books: Vec<BookHeader>,
+    /// Missing in checkpoints written before snapshots were numbered.
+    #[serde(default)]
+    snapshot_seqs: Vec<(u32, u64)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SequenceState {
+    publisher_id: u16,
+    channel_id: u8,
+    sequence: u32,
+}
+
+/// The book's resting orders follow the header in `books` order.
+#[derive(Serialize, Deserialize)]
+struct BookHeader {
+    instrument_id: u32,
+    publisher_id: u16,
+    stale: bool,
+    orders: u64,
+}
+
+/// Writes the full state of `market` to `path`: a stream compressed with `codec`
+/// holding a JSON header followed by every resting order as a DBN MBO record. The file
+/// is replaced atomically, so a crash mid-write leaves the previous checkpoint intact.
+/// Returns the number of orders written.
+pub fn write_checkpoint(
+    path: &Path,
+    market: &Market,
+    snapshot_seqs: Vec<(u32, u64)>,
+    input_path: &str,
+    position: CheckpointPosition,
+    codec: Codec,
+) -> Result<u64> {
+    let books: Vec<_> = market.iter_books().collect();
+    let header = Header {
+        version: VERSION,
+        input_path: input_path.to_owned(),
+        position,
+        sequences: market
+            .last_sequences()
+            .map(|((publisher_id, channel_id), sequence)| SequenceState {
+                publisher_id,
+                channel_id,
+                sequence,
+            })
+            .collect(),
+        gaps: market.gap_report().clone(),
+        books: books
+            .iter()
+            .map(|(instrument_id, publisher, book)| BookHeader {
+                instrument_id: *instrument_id,
+                publisher_id: *publisher as u16,
+                stale: book.is_stale(),
+                orders: book.resting_orders().count() as u64,
+            })
+            .collect(),
+        snapshot_seqs,
+    };
+    let header = serde_json::to_vec(&header).context("failed to serialize checkpoint header")?;
+
+    let tmp = path.with_extension("tmp");
+    let file = File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+    let mut out = codec.encoder(BufWriter::new(file))?;
+    out.write_all(MAGIC)?;
+    out.write_all(&(header.len() as u32).to_le_bytes())?;
+    out.write_all(&header)?;
+    let mut encoder = RecordEncoder::new(&mut out);
+    let mut orders = 0u64;
+    for (instrument_id, publisher, book) in &books {
+        for mut order in book.resting_orders() {
+            order.hd.instrument_id = *instrument_id;
+            order.hd.publisher_id = *publisher as u16;
+            encoder
+                .encode_record(&order)
+                .context("failed to write checkpoint order")?;
+            orders += 1;
+        }
+    }
+    let file = out.finish().context("failed to finish checkpoint")?;
+    file.into_inner()
+        .map_err(|e| e.into_error())
+        .and_then(|file| file.sync_all())
+        .with_context(|| format!("failed to flush {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to rename to {}", path.display()))?;
+    Ok(orders)
+}
+
+/// Rebuilds the market saved by `write_checkpoint`, whichever codec it was written with.
+pub fn read_checkpoint(path: &Path) -> Result<Checkpoint> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let (mut input, _) = codec::decoder(BufReader::new(file))
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let mut magic = [0u8; 8];
+    input
+        .read_exact(&mut magic)
+        .with_context(|| format!("{} is not a checkpoint", path.display()))?;
+    if &magic != MAGIC {
+        bail!("{} is not a checkpoint", path.display());
+    }
+    let mut len = [0u8; 4];
+    input
+        .read_exact(&mut len)
+        .context("checkpoint header is truncated")?;
+    let mut header = vec![0u8; u32::from_le_bytes(len) as usize];
+    input
+        .read_exact(&mut header)
+        .context("checkpoint header is truncated")?;
+    let header: Header = serde_json::from_slice(&header).context("checkpoint header is invalid")?;
+    if header.version != VERSION {
+        bail!(
+            "checkpoint version {} is not supported (expected {})",
+            header.version,
+            VERSION
+        );
+    }
+
+    let mut market = Market::new();
+    market.restore_sequences(
+        header
+            .sequences
+            .iter()
+            .map(|s| ((s.publisher_id, s.channel_id), s.sequence)),
+        header.gaps,
+    );
+    let mut decoder = RecordDecoder::new(input);
+    let mut orders = 0u64;
+    for book_header in &header.books {
+        let publisher = Publisher::try_from(book_header.publisher_id).map_err(|_| {
+            anyhow!(
+                "checkpoint has unknown publisher {}",
+                book_header.publisher_id
+            )
+        })?;
+        let book = market.book_mut(book_header.instrument_id, publisher);
+        book.set_stale(book_header.stale);
+        for _ in 0..book_header.orders {
+            let order = decoder
+                .decode::<MboMsg>()
+                .context("failed to read checkpoint order")?
+                .ok_or_else(|| anyhow!("checkpoint ends before all orders were read"))?
+                .clone();
+            if let Err(e) = book.restore_order(order) {
+                bail!(
+                    "checkpoint has an invalid order for instrument {}: {}",
+                    book_header.instrument_id,
+                    e
+                );
+            }
+            orders += 1;
+        }
+    }
+    Ok(Checkpoint {
+        input_path: header.input_path,
+        position: header.position,
+        market,
+        snapshot_seqs: header.snapshot_seqs,
+        orders,
+    })
+}
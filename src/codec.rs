@@ -0,0 +1,231 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+use anyhow::{Result, anyhow, bail};
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+use parquet::basic::{Compression as ParquetCompression, GzipLevel, ZstdLevel};
+
+/// Compression algorithm of a file output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodecKind {
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+/// Compression shared by every file output, selected per output as `name[:level]`,
+/// e.g. `none`, `gzip:6`, `zstd:3` or `lz4`. Compressed streams are written as
+/// self-delimiting frames, so a file appended to by a restarted writer still decodes
+/// as one stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Codec {
+    pub kind: CodecKind,
+    /// Algorithm-specific level; `None` uses the algorithm's default.
+    pub level: Option<u32>,
+}
+
+impl Codec {
+    pub const NONE: Codec = Codec {
+        kind: CodecKind::None,
+        level: None,
+    };
+
+    pub fn parse(raw: &str) -> Result<Self> {
+        let raw = raw.trim();
+        let (name, level) = match raw.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (raw, None),
+        };
+        let kind = match name {
+            "none" => CodecKind::None,
+            "gzip" | "gz" => CodecKind::Gzip,
+            "zstd" => CodecKind::Zstd,
+            "lz4" => CodecKind::Lz4,
+            _ => bail!(
+                "unknown codec {:?}, expected none, gzip, zstd or lz4 with an optional :<level>",
+                raw
+            ),
+        };
+        let level = level
+            .map(|level| {
+                level
+                    .parse::<u32>()
+                    .map_err(|_| anyhow!("codec {:?} has an invalid level {:?}", raw, level))
+            })
+            .transpose()?;
+        if let Some(level) = level {
+            let (min, max) = match kind {
+                CodecKind::Gzip => (0, 9),
+                CodecKind::Zstd => (1, 22),
+                CodecKind::None | CodecKind::Lz4 => bail!("codec {:?} takes no level", name),
+            };
+            if !(min..=max).contains(&level) {
+                bail!("{} level must be between {} and {}", name, min, max);
+            }
+        }
+        Ok(Self { kind, level })
+    }
+
+    /// Conventional file extension, including the dot; empty for `none`.
+    pub fn extension(&self) -> &'static str {
+        match self.kind {
+            CodecKind::None => "",
+            CodecKind::Gzip => ".gz",
+            CodecKind::Zstd => ".zst",
+            CodecKind::Lz4 => ".lz4",
+        }
+    }
+
+    pub fn encoder<W: Write>(&self, inner: W) -> io::Result<Encoder<W>> {
+        Ok(match self.kind {
+            CodecKind::None => Encoder::None(inner),
+            CodecKind::Gzip => Encoder::Gzip(GzEncoder::new(
+                inner,
+                self.level.map_or(Compression::default(), Compression::new),
+            )),
+            CodecKind::Zstd => Encoder::Zstd(zstd::Encoder::new(
+                inner,
+                self.level
+                    .map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |l| l as i32),
+            )?),
+            CodecKind::Lz4 => Encoder::Lz4(FrameEncoder::new(inner)),
+        })
+    }
+
+    /// Column compression for Parquet files. Parquet's LZ4 codecs are not built in.
+    pub fn parquet(&self) -> Result<ParquetCompression> {
+        Ok(match self.kind {
+            CodecKind::None => ParquetCompression::UNCOMPRESSED,
+            CodecKind::Gzip => ParquetCompression::GZIP(match self.level {
+                Some(level) => GzipLevel::try_new(level)?,
+                None => GzipLevel::default(),
+            }),
+            CodecKind::Zstd => ParquetCompression::ZSTD(match self.level {
+                Some(level) => ZstdLevel::try_new(level as i32)?,
+                None => ZstdLevel::default(),
+            }),
+            CodecKind::Lz4 => bail!("parquet outputs support none, gzip or zstd, not lz4"),
+        })
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.kind {
+            CodecKind::None => "none",
+            CodecKind::Gzip => "gzip",
+            CodecKind::Zstd => "zstd",
+            CodecKind::Lz4 => "lz4",
+        };
+        match self.level {
+            Some(level) => write!(f, "{}:{}", name, level),
+            None => f.write_str(name),
+        }
+    }
+}
+
+/// A writer compressing into `W` with one of the codecs.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Lz4(FrameEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Ends the compressed frame and flushes `W`. Nothing may be written afterwards.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(_) => {}
+            Encoder::Gzip(gz) => gz.try_finish()?,
+            Encoder::Zstd(zstd) => zstd.do_finish()?,
+            Encoder::Lz4(lz4) => lz4.try_finish().map_err(io::Error::other)?,
+        }
+        self.get_mut().flush()
+    }
+
+    /// Ends the compressed frame and returns `W`, which is not flushed.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(inner) => Ok(inner),
+            Encoder::Gzip(gz) => gz.finish(),
+            Encoder::Zstd(zstd) => zstd.finish(),
+            Encoder::Lz4(lz4) => lz4.finish().map_err(io::Error::other),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match self {
+            Encoder::None(inner) => inner,
+            Encoder::Gzip(gz) => gz.get_ref(),
+            Encoder::Zstd(zstd) => zstd.get_ref(),
+            Encoder::Lz4(lz4) => lz4.get_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut W {
+        match self {
+            Encoder::None(inner) => inner,
+            Encoder::Gzip(gz) => gz.get_mut(),
+            Encoder::Zstd(zstd) => zstd.get_mut(),
+            Encoder::Lz4(lz4) => lz4.get_mut(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(inner) => inner.write(buf),
+            Encoder::Gzip(gz) => gz.write(buf),
+            Encoder::Zstd(zstd) => zstd.write(buf),
+            Encoder::Lz4(lz4) => lz4.write(buf),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::None(inner) => inner.write_all(buf),
+            Encoder::Gzip(gz) => gz.write_all(buf),
+            Encoder::Zstd(zstd) => zstd.write_all(buf),
+            Encoder::Lz4(lz4) => lz4.write_all(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(inner) => inner.flush(),
+            Encoder::Gzip(gz) => gz.flush(),
+            Encoder::Zstd(zstd) => zstd.flush(),
+            Encoder::Lz4(lz4) => lz4.flush(),
+        }
+    }
+}
+
+/// Reads `inner` through the codec its leading magic bytes name, so readers accept
+/// any codec without being told which one was used. Returns the detected codec kind.
+pub fn decoder<'a, R: BufRead + Send + 'a>(
+    mut inner: R,
+) -> io::Result<(Box<dyn Read + Send + 'a>, CodecKind)> {
+    let head = inner.fill_buf()?;
+    let kind = if head.starts_with(&[0x1f, 0x8b]) {
+        CodecKind::Gzip
+    } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        CodecKind::Zstd
+    } else if head.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+        CodecKind::Lz4
+    } else {
+        CodecKind::None
+    };
+    let reader: Box<dyn Read + Send + 'a> = match kind {
+        CodecKind::None => Box::new(inner),
+        CodecKind::Gzip => Box::new(MultiGzDecoder::new(inner)),
+        CodecKind::Zstd => Box::new(zstd::Decoder::with_buffer(inner)?),
+        CodecKind::Lz4 => Box::new(FrameDecoder::new(inner)),
+    };
+    Ok((reader, kind))
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+
+use crate::{
+    codec::{Codec, CodecKind},
+    config::{MbpFormat, mbp_output_path},
+    ingest::{MbpJsonSource, SnapshotSource},
+    snapshot::{LevelEntry, Snapshot, SnapshotRecord, SymbolMap},
+    storage::{for_each_run_snapshot, load_ingest_run},
+};
+
+/// Run summary file looked for in an output directory, for timing.
+pub const RUN_SUMMARY_FILE: &str = "run_summary.json";
+
+/// Format of the comparison report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Json => "json",
+            ReportFormat::Html => "html",
+        }
+    }
+}
+
+/// One side of a comparison: a run id from `ingest_runs`, whose snapshots are read
+/// from Postgres, or a run's output directory (or its `final_mbp.json`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunRef {
+    Run(i64),
+    Dir(PathBuf),
+}
+
+impl RunRef {
+    /// Anything that is all digits is a run id.
+    pub fn parse(raw: &str) -> Self {
+        match raw.parse() {
+            Ok(id) if raw.bytes().all(|b| b.is_ascii_digit()) => RunRef::Run(id),
+            _ => RunRef::Dir(PathBuf::from(raw)),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            RunRef::Run(id) => format!("run {}", id),
+            RunRef::Dir(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// What is compared of one snapshot.
+#[derive(Clone, Copy, Debug)]
+struct Fingerprint {
+    seq: u64,
+    ts_event: i64,
+    checksum: u32,
+}
+
+/// CRC-32 of a snapshot's book: every level with its size and order count, plus the
+/// level and order totals. Time, staleness and derived fields are left out, so the
+/// checksum only changes when the book does.
+fn book_checksum(snapshot: &Snapshot) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    let mut side = |levels: &[LevelEntry]| {
+        hasher.update(&(levels.len() as u64).to_le_bytes());
+        for level in levels {
+            hasher.update(&level.price.to_le_bytes());
+            hasher.update(&level.size.to_le_bytes());
+            hasher.update(&level.count.to_le_bytes());
+        }
+    };
+    side(&snapshot.bids);
+    side(&snapshot.asks);
+    for total in [
+        snapshot.total_orders,
+        snapshot.bid_levels,
+        snapshot.ask_levels,
+    ] {
+        hasher.update(&(total as u64).to_le_bytes());
+    }
+    hasher.finalize()
+}
+
+/// Where a run's snapshots came from and how long it took.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunInfo {
+    pub source: String,
+    pub snapshots: u64,
+    pub symbols: usize,
+    pub status: Option<String>,
+    /// MBO records the run processed.
+    pub processed: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub records_per_sec: Option<f64>,
+    /// CRC-32 over every symbol's checksum; equal for runs with identical books.
+    pub checksum: String,
+}
+
+struct RunSnapshots {
+    info: RunInfo,
+    /// Fingerprints per symbol, ordered by `seq`.
+    symbols: BTreeMap<String, Vec<Fingerprint>>,
+}
+
+impl RunSnapshots {
+    fn load(run: &RunRef, db_url: &str) -> Result<Self> {
+        let mut symbols: BTreeMap<String, Vec<Fingerprint>> = BTreeMap::new();
+        let mut add = |record: SnapshotRecord| {
+            symbols
+                .entry(record.payload.symbol.clone())
+                .or_default()
+                .push(Fingerprint {
+                    seq: record.payload.seq,
+                    ts_event: record.ts_event,
+                    checksum: book_checksum(&record.payload),
+                });
+        };
+        let mut info = RunInfo {
+            source: run.label(),
+            ..RunInfo::default()
+        };
+        match run {
+            RunRef::Run(id) => {
+                let ingest_run = load_ingest_run(db_url, *id)?
+                    .ok_or_else(|| anyhow!("no ingest run {} in ingest_runs", id))?;
+                info.source = format!("run {} ({})", id, ingest_run.input_path);
+                info.status = Some(ingest_run.status);
+                info.processed = ingest_run.processed.map(|n| n as u64);
+                info.duration_ms = ingest_run.duration_ms.map(|ms| ms.max(0) as u64);
+                if for_each_run_snapshot(db_url, *id, &mut add)? == 0 {
+                    bail!(
+                        "run {} has no stored snapshots; runs stored before snapshots carried their run id cannot be compared",
+                        id
+                    );
+                }
+            }
+            RunRef::Dir(path) => {
+                let mbp_path = find_mbp_output(path)?;
+                info.source = mbp_path.display().to_string();
+                let mut source = MbpJsonSource::open(&info.source)?;
+                let symbol_map = SymbolMap::new("");
+                while let Some(record) = source.next_snapshot(&symbol_map)? {
+                    add(record);
+                }
+                if path.is_dir() {
+                    read_run_summary(&path.join(RUN_SUMMARY_FILE), &mut info)?;
+                }
+            }
+        }
+        for fingerprints in symbols.values_mut() {
+            // Rows stored before snapshots were numbered have seq 0; number them in order
+            if fingerprints.iter().all(|f| f.seq == 0) {
+                for (i, fingerprint) in fingerprints.iter_mut().enumerate() {
+                    fingerprint.seq = i as u64 + 1;
+                }
+            }
+            fingerprints.sort_by_key(|f| f.seq);
+        }
+        info.snapshots = symbols.values().map(|f| f.len() as u64).sum();
+        info.symbols = symbols.len();
+        info.records_per_sec = match (info.processed, info.duration_ms) {
+            (Some(processed), Some(ms)) if ms > 0 => Some(processed as f64 * 1000.0 / ms as f64),
+            _ => None,
+        };
+        let mut hasher = crc32fast::Hasher::new();
+        for (symbol, fingerprints) in &symbols {
+            hasher.update(symbol.as_bytes());
+            hasher.update(&symbol_checksum(fingerprints).to_le_bytes());
+        }
+        info.checksum = format!("{:08x}", hasher.finalize());
+        Ok(Self { info, symbols })
+    }
+}
+
+fn symbol_checksum(fingerprints: &[Fingerprint]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for fingerprint in fingerprints {
+        hasher.update(&fingerprint.seq.to_le_bytes());
+        hasher.update(&fingerprint.checksum.to_le_bytes());
+    }
+    hasher.finalize()
+}
+
+/// `path` itself when it is a file, otherwise the MBP output inside it under any codec.
+fn find_mbp_output(path: &Path) -> Result<PathBuf> {
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    if !path.is_dir() {
+        bail!(
+            "{} is neither a run id nor an output directory",
+            path.display()
+        );
+    }
+    [
+        CodecKind::None,
+        CodecKind::Gzip,
+        CodecKind::Zstd,
+        CodecKind::Lz4,
+    ]
+    .into_iter()
+    .map(|kind| {
+        path.join(mbp_output_path(
+            MbpFormat::Json,
+            Codec { kind, level: None },
+        ))
+    })
+    .find(|candidate| candidate.is_file())
+    .ok_or_else(|| {
+        anyhow!(
+            "no {} in {}",
+            mbp_output_path(MbpFormat::Json, Codec::NONE),
+            path.display()
+        )
+    })
+}
+
+/// Fills status and timing from a run summary written with `RUN_SUMMARY_PATH`, if any.
+fn read_run_summary(path: &Path, info: &mut RunInfo) -> Result<()> {
+    let Ok(raw) = fs::read_to_string(path) else {
+        return Ok(());
+    };
+    let summary: serde_json::Value = serde_json::from_str(&raw)
+        .with_context(|| format!("invalid run summary {}", path.display()))?;
+    info.status = summary["status"].as_str().map(str::to_owned);
+    info.processed = summary["processed"].as_u64();
+    info.duration_ms = summary["duration_ms"].as_u64();
+    Ok(())
+}
+
+/// The first `seq` at which the runs disagree; a missing side is `None`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Difference {
+    pub seq: u64,
+    pub ts_event_a: Option<i64>,
+    pub ts_event_b: Option<i64>,
+    pub checksum_a: Option<String>,
+    pub checksum_b: Option<String>,
+}
+
+/// How one symbol's snapshots compare, matched up by `seq`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SymbolDiff {
+    pub symbol: String,
+    pub snapshots_a: u64,
+    pub snapshots_b: u64,
+    /// Same `seq` and same book.
+    pub matched: u64,
+    /// Same `seq`, different book.
+    pub mismatched: u64,
+    pub only_a: u64,
+    pub only_b: u64,
+    /// Matched snapshots whose `ts_event` differs.
+    pub ts_mismatched: u64,
+    pub checksum_a: Option<String>,
+    pub checksum_b: Option<String>,
+    pub first_difference: Option<Difference>,
+}
+
+impl SymbolDiff {
+    fn differs(&self) -> bool {
+        self.mismatched > 0 || self.only_a > 0 || self.only_b > 0 || self.ts_mismatched > 0
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CompareTotals {
+    pub matched: u64,
+    pub mismatched: u64,
+    pub only_a: u64,
+    pub only_b: u64,
+    pub ts_mismatched: u64,
+}
+
+/// Everything `batonics compare-runs` reports.
+#[derive(Clone, Debug, Serialize)]
+pub struct CompareReport {
+    pub a: RunInfo,
+    pub b: RunInfo,
+    /// True when every snapshot of either run matches one of the other.
+    pub identical: bool,
+    /// Change in wall time from `a` to `b`, when both runs have one.
+    pub duration_change_pct: Option<f64>,
+    pub totals: CompareTotals,
+    pub symbols: Vec<SymbolDiff>,
+}
+
+/// Loads both runs and compares their snapshots. `db_url` is only used for run ids.
+pub fn compare_runs(a: &RunRef, b: &RunRef, db_url: &str) -> Result<CompareReport> {
+    let run_a =
+        RunSnapshots::load(a, db_url).with_context(|| format!("failed to load {}", a.label()))?;
+    let run_b =
+        RunSnapshots::load(b, db_url).with_context(|| format!("failed to load {}", b.label()))?;
+
+    let mut names: Vec<&String> = run_a.symbols.keys().chain(run_b.symbols.keys()).collect();
+    names.sort();
+    names.dedup();
+    let empty = Vec::new();
+    let symbols: Vec<SymbolDiff> = names
+        .into_iter()
+        .map(|symbol| {
+            compare_symbol(
+                symbol,
+                run_a.symbols.get(symbol).unwrap_or(&empty),
+                run_b.symbols.get(symbol).unwrap_or(&empty),
+            )
+        })
+        .collect();
+
+    let mut totals = CompareTotals::default();
+    for diff in &symbols {
+        totals.matched += diff.matched;
+        totals.mismatched += diff.mismatched;
+        totals.only_a += diff.only_a;
+        totals.only_b += diff.only_b;
+        totals.ts_mismatched += diff.ts_mismatched;
+    }
+    let duration_change_pct = match (run_a.info.duration_ms, run_b.info.duration_ms) {
+        (Some(a), Some(b)) if a > 0 => Some((b as f64 - a as f64) * 100.0 / a as f64),
+        _ => None,
+    };
+    Ok(CompareReport {
+        identical: !symbols.iter().any(SymbolDiff::differs),
+        a: run_a.info,
+        b: run_b.info,
+        duration_change_pct,
+        totals,
+        symbols,
+    })
+}
+
+fn compare_symbol(symbol: &str, a: &[Fingerprint], b: &[Fingerprint]) -> SymbolDiff {
+    let mut diff = SymbolDiff {
+        symbol: symbol.to_owned(),
+        snapshots_a: a.len() as u64,
+        snapshots_b: b.len() as u64,
+        checksum_a: (!a.is_empty()).then(|| format!("{:08x}", symbol_checksum(a))),
+        checksum_b: (!b.is_empty()).then(|| format!("{:08x}", symbol_checksum(b))),
+        ..SymbolDiff::default()
+    };
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    loop {
+        let (fa, fb) = match (a.peek(), b.peek()) {
+            (None, None) => break,
+            (Some(fa), Some(fb)) if fa.seq == fb.seq => (a.next(), b.next()),
+            (Some(fa), Some(fb)) if fa.seq < fb.seq => (a.next(), None),
+            (Some(_), None) => (a.next(), None),
+            _ => (None, b.next()),
+        };
+        let differs = match (fa, fb) {
+            (Some(fa), Some(fb)) if fa.checksum == fb.checksum => {
+                diff.matched += 1;
+                if fa.ts_event != fb.ts_event {
+                    diff.ts_mismatched += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+            (Some(_), Some(_)) => {
+                diff.mismatched += 1;
+                true
+            }
+            (Some(_), None) => {
+                diff.only_a += 1;
+                true
+            }
+            _ => {
+                diff.only_b += 1;
+                true
+            }
+        };
+        if differs && diff.first_difference.is_none() {
+            let seq = fa.or(fb).map_or(0, |f| f.seq);
+            diff.first_difference = Some(Difference {
+                seq,
+                ts_event_a: fa.map(|f| f.ts_event),
+                ts_event_b: fb.map(|f| f.ts_event),
+                checksum_a: fa.map(|f| format!("{:08x}", f.checksum)),
+                checksum_b: fb.map(|f| format!("{:08x}", f.checksum)),
+            });
+        }
+    }
+    diff
+}
+
+impl CompareReport {
+    pub fn render(&self, format: ReportFormat) -> Result<String> {
+        match format {
+            ReportFormat::Json => self.to_json(),
+            ReportFormat::Html => Ok(self.to_html()),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("failed to serialize comparison report")
+    }
+
+    /// A self-contained page with the run table and one row per symbol; symbols that
+    /// differ are highlighted.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        let opt = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Run comparison</title>\n\
+             <style>body{{font-family:sans-serif}}table{{border-collapse:collapse;margin-bottom:1.5em}}\
+             td,th{{border:1px solid #ccc;padding:4px 8px;text-align:right}}td:first-child,th:first-child{{text-align:left}}\
+             tr.differs{{background:#fde2e2}}</style></head><body>\n\
+             <h1>Run comparison: {}</h1>\n",
+            if self.identical {
+                "identical"
+            } else {
+                "different"
+            }
+        );
+        html.push_str(
+            "<table><tr><th>Run</th><th>Source</th><th>Status</th><th>Snapshots</th><th>Symbols</th>\
+             <th>Processed</th><th>Duration ms</th><th>Records/s</th><th>Checksum</th></tr>\n",
+        );
+        for (label, run) in [("A", &self.a), ("B", &self.b)] {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                label,
+                escape(&run.source),
+                escape(&opt(run.status.clone())),
+                run.snapshots,
+                run.symbols,
+                opt(run.processed.map(|n| n.to_string())),
+                opt(run.duration_ms.map(|n| n.to_string())),
+                opt(run.records_per_sec.map(|n| format!("{:.0}", n))),
+                run.checksum
+            );
+        }
+        html.push_str("</table>\n");
+        if let Some(pct) = self.duration_change_pct {
+            let _ = writeln!(html, "<p>Duration change from A to B: {:+.1}%</p>", pct);
+        }
+        html.push_str(
+            "<table><tr><th>Symbol</th><th>A</th><th>B</th><th>Matched</th><th>Mismatched</th>\
+             <th>Only A</th><th>Only B</th><th>ts_event differs</th><th>First difference (seq)</th></tr>\n",
+        );
+        for diff in &self.symbols {
+            let _ = writeln!(
+                html,
+                "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                if diff.differs() {
+                    " class=\"differs\""
+                } else {
+                    ""
+                },
+                escape(&diff.symbol),
+                diff.snapshots_a,
+                diff.snapshots_b,
+                diff.matched,
+                diff.mismatched,
+                diff.only_a,
+                diff.only_b,
+                diff.ts_mismatched,
+                opt(diff.first_difference.as_ref().map(|d| d.seq.to_string()))
+            );
+        }
+        html.push_str("</table>\n</body></html>\n");
+        html
+    }
+}
+
+fn escape(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
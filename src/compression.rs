@@ -0,0 +1,175 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use axum::body::{Body, Bytes};
+use flate2::{
+    Compression,
+    write::{DeflateEncoder, GzEncoder},
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+use tracing::info;
+
+/// Compression settings for streaming endpoints.
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionConfig {
+    /// WebSocket messages at least this large are deflated for clients that opt in.
+    pub ws_min_bytes: usize,
+    /// Gzip SSE streams for clients that send `Accept-Encoding: gzip`.
+    pub sse_gzip: bool,
+}
+
+/// Byte counters for one streaming client.
+#[derive(Debug, Default)]
+pub struct StreamCounters {
+    kind: &'static str,
+    messages: AtomicU64,
+    raw_bytes: AtomicU64,
+    wire_bytes: AtomicU64,
+}
+
+impl StreamCounters {
+    pub fn record(&self, raw_bytes: usize, wire_bytes: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.raw_bytes
+            .fetch_add(raw_bytes as u64, Ordering::Relaxed);
+        self.wire_bytes
+            .fetch_add(wire_bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> StreamSummary {
+        let raw_bytes = self.raw_bytes.load(Ordering::Relaxed);
+        let wire_bytes = self.wire_bytes.load(Ordering::Relaxed);
+        StreamSummary {
+            kind: self.kind,
+            messages: self.messages.load(Ordering::Relaxed),
+            raw_bytes,
+            wire_bytes,
+            compression_ratio: if wire_bytes == 0 {
+                1.0
+            } else {
+                raw_bytes as f64 / wire_bytes as f64
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StreamSummary {
+    pub kind: &'static str,
+    pub messages: u64,
+    pub raw_bytes: u64,
+    pub wire_bytes: u64,
+    /// Uncompressed over sent bytes; 1.0 when nothing was compressed.
+    pub compression_ratio: f64,
+}
+
+/// Live per-client stream counters keyed by request id, reported on `/metrics`.
+#[derive(Debug, Default)]
+pub struct StreamStats {
+    clients: Mutex<BTreeMap<String, Arc<StreamCounters>>>,
+}
+
+impl StreamStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a client; its counters are dropped from the report with the guard.
+    pub fn register(self: &Arc<Self>, request_id: &str, kind: &'static str) -> StreamGuard {
+        let counters = Arc::new(StreamCounters {
+            kind,
+            ..StreamCounters::default()
+        });
+        self.clients
+            .lock()
+            .expect("stream stats lock poisoned")
+            .insert(request_id.to_owned(), counters.clone());
+        StreamGuard {
+            stats: self.clone(),
+            request_id: request_id.to_owned(),
+            counters,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, StreamSummary> {
+        self.clients
+            .lock()
+            .expect("stream stats lock poisoned")
+            .iter()
+            .map(|(id, counters)| (id.clone(), counters.summary()))
+            .collect()
+    }
+}
+
+pub struct StreamGuard {
+    stats: Arc<StreamStats>,
+    request_id: String,
+    counters: Arc<StreamCounters>,
+    started: Instant,
+}
+
+impl StreamGuard {
+    pub fn counters(&self) -> &Arc<StreamCounters> {
+        &self.counters
+    }
+
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let summary = self.counters.summary();
+        info!(
+            request_id = %self.request_id,
+            kind = %summary.kind,
+            duration_ms = self.started.elapsed().as_millis() as u64,
+            messages = summary.messages,
+            raw_bytes = summary.raw_bytes,
+            wire_bytes = summary.wire_bytes,
+            compression_ratio = summary.compression_ratio,
+            "stream_closed"
+        );
+        if let Ok(mut clients) = self.stats.clients.lock() {
+            clients.remove(&self.request_id);
+        }
+    }
+}
+
+/// Raw deflate (RFC 1951) of a single message, without shared window state between
+/// messages so clients can inflate each frame independently.
+pub fn deflate_message(payload: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder =
+        DeflateEncoder::new(Vec::with_capacity(payload.len() / 4), Compression::fast());
+    encoder.write_all(payload)?;
+    encoder.finish()
+}
+
+/// Counts the bytes of a streaming body, gzipping it when `gzip` is set. Each chunk is
+/// sync-flushed so events reach the client immediately rather than when a block fills.
+pub fn stream_body(body: Body, gzip: bool, guard: StreamGuard) -> Body {
+    let mut encoder = gzip.then(|| GzEncoder::new(Vec::new(), Compression::fast()));
+    let chunks = body.into_data_stream().map(move |chunk| {
+        let chunk = chunk?;
+        let Some(encoder) = encoder.as_mut() else {
+            guard.counters().record(chunk.len(), chunk.len());
+            return Ok(chunk);
+        };
+        encoder.write_all(&chunk).map_err(axum::Error::new)?;
+        encoder.flush().map_err(axum::Error::new)?;
+        let compressed = Bytes::from(std::mem::take(encoder.get_mut()));
+        guard.counters().record(chunk.len(), compressed.len());
+        Ok::<_, axum::Error>(compressed)
+    });
+    Body::from_stream(chunks)
+}
@@ -0,0 +1,1657 @@
+//! Turns parsed command-line arguments into validated settings for each command.
+//!
+//! Every check runs before anything starts, and every problem found is reported in one
+//! error, so a misconfigured deployment is fixed in one edit rather than one per restart.
+
+use std::{
+    cmp::Ordering,
+    env,
+    fmt::Display,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use dbn::Publisher;
+use tracing::warn;
+
+use crate::{
+    alerts::{AlertConfig, AlertRule},
+    analytics::bars::BarInterval,
+    cadence::SnapshotCadence,
+    cli::{
+        BenchArgs, CompareRunsArgs, DatabaseArgs, ExportArgs, IngestArgs, InitDbArgs, LogArgs,
+        PruneArgs, ReplaySpillArgs, RetentionArgs, ServeArgs, ServerArgs, StreamArgs,
+        TimescaleArgs,
+    },
+    codec::{Codec, CodecKind},
+    compare::{ReportFormat, RunRef},
+    compression::CompressionConfig,
+    ingest::input_files,
+    latency::LatencyOrigin,
+    live::{LiveConfig, VENUE_SCHEMAS},
+    logging::{self, LogFormat},
+    order_book::{BookPolicies, BookPolicy, DepthCap, FillHandling, SequenceCheck},
+    progress::ProgressMode,
+    queue::{BackpressurePolicy, QueueBackend, QueueConfig},
+    server::ServerConfig,
+    snapshot::{
+        BucketSpec, ContractMeta, LiquiditySpec, PriceFormat, SnapshotBook, SnapshotEncoding,
+        SymbolMap, TimestampSource,
+    },
+    storage::{
+        CopyPipeline, ExportFormat, ExportRequest, FlushSchedule, RedisOptions, RetentionPolicy,
+        RetryPolicy, SinkKind, TimescaleConfig,
+    },
+    stream::Pace,
+    supervisor::RestartPolicy,
+    validate::ValidateConfig,
+};
+
+/// Collects configuration problems so they can be reported together.
+#[derive(Debug, Default)]
+pub struct Problems {
+    problems: Vec<String>,
+}
+
+impl Problems {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, problem: impl Into<String>) {
+        self.problems.push(problem.into());
+    }
+
+    /// Records the problem unless `ok` holds.
+    pub fn ensure(&mut self, ok: bool, problem: impl FnOnce() -> String) {
+        if !ok {
+            self.push(problem());
+        }
+    }
+
+    /// Checks `min <= value <= max` for the flag `name` (e.g. `queue-capacity`), which
+    /// is reported with its env var. NaN is out of every range.
+    pub fn range<T: PartialOrd + Display>(&mut self, name: &str, value: T, min: T, max: T) -> T {
+        if !(value >= min && value <= max) {
+            self.push(format!(
+                "{} must be between {} and {}, got {}",
+                flag(name),
+                min,
+                max,
+                value
+            ));
+        }
+        value
+    }
+
+    /// Checks `value >= min` for the flag `name`.
+    pub fn at_least<T: PartialOrd + Display>(&mut self, name: &str, value: T, min: T) -> T {
+        if !matches!(
+            value.partial_cmp(&min),
+            Some(Ordering::Greater | Ordering::Equal)
+        ) {
+            self.push(format!(
+                "{} must be at least {}, got {}",
+                flag(name),
+                min,
+                value
+            ));
+        }
+        value
+    }
+
+    /// Records the error of `result` instead of returning early, so later checks still run.
+    pub fn take<T>(&mut self, result: Result<T>) -> Option<T> {
+        result.inspect_err(|e| self.push(format!("{:#}", e))).ok()
+    }
+
+    /// Fails with every recorded problem, one per line.
+    pub fn finish(self) -> Result<()> {
+        match self.problems.len() {
+            0 => Ok(()),
+            1 => bail!("invalid configuration: {}", self.problems[0]),
+            n => bail!(
+                "invalid configuration ({} problems):\n  - {}",
+                n,
+                self.problems.join("\n  - ")
+            ),
+        }
+    }
+}
+
+/// `queue-capacity` -> `--queue-capacity/QUEUE_CAPACITY`.
+pub fn flag(name: &str) -> String {
+    format!("--{}/{}", name, name.replace('-', "_").to_uppercase())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    File,
+    Tcp,
+    /// Databento live gateway.
+    Live,
+    /// MBP NDJSON as written to final_mbp.json.
+    MbpJson,
+    /// Databento MBP-10 DBN.
+    Mbp10Dbn,
+}
+
+/// Controls how many snapshots the MBP writer persists. `every_n` keeps every Nth
+/// snapshot; `bucket_ns` keeps only the last snapshot of each data-time bucket.
+#[derive(Clone, Copy, Debug)]
+pub struct MbpSampling {
+    pub every_n: u64,
+    pub bucket_ns: Option<i64>,
+}
+
+/// Record format of the MBP writer's output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MbpFormat {
+    /// One JSON object per line.
+    Json,
+    /// One row per snapshot with the top ten levels as columns.
+    Csv,
+    /// DBN MBP-10 records.
+    Dbn,
+}
+
+/// Where and how the MBP writer writes. With rotation the file at `path` is the one
+/// being written; finished files are renamed with a segment number.
+#[derive(Clone, Debug)]
+pub struct MbpOutputConfig {
+    pub path: String,
+    pub format: MbpFormat,
+    pub codec: Codec,
+    /// Rotate once the file reaches this many bytes.
+    pub rotate_bytes: Option<u64>,
+    /// Rotate once the file has been written to for this long.
+    pub rotate_interval: Option<Duration>,
+    /// Prices of the JSON and CSV formats; DBN records are always fixed-point.
+    pub prices: PriceFormat,
+}
+
+/// The HTTP API and the routes it is mounted under.
+#[derive(Clone, Debug)]
+pub struct HttpConfig {
+    pub server: ServerConfig,
+    /// Route prefix, e.g. `replay/2024-05-01` serves `/replay/2024-05-01/snapshot/CLX5`.
+    pub namespace: String,
+    /// Per-client WebSocket backlog before a client is conflated.
+    pub ws_buffer: usize,
+    /// Where the gRPC snapshot service listens, when served.
+    pub grpc_addr: Option<SocketAddr>,
+}
+
+impl HttpConfig {
+    fn from_args(args: &ServerArgs, problems: &mut Problems) -> Self {
+        problems.ensure(args.grpc_addr != Some(args.server_addr), || {
+            format!(
+                "{} must differ from {}",
+                flag("grpc-addr"),
+                flag("server-addr")
+            )
+        });
+        Self {
+            server: ServerConfig {
+                addr: args.server_addr,
+                sse_max_rate: problems.range(
+                    "sse-max-events-per-sec",
+                    args.sse_max_events_per_sec,
+                    0.01,
+                    10_000.0,
+                ),
+                ws_conflated_rate: problems.range(
+                    "ws-conflated-rate",
+                    args.ws_conflated_rate,
+                    0.01,
+                    1_000.0,
+                ),
+                compression: CompressionConfig {
+                    ws_min_bytes: args.ws_compress_min_bytes,
+                    sse_gzip: args.sse_gzip,
+                },
+            },
+            namespace: args.server_namespace.clone(),
+            ws_buffer: problems.range("ws-buffer", args.ws_buffer, 1, 1_000_000),
+            grpc_addr: args.grpc_addr,
+        }
+    }
+}
+
+fn database_url(args: &DatabaseArgs, problems: &mut Problems) -> String {
+    let db_url = args.database_url.clone();
+    problems.ensure(
+        db_url.starts_with("postgres://") || db_url.starts_with("postgresql://"),
+        || {
+            format!(
+                "{} must be a postgres:// URL, got {}",
+                flag("database-url"),
+                db_url
+            )
+        },
+    );
+    if !db_url.contains("orderbook_snapshots") {
+        warn!("DATABASE_URL does not reference database named orderbook_snapshots");
+    }
+    db_url
+}
+
+/// `None` unless `--timescale` is set.
+fn timescale(args: &TimescaleArgs, problems: &mut Problems) -> Option<TimescaleConfig> {
+    if !args.timescale {
+        return None;
+    }
+    let chunk_interval_ms = problems.at_least(
+        "timescale-chunk-interval-ms",
+        args.timescale_chunk_interval_ms,
+        1,
+    );
+    let compress_after_ms = args
+        .timescale_compress_after_ms
+        .map(|ms| problems.at_least("timescale-compress-after-ms", ms, 0));
+    Some(TimescaleConfig {
+        chunk_interval_ns: chunk_interval_ms.saturating_mul(1_000_000),
+        compress_after_ns: compress_after_ms.map(|ms| ms.saturating_mul(1_000_000)),
+    })
+}
+
+fn retention(args: &RetentionArgs, problems: &mut Problems) -> Option<RetentionPolicy> {
+    let max_age_ms = args
+        .retention_max_age_ms
+        .map(|ms| problems.at_least("retention-max-age-ms", ms, 1));
+    let max_rows = args
+        .retention_max_rows
+        .map(|rows| problems.at_least("retention-max-rows", rows, 1));
+    (max_age_ms.is_some() || max_rows.is_some()).then(|| RetentionPolicy {
+        max_age_ns: max_age_ms.map(|ms| ms.saturating_mul(1_000_000)),
+        max_rows,
+    })
+}
+
+/// Parses a `name[:level]` codec flag, recording a problem when it is invalid.
+fn codec(problems: &mut Problems, flag: &str, raw: &str) -> Codec {
+    problems
+        .take(Codec::parse(raw).with_context(|| format!("invalid {}", flag)))
+        .unwrap_or(Codec::NONE)
+}
+
+/// Parses a Parquet codec flag; unset keeps Snappy, and LZ4 is not available.
+fn parquet_codec(problems: &mut Problems, flag: &str, raw: Option<&str>) -> Option<Codec> {
+    let codec = codec(problems, flag, raw?);
+    problems.ensure(codec.kind != CodecKind::Lz4, || {
+        format!("{} supports none, gzip or zstd, not lz4", flag)
+    });
+    Some(codec)
+}
+
+/// Where the MBP writer puts its output by default: `final_mbp` with the format's and
+/// the codec's extensions.
+pub fn mbp_output_path(format: MbpFormat, codec: Codec) -> String {
+    let extension = match format {
+        MbpFormat::Json => "json",
+        MbpFormat::Csv => "csv",
+        MbpFormat::Dbn => "dbn",
+    };
+    format!("final_mbp.{}{}", extension, codec.extension())
+}
+
+fn ensure_exists(problems: &mut Problems, name: &str, path: &Path) {
+    problems.ensure(path.exists(), || {
+        format!("{} {} does not exist", flag(name), path.display())
+    });
+}
+
+/// Everything `batonics ingest` needs.
+#[derive(Clone)]
+pub struct IngestConfig {
+    pub source: SourceKind,
+    pub tcp_source_addr: String,
+    pub tcp_instruments: Vec<u32>,
+    pub tcp_start_sequence: u64,
+    /// Set when the source is the Databento live gateway.
+    pub live: Option<LiveConfig>,
+    pub input_path: String,
+    pub symbols: SymbolMap,
+    pub storage_queue: QueueConfig,
+    pub mbp_queue: QueueConfig,
+    pub batch_size: usize,
+    /// Book apply threads; instruments are spread across them by `instrument_id`.
+    pub ingest_shards: usize,
+    /// Storage writer shards; symbols are spread across them by hash.
+    pub storage_writers: usize,
+    /// Connections and COPY batches in flight per Postgres sink.
+    pub postgres_pipeline: CopyPipeline,
+    pub postgres_retry: RetryPolicy,
+    pub spill_dir: Option<PathBuf>,
+    /// Write-ahead journal of queued snapshots, with `journal_segment_rows` lines per segment.
+    pub journal_dir: Option<PathBuf>,
+    pub journal_segment_rows: u64,
+    /// Pruning of the Postgres tables while ingest runs, every `retention_interval`.
+    pub retention: Option<RetentionPolicy>,
+    pub retention_interval: Duration,
+    pub flush_interval: Duration,
+    pub flush_schedule: FlushSchedule,
+    pub sinks: Vec<SinkKind>,
+    /// Upsert each book's newest snapshot into `orderbook_latest` as well.
+    pub latest_table: bool,
+    pub redis: RedisOptions,
+    pub parquet_row_group_size: usize,
+    pub snapshot_file_codec: Codec,
+    pub snapshot_file_encoding: SnapshotEncoding,
+    /// `None` keeps Parquet's Snappy default.
+    pub parquet_codec: Option<Codec>,
+    pub timescale: Option<TimescaleConfig>,
+    pub depth: usize,
+    pub db_url: Arc<String>,
+    pub http: HttpConfig,
+    pub contract: Option<ContractMeta>,
+    pub mbp_sampling: MbpSampling,
+    pub mbp_output: MbpOutputConfig,
+    /// JSON lines file of L3 snapshots, written alongside each snapshot when set.
+    pub l3_output: Option<String>,
+    pub from_ts: Option<i64>,
+    pub to_ts: Option<i64>,
+    /// Emit a snapshot for 1 in this many in-window records (1 = all).
+    pub sample_every: u64,
+    /// Which of the sampled records build a snapshot.
+    pub cadence: SnapshotCadence,
+    /// Clock of the interval cadence and bars.
+    pub timestamp_source: TimestampSource,
+    /// How often ingest logs interim metrics; `None` logs them only at the end.
+    pub metrics_interval: Option<Duration>,
+    /// What `/metrics` latencies are measured from.
+    pub latency_origin: LatencyOrigin,
+    /// Time bars built from trades; empty when bars are off.
+    pub bar_intervals: Vec<BarInterval>,
+    /// Send closed bars to the Postgres bars table.
+    pub store_bars: bool,
+    /// Trailing window of the rolling book statistics.
+    pub stats_window_ns: i64,
+    /// Interval at which the statistics go to the book_stats table; `None` stores none.
+    pub stats_persist_ns: Option<i64>,
+    /// Stop after this many records.
+    pub max_records: Option<u64>,
+    pub progress: ProgressMode,
+    /// How often ingest logs a progress line; `None` logs none.
+    pub progress_interval: Option<Duration>,
+    /// Where to periodically save the book state for `--resume-from`.
+    pub checkpoint_path: Option<PathBuf>,
+    pub checkpoint_interval: Duration,
+    pub checkpoint_codec: Codec,
+    pub resume_from: Option<PathBuf>,
+    pub sequence_check: SequenceCheck,
+    pub fills: FillHandling,
+    pub book_policies: BookPolicies,
+    pub depth_cap: DepthCap,
+    /// Books of instruments idle this long in data time are dropped; `None` keeps them.
+    pub book_idle_timeout: Option<Duration>,
+    /// Reference MBP data to cross-check books against.
+    pub validate: Option<ValidateConfig>,
+    pub stale_on_gap: bool,
+    pub sim_orders: bool,
+    /// OFI window when flow signals are on; `None` turns them off.
+    pub ofi_window_ns: Option<i64>,
+    /// Refills that flag an iceberg when detection is on; `None` turns it off.
+    pub iceberg_min_refills: Option<u32>,
+    /// `None` unless alert rules are configured.
+    pub alerts: Option<AlertConfig>,
+    /// `None` unless liquidity sizes or bands are configured.
+    pub liquidity: Option<LiquiditySpec>,
+    pub snapshot_book: SnapshotBook,
+    /// `None` unless depth buckets are configured.
+    pub buckets: Option<BucketSpec>,
+    /// DBN file of instrument definitions loaded before ingest.
+    pub instrument_defs: Option<String>,
+    pub warm_start: bool,
+    pub force: bool,
+    pub restart_policy: RestartPolicy,
+}
+
+impl IngestConfig {
+    pub fn from_args(args: &IngestArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+
+        let source_name = args.mode.as_deref().unwrap_or(&args.ingest_source);
+        let source = match source_name {
+            "tcp" => SourceKind::Tcp,
+            "live" => SourceKind::Live,
+            "mbp_json" => SourceKind::MbpJson,
+            "mbp10_dbn" => SourceKind::Mbp10Dbn,
+            _ => SourceKind::File,
+        };
+        let input_path = args.input_path.clone();
+        match source {
+            SourceKind::Tcp | SourceKind::Live => {}
+            SourceKind::File => {
+                if let Err(e) = input_files(&input_path) {
+                    problems.push(format!("{} {:#}", flag("input-path"), e));
+                }
+            }
+            _ => ensure_exists(&mut problems, "input-path", Path::new(&input_path)),
+        }
+        let mbp_format = match args.mbp_format.as_str() {
+            "csv" => MbpFormat::Csv,
+            "dbn" => MbpFormat::Dbn,
+            _ => MbpFormat::Json,
+        };
+        let mbp_codec = codec(&mut problems, &flag("mbp-codec"), &args.mbp_codec);
+        // DBN tools read plain or zstd-compressed files only
+        problems.ensure(
+            mbp_format != MbpFormat::Dbn
+                || matches!(mbp_codec.kind, CodecKind::None | CodecKind::Zstd),
+            || {
+                format!(
+                    "{} dbn supports {} none or zstd",
+                    flag("mbp-format"),
+                    flag("mbp-codec")
+                )
+            },
+        );
+        let mbp_path = args
+            .mbp_output_path
+            .clone()
+            .unwrap_or_else(|| mbp_output_path(mbp_format, mbp_codec));
+        problems.ensure(!mbp_path.is_empty(), || {
+            format!("{} must not be empty", flag("mbp-output-path"))
+        });
+        // The MBP writer truncates its output on startup, which would erase the input
+        if !matches!(source, SourceKind::Tcp | SourceKind::Live)
+            && Path::new(&input_path).exists()
+            && fs::canonicalize(&input_path).ok() == fs::canonicalize(&mbp_path).ok()
+        {
+            problems.push(format!(
+                "{} {} is {}, which is overwritten on startup; copy it first",
+                flag("input-path"),
+                input_path,
+                mbp_path
+            ));
+        }
+        let mbp_rotate_mb = problems.range("mbp-rotate-mb", args.mbp_rotate_mb, 0, 1 << 20);
+        let mbp_rotate_secs =
+            problems.range("mbp-rotate-secs", args.mbp_rotate_secs, 0, 30 * 86_400);
+        let tick_size = problems.at_least("tick-size", args.tick_size, 1);
+        let mbp_output = MbpOutputConfig {
+            path: mbp_path,
+            format: mbp_format,
+            codec: mbp_codec,
+            rotate_bytes: (mbp_rotate_mb > 0).then_some(mbp_rotate_mb << 20),
+            rotate_interval: (mbp_rotate_secs > 0).then(|| Duration::from_secs(mbp_rotate_secs)),
+            prices: match args.mbp_prices.as_str() {
+                "raw" => PriceFormat::Raw,
+                _ => PriceFormat::for_tick(tick_size),
+            },
+        };
+        let l3_output = args.l3_output_path.clone();
+        if let Some(path) = &l3_output {
+            problems.ensure(!path.is_empty(), || {
+                format!("{} must not be empty", flag("l3-output-path"))
+            });
+            problems.ensure(*path != mbp_output.path, || {
+                format!(
+                    "{} and {} must differ",
+                    flag("l3-output-path"),
+                    flag("mbp-output-path")
+                )
+            });
+            problems.ensure(
+                !matches!(source, SourceKind::MbpJson | SourceKind::Mbp10Dbn),
+                || {
+                    format!(
+                        "{} needs an MBO source; {} sources carry no orders",
+                        flag("l3-output-path"),
+                        source_name
+                    )
+                },
+            );
+        }
+        if source != SourceKind::Tcp {
+            problems.ensure(args.tcp_instruments.is_empty(), || {
+                format!(
+                    "{} only applies to --ingest-source tcp",
+                    flag("tcp-instruments")
+                )
+            });
+            problems.ensure(args.tcp_start_sequence == 0, || {
+                format!(
+                    "{} only applies to --ingest-source tcp",
+                    flag("tcp-start-sequence")
+                )
+            });
+        }
+
+        let live = (source == SourceKind::Live).then(|| LiveConfig {
+            api_key: args.databento_api_key.clone().unwrap_or_default(),
+            dataset: args.live_dataset.clone(),
+            symbols: if args.live_symbols.is_empty() {
+                vec![args.symbol.clone()]
+            } else {
+                args.live_symbols.clone()
+            },
+            gateway: args.live_gateway.clone(),
+            schemas: args
+                .live_schemas
+                .iter()
+                .filter(|schema| !schema.is_empty())
+                .cloned()
+                .collect(),
+        });
+        if let Some(live) = &live {
+            problems.ensure(live.api_key.len() > 5 && live.api_key.is_ascii(), || {
+                format!(
+                    "{} must be set to a Databento API key with --ingest-source live",
+                    flag("databento-api-key")
+                )
+            });
+            problems.ensure(!live.dataset.is_empty(), || {
+                format!("{} must not be empty", flag("live-dataset"))
+            });
+            for schema in &live.schemas {
+                problems.ensure(VENUE_SCHEMAS.contains(&schema.as_str()), || {
+                    format!(
+                        "{} has unknown schema {}; use {}",
+                        flag("live-schemas"),
+                        schema,
+                        VENUE_SCHEMAS.join(" or ")
+                    )
+                });
+            }
+        } else {
+            problems.ensure(
+                args.live_symbols.is_empty() && args.live_gateway.is_none(),
+                || {
+                    format!(
+                        "{} and {} only apply to --ingest-source live",
+                        flag("live-symbols"),
+                        flag("live-gateway")
+                    )
+                },
+            );
+        }
+
+        let mut symbols = SymbolMap::new(args.symbol.clone());
+        // SYMBOLS=432669=CLX5,432670=CLZ5 overrides the mappings found in DBN metadata
+        for entry in args
+            .symbols
+            .iter()
+            .flat_map(|raw| raw.split(','))
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            match entry.split_once('=') {
+                Some((id, sym)) => match id.trim().parse::<u32>() {
+                    Ok(id) => symbols.insert(id, sym.trim()),
+                    Err(_) => problems.push(format!(
+                        "{} entry {} has an invalid instrument_id",
+                        flag("symbols"),
+                        entry
+                    )),
+                },
+                None => problems.push(format!(
+                    "{} entry {} must be instrument_id=symbol",
+                    flag("symbols"),
+                    entry
+                )),
+            }
+        }
+
+        let queue_capacity = problems.range("queue-capacity", args.queue_capacity, 1, 100_000_000);
+        let queue_backend = match args.queue_backend.as_str() {
+            "ring" => QueueBackend::Ring,
+            _ => QueueBackend::Channel,
+        };
+        let mut queue = |name: &str, policy: &str| {
+            let policy = match policy {
+                "drop_newest" => BackpressurePolicy::DropNewest,
+                "drop_oldest" => BackpressurePolicy::DropOldest,
+                "coalesce" => BackpressurePolicy::Coalesce,
+                _ => BackpressurePolicy::Block,
+            };
+            problems.ensure(
+                !(policy == BackpressurePolicy::DropOldest && queue_backend == QueueBackend::Ring),
+                || {
+                    format!(
+                        "{}=drop_oldest needs {}=channel",
+                        flag(name),
+                        flag("queue-backend")
+                    )
+                },
+            );
+            QueueConfig {
+                backend: queue_backend,
+                capacity: queue_capacity,
+                policy,
+            }
+        };
+        let storage_queue = queue("storage-backpressure", &args.storage_backpressure);
+        let mbp_queue = queue("mbp-backpressure", &args.mbp_backpressure);
+        let batch_size = problems.range(
+            "snapshot-batch-size",
+            args.snapshot_batch_size,
+            1,
+            1_000_000,
+        );
+        let flush_ms = problems.range("snapshot-flush-ms", args.snapshot_flush_ms, 1, 3_600_000);
+        let flush_schedule = match args.snapshot_flush_mode.as_str() {
+            "data" => FlushSchedule::DataTime {
+                bucket_ns: problems.range(
+                    "snapshot-flush-bucket-ms",
+                    args.snapshot_flush_bucket_ms,
+                    1,
+                    86_400_000,
+                ) * 1_000_000,
+            },
+            _ => FlushSchedule::WallClock,
+        };
+        let sinks = problems
+            .take(SinkKind::parse_list(&args.snapshot_sinks).with_context(|| {
+                format!(
+                    "{} must list postgres, file:<path>, parquet:<dir> and/or redis:<url>",
+                    flag("snapshot-sinks")
+                )
+            }))
+            .unwrap_or_default();
+        let storage_writers = problems.range("storage-writers", args.storage_writers, 1, 64);
+        if storage_writers > 1 {
+            // Shards would interleave partial lines in a shared file
+            problems.ensure(
+                !sinks
+                    .iter()
+                    .any(|sink| matches!(sink, SinkKind::JsonFile { .. })),
+                || {
+                    format!(
+                        "{} above 1 cannot be combined with a file: sink in {}",
+                        flag("storage-writers"),
+                        flag("snapshot-sinks")
+                    )
+                },
+            );
+        }
+        problems.ensure(
+            args.spill_dir.is_none() || sinks.contains(&SinkKind::Postgres),
+            || {
+                format!(
+                    "{} only applies to the postgres sink in {}",
+                    flag("spill-dir"),
+                    flag("snapshot-sinks")
+                )
+            },
+        );
+        let journal_segment_rows = problems.range(
+            "journal-segment-rows",
+            args.journal_segment_rows,
+            1,
+            100_000_000,
+        );
+        if args.journal_dir.is_some() {
+            problems.ensure(sinks.contains(&SinkKind::Postgres), || {
+                format!(
+                    "{} replays into the postgres sink, which {} does not list",
+                    flag("journal-dir"),
+                    flag("snapshot-sinks")
+                )
+            });
+            // A dropped or coalesced snapshot would never be acknowledged
+            problems.ensure(storage_queue.policy == BackpressurePolicy::Block, || {
+                format!(
+                    "{} needs {} block",
+                    flag("journal-dir"),
+                    flag("storage-backpressure")
+                )
+            });
+        }
+        let postgres_pipeline = CopyPipeline {
+            connections: problems.range("postgres-connections", args.postgres_connections, 1, 16),
+            in_flight: problems.range("postgres-pipeline", args.postgres_pipeline, 1, 64),
+        };
+        let postgres_retry = RetryPolicy {
+            max_attempts: problems.range(
+                "postgres-retry-attempts",
+                args.postgres_retry_attempts,
+                1,
+                1_000,
+            ),
+            initial_backoff: Duration::from_millis(problems.range(
+                "postgres-retry-backoff-ms",
+                args.postgres_retry_backoff_ms,
+                0,
+                600_000,
+            )),
+            max_backoff: Duration::from_millis(problems.range(
+                "postgres-retry-max-backoff-ms",
+                args.postgres_retry_max_backoff_ms,
+                args.postgres_retry_backoff_ms,
+                600_000,
+            )),
+            deadline: Duration::from_millis(problems.range(
+                "postgres-retry-deadline-ms",
+                args.postgres_retry_deadline_ms,
+                1,
+                86_400_000,
+            )),
+        };
+        let parquet_row_group_size = problems.range(
+            "parquet-row-group-size",
+            args.parquet_row_group_size,
+            1,
+            10_000_000,
+        );
+        let snapshot_file_codec = codec(
+            &mut problems,
+            &flag("snapshot-file-codec"),
+            &args.snapshot_file_codec,
+        );
+        let snapshot_file_encoding = problems
+            .take(
+                SnapshotEncoding::parse(&args.snapshot_file_format)
+                    .with_context(|| flag("snapshot-file-format")),
+            )
+            .unwrap_or_default();
+        let redis_encoding = problems
+            .take(SnapshotEncoding::parse(&args.redis_format).with_context(|| flag("redis-format")))
+            .unwrap_or_default();
+        let parquet_codec = parquet_codec(
+            &mut problems,
+            &flag("parquet-codec"),
+            args.parquet_codec.as_deref(),
+        );
+        problems.ensure(
+            !args.snapshot_latest_table || sinks.contains(&SinkKind::Postgres),
+            || {
+                format!(
+                    "{} needs the postgres sink in {}",
+                    flag("snapshot-latest-table"),
+                    flag("snapshot-sinks")
+                )
+            },
+        );
+        let retention = retention(&args.retention, &mut problems);
+        let retention_interval = Duration::from_millis(problems.range(
+            "retention-interval-ms",
+            args.retention_interval_ms,
+            1_000,
+            86_400_000,
+        ));
+        problems.ensure(
+            retention.is_none() || sinks.contains(&SinkKind::Postgres),
+            || {
+                format!(
+                    "{} and {} need the postgres sink in {}",
+                    flag("retention-max-age-ms"),
+                    flag("retention-max-rows"),
+                    flag("snapshot-sinks")
+                )
+            },
+        );
+        let timescale = timescale(&args.timescale, &mut problems);
+        if timescale.is_some() && !sinks.is_empty() {
+            problems.ensure(sinks.contains(&SinkKind::Postgres), || {
+                format!(
+                    "{} needs the postgres sink in {}",
+                    flag("timescale"),
+                    flag("snapshot-sinks")
+                )
+            });
+        }
+        let depth = problems.range("snapshot-depth", args.snapshot_depth, 1, 1_000);
+        let db_url = database_url(&args.database, &mut problems);
+        let http = HttpConfig::from_args(&args.server, &mut problems);
+
+        let every_n = problems.at_least("mbp-every-n", args.mbp_every_n, 1);
+        let mbp_bucket_ms = problems.range("mbp-bucket-ms", args.mbp_bucket_ms, 0, 86_400_000);
+        if let (Some(from), Some(to)) = (args.from_ts, args.to_ts) {
+            problems.ensure(from <= to, || {
+                format!("--from-ts ({}) must not be after --to-ts ({})", from, to)
+            });
+        }
+        let sample_every = args
+            .sample
+            .as_deref()
+            .and_then(|raw| {
+                problems.take(parse_sample(raw).with_context(|| {
+                    format!(
+                        "{} must look like 1/N with N >= 1, e.g. 1/100",
+                        flag("sample")
+                    )
+                }))
+            })
+            .unwrap_or(1);
+        let cadence = snapshot_cadence(args, &mut problems);
+        if cadence != SnapshotCadence::EveryMessage {
+            problems.ensure(
+                !matches!(source, SourceKind::MbpJson | SourceKind::Mbp10Dbn),
+                || {
+                    format!(
+                        "snapshot cadence settings apply to MBO sources, not {}",
+                        flag("ingest-source")
+                    )
+                },
+            );
+        }
+        // Resting simulated orders fill from the MBO trades and cancels they sit among
+        problems.ensure(
+            !(args.sim_orders && matches!(source, SourceKind::MbpJson | SourceKind::Mbp10Dbn)),
+            || format!("{} needs an MBO source", flag("sim-orders")),
+        );
+        let bar_intervals = problems
+            .take(
+                BarInterval::parse_list(&args.bar_intervals)
+                    .with_context(|| format!("{} is invalid", flag("bar-intervals"))),
+            )
+            .unwrap_or_default();
+        if args.store_bars {
+            problems.ensure(!bar_intervals.is_empty(), || {
+                format!(
+                    "{} needs at least one {}",
+                    flag("store-bars"),
+                    flag("bar-intervals")
+                )
+            });
+            problems.ensure(sinks.contains(&SinkKind::Postgres), || {
+                format!(
+                    "{} needs the postgres sink in {}",
+                    flag("store-bars"),
+                    flag("snapshot-sinks")
+                )
+            });
+        }
+        let metrics_interval_secs = problems.range(
+            "metrics-interval-secs",
+            args.metrics_interval_secs,
+            0,
+            86_400,
+        );
+        let stats_window_secs =
+            problems.range("stats-window-secs", args.stats_window_secs, 1, 86_400);
+        let stats_persist_secs =
+            problems.range("stats-persist-secs", args.stats_persist_secs, 0, 86_400);
+        if stats_persist_secs > 0 {
+            problems.ensure(sinks.contains(&SinkKind::Postgres), || {
+                format!(
+                    "{} needs the postgres sink in {}",
+                    flag("stats-persist-secs"),
+                    flag("snapshot-sinks")
+                )
+            });
+        }
+        if let Some(max_records) = args.max_records {
+            problems.at_least("max-records", max_records, 1);
+        }
+        let progress = ProgressMode::parse(&args.progress).unwrap_or_else(|| {
+            problems.push(format!(
+                "{} must be auto or off, got {}",
+                flag("progress"),
+                args.progress
+            ));
+            ProgressMode::Off
+        });
+        let progress_interval_secs = problems.range(
+            "progress-interval-secs",
+            args.progress_interval_secs,
+            0,
+            86_400,
+        );
+
+        // Positions are record counts into a file, which live feeds do not have
+        if (args.checkpoint_path.is_some() || args.resume_from.is_some())
+            && source != SourceKind::File
+        {
+            problems.push(
+                "--checkpoint and --resume-from are only supported with --ingest-source file",
+            );
+        }
+        if let Some(path) = &args.resume_from {
+            ensure_exists(&mut problems, "resume-from", path);
+        }
+        if let Some(path) = &args.checkpoint_path {
+            let same_file = fs::canonicalize(path)
+                .ok()
+                .zip(fs::canonicalize(&input_path).ok())
+                .is_some_and(|(checkpoint, input)| checkpoint == input);
+            problems.ensure(!same_file, || {
+                format!(
+                    "{} {} is the input file and would be overwritten",
+                    flag("checkpoint-path"),
+                    path.display()
+                )
+            });
+        }
+        let checkpoint_interval_secs = problems.range(
+            "checkpoint-interval-secs",
+            args.checkpoint_interval_secs,
+            0,
+            86_400,
+        );
+        let checkpoint_codec = codec(
+            &mut problems,
+            &flag("checkpoint-codec"),
+            &args.checkpoint_codec,
+        );
+
+        let sequence_check = match args.sequence_check.as_str() {
+            "off" => SequenceCheck::Off,
+            "contiguous" => SequenceCheck::Contiguous,
+            _ => SequenceCheck::Monotonic,
+        };
+        problems.ensure(
+            !(args.stale_on_gap && sequence_check == SequenceCheck::Off),
+            || {
+                format!(
+                    "{} has no effect with --sequence-check off; enable monotonic or contiguous",
+                    flag("stale-on-gap")
+                )
+            },
+        );
+        let ingest_shards = problems.range("ingest-shards", args.ingest_shards, 1, 64);
+        if ingest_shards > 1 {
+            problems.ensure(
+                !matches!(source, SourceKind::MbpJson | SourceKind::Mbp10Dbn),
+                || {
+                    format!(
+                        "{} above 1 needs an MBO source; {} replays snapshots without books",
+                        flag("ingest-shards"),
+                        flag("ingest-source")
+                    )
+                },
+            );
+            // A checkpoint is one market, and a gap stales books on every shard
+            problems.ensure(
+                args.checkpoint_path.is_none() && args.resume_from.is_none(),
+                || {
+                    format!(
+                        "{} above 1 cannot be combined with {} or {}",
+                        flag("ingest-shards"),
+                        flag("checkpoint-path"),
+                        flag("resume-from")
+                    )
+                },
+            );
+            problems.ensure(!args.stale_on_gap, || {
+                format!(
+                    "{} above 1 cannot be combined with {}",
+                    flag("ingest-shards"),
+                    flag("stale-on-gap")
+                )
+            });
+        }
+        let fills = fill_handling(&args.fill_reduces_size, &mut problems);
+        let book_policies = book_policies(&args.book_policies, &mut problems);
+        let book_idle_timeout = args
+            .book_idle_timeout_ms
+            .map(|ms| Duration::from_millis(problems.at_least("book-idle-timeout-ms", ms, 1)));
+        let depth_cap = DepthCap {
+            max_levels_per_side: args
+                .book_max_levels
+                .map(|levels| problems.at_least("book-max-levels", levels, 1)),
+            max_orders: args
+                .book_max_orders
+                .map(|orders| problems.at_least("book-max-orders", orders, 1)),
+        };
+        let validate = args.validate_against.as_ref().map(|path| {
+            ensure_exists(&mut problems, "validate-against", path);
+            problems.ensure(
+                !matches!(source, SourceKind::MbpJson | SourceKind::Mbp10Dbn),
+                || {
+                    format!(
+                        "{} needs an MBO source to build books",
+                        flag("validate-against")
+                    )
+                },
+            );
+            // The reference is one time-ordered stream, read alongside the records
+            problems.ensure(ingest_shards == 1, || {
+                format!(
+                    "{} cannot be combined with {} above 1",
+                    flag("validate-against"),
+                    flag("ingest-shards")
+                )
+            });
+            ValidateConfig {
+                path: path.display().to_string(),
+                max_reports: args.validate_max_reports,
+            }
+        });
+        let ofi_window_ms = problems.range("ofi-window-ms", args.ofi_window_ms, 1, 3_600_000);
+        let ofi_window_ns = args.flow_signals.then_some(ofi_window_ms * 1_000_000);
+        let iceberg_min_refills =
+            problems.range("iceberg-min-refills", args.iceberg_min_refills, 1, 1_000);
+        problems.ensure(
+            !args.iceberg_detection
+                || !matches!(source, SourceKind::MbpJson | SourceKind::Mbp10Dbn),
+            || {
+                format!(
+                    "{} needs an MBO source; {} sources carry no fills",
+                    flag("iceberg-detection"),
+                    source_name
+                )
+            },
+        );
+        let iceberg_min_refills = args.iceberg_detection.then_some(iceberg_min_refills);
+        for &size in &args.liquidity_sizes {
+            problems.at_least("liquidity-sizes", size, 1);
+        }
+        for &bps in &args.liquidity_bps {
+            problems.range("liquidity-bps", bps, 1, 10_000);
+        }
+        let liquidity =
+            (!args.liquidity_sizes.is_empty() || !args.liquidity_bps.is_empty()).then(|| {
+                LiquiditySpec {
+                    sizes: args.liquidity_sizes.clone(),
+                    bps: args.liquidity_bps.clone(),
+                }
+            });
+        let snapshot_book = match args.snapshot_book.as_str() {
+            "consolidated" => SnapshotBook::Consolidated,
+            _ => SnapshotBook::First,
+        };
+        if let Some(path) = &args.instrument_defs_path {
+            ensure_exists(&mut problems, "instrument-defs-path", Path::new(path));
+        }
+        let depth_buckets = problems.range("depth-buckets", args.depth_buckets, 1, 1_000);
+        let buckets = args.depth_bucket_ticks.map(|bucket_ticks| BucketSpec {
+            tick_size,
+            bucket_ticks: problems.range("depth-bucket-ticks", bucket_ticks, 1, 1_000_000),
+            buckets: depth_buckets,
+        });
+        let alert_rules = problems
+            .take(
+                AlertRule::parse_list(&args.alert_rules)
+                    .with_context(|| format!("{} is invalid", flag("alert-rules"))),
+            )
+            .unwrap_or_default();
+        if let Some(url) = &args.alert_webhook_url {
+            problems.ensure(!alert_rules.is_empty(), || {
+                format!(
+                    "{} needs at least one {}",
+                    flag("alert-webhook-url"),
+                    flag("alert-rules")
+                )
+            });
+            problems.ensure(
+                url.starts_with("http://") || url.starts_with("https://"),
+                || format!("{} must be an http(s) URL", flag("alert-webhook-url")),
+            );
+        }
+        let alerts = (!alert_rules.is_empty()).then(|| AlertConfig {
+            rules: alert_rules,
+            tick_size,
+            webhook: args.alert_webhook_url.clone(),
+        });
+        let restart_policy = RestartPolicy {
+            max_restarts: args.sink_max_restarts,
+            backoff: Duration::from_millis(problems.range(
+                "sink-restart-backoff-ms",
+                args.sink_restart_backoff_ms,
+                0,
+                600_000,
+            )),
+        };
+        let contract = args.contract_multiplier.map(|multiplier| ContractMeta {
+            multiplier: problems.range("contract-multiplier", multiplier, f64::MIN_POSITIVE, 1e12),
+            currency: args.contract_currency.clone(),
+        });
+
+        problems.finish()?;
+        Ok(Self {
+            source,
+            tcp_source_addr: args.tcp_source_addr.clone(),
+            tcp_instruments: args.tcp_instruments.clone(),
+            tcp_start_sequence: args.tcp_start_sequence,
+            live,
+            input_path,
+            symbols,
+            storage_queue,
+            mbp_queue,
+            batch_size,
+            ingest_shards,
+            storage_writers,
+            postgres_pipeline,
+            postgres_retry,
+            spill_dir: args.spill_dir.clone(),
+            journal_dir: args.journal_dir.clone(),
+            journal_segment_rows,
+            retention,
+            retention_interval,
+            flush_interval: Duration::from_millis(flush_ms),
+            flush_schedule,
+            sinks,
+            latest_table: args.snapshot_latest_table,
+            redis: RedisOptions {
+                key_prefix: args.redis_key_prefix.clone(),
+                channel: args.redis_channel.clone(),
+                encoding: redis_encoding,
+            },
+            parquet_row_group_size,
+            snapshot_file_codec,
+            snapshot_file_encoding,
+            parquet_codec,
+            timescale,
+            depth,
+            db_url: Arc::new(db_url),
+            http,
+            contract,
+            mbp_sampling: MbpSampling {
+                every_n,
+                bucket_ns: (mbp_bucket_ms > 0).then_some(mbp_bucket_ms * 1_000_000),
+            },
+            mbp_output,
+            l3_output,
+            from_ts: args.from_ts,
+            to_ts: args.to_ts,
+            sample_every,
+            cadence,
+            timestamp_source: match args.timestamp_source.as_str() {
+                "recv" => TimestampSource::Recv,
+                _ => TimestampSource::Event,
+            },
+            metrics_interval: (metrics_interval_secs > 0)
+                .then(|| Duration::from_secs(metrics_interval_secs)),
+            latency_origin: match args.latency_origin.as_str() {
+                "recv" => LatencyOrigin::Recv,
+                "built" => LatencyOrigin::Built,
+                // Replayed and relayed records were received long before they are read
+                _ if source == SourceKind::Live => LatencyOrigin::Recv,
+                _ => LatencyOrigin::Built,
+            },
+            bar_intervals,
+            store_bars: args.store_bars,
+            stats_window_ns: stats_window_secs * 1_000_000_000,
+            stats_persist_ns: (stats_persist_secs > 0)
+                .then_some(stats_persist_secs * 1_000_000_000),
+            max_records: args.max_records,
+            progress,
+            progress_interval: (progress_interval_secs > 0)
+                .then(|| Duration::from_secs(progress_interval_secs)),
+            checkpoint_path: args.checkpoint_path.clone(),
+            checkpoint_interval: Duration::from_secs(checkpoint_interval_secs),
+            checkpoint_codec,
+            resume_from: args.resume_from.clone(),
+            sequence_check,
+            fills,
+            book_policies,
+            depth_cap,
+            book_idle_timeout,
+            validate,
+            stale_on_gap: args.stale_on_gap,
+            sim_orders: args.sim_orders,
+            ofi_window_ns,
+            iceberg_min_refills,
+            alerts,
+            liquidity,
+            snapshot_book,
+            buckets,
+            instrument_defs: args.instrument_defs_path.clone(),
+            warm_start: args.warm_start,
+            force: args.force,
+            restart_policy,
+        })
+    }
+
+    /// Sampled or capped runs, which are not recorded in `ingest_runs`.
+    pub fn is_preview(&self) -> bool {
+        self.sample_every > 1 || self.max_records.is_some()
+    }
+}
+
+/// Picks the snapshot cadence; the three modes are exclusive.
+fn snapshot_cadence(args: &IngestArgs, problems: &mut Problems) -> SnapshotCadence {
+    let every_n = problems.at_least("snapshot-every-n", args.snapshot_every_n, 1);
+    let interval_ms = problems.range(
+        "snapshot-interval-ms",
+        args.snapshot_interval_ms,
+        0,
+        86_400_000,
+    );
+    let mut modes = Vec::new();
+    if every_n > 1 {
+        modes.push((flag("snapshot-every-n"), SnapshotCadence::EveryN(every_n)));
+    }
+    if interval_ms > 0 {
+        modes.push((
+            flag("snapshot-interval-ms"),
+            SnapshotCadence::Interval {
+                interval_ns: interval_ms * 1_000_000,
+            },
+        ));
+    }
+    if args.snapshot_on_bbo_change {
+        modes.push((flag("snapshot-on-bbo-change"), SnapshotCadence::BboChange));
+    }
+    match modes.as_slice() {
+        [] => SnapshotCadence::EveryMessage,
+        [(_, cadence)] => *cadence,
+        _ => {
+            let names: Vec<&str> = modes.iter().map(|(name, _)| name.as_str()).collect();
+            problems.push(format!(
+                "only one snapshot cadence can be set, got {}",
+                names.join(", ")
+            ));
+            SnapshotCadence::EveryMessage
+        }
+    }
+}
+
+/// Publishers whose fills reduce resting orders, by id or name, or `all`.
+fn fill_handling(raw: &[String], problems: &mut Problems) -> FillHandling {
+    if raw.is_empty() {
+        return FillHandling::Ignore;
+    }
+    if raw.iter().any(|publisher| publisher.trim() == "all") {
+        return FillHandling::All;
+    }
+    let mut publishers = Vec::new();
+    for publisher in raw.iter().map(|publisher| publisher.trim()) {
+        match parse_publisher(publisher) {
+            Some(parsed) => publishers.push(parsed),
+            None => problems.push(format!(
+                "{} has unknown publisher {}; use an id, a name such as GLBX.MDP3.GLBX, or all",
+                flag("fill-reduces-size"),
+                publisher
+            )),
+        }
+    }
+    FillHandling::Publishers(publishers)
+}
+
+/// `publisher=policy` pairs, with `all` as the publisher setting the default.
+fn book_policies(raw: &[String], problems: &mut Problems) -> BookPolicies {
+    let mut policies = BookPolicies::default();
+    for entry in raw.iter().map(|entry| entry.trim()) {
+        let Some((publisher, policy)) = entry.split_once('=') else {
+            problems.push(format!(
+                "{} entry {} is not publisher=policy",
+                flag("book-policies"),
+                entry
+            ));
+            continue;
+        };
+        let Some(policy) = BookPolicy::parse(policy.trim()) else {
+            problems.push(format!(
+                "{} has unknown policy {}; use replace, implied or ignore",
+                flag("book-policies"),
+                policy
+            ));
+            continue;
+        };
+        let publisher = publisher.trim();
+        if publisher == "all" {
+            policies.default = policy;
+            continue;
+        }
+        match parse_publisher(publisher) {
+            Some(parsed) => policies.publishers.push((parsed, policy)),
+            None => problems.push(format!(
+                "{} has unknown publisher {}; use an id, a name such as GLBX.MDP3.GLBX, or all",
+                flag("book-policies"),
+                publisher
+            )),
+        }
+    }
+    policies
+}
+
+/// A publisher by id or name.
+fn parse_publisher(raw: &str) -> Option<Publisher> {
+    match raw.parse::<u16>() {
+        Ok(id) => Publisher::try_from(id).ok(),
+        Err(_) => raw.parse::<Publisher>().ok(),
+    }
+}
+
+/// Parses a `1/N` sampling ratio (a bare `N` is accepted too) into N.
+fn parse_sample(raw: &str) -> Result<u64> {
+    let n = match raw.trim().split_once('/') {
+        Some(("1", n)) => n,
+        Some(_) => bail!("sample ratio must have numerator 1, got {}", raw),
+        None => raw,
+    };
+    let n = n.trim().parse::<u64>()?;
+    anyhow::ensure!(n >= 1, "sample ratio denominator must be at least 1");
+    Ok(n)
+}
+
+/// Everything `batonics serve` needs.
+#[derive(Clone, Debug)]
+pub struct ServeConfig {
+    pub db_url: Arc<String>,
+    pub http: HttpConfig,
+}
+
+impl ServeConfig {
+    pub fn from_args(args: &ServeArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let db_url = database_url(&args.database, &mut problems);
+        let http = HttpConfig::from_args(&args.server, &mut problems);
+        problems.finish()?;
+        Ok(Self {
+            db_url: Arc::new(db_url),
+            http,
+        })
+    }
+}
+
+/// How every command logs.
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Env-filter directives, e.g. `info,batonics::storage=debug`.
+    pub filter: String,
+}
+
+impl LogConfig {
+    pub fn from_args(args: &LogArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        if let Err(e) = logging::parse_filter(&args.log_filter) {
+            problems.push(format!(
+                "--log-filter/RUST_LOG is not a valid filter: {}",
+                e
+            ));
+        }
+        problems.finish()?;
+        Ok(Self {
+            format: match args.log_format.as_str() {
+                "json" => LogFormat::Json,
+                _ => LogFormat::Text,
+            },
+            filter: args.log_filter.clone(),
+        })
+    }
+
+    /// From the environment alone, for errors raised before the flags are parsed.
+    /// Anything unusable falls back to the defaults.
+    pub fn from_env() -> Self {
+        let filter = env::var("RUST_LOG")
+            .ok()
+            .filter(|filter| logging::parse_filter(filter).is_ok());
+        Self {
+            format: match env::var("LOG_FORMAT").as_deref() {
+                Ok("json") => LogFormat::Json,
+                _ => LogFormat::Text,
+            },
+            filter: filter.unwrap_or_else(|| logging::DEFAULT_FILTER.to_owned()),
+        }
+    }
+}
+
+/// Everything `init_db` needs.
+#[derive(Clone, Debug)]
+pub struct InitDbConfig {
+    pub db_url: String,
+    pub timescale: Option<TimescaleConfig>,
+}
+
+impl InitDbConfig {
+    pub fn from_args(args: &InitDbArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let db_url = database_url(
+            &DatabaseArgs {
+                database_url: args.database_url.clone(),
+            },
+            &mut problems,
+        );
+        let timescale = timescale(&args.timescale, &mut problems);
+        problems.finish()?;
+        Ok(Self { db_url, timescale })
+    }
+}
+
+/// Everything `batonics prune` needs.
+#[derive(Clone, Debug)]
+pub struct PruneConfig {
+    pub db_url: String,
+    pub policy: RetentionPolicy,
+}
+
+impl PruneConfig {
+    pub fn from_args(args: &PruneArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let db_url = database_url(&args.database, &mut problems);
+        let policy = retention(&args.retention, &mut problems);
+        problems.ensure(policy.is_some(), || {
+            format!(
+                "{} or {} must be set",
+                flag("retention-max-age-ms"),
+                flag("retention-max-rows")
+            )
+        });
+        problems.finish()?;
+        Ok(Self {
+            db_url,
+            policy: policy.expect("checked above"),
+        })
+    }
+}
+
+/// Everything `batonics replay-spill` needs.
+#[derive(Clone, Debug)]
+pub struct ReplaySpillConfig {
+    pub db_url: String,
+    pub dir: PathBuf,
+}
+
+impl ReplaySpillConfig {
+    pub fn from_args(args: &ReplaySpillArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let db_url = database_url(&args.database, &mut problems);
+        problems.ensure(args.spill_dir.is_dir(), || {
+            format!(
+                "{} {} is not a directory",
+                flag("spill-dir"),
+                args.spill_dir.display()
+            )
+        });
+        problems.finish()?;
+        Ok(Self {
+            db_url,
+            dir: args.spill_dir.clone(),
+        })
+    }
+}
+
+const EXPORT_CODEC_FLAG: &str = "--codec/EXPORT_CODEC";
+
+/// Everything `batonics export` needs.
+#[derive(Clone, Debug)]
+pub struct ExportConfig {
+    pub db_url: String,
+    pub request: ExportRequest,
+}
+
+impl ExportConfig {
+    pub fn from_args(args: &ExportArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let format = match args.format.as_str() {
+            "parquet" => ExportFormat::Parquet,
+            _ => ExportFormat::Csv,
+        };
+        let codec = match format {
+            ExportFormat::Csv => args
+                .codec
+                .as_deref()
+                .map(|raw| codec(&mut problems, EXPORT_CODEC_FLAG, raw)),
+            ExportFormat::Parquet => {
+                parquet_codec(&mut problems, EXPORT_CODEC_FLAG, args.codec.as_deref())
+            }
+        };
+        let output = args.output.clone().unwrap_or_else(|| {
+            let extension = match format {
+                ExportFormat::Csv => "csv",
+                ExportFormat::Parquet => "parquet",
+            };
+            // Parquet compresses its columns, so the file keeps its extension
+            let suffix = match (format, codec) {
+                (ExportFormat::Csv, Some(codec)) => codec.extension(),
+                _ => "",
+            };
+            PathBuf::from(format!(
+                "{}_snapshots.{}{}",
+                args.symbol.as_deref().unwrap_or("all"),
+                extension,
+                suffix
+            ))
+        });
+        if let (Some(from), Some(to)) = (args.from, args.to) {
+            problems.ensure(from <= to, || {
+                format!("--from ({}) must not be after --to ({})", from, to)
+            });
+        }
+        let db_url = database_url(&args.database, &mut problems);
+        problems.finish()?;
+        Ok(Self {
+            db_url,
+            request: ExportRequest {
+                symbol: args.symbol.clone(),
+                from_ts: args.from,
+                to_ts: args.to,
+                format,
+                codec,
+                output,
+            },
+        })
+    }
+}
+
+/// Everything `batonics compare-runs` needs.
+#[derive(Clone, Debug)]
+pub struct CompareConfig {
+    /// Only used when either side is a run id.
+    pub db_url: String,
+    pub a: RunRef,
+    pub b: RunRef,
+    pub format: ReportFormat,
+    pub output: PathBuf,
+}
+
+impl CompareConfig {
+    pub fn from_args(args: &CompareRunsArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let format = match args.format.as_str() {
+            "html" => ReportFormat::Html,
+            _ => ReportFormat::Json,
+        };
+        let (a, b) = (RunRef::parse(&args.a), RunRef::parse(&args.b));
+        for run in [&a, &b] {
+            if let RunRef::Dir(path) = run {
+                problems.ensure(path.exists(), || {
+                    format!(
+                        "{} is neither a run id nor an output directory or MBP JSON file",
+                        path.display()
+                    )
+                });
+            }
+        }
+        let db_url = if [&a, &b].iter().any(|run| matches!(run, RunRef::Run(_))) {
+            database_url(&args.database, &mut problems)
+        } else {
+            args.database.database_url.clone()
+        };
+        problems.finish()?;
+        Ok(Self {
+            db_url,
+            a,
+            b,
+            format,
+            output: args
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(format!("compare_report.{}", format.extension()))),
+        })
+    }
+}
+
+/// Settings for the TCP replay server, built from `--tcp-*` flags or their env vars.
+#[derive(Clone, Debug)]
+pub struct StreamConfig {
+    pub bind_addr: String,
+    pub input_path: String,
+    pub encoded_path: String,
+    pub loop_replay: bool,
+    pub batch_size: usize,
+    pub preencode: bool,
+    pub pace: Pace,
+    pub handshake_timeout: Duration,
+}
+
+/// Messages per protobuf batch unless `--tcp-batch-size` says otherwise.
+pub const DEFAULT_TCP_BATCH_SIZE: usize = 1000;
+
+impl StreamConfig {
+    pub fn from_args(args: &StreamArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let batch_size = args
+            .tcp_batch_size
+            .map(|size| problems.range("tcp-batch-size", size, 1, 100_000))
+            .unwrap_or(DEFAULT_TCP_BATCH_SIZE);
+        let pace = problems
+            .take(Pace::parse(&args.tcp_pace))
+            .unwrap_or(Pace::Unlimited);
+        if args.preencode {
+            ensure_exists(&mut problems, "input-path", Path::new(&args.input_path));
+        } else {
+            problems.ensure(Path::new(&args.encoded_path).exists(), || {
+                format!(
+                    "{} {} does not exist; drop --preencode false to build it",
+                    flag("encoded-path"),
+                    args.encoded_path
+                )
+            });
+            // Both act on the batches, which were fixed when the file was encoded
+            problems.ensure(args.tcp_batch_size.is_none(), || {
+                format!(
+                    "{} has no effect with --preencode false; {} keeps the batch size it was encoded with",
+                    flag("tcp-batch-size"),
+                    args.encoded_path
+                )
+            });
+            problems.ensure(pace == Pace::Unlimited, || {
+                format!(
+                    "{} paces whole batches, so it needs --preencode to choose their size with {}",
+                    flag("tcp-pace"),
+                    flag("tcp-batch-size")
+                )
+            });
+        }
+        let handshake_timeout_ms = problems.range(
+            "tcp-handshake-timeout-ms",
+            args.tcp_handshake_timeout_ms,
+            0,
+            60_000,
+        );
+        problems.finish()?;
+        Ok(Self {
+            bind_addr: args.tcp_bind_addr.clone(),
+            input_path: args.input_path.clone(),
+            encoded_path: args.encoded_path.clone(),
+            loop_replay: args.tcp_loop_replay,
+            batch_size,
+            preencode: args.preencode,
+            pace,
+            handshake_timeout: Duration::from_millis(handshake_timeout_ms),
+        })
+    }
+}
+
+/// Settings for the `stream_tcp` throughput benchmark.
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    pub server_addr: String,
+    pub duration_secs: u64,
+    /// Instruments to subscribe to (empty = everything).
+    pub instrument_ids: Vec<u32>,
+}
+
+impl BenchConfig {
+    pub fn from_args(args: &BenchArgs) -> Result<Self> {
+        let mut problems = Problems::new();
+        let duration_secs = problems.range("bench-duration", args.bench_duration, 1, 86_400);
+        problems.finish()?;
+        Ok(Self {
+            server_addr: args.bench_server.clone(),
+            duration_secs,
+            instrument_ids: args.bench_instruments.clone(),
+        })
+    }
+}
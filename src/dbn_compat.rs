@@ -0,0 +1,194 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use dbn::{
+    DBN_VERSION, HasRType, Metadata, Record, VersionUpgradePolicy,
+    decode::{
+        DecodeRecordRef,
+        dbn::{MetadataDecoder, RecordDecoder},
+    },
+    record::{ImbalanceMsg, InstrumentDefMsg, StatMsg},
+};
+use tracing::info;
+
+use crate::{
+    instruments::{Instrument, InstrumentRegistry},
+    venue_stats::VenueMsg,
+};
+
+/// Reads the records of one type from a DBN file of any version.
+///
+/// Files up to `DBN_VERSION` are decoded normally, upgrading older layouts. A file from
+/// a newer DBN version is not rejected: its metadata is skipped, since its layout may
+/// have changed, and records are decoded with the current layout, which newer versions
+/// have so far only extended. Records of other types, including record types this
+/// build does not know, and records too short for the current layout are skipped and
+/// counted rather than failing the stream. Instrument definitions go to the registry
+/// given with `with_definitions` instead, when there is one, and imbalance and
+/// statistics records are kept for `take_venue_records` after `with_venue_records`.
+pub struct DbnReader {
+    path: String,
+    decoder: RecordDecoder<File>,
+    metadata: Option<Metadata>,
+    version: u8,
+    /// Skipped records by rtype.
+    skipped: BTreeMap<u8, u64>,
+    definitions: Option<Arc<InstrumentRegistry>>,
+    /// Imbalance and statistics records read since the last `take_venue_records`,
+    /// when they are kept.
+    venue: Option<Vec<VenueMsg>>,
+    finished: bool,
+}
+
+impl DbnReader {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("failed to open DBN file {}", path))?;
+        let mut prelude = [0u8; 8];
+        file.read_exact(&mut prelude)
+            .with_context(|| format!("{} is too short for a DBN file", path))?;
+        if &prelude[..3] != b"DBN" {
+            bail!(
+                "{} is not a DBN file (compressed DBN is not supported)",
+                path
+            );
+        }
+        let version = prelude[3];
+        let metadata_len = u32::from_le_bytes([prelude[4], prelude[5], prelude[6], prelude[7]]);
+        let (decoder, metadata) = if version <= DBN_VERSION {
+            file.rewind()?;
+            let mut metadata_decoder =
+                MetadataDecoder::with_upgrade_policy(file, VersionUpgradePolicy::UpgradeToV3);
+            let metadata = metadata_decoder
+                .decode()
+                .with_context(|| format!("failed to read DBN metadata of {}", path))?;
+            (RecordDecoder::from(metadata_decoder), Some(metadata))
+        } else {
+            info!(
+                path,
+                version,
+                supported = DBN_VERSION,
+                "newer DBN version, metadata skipped (set SYMBOLS for symbol names)"
+            );
+            file.seek(SeekFrom::Start(8 + metadata_len as u64))?;
+            let decoder =
+                RecordDecoder::with_version(file, DBN_VERSION, VersionUpgradePolicy::AsIs, false)?;
+            (decoder, None)
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            decoder,
+            metadata,
+            version,
+            skipped: BTreeMap::new(),
+            definitions: None,
+            venue: None,
+            finished: false,
+        })
+    }
+
+    /// Collects the definitions interleaved with the records into `registry`.
+    pub fn with_definitions(mut self, registry: Arc<InstrumentRegistry>) -> Self {
+        self.definitions = Some(registry);
+        self
+    }
+
+    /// Keeps the imbalance and statistics records interleaved with the records.
+    pub fn with_venue_records(mut self) -> Self {
+        self.venue = Some(Vec::new());
+        self
+    }
+
+    /// Imbalance and statistics records read since the last call, in file order.
+    pub fn take_venue_records(&mut self) -> Vec<VenueMsg> {
+        self.venue.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// DBN version the file was written with.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// `None` for files from a newer DBN version.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn get_ref(&self) -> &File {
+        self.decoder.get_ref()
+    }
+
+    /// Records skipped so far because they were not a `T` this build can decode.
+    pub fn skipped(&self) -> u64 {
+        self.skipped.values().sum()
+    }
+
+    /// Next record of type `T`, skipping any other record. A longer record of `T`'s
+    /// rtype, as a newer version would write, is read through its known prefix.
+    pub fn next_record<T: HasRType + Clone>(&mut self) -> Result<Option<T>> {
+        loop {
+            let Some(record) = self.decoder.decode_record_ref()? else {
+                self.finish();
+                return Ok(None);
+            };
+            if let Ok(record) = record.try_get::<T>() {
+                return Ok(Some(record.clone()));
+            }
+            if let Some(registry) = &self.definitions
+                && let Some(def) = record.get::<InstrumentDefMsg>()
+            {
+                registry.insert(Instrument::from_def(def));
+                continue;
+            }
+            if let Some(venue) = &mut self.venue {
+                if let Some(imbalance) = record.get::<ImbalanceMsg>() {
+                    venue.push(VenueMsg::Imbalance(imbalance.clone()));
+                    continue;
+                }
+                if let Some(stat) = record.get::<StatMsg>() {
+                    venue.push(VenueMsg::Stat(stat.clone()));
+                    continue;
+                }
+            }
+            let rtype = record.header().rtype;
+            let count = self.skipped.entry(rtype).or_default();
+            *count += 1;
+            if *count == 1 {
+                info!(
+                    path = %self.path,
+                    rtype = format!("0x{:02x}", rtype),
+                    version = self.version,
+                    expected = std::any::type_name::<T>()
+                        .rsplit("::")
+                        .next()
+                        .unwrap_or_default(),
+                    "skipping records of another type"
+                );
+            }
+        }
+    }
+
+    /// Logs the skipped records once the file is exhausted.
+    fn finish(&mut self) {
+        if self.finished || self.skipped.is_empty() {
+            return;
+        }
+        self.finished = true;
+        let by_rtype: Vec<String> = self
+            .skipped
+            .iter()
+            .map(|(rtype, count)| format!("0x{:02x}:{}", rtype, count))
+            .collect();
+        info!(
+            path = %self.path,
+            skipped = self.skipped(),
+            by_rtype = by_rtype.join(","),
+            "skipped records"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Entry points for the `cargo fuzz` targets in `fuzz/`. Each takes arbitrary bytes
+//! and must never panic; malformed input is expected to be rejected or skipped.
+
+use std::io::Cursor;
+
+use dbn::{
+    FlagSet, UNDEF_PRICE,
+    record::{MboMsg, RecordHeader},
+    rtype,
+};
+
+use crate::{
+    ingest::{proto_to_mbo, read_batch},
+    order_book::Market,
+    snapshot::{SymbolMap, build_full_snapshot_record},
+};
+
+/// Bytes per record decoded by `apply_records`.
+const RECORD_BYTES: usize = 8;
+/// Includes an invalid action so rejection paths are exercised.
+const ACTIONS: [u8; 8] = [b'A', b'M', b'C', b'R', b'T', b'F', b'N', b'X'];
+const SIDES: [u8; 4] = [b'B', b'A', b'N', 0];
+/// GLBX.MDP3, another valid publisher, and ids with no known publisher.
+const PUBLISHERS: [u16; 4] = [1, 2, 0, u16::MAX];
+
+/// Decodes `data` as a sequence of compact 8-byte records and applies them to a
+/// market, building a snapshot after each. Order ids, prices and instruments are
+/// drawn from small ranges so records collide on the same orders and levels.
+pub fn apply_records(data: &[u8]) {
+    let mut market = Market::new();
+    let symbols = SymbolMap::new("FUZZ");
+    for (sequence, chunk) in data.chunks_exact(RECORD_BYTES).enumerate() {
+        let mbo = compact_mbo(chunk, sequence as u32);
+        let instrument_id = mbo.hd.instrument_id;
+        let ts_event = mbo.hd.ts_event as i64;
+        if market.apply(mbo).is_ok() {
+            build_full_snapshot_record(&market, instrument_id, &symbols, ts_event);
+        }
+    }
+}
+
+/// Feeds `data` through the TCP frame parser as if it came off the socket, applying
+/// every decoded message to a market.
+pub fn decode_frames(data: &[u8]) {
+    let mut reader = Cursor::new(data);
+    let mut frame = Vec::new();
+    let mut market = Market::new();
+    while let Ok(Some(batch)) = read_batch(&mut reader, &mut frame) {
+        for msg in &batch.msgs {
+            if let Ok(mbo) = proto_to_mbo(msg) {
+                // Rejected records are expected from arbitrary input
+                let _ = market.apply(mbo);
+            }
+        }
+    }
+}
+
+fn compact_mbo(chunk: &[u8], sequence: u32) -> MboMsg {
+    let price = match chunk[3] {
+        u8::MAX => UNDEF_PRICE,
+        ticks => (ticks as i64 - 128) * 250_000_000,
+    };
+    let size = match chunk[4] {
+        u8::MAX => u32::MAX,
+        size => size as u32,
+    };
+    let ts_event = sequence as u64 * 1_000;
+    MboMsg {
+        hd: RecordHeader::new::<MboMsg>(
+            rtype::MBO,
+            PUBLISHERS[(chunk[6] & 0b11) as usize],
+            (chunk[6] >> 2 & 0b11) as u32,
+            ts_event,
+        ),
+        order_id: (chunk[2] % 32) as u64,
+        price,
+        size,
+        flags: FlagSet::new(chunk[5]),
+        channel_id: chunk[7] & 0b1,
+        action: ACTIONS[(chunk[0] % 8) as usize] as _,
+        side: SIDES[(chunk[1] % 4) as usize] as _,
+        ts_recv: ts_event,
+        ts_in_delta: 0,
+        // Mostly increasing, with occasional repeats and jumps backwards
+        sequence: sequence.wrapping_sub((chunk[7] >> 1) as u32),
+    }
+}
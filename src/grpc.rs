@@ -0,0 +1,216 @@
+//! The gRPC snapshot service defined in `src/proto/snapshots.proto`, served with tonic
+//! beside the HTTP API from the same registry and update broadcast.
+
+use std::{collections::HashSet, net::SocketAddr, sync::Arc, thread};
+
+use anyhow::{Context, Result};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tonic::{Request, Response, Status};
+use tracing::{error, info, info_span, warn};
+
+use crate::{
+    proto::v1::{
+        self,
+        snapshot_service_server::{SnapshotService, SnapshotServiceServer},
+    },
+    shutdown::Shutdown,
+    snapshot::{DepthChart, LevelEntry, SharedSnapshot, SnapshotRecord, SnapshotRegistry},
+    storage::load_snapshot_at,
+};
+
+/// What the service reads from: one pipeline's snapshots, as a `Namespace` of the HTTP
+/// API holds them.
+#[derive(Clone)]
+pub struct SnapshotGrpc {
+    pub registry: Arc<SnapshotRegistry>,
+    /// Every new snapshot from ingest, for `StreamSnapshots`.
+    pub updates: broadcast::Sender<SharedSnapshot>,
+    /// Postgres holding persisted snapshots, for `GetDepth` at a past time.
+    pub db_url: Option<Arc<String>>,
+    /// Ends the server and its open streams.
+    pub shutdown: Shutdown,
+}
+
+/// Serves `service` on `addr` until its `Shutdown` fires.
+pub fn spawn_grpc_server(
+    service: SnapshotGrpc,
+    addr: SocketAddr,
+) -> thread::JoinHandle<Result<()>> {
+    thread::spawn(move || blocking_server(service, addr))
+}
+
+fn blocking_server(service: SnapshotGrpc, addr: SocketAddr) -> Result<()> {
+    let _span = info_span!("grpc", %addr).entered();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed to build tokio runtime for grpc server")?;
+    runtime.block_on(async move {
+        let shutdown = service.shutdown.clone();
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind grpc server to {}", addr))?;
+        info!("grpc_ready");
+        tonic::transport::Server::builder()
+            .add_service(SnapshotServiceServer::new(service))
+            .serve_with_incoming_shutdown(
+                tonic::transport::server::TcpIncoming::from(listener),
+                async move { shutdown.wait().await },
+            )
+            .await
+            .context("grpc server terminated unexpectedly")
+    })
+}
+
+#[tonic::async_trait]
+impl SnapshotService for SnapshotGrpc {
+    async fn get_snapshot(
+        &self,
+        request: Request<v1::GetSnapshotRequest>,
+    ) -> Result<Response<v1::Snapshot>, Status> {
+        let request = request.into_inner();
+        let snapshot = if request.symbol.is_empty() {
+            self.registry.latest()
+        } else {
+            self.registry.get_by_symbol(&request.symbol)
+        };
+        let snapshot = snapshot.ok_or_else(|| Status::not_found("no snapshot yet"))?;
+        Ok(Response::new(snapshot_message(
+            &snapshot,
+            request.depth as usize,
+        )))
+    }
+
+    type StreamSnapshotsStream = BoxStream<'static, Result<v1::Snapshot, Status>>;
+
+    /// Snapshots as ingest publishes them. A client that falls more than the broadcast
+    /// buffer behind skips ahead, which shows as a jump in each symbol's `seq`.
+    async fn stream_snapshots(
+        &self,
+        request: Request<v1::StreamSnapshotsRequest>,
+    ) -> Result<Response<Self::StreamSnapshotsStream>, Status> {
+        let request = request.into_inner();
+        let symbols: HashSet<String> = request.symbols.into_iter().collect();
+        let depth = request.depth as usize;
+        let updates = self.updates.subscribe();
+        let shutdown = self.shutdown.clone();
+        let snapshots = stream::unfold(updates, move |mut updates| {
+            let symbols = symbols.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                loop {
+                    let update = tokio::select! {
+                        _ = shutdown.wait() => return None,
+                        update = updates.recv() => update,
+                    };
+                    match update {
+                        Ok(snapshot)
+                            if symbols.is_empty() || symbols.contains(&snapshot.payload.symbol) =>
+                        {
+                            return Some((Ok(snapshot_message(&snapshot, depth)), updates));
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(skipped, "grpc stream lagged, skipping ahead");
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+        Ok(Response::new(snapshots.boxed()))
+    }
+
+    /// Like `/depthchart`: the live book answers any time at or after its last update,
+    /// earlier times need the postgres sink.
+    async fn get_depth(
+        &self,
+        request: Request<v1::GetDepthRequest>,
+    ) -> Result<Response<v1::Depth>, Status> {
+        let request = request.into_inner();
+        let live = self.registry.get_by_symbol(&request.symbol);
+        let live = live.filter(|live| request.ts.is_none_or(|ts| ts >= live.ts_event));
+        if let Some(live) = live {
+            let chart = DepthChart::from_snapshot(&live.payload);
+            return Ok(Response::new(depth_message(&chart)));
+        }
+        let Some(ts) = request.ts else {
+            return Err(Status::not_found("no snapshot for symbol"));
+        };
+        let Some(db_url) = self.db_url.clone() else {
+            return Err(Status::unavailable(
+                "historical depth requires the postgres sink",
+            ));
+        };
+        let symbol = request.symbol;
+        let stored = tokio::task::spawn_blocking(move || load_snapshot_at(&db_url, &symbol, ts))
+            .await
+            .map_err(|_| Status::internal("depth query panicked"))?;
+        match stored {
+            Ok(Some(snapshot)) => {
+                let chart = DepthChart::from_snapshot(&snapshot.payload);
+                Ok(Response::new(depth_message(&chart)))
+            }
+            Ok(None) => Err(Status::not_found("no snapshot for symbol at ts")),
+            Err(e) => {
+                error!(error = format!("{:#}", e), "grpc depth query failed");
+                Err(Status::internal("depth query failed"))
+            }
+        }
+    }
+}
+
+/// `GetSnapshot` and `StreamSnapshots` reply; `depth` 0 keeps every level.
+pub fn snapshot_message(record: &SnapshotRecord, depth: usize) -> v1::Snapshot {
+    let snapshot = &record.payload;
+    let depth = if depth == 0 { usize::MAX } else { depth };
+    v1::Snapshot {
+        symbol: snapshot.symbol.clone(),
+        instrument_id: record.instrument_id,
+        ts_event: record.ts_event,
+        seq: snapshot.seq,
+        bids: snapshot
+            .bids
+            .iter()
+            .take(depth)
+            .map(level_message)
+            .collect(),
+        asks: snapshot
+            .asks
+            .iter()
+            .take(depth)
+            .map(level_message)
+            .collect(),
+        total_orders: snapshot.total_orders as u64,
+        bid_levels: snapshot.bid_levels as u32,
+        ask_levels: snapshot.ask_levels as u32,
+        stale: snapshot.stale,
+    }
+}
+
+/// `GetDepth` reply.
+pub fn depth_message(chart: &DepthChart) -> v1::Depth {
+    let points = |side: &[(f64, u64)]| {
+        side.iter()
+            .map(|&(price, cumulative_size)| v1::DepthPoint {
+                price,
+                cumulative_size,
+            })
+            .collect()
+    };
+    v1::Depth {
+        symbol: chart.symbol.clone(),
+        ts_event: chart.ts_ns,
+        bids: points(&chart.bids),
+        asks: points(&chart.asks),
+    }
+}
+
+fn level_message(level: &LevelEntry) -> v1::Level {
+    v1::Level {
+        price: level.price,
+        size: level.size,
+        count: level.count,
+    }
+}
@@ -0,0 +1,717 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{BufRead, BufReader, ErrorKind, Lines, Read, Seek, Write},
+    net::{self, TcpStream},
+    os::raw::c_char,
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use dbn::{
+    FlagSet, Metadata, UNDEF_PRICE,
+    record::{MboMsg, Mbp10Msg, RecordHeader},
+    rtype,
+};
+use prost::Message;
+use tracing::{info, warn};
+
+use crate::{
+    codec::{self, CodecKind},
+    dbn_compat::DbnReader,
+    instruments::InstrumentRegistry,
+    latency::wall_clock_ns,
+    proto,
+    shutdown::Shutdown,
+    snapshot::{
+        Bbo, EncodedCache, LevelEntry, MbpOutput, Snapshot, SnapshotRecord, SymbolMap,
+        mbp_output_to_snapshot,
+    },
+    venue_stats::VenueMsg,
+};
+
+/// Max accepted frame size, matching the limit used by `stream_tcp`.
+pub const MAX_FRAME_BYTES: usize = 512 * 1024;
+
+/// A source of MBO records feeding the book builder.
+///
+/// An `Err` only affects the current record; the caller may keep pulling.
+/// Sources that cannot continue (e.g. a closed socket) return `Ok(None)` afterwards.
+pub trait IngestSource {
+    fn next_record(&mut self) -> Result<Option<MboMsg>>;
+
+    /// Short label used in logs.
+    fn describe(&self) -> String;
+
+    /// Instrument id to symbol mappings known to the source, if any.
+    fn symbols(&self) -> Vec<(u32, String)> {
+        Vec::new()
+    }
+
+    /// `(bytes read, total bytes)` for sources backed by a file.
+    fn byte_progress(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// Records skipped because this build cannot decode them, e.g. record types or
+    /// versions newer than its DBN library.
+    fn unsupported_records(&self) -> u64 {
+        0
+    }
+
+    /// Imbalance and statistics records read since the last call, in feed order. They
+    /// come before the record `next_record` last returned.
+    fn take_venue_records(&mut self) -> Vec<VenueMsg> {
+        Vec::new()
+    }
+}
+
+/// Replays records from DBN files. Records other than MBO are skipped, apart from
+/// instrument definitions when `with_definitions` gives them a registry, and imbalance
+/// and statistics records after `with_venue_records`.
+///
+/// The path may name one file, a directory of `.dbn` files or a glob such as
+/// `data/CLX5_*.dbn`; several files are replayed one after another in order of their
+/// start timestamp, as a single stream.
+pub struct DbnFileSource {
+    path: String,
+    files: Vec<InputFile>,
+    /// Index into `files` of the file being read.
+    current: usize,
+    reader: DbnReader,
+    symbols: Vec<(u32, String)>,
+    total_bytes: u64,
+    /// Bytes and unsupported records of the files already replayed.
+    done_bytes: u64,
+    done_unsupported: u64,
+    file_records: u64,
+    file_started: Instant,
+    definitions: Option<Arc<InstrumentRegistry>>,
+    venue_records: bool,
+    /// Venue records the files already replayed left untaken.
+    venue: Vec<VenueMsg>,
+    finished: bool,
+}
+
+/// One file of a `DbnFileSource`.
+struct InputFile {
+    path: String,
+    /// Start of the data in ns since the epoch, from the metadata or else the first record.
+    start: u64,
+    size: u64,
+}
+
+impl DbnFileSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let mut files = Vec::new();
+        let mut symbols = Vec::new();
+        for file in input_files(path)? {
+            let mut reader = DbnReader::open(&file)?;
+            let start = match reader.metadata() {
+                Some(metadata) => metadata.start,
+                // Files from a newer DBN version carry no readable metadata
+                None => reader
+                    .next_record::<MboMsg>()?
+                    .map_or(u64::MAX, |rec| rec.hd.ts_event),
+            };
+            if let Some(metadata) = reader.metadata() {
+                symbols.extend(metadata_symbols(metadata));
+            }
+            files.push(InputFile {
+                size: file_size(reader.get_ref()),
+                path: file,
+                start,
+            });
+        }
+        files.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.path.cmp(&b.path)));
+        let reader = DbnReader::open(&files[0].path)?;
+        let source = Self {
+            path: path.to_owned(),
+            total_bytes: files.iter().map(|file| file.size).sum(),
+            files,
+            current: 0,
+            reader,
+            symbols,
+            done_bytes: 0,
+            done_unsupported: 0,
+            file_records: 0,
+            file_started: Instant::now(),
+            definitions: None,
+            venue_records: false,
+            venue: Vec::new(),
+            finished: false,
+        };
+        source.log_file_start();
+        Ok(source)
+    }
+
+    /// Collects the instrument definitions interleaved in the files into `registry`.
+    pub fn with_definitions(mut self, registry: Arc<InstrumentRegistry>) -> Self {
+        self.reader = self.reader.with_definitions(registry.clone());
+        self.definitions = Some(registry);
+        self
+    }
+
+    /// Keeps the imbalance and statistics records interleaved in the files for
+    /// `take_venue_records`.
+    pub fn with_venue_records(mut self) -> Self {
+        self.reader = self.reader.with_venue_records();
+        self.venue_records = true;
+        self
+    }
+
+    fn log_file_start(&self) {
+        if self.files.len() > 1 {
+            let file = &self.files[self.current];
+            info!(
+                path = %file.path,
+                file = self.current + 1,
+                files = self.files.len(),
+                start_ts = file.start,
+                bytes = file.size,
+                "input_file_start"
+            );
+        }
+    }
+
+    fn log_file_done(&self) {
+        if self.files.len() > 1 {
+            let elapsed = self.file_started.elapsed();
+            info!(
+                path = %self.files[self.current].path,
+                file = self.current + 1,
+                files = self.files.len(),
+                records = self.file_records,
+                unsupported = self.reader.skipped(),
+                elapsed_ms = elapsed.as_millis() as u64,
+                rate_per_s = self.file_records as f64 / elapsed.as_secs_f64().max(1e-9),
+                "input_file_done"
+            );
+        }
+    }
+
+    /// Moves on to the next file; false once every file has been replayed.
+    fn advance(&mut self) -> Result<bool> {
+        if self.finished {
+            return Ok(false);
+        }
+        self.log_file_done();
+        if self.current + 1 == self.files.len() {
+            self.finished = true;
+            return Ok(false);
+        }
+        self.done_bytes += self.files[self.current].size;
+        self.done_unsupported += self.reader.skipped();
+        self.venue.append(&mut self.reader.take_venue_records());
+        self.current += 1;
+        let mut reader = DbnReader::open(&self.files[self.current].path)?;
+        if let Some(registry) = &self.definitions {
+            reader = reader.with_definitions(registry.clone());
+        }
+        if self.venue_records {
+            reader = reader.with_venue_records();
+        }
+        self.reader = reader;
+        self.file_records = 0;
+        self.file_started = Instant::now();
+        self.log_file_start();
+        Ok(true)
+    }
+}
+
+/// Expands an input path into the DBN files it names, sorted by path: the file itself,
+/// the `.dbn` files of a directory, or the files whose name matches `*` and `?`
+/// wildcards in the last path component.
+pub fn input_files(path: &str) -> Result<Vec<String>> {
+    let as_path = Path::new(path);
+    let (dir, pattern) = if as_path.is_dir() {
+        (as_path, None)
+    } else if as_path.exists() {
+        return Ok(vec![path.to_owned()]);
+    } else if !has_wildcards(path) {
+        bail!("{} does not exist", path);
+    } else {
+        let dir = as_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        if has_wildcards(&dir.to_string_lossy()) {
+            bail!("{}: wildcards are only supported in the file name", path);
+        }
+        let name = as_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        (dir, name)
+    };
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to list {}", dir.display()))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry_path = entry?.path();
+        if !entry_path.is_file() {
+            continue;
+        }
+        let name = entry_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let matched = match &pattern {
+            Some(pattern) => wildcard_match(pattern.as_bytes(), name.as_bytes()),
+            None => entry_path.extension().is_some_and(|ext| ext == "dbn"),
+        };
+        if matched {
+            files.push(entry_path.to_string_lossy().into_owned());
+        }
+    }
+    if files.is_empty() {
+        bail!("{} matches no DBN files", path);
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn has_wildcards(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Matches `name` against a pattern where `*` is any run of bytes and `?` any one byte.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it was tried against
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn file_size(file: &File) -> u64 {
+    file.metadata().map(|m| m.len()).unwrap_or_default()
+}
+
+/// Offset of the underlying file, which runs slightly ahead of the decoder's buffer.
+fn file_offset(mut file: &File) -> u64 {
+    file.stream_position().unwrap_or_default()
+}
+
+impl IngestSource for DbnFileSource {
+    fn next_record(&mut self) -> Result<Option<MboMsg>> {
+        loop {
+            if let Some(rec) = self.reader.next_record::<MboMsg>()? {
+                self.file_records += 1;
+                return Ok(Some(rec));
+            }
+            if !self.advance()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        if self.files.len() > 1 {
+            return format!("dbn_files path={} files={}", self.path, self.files.len());
+        }
+        format!(
+            "dbn_file path={} version={}",
+            self.path,
+            self.reader.version()
+        )
+    }
+
+    fn symbols(&self) -> Vec<(u32, String)> {
+        self.symbols.clone()
+    }
+
+    fn byte_progress(&self) -> Option<(u64, u64)> {
+        let offset = file_offset(self.reader.get_ref()).min(self.files[self.current].size);
+        Some((self.done_bytes + offset, self.total_bytes))
+    }
+
+    fn unsupported_records(&self) -> u64 {
+        self.done_unsupported + self.reader.skipped()
+    }
+
+    fn take_venue_records(&mut self) -> Vec<VenueMsg> {
+        let mut records = std::mem::take(&mut self.venue);
+        records.append(&mut self.reader.take_venue_records());
+        records
+    }
+}
+
+/// Consumes the length-prefixed protobuf `MboBatch` frames produced by `stream_tcp`.
+pub struct TcpSource {
+    addr: String,
+    reader: BufReader<TcpStream>,
+    pending: VecDeque<MboMsg>,
+    frame: Vec<u8>,
+    closed: bool,
+}
+
+impl TcpSource {
+    /// Connects and subscribes to `instrument_ids` (empty = all) from `start_sequence`.
+    pub fn connect(addr: &str, instrument_ids: &[u32], start_sequence: u64) -> Result<Self> {
+        let mut stream = TcpStream::connect(addr)
+            .with_context(|| format!("failed to connect to TCP feed {}", addr))?;
+        stream
+            .set_nodelay(true)
+            .context("failed to enable TCP_NODELAY on feed socket")?;
+        let request = proto::SubscribeRequest {
+            instrument_ids: instrument_ids.to_vec(),
+            start_sequence,
+        };
+        let mut frame = (request.encoded_len() as u32).to_be_bytes().to_vec();
+        request.encode(&mut frame)?;
+        stream
+            .write_all(&frame)
+            .context("failed to send subscribe request")?;
+        Ok(Self {
+            addr: addr.to_owned(),
+            reader: BufReader::with_capacity(1024 * 1024, stream),
+            pending: VecDeque::new(),
+            frame: Vec::new(),
+            closed: false,
+        })
+    }
+
+    /// Closes the read side of the socket on shutdown, so a read blocked waiting for the
+    /// feed returns and the source ends.
+    pub fn stop_on(&self, shutdown: &Shutdown) -> Result<()> {
+        let stream = self
+            .reader
+            .get_ref()
+            .try_clone()
+            .context("failed to clone feed socket")?;
+        shutdown.on_trigger(move || {
+            let _ = stream.shutdown(net::Shutdown::Read);
+        });
+        Ok(())
+    }
+
+    /// Reads one frame into `pending`. Returns `Ok(false)` on clean end of stream.
+    fn read_frame(&mut self) -> Result<bool> {
+        let Some(batch) = read_batch(&mut self.reader, &mut self.frame)? else {
+            return Ok(false);
+        };
+        self.pending
+            .extend(batch.msgs.iter().filter_map(|msg| match proto_to_mbo(msg) {
+                Ok(mbo) => Some(mbo),
+                Err(e) => {
+                    warn!(error = %e, "tcp source dropping malformed message");
+                    None
+                }
+            }));
+        Ok(true)
+    }
+}
+
+/// Reads one length-prefixed `MboBatch` frame, reusing `frame` as the payload buffer.
+/// Returns `Ok(None)` on a clean end of stream before the length prefix.
+pub fn read_batch(reader: &mut impl Read, frame: &mut Vec<u8>) -> Result<Option<proto::MboBatch>> {
+    let mut len_buf = [0u8; 4];
+    match reader.read_exact(&mut len_buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(anyhow!(e).context("failed to read frame length")),
+    }
+    let frame_len = u32::from_be_bytes(len_buf) as usize;
+    if frame_len > MAX_FRAME_BYTES {
+        bail!(
+            "frame length {} exceeds max {} bytes",
+            frame_len,
+            MAX_FRAME_BYTES
+        );
+    }
+    frame.resize(frame_len, 0);
+    reader
+        .read_exact(frame)
+        .context("failed to read frame payload")?;
+    let batch =
+        proto::MboBatch::decode(frame.as_slice()).context("failed to decode MboBatch frame")?;
+    Ok(Some(batch))
+}
+
+impl IngestSource for TcpSource {
+    fn next_record(&mut self) -> Result<Option<MboMsg>> {
+        loop {
+            if let Some(mbo) = self.pending.pop_front() {
+                return Ok(Some(mbo));
+            }
+            if self.closed {
+                return Ok(None);
+            }
+            match self.read_frame() {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.closed = true;
+                }
+                Err(e) => {
+                    // Framing is lost after a read error, so stop after reporting it
+                    self.closed = true;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("tcp addr={}", self.addr)
+    }
+}
+
+/// A source of ready-made snapshots that bypasses the MBO book, for replaying
+/// snapshot-only datasets through the sinks and server.
+pub trait SnapshotSource {
+    /// `symbols` resolves instrument ids for formats that only carry ids.
+    fn next_snapshot(&mut self, symbols: &SymbolMap) -> Result<Option<SnapshotRecord>>;
+
+    /// Short label used in logs.
+    fn describe(&self) -> String;
+
+    /// Instrument id to symbol mappings known to the source, if any.
+    fn symbols(&self) -> Vec<(u32, String)> {
+        Vec::new()
+    }
+
+    /// `(bytes read, total bytes)` for sources backed by a file.
+    fn byte_progress(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// Records skipped because this build cannot decode them, e.g. record types or
+    /// versions newer than its DBN library.
+    fn unsupported_records(&self) -> u64 {
+        0
+    }
+}
+
+/// Reads MBP NDJSON in the format written to `final_mbp.json`.
+pub struct MbpJsonSource {
+    path: String,
+    lines: Lines<BufReader<Box<dyn Read + Send>>>,
+    line_no: u64,
+    bytes_read: u64,
+    size: u64,
+    /// Decompressed bytes say nothing about progress through a compressed file.
+    compressed: bool,
+}
+
+impl MbpJsonSource {
+    /// Opens `path`, which may be compressed with any codec.
+    pub fn open(path: &str) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open MBP JSON file {}", path))?;
+        let size = file_size(&file);
+        let (reader, kind) = codec::decoder(BufReader::new(file))
+            .with_context(|| format!("failed to read MBP JSON file {}", path))?;
+        Ok(Self {
+            path: path.to_owned(),
+            size,
+            lines: BufReader::new(reader).lines(),
+            line_no: 0,
+            bytes_read: 0,
+            compressed: kind != CodecKind::None,
+        })
+    }
+}
+
+impl SnapshotSource for MbpJsonSource {
+    fn next_snapshot(&mut self, symbols: &SymbolMap) -> Result<Option<SnapshotRecord>> {
+        loop {
+            let Some(line) = self.lines.next() else {
+                return Ok(None);
+            };
+            self.line_no += 1;
+            let line = line.with_context(|| format!("failed to read {}", self.path))?;
+            self.bytes_read += line.len() as u64 + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mbp: MbpOutput = serde_json::from_str(&line)
+                .with_context(|| format!("invalid MBP JSON on line {}", self.line_no))?;
+            let instrument_id = symbols.instrument_id(&mbp.symbol).unwrap_or_default();
+            return mbp_output_to_snapshot(&mbp, instrument_id).map(Some);
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("mbp_json path={}", self.path)
+    }
+
+    fn byte_progress(&self) -> Option<(u64, u64)> {
+        (!self.compressed).then_some((self.bytes_read, self.size))
+    }
+}
+
+/// Replays Databento MBP-10 records from a DBN file as 10-level snapshots. The feed
+/// only carries the top ten levels, so level counts cover those levels and
+/// `total_orders` is the order count within them.
+pub struct Mbp10DbnSource {
+    path: String,
+    reader: DbnReader,
+    size: u64,
+}
+
+impl Mbp10DbnSource {
+    pub fn open(path: &str) -> Result<Self> {
+        let reader = DbnReader::open(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            size: file_size(reader.get_ref()),
+            reader,
+        })
+    }
+}
+
+impl SnapshotSource for Mbp10DbnSource {
+    fn next_snapshot(&mut self, symbols: &SymbolMap) -> Result<Option<SnapshotRecord>> {
+        let Some(msg) = self.reader.next_record::<Mbp10Msg>()? else {
+            return Ok(None);
+        };
+        let side = |px: i64, sz: u32, ct: u32| {
+            (px != UNDEF_PRICE && sz > 0).then_some(LevelEntry {
+                price: px,
+                size: sz,
+                count: ct,
+                est_hidden: None,
+            })
+        };
+        let bids: Vec<LevelEntry> = msg
+            .levels
+            .iter()
+            .filter_map(|l| side(l.bid_px, l.bid_sz, l.bid_ct))
+            .collect();
+        let asks: Vec<LevelEntry> = msg
+            .levels
+            .iter()
+            .filter_map(|l| side(l.ask_px, l.ask_sz, l.ask_ct))
+            .collect();
+        let ts_event = msg.hd.ts_event as i64;
+        let total_orders = bids.iter().chain(&asks).map(|l| l.count as usize).sum();
+        Ok(Some(SnapshotRecord {
+            instrument_id: msg.hd.instrument_id,
+            publisher_id: msg.hd.publisher_id,
+            ts_event,
+            ts_recv: msg.ts_recv as i64,
+            built_at: wall_clock_ns(),
+            payload: Snapshot {
+                bbo: Bbo {
+                    best_bid: bids.first().cloned(),
+                    best_ask: asks.first().cloned(),
+                },
+                symbol: symbols.resolve(msg.hd.instrument_id).to_owned(),
+                ts_ns: ts_event,
+                seq: 0,
+                total_orders,
+                bid_levels: bids.len(),
+                ask_levels: asks.len(),
+                bids,
+                asks,
+                notional: None,
+                signals: None,
+                liquidity: None,
+                buckets: None,
+                implied: None,
+                stale: false,
+            },
+            cache: EncodedCache::default(),
+        }))
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "mbp10_dbn path={} version={}",
+            self.path,
+            self.reader.version()
+        )
+    }
+
+    fn symbols(&self) -> Vec<(u32, String)> {
+        self.reader
+            .metadata()
+            .map(metadata_symbols)
+            .unwrap_or_default()
+    }
+
+    fn byte_progress(&self) -> Option<(u64, u64)> {
+        Some((file_offset(self.reader.get_ref()), self.size))
+    }
+
+    fn unsupported_records(&self) -> u64 {
+        self.reader.skipped()
+    }
+}
+
+fn metadata_symbols(metadata: &Metadata) -> Vec<(u32, String)> {
+    // With stype_out=instrument_id each interval symbol is the numeric id
+    metadata
+        .mappings
+        .iter()
+        .flat_map(|mapping| {
+            mapping.intervals.iter().filter_map(|interval| {
+                interval
+                    .symbol
+                    .parse::<u32>()
+                    .ok()
+                    .map(|id| (id, mapping.raw_symbol.clone()))
+            })
+        })
+        .collect()
+}
+
+/// Converts a protobuf message from `stream_tcp` back into a DBN `MboMsg`.
+pub fn proto_to_mbo(msg: &proto::MboMsg) -> Result<MboMsg> {
+    let hd = msg
+        .hd
+        .as_ref()
+        .ok_or_else(|| anyhow!("message missing header"))?;
+    let action = single_char(&msg.action, "action")?;
+    let side = single_char(&msg.side, "side")?;
+    Ok(MboMsg {
+        hd: RecordHeader::new::<MboMsg>(
+            rtype::MBO,
+            hd.publisher_id as u16,
+            hd.instrument_id,
+            hd.ts_event,
+        ),
+        order_id: msg.order_id,
+        price: msg.price as i64,
+        size: msg.size,
+        flags: FlagSet::new(msg.flags as u8),
+        channel_id: msg.channel_id as u8,
+        action,
+        side,
+        // Frames encoded before ts_recv was on the wire fall back to ts_event
+        ts_recv: if msg.ts_recv == 0 {
+            hd.ts_event
+        } else {
+            msg.ts_recv
+        },
+        ts_in_delta: msg.ts_in_delta as i32,
+        sequence: msg.sequence as u32,
+    })
+}
+
+fn single_char(value: &str, field: &str) -> Result<c_char> {
+    match value.as_bytes() {
+        [b] => Ok(*b as c_char),
+        _ => bail!("invalid {} {:?}", field, value),
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use dbn::{UNDEF_PRICE, UNDEF_TIMESTAMP, record::InstrumentDefMsg};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{dbn_compat::DbnReader, snapshot::PriceFormat};
+
+/// Reference data of one instrument, from its latest DBN definition.
+#[derive(Clone, Debug, Serialize)]
+pub struct Instrument {
+    pub instrument_id: u32,
+    pub raw_symbol: String,
+    /// E.g. `F` future, `C` call, `K` stock.
+    pub instrument_class: char,
+    pub exchange: String,
+    pub asset: String,
+    pub currency: String,
+    /// Minimum price increment, fixed-point (1e-9 units); None when undefined.
+    pub tick_size: Option<i64>,
+    /// Contract size in units of `unit_of_measure`, e.g. 1000 barrels.
+    pub multiplier: Option<f64>,
+    pub unit_of_measure: String,
+    /// Last eligible trade time, ns since the epoch; None for instruments that do not
+    /// expire.
+    pub expiration: Option<u64>,
+    /// When the definition was received.
+    pub ts_recv: u64,
+}
+
+impl Instrument {
+    pub fn from_def(def: &InstrumentDefMsg) -> Self {
+        Self {
+            instrument_id: def.hd.instrument_id,
+            raw_symbol: def.raw_symbol().unwrap_or_default().to_owned(),
+            instrument_class: def.instrument_class as u8 as char,
+            exchange: def.exchange().unwrap_or_default().to_owned(),
+            asset: def.asset().unwrap_or_default().to_owned(),
+            currency: def.currency().unwrap_or_default().to_owned(),
+            tick_size: (def.min_price_increment != UNDEF_PRICE && def.min_price_increment > 0)
+                .then_some(def.min_price_increment),
+            multiplier: (def.unit_of_measure_qty != UNDEF_PRICE)
+                .then(|| def.unit_of_measure_qty as f64 / dbn::FIXED_PRICE_SCALE as f64),
+            unit_of_measure: def.unit_of_measure().unwrap_or_default().to_owned(),
+            expiration: (def.expiration != UNDEF_TIMESTAMP).then_some(def.expiration),
+            ts_recv: def.ts_recv,
+        }
+    }
+}
+
+/// Instruments by id, shared between the sources that decode definitions, the
+/// snapshot and MBP writers that use their tick sizes, and `/instruments`.
+#[derive(Debug, Default)]
+pub struct InstrumentRegistry {
+    instruments: RwLock<BTreeMap<u32, Arc<Instrument>>>,
+}
+
+impl InstrumentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces the instrument; a later definition, e.g. an intraday update,
+    /// wins.
+    pub fn insert(&self, instrument: Instrument) {
+        let mut instruments = self
+            .instruments
+            .write()
+            .expect("instrument registry lock poisoned");
+        let known = instruments.contains_key(&instrument.instrument_id);
+        if !known {
+            info!(
+                instrument_id = instrument.instrument_id,
+                raw_symbol = %instrument.raw_symbol,
+                tick_size = instrument.tick_size,
+                "instrument_defined"
+            );
+        }
+        instruments.insert(instrument.instrument_id, Arc::new(instrument));
+    }
+
+    pub fn get(&self, instrument_id: u32) -> Option<Arc<Instrument>> {
+        self.instruments
+            .read()
+            .expect("instrument registry lock poisoned")
+            .get(&instrument_id)
+            .cloned()
+    }
+
+    /// All instruments by id.
+    pub fn all(&self) -> Vec<Arc<Instrument>> {
+        self.instruments
+            .read()
+            .expect("instrument registry lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.instruments
+            .read()
+            .expect("instrument registry lock poisoned")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The instrument's tick size, or `fallback` (`TICK_SIZE`) for an instrument
+    /// without a definition.
+    pub fn tick_size(&self, instrument_id: u32, fallback: i64) -> i64 {
+        self.get(instrument_id)
+            .and_then(|instrument| instrument.tick_size)
+            .unwrap_or(fallback)
+    }
+
+    /// `prices` with the decimal places of the instrument's own tick size; raw prices
+    /// and instruments without a definition keep `prices`.
+    pub fn price_format(&self, instrument_id: u32, prices: PriceFormat) -> PriceFormat {
+        match (prices, self.get(instrument_id).and_then(|i| i.tick_size)) {
+            (PriceFormat::Decimal { .. }, Some(tick_size)) => PriceFormat::for_tick(tick_size),
+            _ => prices,
+        }
+    }
+
+    /// Instrument id to raw symbol mappings, for `SymbolMap::extend_missing`.
+    pub fn symbols(&self) -> Vec<(u32, String)> {
+        self.all()
+            .iter()
+            .filter(|instrument| !instrument.raw_symbol.is_empty())
+            .map(|instrument| (instrument.instrument_id, instrument.raw_symbol.clone()))
+            .collect()
+    }
+}
+
+/// Reads every definition of a DBN file, e.g. one of Databento's `definition`
+/// schema, into `registry`. Other records are skipped.
+pub fn load_definitions(path: &str, registry: &InstrumentRegistry) -> Result<usize> {
+    let mut reader =
+        DbnReader::open(path).with_context(|| format!("failed to open definitions {}", path))?;
+    let mut count = 0;
+    while let Some(def) = reader
+        .next_record::<InstrumentDefMsg>()
+        .with_context(|| format!("failed to read definitions from {}", path))?
+    {
+        registry.insert(Instrument::from_def(&def));
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,177 @@
+//! Latency histograms: how long records take to apply, and snapshots to get from the feed
+//! to the registry and to storage.
+
+use std::{
+    fmt,
+    sync::{Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::snapshot::SnapshotRecord;
+
+/// Significant figures the histograms keep, so a reported value is within 0.1% of
+/// the recorded one.
+const SIGNIFICANT_FIGURES: u8 = 3;
+
+/// Nanosecond histogram over `hdrhistogram`, recorded into from any thread. Each
+/// thread records into its own, so the lock is uncontended but for metrics reads.
+pub struct Histogram {
+    inner: Mutex<hdrhistogram::Histogram<u64>>,
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("Histogram")
+            .field("total", &inner.len())
+            .field("max", &inner.max())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Percentiles of a `Histogram`, in nanoseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50_ns: u64,
+    pub p90_ns: u64,
+    pub p99_ns: u64,
+    pub p999_ns: u64,
+    pub max_ns: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        let inner = hdrhistogram::Histogram::new(SIGNIFICANT_FIGURES)
+            .expect("significant figures are within 0 to 5");
+        Self {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, hdrhistogram::Histogram<u64>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn record(&self, ns: u64) {
+        let mut inner = self.lock();
+        // Grows to fit `ns` unless that is beyond what a histogram can track
+        if inner.record(ns).is_err() {
+            inner.saturating_record(ns);
+        }
+    }
+
+    /// Adds what `other` recorded, e.g. to combine the histograms of several threads.
+    pub fn merge(&self, other: &Histogram) {
+        let other = other.lock().clone();
+        // Auto-resizing histograms take any value, so adding cannot fail
+        let _ = self.lock().add(other);
+    }
+
+    /// Mean of the recorded values, within the histogram's precision; 0 when nothing
+    /// was recorded.
+    pub fn mean(&self) -> f64 {
+        self.lock().mean()
+    }
+
+    /// The value at quantile `q` (0 to 1), within 0.1%; 0 when nothing was recorded.
+    pub fn quantile(&self, q: f64) -> u64 {
+        self.lock().value_at_quantile(q)
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let inner = self.lock();
+        LatencySummary {
+            count: inner.len(),
+            p50_ns: inner.value_at_quantile(0.5),
+            p90_ns: inner.value_at_quantile(0.9),
+            p99_ns: inner.value_at_quantile(0.99),
+            p999_ns: inner.value_at_quantile(0.999),
+            max_ns: inner.max(),
+        }
+    }
+}
+
+/// What publication latency is measured from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyOrigin {
+    /// The record's `ts_recv`, for live feeds whose receive time is now.
+    Recv,
+    /// When ingest built the snapshot, for replays whose timestamps are historical.
+    #[default]
+    Built,
+}
+
+impl LatencyOrigin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LatencyOrigin::Recv => "recv",
+            LatencyOrigin::Built => "built",
+        }
+    }
+}
+
+/// Latency of every snapshot until the registry serves it and until the storage
+/// writers have written it.
+#[derive(Debug, Default)]
+pub struct PublishLatency {
+    pub origin: LatencyOrigin,
+    /// Until the latest publisher stored the snapshot in the registry.
+    pub latest: Histogram,
+    /// Until a storage writer's batch holding the snapshot was written, or for
+    /// postgres, until its COPY transaction committed.
+    pub stored: Histogram,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PublishLatencyMetrics {
+    pub origin: LatencyOrigin,
+    pub latest: LatencySummary,
+    pub stored: LatencySummary,
+}
+
+impl PublishLatency {
+    pub fn new(origin: LatencyOrigin) -> Self {
+        Self {
+            origin,
+            ..Self::default()
+        }
+    }
+
+    /// Nanoseconds since `snapshot` left its origin, or `None` for snapshots without
+    /// one, e.g. loaded from storage, or with a receive time ahead of the local clock.
+    pub fn since_origin(&self, snapshot: &SnapshotRecord) -> Option<u64> {
+        let origin = match self.origin {
+            LatencyOrigin::Recv => snapshot.ts_recv,
+            LatencyOrigin::Built => snapshot.built_at,
+        };
+        if origin <= 0 {
+            return None;
+        }
+        u64::try_from(wall_clock_ns() - origin).ok()
+    }
+
+    pub fn snapshot(&self) -> PublishLatencyMetrics {
+        PublishLatencyMetrics {
+            origin: self.origin,
+            latest: self.latest.summary(),
+            stored: self.stored.summary(),
+        }
+    }
+}
+
+/// Wall clock as Unix nanoseconds.
+pub fn wall_clock_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as i64)
+}
@@ -1,4 +1,48 @@
+pub mod access_log;
+pub mod alerts;
+pub mod analytics;
+pub mod bench;
+pub mod book_query;
+pub mod cadence;
+pub mod checkpoint;
+pub mod cli;
+pub mod codec;
+pub mod compare;
+pub mod compression;
+pub mod config;
+pub mod dbn_compat;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod grpc;
+pub mod ingest;
+pub mod instruments;
+pub mod latency;
+pub mod live;
+pub mod logging;
+pub mod mbp_dbn;
+pub mod mbp_writer;
 pub mod order_book;
+pub mod progress;
+pub mod queue;
+pub mod replay;
 pub mod server;
+pub mod shutdown;
+pub mod sim;
 pub mod snapshot;
 pub mod storage;
+pub mod stream;
+pub mod summary;
+pub mod supervisor;
+pub mod trades;
+pub mod validate;
+pub mod venue_stats;
+
+// Generated protobuf types for the TCP feed
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/_.rs"));
+
+    /// Messages and tonic server of the snapshot service in `snapshots.proto`.
+    pub mod v1 {
+        include!(concat!(env!("OUT_DIR"), "/batonics.v1.rs"));
+    }
+}
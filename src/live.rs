@@ -0,0 +1,278 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{self, TcpStream},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use dbn::{
+    VersionUpgradePolicy,
+    decode::{
+        DecodeRecordRef,
+        dbn::{MetadataDecoder, RecordDecoder},
+    },
+    record::{
+        ErrorMsg, ImbalanceMsg, InstrumentDefMsg, MboMsg, StatMsg, SymbolMappingMsg, SystemMsg,
+    },
+};
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use crate::{
+    ingest::IngestSource,
+    instruments::{Instrument, InstrumentRegistry},
+    shutdown::Shutdown,
+    venue_stats::VenueMsg,
+};
+
+/// Port of the Databento live subscription gateways.
+const GATEWAY_PORT: u16 = 13000;
+
+/// The gateway sends a heartbeat every 30s on an idle session, so a longer silence
+/// means the connection is gone.
+const READ_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Characters of the API key that identify its bucket during authentication.
+const BUCKET_ID_LEN: usize = 5;
+
+/// Schemas `LIVE_SCHEMAS` may add to the subscription.
+pub const VENUE_SCHEMAS: [&str; 2] = ["imbalance", "statistics"];
+
+/// A Databento live MBO subscription.
+#[derive(Clone)]
+pub struct LiveConfig {
+    pub api_key: String,
+    /// Dataset code such as `GLBX.MDP3`.
+    pub dataset: String,
+    /// Raw symbols to subscribe to, such as `CLX5`.
+    pub symbols: Vec<String>,
+    /// `host:port` overriding the dataset's gateway.
+    pub gateway: Option<String>,
+    /// Venue schemas subscribed besides `mbo` and `definition`: `imbalance` and
+    /// `statistics`.
+    pub schemas: Vec<String>,
+}
+
+impl LiveConfig {
+    pub fn gateway_addr(&self) -> String {
+        self.gateway.clone().unwrap_or_else(|| {
+            format!(
+                "{}.lsg.databento.com:{}",
+                self.dataset.to_lowercase().replace('.', "-"),
+                GATEWAY_PORT
+            )
+        })
+    }
+}
+
+/// Real-time MBO records from the Databento live gateway.
+///
+/// Speaks the gateway's line protocol (CRAM authentication, subscription, session
+/// start) and then decodes the DBN stream the session sends, so records enter the
+/// same book and sinks as a file replay.
+pub struct LiveSource {
+    addr: String,
+    dataset: String,
+    decoder: RecordDecoder<BufReader<TcpStream>>,
+    /// Mappings the gateway sent before the first MBO record.
+    symbols: Vec<(u32, String)>,
+    /// First MBO record, read while collecting the mappings.
+    pending: Option<MboMsg>,
+    /// Receives the definitions of the subscribed instruments.
+    definitions: Arc<InstrumentRegistry>,
+    /// Imbalance and statistics records not yet taken.
+    venue: Vec<VenueMsg>,
+    closed: bool,
+}
+
+impl LiveSource {
+    /// Authenticates, subscribes to the MBO records, definitions and `config.schemas` of
+    /// `config.symbols` and starts the session. Closes the socket on shutdown, so a read waiting for the
+    /// gateway returns and the source ends.
+    pub fn connect(
+        config: &LiveConfig,
+        definitions: Arc<InstrumentRegistry>,
+        shutdown: &Shutdown,
+    ) -> Result<Self> {
+        let addr = config.gateway_addr();
+        let stream = TcpStream::connect(&addr)
+            .with_context(|| format!("failed to connect to Databento gateway {}", addr))?;
+        stream
+            .set_nodelay(true)
+            .context("failed to enable TCP_NODELAY on gateway socket")?;
+        stream
+            .set_read_timeout(Some(READ_TIMEOUT))
+            .context("failed to set gateway read timeout")?;
+        let closer = stream
+            .try_clone()
+            .context("failed to clone gateway socket")?;
+        shutdown.on_trigger(move || {
+            let _ = closer.shutdown(net::Shutdown::Read);
+        });
+        let mut reader = BufReader::new(stream);
+
+        let greeting = read_line(&mut reader)?;
+        info!(addr = %addr, greeting, "live_gateway");
+        let challenge = read_line(&mut reader)?;
+        let challenge = fields(&challenge)
+            .get("cram")
+            .map(|cram| cram.to_string())
+            .ok_or_else(|| anyhow!("gateway sent no challenge: {}", challenge))?;
+        send(
+            &mut reader,
+            &format!(
+                "auth={}|dataset={}|encoding=dbn|ts_out=0",
+                cram_response(&challenge, &config.api_key),
+                config.dataset
+            ),
+        )?;
+        let reply = read_line(&mut reader)?;
+        let reply_fields = fields(&reply);
+        if reply_fields.get("success") != Some(&"1") {
+            bail!(
+                "Databento gateway rejected authentication: {}",
+                reply_fields.get("error").unwrap_or(&reply.as_str())
+            );
+        }
+        info!(
+            dataset = %config.dataset,
+            session_id = reply_fields.get("session_id").unwrap_or(&"-"),
+            "live_session"
+        );
+        let schemas = ["mbo", "definition"]
+            .into_iter()
+            .chain(config.schemas.iter().map(String::as_str));
+        for schema in schemas {
+            send(
+                &mut reader,
+                &format!(
+                    "schema={}|stype_in=raw_symbol|symbols={}",
+                    schema,
+                    config.symbols.join(",")
+                ),
+            )?;
+        }
+        send(&mut reader, "start_session=0")?;
+
+        let mut metadata_decoder =
+            MetadataDecoder::with_upgrade_policy(reader, VersionUpgradePolicy::UpgradeToV3);
+        metadata_decoder
+            .decode()
+            .context("failed to read live session metadata")?;
+        let mut source = Self {
+            addr,
+            dataset: config.dataset.clone(),
+            decoder: RecordDecoder::from(metadata_decoder),
+            symbols: Vec::new(),
+            pending: None,
+            definitions,
+            venue: Vec::new(),
+            closed: false,
+        };
+        // The gateway resolves the subscribed symbols before sending data, so the
+        // mappings are known by the time the caller asks for them
+        source.pending = source.read_mbo()?;
+        Ok(source)
+    }
+
+    /// Next MBO record, handling the control records in between.
+    fn read_mbo(&mut self) -> Result<Option<MboMsg>> {
+        loop {
+            let record = match self.decoder.decode_record_ref() {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    self.closed = true;
+                    return Ok(None);
+                }
+                Err(e) => {
+                    // Framing is lost after a read error, so stop after reporting it
+                    self.closed = true;
+                    return Err(anyhow!(e).context("failed to read from Databento gateway"));
+                }
+            };
+            if let Some(mbo) = record.get::<MboMsg>() {
+                return Ok(Some(mbo.clone()));
+            }
+            if let Some(def) = record.get::<InstrumentDefMsg>() {
+                self.definitions.insert(Instrument::from_def(def));
+            } else if let Some(imbalance) = record.get::<ImbalanceMsg>() {
+                self.venue.push(VenueMsg::Imbalance(imbalance.clone()));
+            } else if let Some(stat) = record.get::<StatMsg>() {
+                self.venue.push(VenueMsg::Stat(stat.clone()));
+            } else if let Some(mapping) = record.get::<SymbolMappingMsg>() {
+                if let Ok(symbol) = mapping.stype_in_symbol() {
+                    let instrument_id = mapping.hd.instrument_id;
+                    info!(instrument_id, symbol, "live_symbol");
+                    self.symbols.push((instrument_id, symbol.to_owned()));
+                }
+            } else if let Some(system) = record.get::<SystemMsg>() {
+                if !system.is_heartbeat() {
+                    info!(msg = system.msg().unwrap_or_default(), "live_gateway");
+                }
+            } else if let Some(error) = record.get::<ErrorMsg>() {
+                error!(error = error.err().unwrap_or_default(), "live_gateway");
+            }
+        }
+    }
+}
+
+impl IngestSource for LiveSource {
+    fn next_record(&mut self) -> Result<Option<MboMsg>> {
+        if let Some(mbo) = self.pending.take() {
+            return Ok(Some(mbo));
+        }
+        if self.closed {
+            return Ok(None);
+        }
+        self.read_mbo()
+    }
+
+    fn describe(&self) -> String {
+        format!("databento_live addr={} dataset={}", self.addr, self.dataset)
+    }
+
+    fn symbols(&self) -> Vec<(u32, String)> {
+        self.symbols.clone()
+    }
+
+    fn take_venue_records(&mut self) -> Vec<VenueMsg> {
+        std::mem::take(&mut self.venue)
+    }
+}
+
+/// Answer to the gateway's challenge: the SHA-256 of `challenge|key` in hex, followed
+/// by the key's bucket id.
+fn cram_response(challenge: &str, api_key: &str) -> String {
+    let digest = Sha256::digest(format!("{}|{}", challenge, api_key).as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let bucket_id = &api_key[api_key.len().saturating_sub(BUCKET_ID_LEN)..];
+    format!("{}-{}", hex, bucket_id)
+}
+
+fn read_line(reader: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    if reader
+        .read_line(&mut line)
+        .context("failed to read from Databento gateway")?
+        == 0
+    {
+        bail!("Databento gateway closed the connection");
+    }
+    Ok(line.trim_end().to_owned())
+}
+
+fn send(reader: &mut BufReader<TcpStream>, line: &str) -> Result<()> {
+    reader
+        .get_mut()
+        .write_all(format!("{}\n", line).as_bytes())
+        .context("failed to write to Databento gateway")
+}
+
+/// Splits a gateway line such as `success=1|session_id=5` into its fields.
+fn fields(line: &str) -> HashMap<&str, &str> {
+    line.split('|')
+        .filter_map(|field| field.split_once('='))
+        .collect()
+}
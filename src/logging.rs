@@ -0,0 +1,49 @@
+//! Log output. Every component logs through `tracing`, in spans named after the
+//! subsystem (`sink`, `ingest`, `http_request`, `tcp_streamer`, ...), as text or as one
+//! JSON object per line for log collectors.
+
+use std::io::{self, IsTerminal};
+
+use anyhow::{Result, anyhow};
+use tracing::Level;
+use tracing_subscriber::{EnvFilter, filter::ParseError, fmt::writer::MakeWriterExt};
+
+use crate::config::LogConfig;
+
+/// Postgres notices (`relation ... already exists, skipping`) would otherwise be
+/// logged at info on every start.
+pub const DEFAULT_FILTER: &str = "info,postgres=warn,tokio_postgres=warn";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub fn parse_filter(filter: &str) -> Result<EnvFilter, ParseError> {
+    EnvFilter::try_new(filter)
+}
+
+/// Installs the global logger. Warnings and errors go to stderr and the rest to
+/// stdout, so the split matches what the process wrote before it logged through
+/// `tracing`.
+pub fn init(config: &LogConfig) -> Result<()> {
+    let filter = parse_filter(&config.filter)?;
+    let writer = io::stderr.with_max_level(Level::WARN).or_else(io::stdout);
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    let installed = match config.format {
+        LogFormat::Text => builder
+            .with_ansi(io::stdout().is_terminal() && io::stderr().is_terminal())
+            .try_init(),
+        // Event fields sit next to the message rather than under "fields"
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .try_init(),
+    };
+    installed.map_err(|e| anyhow!("failed to install the logger: {}", e))
+}
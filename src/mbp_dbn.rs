@@ -0,0 +1,122 @@
+use std::{collections::HashSet, io::Write, os::raw::c_char};
+
+use anyhow::{Context, Result};
+use dbn::{
+    Action, FlagSet, Metadata, SType, Schema, Side, UNDEF_PRICE,
+    encode::{
+        EncodeRecord,
+        dbn::{MetadataEncoder, RecordEncoder},
+    },
+    flags,
+    record::{BidAskPair, Mbp10Msg, RecordHeader, SymbolMappingMsg},
+    rtype,
+};
+
+use crate::snapshot::{LevelEntry, SnapshotRecord};
+
+/// Levels in an MBP-10 record.
+pub const MBP10_LEVELS: usize = 10;
+
+/// Writes snapshots as DBN MBP-10 records, readable by any DBN tool and by
+/// `INGEST_SOURCE=mbp10_dbn`.
+///
+/// The metadata declares raw-symbol input and instrument-id output symbology. Instead
+/// of metadata mappings, which would have to be known before the first record, each
+/// instrument's symbol goes into a `SymbolMappingMsg` ahead of its first snapshot, as
+/// a live session sends them.
+pub struct Mbp10Writer<W: Write> {
+    writer: W,
+    /// Metadata still has to be written, i.e. the file is new.
+    needs_metadata: bool,
+    /// Instruments whose symbol mapping has been written.
+    mapped: HashSet<u32>,
+}
+
+impl<W: Write> Mbp10Writer<W> {
+    /// `needs_metadata` is false when appending to a file that already starts with it.
+    pub fn new(writer: W, needs_metadata: bool) -> Self {
+        Self {
+            writer,
+            needs_metadata,
+            mapped: HashSet::new(),
+        }
+    }
+
+    pub fn write(&mut self, record: &SnapshotRecord) -> Result<()> {
+        if self.needs_metadata {
+            MetadataEncoder::new(&mut self.writer)
+                .encode(&metadata(record.ts_event))
+                .context("failed to write DBN metadata")?;
+            self.needs_metadata = false;
+        }
+        let mut encoder = RecordEncoder::new(&mut self.writer);
+        if self.mapped.insert(record.instrument_id) {
+            let ts_event = record.ts_event as u64;
+            let mapping = SymbolMappingMsg::new(
+                record.instrument_id,
+                ts_event,
+                SType::RawSymbol,
+                &record.payload.symbol,
+                SType::InstrumentId,
+                &record.instrument_id.to_string(),
+                ts_event,
+                u64::MAX,
+            )?;
+            encoder.encode_record(&mapping)?;
+        }
+        encoder.encode_record(&snapshot_to_mbp10(record))?;
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+}
+
+fn metadata(start: i64) -> Metadata {
+    Metadata::builder()
+        .dataset("")
+        .schema(Some(Schema::Mbp10))
+        .start(start as u64)
+        .stype_in(Some(SType::RawSymbol))
+        .stype_out(SType::InstrumentId)
+        .build()
+}
+
+/// The top ten levels of a snapshot as an MBP-10 record, laid out like
+/// `Book::snapshot`. A snapshot is not tied to the event that produced it, so the
+/// record carries no trade or order fields (`action` and `side` are `N`) and
+/// `ts_recv` is that of the record behind the snapshot. `sequence` holds the snapshot's `seq`, truncated to
+/// 32 bits, and stale books are flagged `F_MAYBE_BAD_BOOK`.
+pub fn snapshot_to_mbp10(record: &SnapshotRecord) -> Mbp10Msg {
+    let snapshot = &record.payload;
+    let mut levels: [BidAskPair; MBP10_LEVELS] = std::array::from_fn(|_| BidAskPair::default());
+    for (pair, bid) in levels.iter_mut().zip(&snapshot.bids) {
+        (pair.bid_px, pair.bid_sz, pair.bid_ct) = level_fields(bid);
+    }
+    for (pair, ask) in levels.iter_mut().zip(&snapshot.asks) {
+        (pair.ask_px, pair.ask_sz, pair.ask_ct) = level_fields(ask);
+    }
+    let mut raw_flags = flags::LAST;
+    if snapshot.stale {
+        raw_flags |= flags::MAYBE_BAD_BOOK;
+    }
+    let ts_event = record.ts_event as u64;
+    Mbp10Msg {
+        hd: RecordHeader::new::<Mbp10Msg>(rtype::MBP_10, 0, record.instrument_id, ts_event),
+        price: UNDEF_PRICE,
+        size: 0,
+        action: Action::None as u8 as c_char,
+        side: Side::None as u8 as c_char,
+        flags: FlagSet::new(raw_flags),
+        depth: 0,
+        ts_recv: record.ts_recv as u64,
+        ts_in_delta: 0,
+        sequence: snapshot.seq as u32,
+        levels,
+    }
+}
+
+fn level_fields(level: &LevelEntry) -> (i64, u32, u32) {
+    (level.price, level.size, level.count)
+}
@@ -0,0 +1,267 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use crate::{
+    codec::Encoder,
+    config::{MbpFormat, MbpOutputConfig},
+    instruments::InstrumentRegistry,
+    mbp_dbn::{MBP10_LEVELS, Mbp10Writer},
+    snapshot::{LevelEntry, PriceFormat, SnapshotRecord, snapshot_to_mbp_output},
+};
+
+type FileEncoder = Encoder<BufWriter<File>>;
+
+/// The MBP writer's output file, in the configured format and codec, rotated by size
+/// or age.
+///
+/// The file at `path` is always the one being written. On rotation it is completed
+/// (compressed frame ended, DBN stream closed) and renamed with the next segment
+/// number before the extensions, e.g. `final_mbp.json.gz` to `final_mbp.3.json.gz`,
+/// so finished segments sort in the order they were written.
+pub struct MbpFile {
+    config: MbpOutputConfig,
+    /// Tick sizes that set each instrument's price decimals.
+    instruments: Arc<InstrumentRegistry>,
+    writer: FormatWriter,
+    opened: Instant,
+    next_segment: u64,
+}
+
+impl MbpFile {
+    /// With `append`, continues the file an earlier attempt left behind; with a codec
+    /// the appended part is a frame of its own.
+    pub fn open(
+        config: &MbpOutputConfig,
+        instruments: Arc<InstrumentRegistry>,
+        append: bool,
+    ) -> Result<Self> {
+        Ok(Self {
+            writer: FormatWriter::open(config, append)?,
+            config: config.clone(),
+            instruments,
+            opened: Instant::now(),
+            next_segment: next_segment(Path::new(&config.path)),
+        })
+    }
+
+    pub fn write(&mut self, snapshot: &SnapshotRecord) -> Result<()> {
+        let prices = self
+            .instruments
+            .price_format(snapshot.instrument_id, self.config.prices);
+        self.writer.write(snapshot, prices)?;
+        if self.rotation_due() {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer
+            .encoder()
+            .flush()
+            .with_context(|| format!("failed to flush {}", self.config.path))
+    }
+
+    /// Completes the file. Nothing may be written afterwards.
+    pub fn finish(&mut self) -> Result<()> {
+        self.writer
+            .encoder()
+            .try_finish()
+            .with_context(|| format!("failed to flush {}", self.config.path))
+    }
+
+    fn rotation_due(&mut self) -> bool {
+        self.config
+            .rotate_interval
+            .is_some_and(|interval| self.opened.elapsed() >= interval)
+            || self
+                .config
+                .rotate_bytes
+                .is_some_and(|max| self.writer.file_len() >= max)
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.finish()?;
+        let bytes = self.writer.file_len();
+        let segment = segment_path(Path::new(&self.config.path), self.next_segment);
+        fs::rename(&self.config.path, &segment).with_context(|| {
+            format!(
+                "failed to rename {} to {}",
+                self.config.path,
+                segment.display()
+            )
+        })?;
+        info!(
+            path = %self.config.path,
+            segment = %segment.display(),
+            bytes,
+            "mbp file rotated"
+        );
+        self.next_segment += 1;
+        self.writer = FormatWriter::open(&self.config, false)?;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+enum FormatWriter {
+    Json(FileEncoder),
+    Csv(FileEncoder),
+    Dbn(Mbp10Writer<FileEncoder>),
+}
+
+impl FormatWriter {
+    fn open(config: &MbpOutputConfig, append: bool) -> Result<Self> {
+        let path = &config.path;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(!append)
+            .append(append)
+            .open(path)
+            .with_context(|| format!("failed to create {}", path))?;
+        let is_empty = file.metadata().map_or(true, |m| m.len() == 0);
+        let mut encoder = config
+            .codec
+            .encoder(BufWriter::new(file))
+            .with_context(|| format!("failed to start {} encoder for {}", config.codec, path))?;
+        Ok(match config.format {
+            MbpFormat::Json => FormatWriter::Json(encoder),
+            MbpFormat::Csv => {
+                if is_empty {
+                    writeln!(encoder, "{}", csv_header())?;
+                }
+                FormatWriter::Csv(encoder)
+            }
+            MbpFormat::Dbn => FormatWriter::Dbn(Mbp10Writer::new(encoder, is_empty)),
+        })
+    }
+
+    fn write(&mut self, snapshot: &SnapshotRecord, prices: PriceFormat) -> Result<()> {
+        match self {
+            FormatWriter::Json(encoder) => {
+                let json = serde_json::to_string(&snapshot_to_mbp_output(snapshot, prices))?;
+                writeln!(encoder, "{}", json)?;
+            }
+            FormatWriter::Csv(encoder) => writeln!(encoder, "{}", csv_row(snapshot, prices))?,
+            FormatWriter::Dbn(writer) => writer.write(snapshot)?,
+        }
+        Ok(())
+    }
+
+    fn encoder(&mut self) -> &mut FileEncoder {
+        match self {
+            FormatWriter::Json(encoder) | FormatWriter::Csv(encoder) => encoder,
+            FormatWriter::Dbn(writer) => writer.get_mut(),
+        }
+    }
+
+    /// Bytes on disk, which trail what was written by the buffered and compressed data.
+    fn file_len(&mut self) -> u64 {
+        self.encoder()
+            .get_ref()
+            .get_ref()
+            .metadata()
+            .map_or(0, |m| m.len())
+    }
+}
+
+/// `final_mbp.json.gz` with segment 3 is `final_mbp.3.json.gz`.
+fn segment_path(path: &Path, segment: u64) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let segment_name = match name.split_once('.') {
+        Some((stem, extensions)) => format!("{}.{}.{}", stem, segment, extensions),
+        None => format!("{}.{}", name, segment),
+    };
+    path.with_file_name(segment_name)
+}
+
+/// One past the highest segment already next to `path`, so segments of earlier runs
+/// are never overwritten.
+fn next_segment(path: &Path) -> u64 {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extensions) = name.split_once('.').unwrap_or((name.as_str(), ""));
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 1;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let entry_name = entry.file_name().to_string_lossy().into_owned();
+            let rest = entry_name.strip_prefix(stem)?.strip_prefix('.')?;
+            let number = if extensions.is_empty() {
+                rest
+            } else {
+                rest.strip_suffix(extensions)?.strip_suffix('.')?
+            };
+            number.parse::<u64>().ok()
+        })
+        .max()
+        .map_or(1, |last| last + 1)
+}
+
+fn csv_header() -> String {
+    let mut header = String::from("ts_event,instrument_id,symbol,seq,stale");
+    for i in 0..MBP10_LEVELS {
+        for side in ["bid", "ask"] {
+            let _ = write!(
+                header,
+                ",{side}_px_{i:02},{side}_sz_{i:02},{side}_ct_{i:02}"
+            );
+        }
+    }
+    header
+}
+
+/// Prices stay fixed-point as in the other outputs; missing levels are empty cells.
+fn csv_row(record: &SnapshotRecord, prices: PriceFormat) -> String {
+    let snapshot = &record.payload;
+    let mut row = format!(
+        "{},{},{},{},{}",
+        record.ts_event,
+        record.instrument_id,
+        csv_field(&snapshot.symbol),
+        snapshot.seq,
+        snapshot.stale
+    );
+    for i in 0..MBP10_LEVELS {
+        for level in [snapshot.bids.get(i), snapshot.asks.get(i)] {
+            match level {
+                Some(LevelEntry {
+                    price, size, count, ..
+                }) => {
+                    let _ = write!(row, ",{},{},{}", prices.format(*price), size, count);
+                }
+                None => row.push_str(",,,"),
+            }
+        }
+    }
+    row
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
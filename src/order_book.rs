@@ -1,25 +1,328 @@
 use std::{
-    collections::{BTreeMap, HashMap, VecDeque},
+    collections::{BTreeMap, HashMap},
     fmt::Display,
+    iter::Sum,
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use dbn::{
-    Publisher, UNDEF_PRICE,
+    FlagSet, Publisher, UNDEF_PRICE,
     enums::{Action, Side},
     pretty,
-    record::{BidAskPair, MboMsg, Record},
+    record::{BidAskPair, MboMsg, Record, RecordHeader},
+    rtype,
 };
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Default)]
 pub struct Market {
     books: HashMap<u32, Vec<(Publisher, Book)>>,
+    sequence_check: SequenceCheck,
+    stale_on_gap: bool,
+    // Last sequence seen per (publisher, channel)
+    last_sequence: HashMap<(u16, u8), u32>,
+    gaps: GapReport,
+    fills: FillHandling,
+    policies: BookPolicies,
+    depth_cap: DepthCap,
+    /// Books dropped by `remove_instrument`.
+    removed_books: u64,
 }
 
 #[derive(Debug, Default)]
 pub struct Book {
-    orders_by_id: HashMap<u64, (Side, i64)>,
+    orders_by_id: HashMap<u64, OrderRef>,
     offers: BTreeMap<i64, Level>,
     bids: BTreeMap<i64, Level>,
+    /// Storage for the orders of every level.
+    slab: OrderSlab,
+    stale: bool,
+    bbo_epoch: u64,
+    /// ts_event of the last record that changed the book.
+    ts_event: u64,
+    /// ts_event of the last record applied to the book, whether or not it changed it.
+    ts_seen: u64,
+    /// Implied top of book from a publisher whose `BookPolicy` is `Implied`, kept
+    /// apart from the outright orders.
+    implied_bid: Option<PriceLevel>,
+    implied_ask: Option<PriceLevel>,
+    depth_cap: DepthCap,
+    /// Size left of each tracked order `depth_cap` evicted, until the venue cancels
+    /// or fills it, so records for it are not errors.
+    evicted: HashMap<u64, u32>,
+    evicted_levels: u64,
+    evicted_orders: u64,
+    /// Cancels and fills of evicted orders passed over.
+    forgiven: u64,
+}
+
+/// How `Market::apply` validates `MboMsg::sequence`, tracked per publisher and channel.
+/// Records of one venue packet share a sequence, so repeats are always accepted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SequenceCheck {
+    Off,
+    /// Flag sequences that go backwards. Safe for files filtered to a few instruments,
+    /// where the channel's other instruments leave legitimate holes.
+    #[default]
+    Monotonic,
+    /// Also flag forward jumps. Only meaningful for complete channel feeds.
+    Contiguous,
+}
+
+/// Which publishers' fills reduce the resting order. Databento's MBO feeds follow each
+/// fill with the cancel or modify that shrinks the order, so fills are ignored by
+/// default; venues that report partial fills only through `Action::Fill` would
+/// otherwise leave their filled size on the book.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FillHandling {
+    #[default]
+    Ignore,
+    All,
+    Publishers(Vec<Publisher>),
+}
+
+impl FillHandling {
+    pub fn reduces(&self, publisher: Publisher) -> bool {
+        match self {
+            FillHandling::Ignore => false,
+            FillHandling::All => true,
+            FillHandling::Publishers(publishers) => publishers.contains(&publisher),
+        }
+    }
+}
+
+/// How `Market::apply` treats a publisher's top-of-book records (`F_TOB`). Venues
+/// that only publish their best level send them in place of orders, and each replaces
+/// its side of the book. On CME, top-of-book records on an outright carry implied
+/// liquidity derived from calendar spreads, which must not wipe the resting orders.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BookPolicy {
+    #[default]
+    Replace,
+    /// Kept as the book's implied top of book, beside the outright orders and never
+    /// merged into their levels or BBO.
+    Implied,
+    /// Dropped, leaving only the outright orders.
+    Ignore,
+}
+
+impl BookPolicy {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "replace" => Some(BookPolicy::Replace),
+            "implied" => Some(BookPolicy::Implied),
+            "ignore" => Some(BookPolicy::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// The `BookPolicy` of each publisher, `default` for those not listed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BookPolicies {
+    pub default: BookPolicy,
+    pub publishers: Vec<(Publisher, BookPolicy)>,
+}
+
+impl BookPolicies {
+    pub fn policy(&self, publisher: Publisher) -> BookPolicy {
+        self.publishers
+            .iter()
+            .find(|(listed, _)| *listed == publisher)
+            .map_or(self.default, |(_, policy)| *policy)
+    }
+}
+
+/// Bounds on each book's depth, to keep an instrument with pathological depth from
+/// growing without limit over a long run. Past either bound the levels furthest from
+/// the touch are evicted with their orders, and later records for those orders are
+/// passed over rather than rejected. A side's best level is never evicted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DepthCap {
+    pub max_levels_per_side: Option<usize>,
+    pub max_orders: Option<usize>,
+}
+
+/// Running count of sequence anomalies seen by a `Market`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GapReport {
+    /// Forward jumps (contiguous checking only).
+    pub gaps: u64,
+    /// Sequence numbers skipped over by those jumps.
+    pub missing: u64,
+    /// Sequences lower than one already seen.
+    pub regressions: u64,
+    pub last: Option<SequenceGap>,
+}
+
+impl GapReport {
+    pub fn total(&self) -> u64 {
+        self.gaps + self.regressions
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SequenceGap {
+    pub publisher_id: u16,
+    pub channel_id: u8,
+    pub instrument_id: u32,
+    pub expected: u32,
+    pub received: u32,
+    pub ts_event: u64,
+}
+
+/// What applying a record did to its book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApplyOutcome {
+    /// The book changed.
+    Updated,
+    /// Trades, fills and records without an action, which leave the book as it was.
+    Passthrough,
+}
+
+/// Why a record could not be applied consistently. These come off the wire, so the
+/// record is rejected and the book left as it was rather than aborting ingest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookError {
+    UnknownPublisher(u16),
+    UnknownAction(u8),
+    /// Side other than bid or ask on a record that changes the book.
+    InvalidSide {
+        order_id: u64,
+        side: u8,
+    },
+    /// Add or modify without a price, outside of top-of-book records.
+    UndefinedPrice {
+        order_id: u64,
+    },
+    /// Add of an order id that already rests in the book.
+    DuplicateOrder {
+        order_id: u64,
+    },
+    /// Cancel (or reducing fill) at a price with no level on that side.
+    UnknownLevel {
+        order_id: u64,
+        price: i64,
+    },
+    /// Cancel (or reducing fill) of an order not resting at its level.
+    UnknownOrder {
+        order_id: u64,
+        price: i64,
+    },
+    /// Cancel (or reducing fill) of more than the order has left.
+    OversizedCancel {
+        order_id: u64,
+        size: u32,
+        resting: u32,
+    },
+}
+
+impl BookError {
+    /// Name used for the error's counter and in logs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BookError::UnknownPublisher(_) => "unknown_publisher",
+            BookError::UnknownAction(_) => "unknown_action",
+            BookError::InvalidSide { .. } => "invalid_side",
+            BookError::UndefinedPrice { .. } => "undefined_price",
+            BookError::DuplicateOrder { .. } => "duplicate_order",
+            BookError::UnknownLevel { .. } => "unknown_level",
+            BookError::UnknownOrder { .. } => "unknown_order",
+            BookError::OversizedCancel { .. } => "oversized_cancel",
+        }
+    }
+}
+
+impl Display for BookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BookError::UnknownPublisher(id) => write!(f, "unknown publisher {}", id),
+            BookError::UnknownAction(action) => write!(f, "unknown action {}", action),
+            BookError::InvalidSide { order_id, side } => {
+                write!(f, "order {} has invalid side {}", order_id, side)
+            }
+            BookError::UndefinedPrice { order_id } => {
+                write!(f, "order {} has an undefined price", order_id)
+            }
+            BookError::DuplicateOrder { order_id } => {
+                write!(f, "order {} is already in the book", order_id)
+            }
+            BookError::UnknownLevel { order_id, price } => {
+                write!(f, "cancel of order {} at missing level {}", order_id, price)
+            }
+            BookError::UnknownOrder { order_id, price } => {
+                write!(f, "order {} is not resting at {}", order_id, price)
+            }
+            BookError::OversizedCancel {
+                order_id,
+                size,
+                resting,
+            } => write!(
+                f,
+                "cancel of {} from order {} with {} resting",
+                size, order_id, resting
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BookError {}
+
+/// Records rejected by `Market::apply`, by kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BookErrorCounts {
+    pub unknown_publisher: u64,
+    pub unknown_action: u64,
+    pub invalid_side: u64,
+    pub undefined_price: u64,
+    pub duplicate_order: u64,
+    pub unknown_level: u64,
+    pub unknown_order: u64,
+    pub oversized_cancel: u64,
+}
+
+impl BookErrorCounts {
+    /// Counts `error`, returning true for the first of its kind.
+    pub fn record(&mut self, error: &BookError) -> bool {
+        let count = match error {
+            BookError::UnknownPublisher(_) => &mut self.unknown_publisher,
+            BookError::UnknownAction(_) => &mut self.unknown_action,
+            BookError::InvalidSide { .. } => &mut self.invalid_side,
+            BookError::UndefinedPrice { .. } => &mut self.undefined_price,
+            BookError::DuplicateOrder { .. } => &mut self.duplicate_order,
+            BookError::UnknownLevel { .. } => &mut self.unknown_level,
+            BookError::UnknownOrder { .. } => &mut self.unknown_order,
+            BookError::OversizedCancel { .. } => &mut self.oversized_cancel,
+        };
+        *count += 1;
+        *count == 1
+    }
+
+    pub fn total(&self) -> u64 {
+        self.unknown_publisher
+            + self.unknown_action
+            + self.invalid_side
+            + self.undefined_price
+            + self.duplicate_order
+            + self.unknown_level
+            + self.unknown_order
+            + self.oversized_cancel
+    }
+}
+
+impl Sum for BookErrorCounts {
+    fn sum<I: Iterator<Item = BookErrorCounts>>(iter: I) -> Self {
+        iter.fold(BookErrorCounts::default(), |acc, counts| BookErrorCounts {
+            unknown_publisher: acc.unknown_publisher + counts.unknown_publisher,
+            unknown_action: acc.unknown_action + counts.unknown_action,
+            invalid_side: acc.invalid_side + counts.invalid_side,
+            undefined_price: acc.undefined_price + counts.undefined_price,
+            duplicate_order: acc.duplicate_order + counts.duplicate_order,
+            unknown_level: acc.unknown_level + counts.unknown_level,
+            unknown_order: acc.unknown_order + counts.unknown_order,
+            oversized_cancel: acc.oversized_cancel + counts.oversized_cancel,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -29,13 +332,483 @@ pub struct PriceLevel {
     pub count: u32,
 }
 
-type Level = VecDeque<MboMsg>;
+/// A resting order as a level keeps it. Side and price are the level's, and the rest of
+/// the `MboMsg` it came from plays no part in the book.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Order {
+    pub order_id: u64,
+    /// ts_event of the record that gave the order its place in the queue.
+    pub ts_event: u64,
+    pub size: u32,
+    pub flags: FlagSet,
+}
+
+/// Order slab usage and size of a market's books, summed, served under `/metrics`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct PoolStats {
+    /// Slots ever allocated. They stay with their book for reuse, so this is the peak
+    /// number of orders resting at once.
+    pub allocated: u64,
+    /// Orders placed in a slot an earlier order freed, i.e. without allocating.
+    pub recycled: u64,
+    /// Slots holding a resting order.
+    pub live: u64,
+    /// Slots waiting for reuse.
+    pub free: u64,
+    /// Levels, and the orders resting at them, evicted by the `DepthCap`.
+    pub evicted_levels: u64,
+    pub evicted_orders: u64,
+    /// Cancels and fills of evicted orders, passed over without changing the book.
+    pub forgiven: u64,
+    pub books: u64,
+    /// Estimated heap bytes of the books, see `Book::memory_bytes`.
+    pub memory_bytes: u64,
+    /// Books dropped by `Market::remove_instrument`, e.g. once idle.
+    pub removed_books: u64,
+}
+
+impl Sum for PoolStats {
+    fn sum<I: Iterator<Item = PoolStats>>(iter: I) -> Self {
+        iter.fold(PoolStats::default(), |total, stats| PoolStats {
+            allocated: total.allocated + stats.allocated,
+            recycled: total.recycled + stats.recycled,
+            live: total.live + stats.live,
+            free: total.free + stats.free,
+            evicted_levels: total.evicted_levels + stats.evicted_levels,
+            evicted_orders: total.evicted_orders + stats.evicted_orders,
+            forgiven: total.forgiven + stats.forgiven,
+            books: total.books + stats.books,
+            memory_bytes: total.memory_bytes + stats.memory_bytes,
+            removed_books: total.removed_books + stats.removed_books,
+        })
+    }
+}
+
+/// The latest `PoolStats` published by the ingest loop, for readers on other threads.
+#[derive(Debug, Default)]
+pub struct PoolMetrics {
+    allocated: AtomicU64,
+    recycled: AtomicU64,
+    live: AtomicU64,
+    free: AtomicU64,
+    evicted_levels: AtomicU64,
+    evicted_orders: AtomicU64,
+    forgiven: AtomicU64,
+    books: AtomicU64,
+    memory_bytes: AtomicU64,
+    removed_books: AtomicU64,
+}
+
+/// Index of an order in its book's `OrderSlab`.
+type Slot = u32;
+
+/// Where a tracked order rests. `slot` is only trusted while the slab still holds the
+/// order there, since a top-of-book record can clear a side under tracked orders.
+#[derive(Clone, Copy, Debug)]
+struct OrderRef {
+    side: Side,
+    price: i64,
+    slot: Slot,
+}
+
+/// The orders resting at one price, in queue order, with their aggregate kept up to
+/// date as orders are added, resized and removed, so reading a level does not walk it.
+///
+/// The queue is a doubly linked list through the book's `OrderSlab`, so an order
+/// located through `orders_by_id` is resized or unlinked without scanning its level.
+#[derive(Debug, Default)]
+struct Level {
+    head: Option<Slot>,
+    tail: Option<Slot>,
+    /// Sum of the orders' sizes. Wider than `PriceLevel::size` so the running total
+    /// stays exact; reads saturate as summing the queue did.
+    size: u64,
+    /// Orders counted by `PriceLevel::count`, i.e. excluding top-of-book records.
+    count: u32,
+}
+
+/// Pool of order entries for one book. Slots freed by cancels, fills and clears are
+/// reused before `nodes` grows, so a busy book stops allocating once it has reached its
+/// peak size. Levels own no allocation themselves, as their queues live here.
+#[derive(Debug, Default)]
+struct OrderSlab {
+    nodes: Vec<Option<OrderNode>>,
+    /// Vacant slots, reused before `nodes` grows.
+    free: Vec<Slot>,
+    /// Inserts served from `free`.
+    recycled: u64,
+}
+
+#[derive(Debug)]
+struct OrderNode {
+    order: Order,
+    prev: Option<Slot>,
+    next: Option<Slot>,
+}
+
+/// Result of sweeping one side of a book for `size`, as a marketable order would.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionCost {
+    pub size: u64,
+    /// Volume-weighted average price of the filled part, rounded to the nearest
+    /// fixed-point unit; `None` when the side is empty.
+    pub avg_price: Option<i64>,
+    /// Quantity left unfilled once every level was taken.
+    pub residual: u64,
+}
+
+impl ExecutionCost {
+    /// Fills `size` from `levels` of `(price, size)`, best price first.
+    pub fn sweep(size: u64, levels: impl IntoIterator<Item = (i64, u32)>) -> Self {
+        let mut remaining = size;
+        let mut notional = 0i128;
+        for (price, level_size) in levels {
+            if remaining == 0 {
+                break;
+            }
+            let take = remaining.min(level_size as u64);
+            notional += price as i128 * take as i128;
+            remaining -= take;
+        }
+        let filled = (size - remaining) as i128;
+        Self {
+            size,
+            avg_price: (filled > 0).then(|| (notional + filled / 2).div_euclid(filled) as i64),
+            residual: remaining,
+        }
+    }
+}
+
+/// Displayed size within `bps` basis points of the mid price on each side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthWithin {
+    pub bps: u32,
+    pub bid_size: u64,
+    pub ask_size: u64,
+}
+
+impl DepthWithin {
+    /// Measures `bids` (best first, descending) and `asks` (best first, ascending) of
+    /// `(price, size)`. `None` unless both sides are quoted, since there is no mid.
+    pub fn measure(
+        bps: u32,
+        bids: impl IntoIterator<Item = (i64, u32)>,
+        asks: impl IntoIterator<Item = (i64, u32)>,
+    ) -> Option<Self> {
+        let mut bids = bids.into_iter().peekable();
+        let mut asks = asks.into_iter().peekable();
+        let (best_bid, _) = *bids.peek()?;
+        let (best_ask, _) = *asks.peek()?;
+        let mid = (best_bid as i128 + best_ask as i128) / 2;
+        let band = mid.abs() * bps as i128 / 10_000;
+        Some(Self {
+            bps,
+            bid_size: bids
+                .take_while(|(price, _)| *price as i128 >= mid - band)
+                .map(|(_, size)| size as u64)
+                .sum(),
+            ask_size: asks
+                .take_while(|(price, _)| *price as i128 <= mid + band)
+                .map(|(_, size)| size as u64)
+                .sum(),
+        })
+    }
+}
+
+/// Levels of one side coalesced into a price bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthBucket {
+    /// Bucket edge nearest the other side: the lower edge for bids, the upper for
+    /// asks, so it is never a better price than the levels inside.
+    pub price: i64,
+    pub size: u64,
+    pub count: u32,
+    /// Non-empty levels in the bucket.
+    pub levels: u32,
+}
+
+/// Both sides of a book in price buckets `width` wide, best first.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BucketedDepth {
+    pub width: i64,
+    pub bids: Vec<DepthBucket>,
+    pub asks: Vec<DepthBucket>,
+}
+
+impl BucketedDepth {
+    /// Buckets `bids` (best first, descending) and `asks` (best first, ascending) of
+    /// `(price, size, count)`, keeping at most `max_buckets` per side. Bucket edges are
+    /// multiples of `width`, so buckets line up across snapshots.
+    pub fn aggregate(
+        width: i64,
+        max_buckets: usize,
+        bids: impl IntoIterator<Item = (i64, u32, u32)>,
+        asks: impl IntoIterator<Item = (i64, u32, u32)>,
+    ) -> Self {
+        Self {
+            width,
+            bids: bucket_side(bids, max_buckets, |price| price - price.rem_euclid(width)),
+            asks: bucket_side(asks, max_buckets, |price| {
+                let offset = price.rem_euclid(width);
+                if offset == 0 {
+                    price
+                } else {
+                    price + (width - offset)
+                }
+            }),
+        }
+    }
+}
+
+fn bucket_side(
+    levels: impl IntoIterator<Item = (i64, u32, u32)>,
+    max_buckets: usize,
+    edge: impl Fn(i64) -> i64,
+) -> Vec<DepthBucket> {
+    let mut buckets: Vec<DepthBucket> = Vec::new();
+    for (price, size, count) in levels {
+        let price = edge(price);
+        if let Some(bucket) = buckets.last_mut()
+            && bucket.price == price
+        {
+            bucket.size += size as u64;
+            bucket.count += count;
+            bucket.levels += 1;
+            continue;
+        }
+        if buckets.len() == max_buckets {
+            break;
+        }
+        buckets.push(DepthBucket {
+            price,
+            size: size as u64,
+            count,
+            levels: 1,
+        });
+    }
+    buckets
+}
+
+/// Full depth of every publisher's book for one instrument merged into a single
+/// ladder, with size and count summed at equal prices.
+#[derive(Clone, Debug, Default)]
+pub struct ConsolidatedBook {
+    /// Best first, descending.
+    pub bids: Vec<PriceLevel>,
+    /// Best first, ascending.
+    pub asks: Vec<PriceLevel>,
+    pub total_orders: usize,
+}
+
+impl ConsolidatedBook {
+    pub fn bbo(&self) -> (Option<PriceLevel>, Option<PriceLevel>) {
+        (self.bids.first().cloned(), self.asks.first().cloned())
+    }
+
+    pub fn cost_to_buy(&self, size: u64) -> ExecutionCost {
+        ExecutionCost::sweep(size, self.asks.iter().map(|l| (l.price, l.size)))
+    }
+
+    pub fn cost_to_sell(&self, size: u64) -> ExecutionCost {
+        ExecutionCost::sweep(size, self.bids.iter().map(|l| (l.price, l.size)))
+    }
+
+    pub fn depth_within_bps(&self, bps: u32) -> Option<DepthWithin> {
+        DepthWithin::measure(
+            bps,
+            self.bids.iter().map(|l| (l.price, l.size)),
+            self.asks.iter().map(|l| (l.price, l.size)),
+        )
+    }
+
+    pub fn aggregate_by_ticks(
+        &self,
+        tick_size: i64,
+        bucket_ticks: u32,
+        max_buckets: usize,
+    ) -> BucketedDepth {
+        BucketedDepth::aggregate(
+            tick_size.saturating_mul(bucket_ticks as i64),
+            max_buckets,
+            self.bids.iter().map(|l| (l.price, l.size, l.count)),
+            self.asks.iter().map(|l| (l.price, l.size, l.count)),
+        )
+    }
+}
+
+/// Sums `levels` from several books into one ladder ordered by price ascending.
+fn merge_levels(levels: impl Iterator<Item = PriceLevel>) -> BTreeMap<i64, PriceLevel> {
+    let mut merged: BTreeMap<i64, PriceLevel> = BTreeMap::new();
+    for level in levels {
+        merged
+            .entry(level.price)
+            .and_modify(|total| {
+                total.size = total.size.saturating_add(level.size);
+                total.count = total.count.saturating_add(level.count);
+            })
+            .or_insert(level);
+    }
+    merged
+}
 
 impl Market {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// With `stale_on_gap`, an anomaly marks every book of the publisher stale until a
+    /// Clear (which also starts a venue snapshot) rebuilds it.
+    pub fn with_sequence_check(mut self, check: SequenceCheck, stale_on_gap: bool) -> Self {
+        self.sequence_check = check;
+        self.stale_on_gap = stale_on_gap;
+        self
+    }
+
+    pub fn with_fill_handling(mut self, fills: FillHandling) -> Self {
+        self.fills = fills;
+        self
+    }
+
+    pub fn with_book_policies(mut self, policies: BookPolicies) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    /// Caps every book, including those already built, e.g. from a checkpoint. Their
+    /// depth is trimmed by the next record that adds to them.
+    pub fn with_depth_cap(mut self, cap: DepthCap) -> Self {
+        self.depth_cap = cap;
+        for book in self.books.values_mut().flatten() {
+            book.1.depth_cap = cap;
+        }
+        self
+    }
+
+    pub fn gap_report(&self) -> &GapReport {
+        &self.gaps
+    }
+
+    /// True when any publisher's book for the instrument may be missing updates.
+    pub fn is_stale(&self, instrument_id: u32) -> bool {
+        self.books_by_pub(instrument_id)
+            .is_some_and(|books| books.iter().any(|(_, book)| book.stale))
+    }
+
+    /// Order slab usage of every book.
+    pub fn pool_stats(&self) -> PoolStats {
+        let stats: PoolStats = self
+            .iter_books()
+            .map(|(_, _, book)| book.pool_stats())
+            .sum();
+        PoolStats {
+            removed_books: self.removed_books,
+            ..stats
+        }
+    }
+
+    /// Instruments with at least one book, in no particular order.
+    pub fn instrument_ids(&self) -> impl Iterator<Item = u32> + '_ {
+        self.books.keys().copied()
+    }
+
+    /// Number of instruments with books.
+    pub fn len(&self) -> usize {
+        self.books.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+
+    /// Estimated heap bytes of every book, see `Book::memory_bytes`.
+    pub fn memory_bytes(&self) -> usize {
+        self.iter_books()
+            .map(|(_, _, book)| book.memory_bytes())
+            .sum()
+    }
+
+    /// Drops every publisher's book for the instrument, returning false when it had
+    /// none. Sequence tracking is per channel and carries on. A later record for the
+    /// instrument starts a new, empty book.
+    pub fn remove_instrument(&mut self, instrument_id: u32) -> bool {
+        let Some(books) = self.books.remove(&instrument_id) else {
+            return false;
+        };
+        self.removed_books += books.len() as u64;
+        true
+    }
+
+    /// Instruments none of whose books has seen a record in the `idle_ns` before
+    /// `now` (ts_event), sorted by id. Only instruments whose books are all empty are
+    /// idle, since dropping resting orders would leave their later records unknown.
+    pub fn idle_instruments(&self, now: u64, idle_ns: u64) -> Vec<u32> {
+        let mut idle: Vec<u32> = self
+            .books
+            .iter()
+            .filter(|(_, books)| {
+                books
+                    .iter()
+                    .all(|(_, book)| book.is_empty() && book.ts_seen.saturating_add(idle_ns) <= now)
+            })
+            .map(|(instrument_id, _)| *instrument_id)
+            .collect();
+        idle.sort_unstable();
+        idle
+    }
+
+    /// Every book as (instrument_id, publisher, book), in no particular order.
+    pub fn iter_books(&self) -> impl Iterator<Item = (u32, Publisher, &Book)> {
+        self.books.iter().flat_map(|(instrument_id, books)| {
+            books
+                .iter()
+                .map(move |(publisher, book)| (*instrument_id, *publisher, book))
+        })
+    }
+
+    /// Last sequence seen per (publisher_id, channel_id).
+    pub fn last_sequences(&self) -> impl Iterator<Item = ((u16, u8), u32)> + '_ {
+        self.last_sequence.iter().map(|(key, seq)| (*key, *seq))
+    }
+
+    /// Returns the book for the instrument and publisher, creating it if needed.
+    /// Used to rebuild a market from a checkpoint.
+    pub(crate) fn book_mut(&mut self, instrument_id: u32, publisher: Publisher) -> &mut Book {
+        let books = self.books.entry(instrument_id).or_default();
+        match books
+            .iter()
+            .position(|(book_pub, _)| *book_pub == publisher)
+        {
+            Some(idx) => &mut books[idx].1,
+            None => {
+                let book = Book {
+                    depth_cap: self.depth_cap,
+                    ..Book::default()
+                };
+                books.push((publisher, book));
+                &mut books.last_mut().unwrap().1
+            }
+        }
+    }
+
+    /// Restores sequence tracking from a checkpoint so checks continue where they left off.
+    pub(crate) fn restore_sequences(
+        &mut self,
+        sequences: impl IntoIterator<Item = ((u16, u8), u32)>,
+        gaps: GapReport,
+    ) {
+        self.last_sequence.extend(sequences);
+        self.gaps = gaps;
+    }
+
+    /// Changes whenever any publisher's top of book for the instrument changes.
+    pub fn bbo_epoch(&self, instrument_id: u32) -> u64 {
+        self.books_by_pub(instrument_id).map_or(0, |books| {
+            books
+                .iter()
+                .fold(0u64, |epoch, (_, book)| epoch.wrapping_add(book.bbo_epoch))
+        })
+    }
+
     pub fn books_by_pub(&self, instrument_id: u32) -> Option<&[(Publisher, Book)]> {
         self.books
             .get(&instrument_id)
@@ -64,20 +837,34 @@ impl Market {
     }
 
     pub fn aggregated_bbo(&self, instrument_id: u32) -> (Option<PriceLevel>, Option<PriceLevel>) {
+        self.merged_touch(instrument_id, Book::bbo)
+    }
+
+    /// Best implied bid and ask across the instrument's publishers, summed where they
+    /// share a price. Only publishers with `BookPolicy::Implied` have any.
+    pub fn implied_bbo(&self, instrument_id: u32) -> (Option<PriceLevel>, Option<PriceLevel>) {
+        self.merged_touch(instrument_id, Book::implied_bbo)
+    }
+
+    fn merged_touch(
+        &self,
+        instrument_id: u32,
+        touch: impl Fn(&Book) -> (Option<PriceLevel>, Option<PriceLevel>),
+    ) -> (Option<PriceLevel>, Option<PriceLevel>) {
         let mut agg_bid = None;
         let mut agg_ask = None;
         let Some(books_by_pub) = self.books_by_pub(instrument_id) else {
             return (None, None);
         };
         for (_, book) in books_by_pub.iter() {
-            let (bid, ask) = book.bbo();
+            let (bid, ask) = touch(book);
             if let Some(bid) = bid {
                 match &mut agg_bid {
                     None => agg_bid = Some(bid),
                     Some(ab) if bid.price > ab.price => agg_bid = Some(bid),
                     Some(ab) if bid.price == ab.price => {
-                        ab.size += bid.size;
-                        ab.count += bid.count;
+                        ab.size = ab.size.saturating_add(bid.size);
+                        ab.count = ab.count.saturating_add(bid.count);
                     }
                     Some(_) => {}
                 }
@@ -87,8 +874,8 @@ impl Market {
                     None => agg_ask = Some(ask),
                     Some(aa) if ask.price < aa.price => agg_ask = Some(ask),
                     Some(aa) if ask.price == aa.price => {
-                        aa.size += ask.size;
-                        aa.count += ask.count;
+                        aa.size = aa.size.saturating_add(ask.size);
+                        aa.count = aa.count.saturating_add(ask.count);
                     }
                     Some(_) => {}
                 }
@@ -97,19 +884,97 @@ impl Market {
         (agg_bid, agg_ask)
     }
 
-    pub fn apply(&mut self, mbo: MboMsg) -> bool {
-        let publisher = mbo.publisher().unwrap();
-        let books = self.books.entry(mbo.hd.instrument_id).or_default();
-        let book = if let Some((_, book)) = books
-            .iter_mut()
-            .find(|(book_pub, _)| *book_pub == publisher)
+    /// Every publisher's full depth for the instrument merged into one ladder. Its top
+    /// of book matches `aggregated_bbo`.
+    pub fn consolidated_book(&self, instrument_id: u32) -> Option<ConsolidatedBook> {
+        let books = self.books_by_pub(instrument_id)?;
+        let bids = merge_levels(books.iter().flat_map(|(_, book)| book.iter_bids_desc()));
+        let asks = merge_levels(books.iter().flat_map(|(_, book)| book.iter_asks_asc()));
+        Some(ConsolidatedBook {
+            bids: bids.into_values().rev().collect(),
+            asks: asks.into_values().collect(),
+            total_orders: books.iter().map(|(_, book)| book.total_orders()).sum(),
+        })
+    }
+
+    /// Applies `mbo` to its book. Malformed or inconsistent records are rejected with
+    /// the reason and leave the books unchanged.
+    pub fn apply(&mut self, mbo: MboMsg) -> Result<ApplyOutcome, BookError> {
+        let Ok(publisher) = mbo.publisher() else {
+            return Err(BookError::UnknownPublisher(mbo.hd.publisher_id));
+        };
+        if self.sequence_check != SequenceCheck::Off
+            && self.check_sequence(&mbo)
+            && self.stale_on_gap
+        {
+            self.mark_stale(publisher);
+        }
+        let action = mbo.action();
+        let policy = self.policies.policy(publisher);
+        let fill = matches!(action, Ok(Action::Fill)) && self.fills.reduces(publisher);
+        let book = self.book_mut(mbo.hd.instrument_id, publisher);
+        book.ts_seen = book.ts_seen.max(mbo.hd.ts_event);
+        if mbo.flags.is_tob() && matches!(action, Ok(Action::Add | Action::Modify | Action::Cancel))
         {
-            book
+            match policy {
+                BookPolicy::Replace => {}
+                BookPolicy::Implied => return book.apply_implied(mbo),
+                BookPolicy::Ignore => return Ok(ApplyOutcome::Passthrough),
+            }
+        }
+        if fill {
+            book.apply_fill(mbo)
         } else {
-            books.push((publisher, Book::default()));
-            &mut books.last_mut().unwrap().1
+            book.apply(mbo)
+        }
+    }
+
+    /// Runs the sequence check on a record without applying it, for a reader handing
+    /// records to books on other threads. Returns true when `mbo` breaks the sequence.
+    pub fn observe_sequence(&mut self, mbo: &MboMsg) -> bool {
+        self.sequence_check != SequenceCheck::Off
+            && mbo.publisher().is_ok()
+            && self.check_sequence(mbo)
+    }
+
+    /// Returns true when `mbo` breaks the expected sequence.
+    fn check_sequence(&mut self, mbo: &MboMsg) -> bool {
+        let key = (mbo.hd.publisher_id, mbo.channel_id);
+        let Some(last) = self.last_sequence.insert(key, mbo.sequence) else {
+            return false;
         };
-        book.apply(mbo)
+        let expected = last.wrapping_add(1);
+        let broken = if mbo.sequence < last {
+            self.gaps.regressions += 1;
+            true
+        } else if mbo.sequence > expected && self.sequence_check == SequenceCheck::Contiguous {
+            self.gaps.gaps += 1;
+            self.gaps.missing += (mbo.sequence - expected) as u64;
+            true
+        } else {
+            false
+        };
+        if broken {
+            self.gaps.last = Some(SequenceGap {
+                publisher_id: mbo.hd.publisher_id,
+                channel_id: mbo.channel_id,
+                instrument_id: mbo.hd.instrument_id,
+                expected,
+                received: mbo.sequence,
+                ts_event: mbo.hd.ts_event,
+            });
+        }
+        broken
+    }
+
+    fn mark_stale(&mut self, publisher: Publisher) {
+        for books in self.books.values_mut() {
+            for (book_pub, book) in books.iter_mut() {
+                if *book_pub == publisher {
+                    book.stale = true;
+                }
+            }
+        }
     }
 }
 
@@ -122,39 +987,205 @@ impl Book {
         (self.bid_level(0), self.ask_level(0))
     }
 
+    /// The implied bid and ask from top-of-book records under `BookPolicy::Implied`.
+    /// They are not checkpointed; the venue's next implied update restores them.
+    pub fn implied_bbo(&self) -> (Option<PriceLevel>, Option<PriceLevel>) {
+        (self.implied_bid.clone(), self.implied_ask.clone())
+    }
+
+    /// Best bid and ask prices, without summing the orders at each level.
+    pub fn best_prices(&self) -> (Option<i64>, Option<i64>) {
+        (
+            self.bids.keys().next_back().copied(),
+            self.offers.keys().next().copied(),
+        )
+    }
+
     pub fn bid_level(&self, idx: usize) -> Option<PriceLevel> {
         self.bids
             .iter()
             // Reverse to get highest first
             .rev()
             .nth(idx)
-            .map(|(price, orders)| PriceLevel::new(*price, orders.iter()))
+            .map(|(price, level)| level.aggregate(*price))
     }
 
     pub fn ask_level(&self, idx: usize) -> Option<PriceLevel> {
         self.offers
             .iter()
             .nth(idx)
-            .map(|(price, orders)| PriceLevel::new(*price, orders.iter()))
+            .map(|(price, level)| level.aggregate(*price))
     }
 
     pub fn iter_bids_desc(&self) -> impl Iterator<Item = PriceLevel> + '_ {
         self.bids
             .iter()
             .rev()
-            .map(|(price, orders)| PriceLevel::new(*price, orders.iter()))
+            .map(|(price, level)| level.aggregate(*price))
     }
 
     pub fn iter_asks_asc(&self) -> impl Iterator<Item = PriceLevel> + '_ {
         self.offers
             .iter()
-            .map(|(price, orders)| PriceLevel::new(*price, orders.iter()))
+            .map(|(price, level)| level.aggregate(*price))
+    }
+
+    /// Cost of a buy order for `size` taking the asks.
+    pub fn cost_to_buy(&self, size: u64) -> ExecutionCost {
+        ExecutionCost::sweep(size, self.iter_asks_asc().map(|l| (l.price, l.size)))
+    }
+
+    /// Cost of a sell order for `size` taking the bids.
+    pub fn cost_to_sell(&self, size: u64) -> ExecutionCost {
+        ExecutionCost::sweep(size, self.iter_bids_desc().map(|l| (l.price, l.size)))
+    }
+
+    pub fn depth_within_bps(&self, bps: u32) -> Option<DepthWithin> {
+        DepthWithin::measure(
+            bps,
+            self.iter_bids_desc().map(|l| (l.price, l.size)),
+            self.iter_asks_asc().map(|l| (l.price, l.size)),
+        )
+    }
+
+    /// Levels coalesced into buckets of `bucket_ticks` ticks of `tick_size`, at most
+    /// `max_buckets` per side. Only the levels those buckets need are walked.
+    pub fn aggregate_by_ticks(
+        &self,
+        tick_size: i64,
+        bucket_ticks: u32,
+        max_buckets: usize,
+    ) -> BucketedDepth {
+        BucketedDepth::aggregate(
+            tick_size.saturating_mul(bucket_ticks as i64),
+            max_buckets,
+            self.iter_bids_desc().map(|l| (l.price, l.size, l.count)),
+            self.iter_asks_asc().map(|l| (l.price, l.size, l.count)),
+        )
+    }
+
+    /// Total displayed size at `price` on `side`, 0 when there is no such level.
+    pub fn level_size(&self, side: Side, price: i64) -> u32 {
+        self.side_levels(side)
+            .get(&price)
+            .map_or(0, |level| level.aggregate(price).size)
+    }
+
+    /// True after a sequence gap until the book is cleared and rebuilt.
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// ts_event of the last record that changed the book; for a book restored from a
+    /// checkpoint, of its newest order until then.
+    pub fn ts_event(&self) -> u64 {
+        self.ts_event
+    }
+
+    /// Resting bids as (price, order), from the best price down, each level in queue
+    /// order.
+    pub fn iter_bid_orders(&self) -> impl Iterator<Item = (i64, &Order)> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .flat_map(|(&price, level)| level.iter(&self.slab).map(move |order| (price, order)))
+    }
+
+    /// Resting asks as (price, order), from the best price up, each level in queue order.
+    pub fn iter_ask_orders(&self) -> impl Iterator<Item = (i64, &Order)> + '_ {
+        self.offers
+            .iter()
+            .flat_map(|(&price, level)| level.iter(&self.slab).map(move |order| (price, order)))
+    }
+
+    /// Resting orders as add records, bids then asks from the lowest price, each level
+    /// in queue order. Re-adding them in this order with `restore_order` rebuilds the
+    /// book. Records carry no instrument or publisher; the book's owner knows those.
+    pub fn resting_orders(&self) -> impl Iterator<Item = MboMsg> + '_ {
+        let bids = self
+            .bids
+            .iter()
+            .map(|(price, level)| (Side::Bid, price, level));
+        let asks = self
+            .offers
+            .iter()
+            .map(|(price, level)| (Side::Ask, price, level));
+        bids.chain(asks).flat_map(|(side, &price, level)| {
+            level
+                .iter(&self.slab)
+                .map(move |order| order.to_add(side, price))
+        })
+    }
+
+    /// Appends an order to the back of its level, as captured by `resting_orders`.
+    pub(crate) fn restore_order(&mut self, mbo: MboMsg) -> Result<(), BookError> {
+        let side = book_side(&mbo)?;
+        // Top-of-book records replace the side and are not tracked by order id
+        let tracked = !mbo.flags.is_tob();
+        if tracked && self.orders_by_id.contains_key(&mbo.order_id) {
+            return Err(BookError::DuplicateOrder {
+                order_id: mbo.order_id,
+            });
+        }
+        let (order_id, price) = (mbo.order_id, mbo.price);
+        self.ts_event = self.ts_event.max(mbo.hd.ts_event);
+        self.ts_seen = self.ts_event;
+        let slot = self.push_order(side, price, Order::from(&mbo));
+        if tracked {
+            self.orders_by_id
+                .insert(order_id, OrderRef { side, price, slot });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set_stale(&mut self, stale: bool) {
+        self.stale = stale;
+    }
+
+    pub fn pool_stats(&self) -> PoolStats {
+        let allocated = self.slab.nodes.len() as u64;
+        let free = self.slab.free.len() as u64;
+        PoolStats {
+            allocated,
+            recycled: self.slab.recycled,
+            live: allocated - free,
+            free,
+            evicted_levels: self.evicted_levels,
+            evicted_orders: self.evicted_orders,
+            forgiven: self.forgiven,
+            books: 1,
+            memory_bytes: self.memory_bytes() as u64,
+            removed_books: 0,
+        }
+    }
+
+    /// Rough heap bytes held by the book: its order slab, order id indexes and levels.
+    /// Hash table and B-tree overheads are approximated from their capacity and length,
+    /// so this is an estimate for spotting books that grow, not an exact count.
+    pub fn memory_bytes(&self) -> usize {
+        let slab = self.slab.nodes.capacity() * size_of::<Option<OrderNode>>()
+            + self.slab.free.capacity() * size_of::<Slot>();
+        // One control byte per bucket besides the entry
+        let index = self.orders_by_id.capacity() * (size_of::<(u64, OrderRef)>() + 1)
+            + self.evicted.capacity() * (size_of::<(u64, u32)>() + 1);
+        // B-tree nodes run from half to fully occupied
+        let levels = (self.bids.len() + self.offers.len()) * size_of::<(i64, Level)>() * 3 / 2;
+        size_of::<Book>() + slab + index + levels
     }
 
     pub fn total_orders(&self) -> usize {
         self.orders_by_id.len()
     }
 
+    /// Whether nothing rests in the book: no orders, top-of-book records or implied
+    /// levels.
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty()
+            && self.offers.is_empty()
+            && self.implied_bid.is_none()
+            && self.implied_ask.is_none()
+    }
+
     pub fn bid_level_count(&self) -> usize {
         self.bids.len()
     }
@@ -164,46 +1195,50 @@ impl Book {
     }
 
     pub fn bid_level_by_px(&self, px: i64) -> Option<PriceLevel> {
-        self.bids
-            .get(&px)
-            .map(|orders| PriceLevel::new(px, orders.iter()))
+        self.bids.get(&px).map(|level| level.aggregate(px))
     }
 
     pub fn ask_level_by_px(&self, px: i64) -> Option<PriceLevel> {
-        self.offers
-            .get(&px)
-            .map(|orders| PriceLevel::new(px, orders.iter()))
+        self.offers.get(&px).map(|level| level.aggregate(px))
     }
 
-    pub fn order(&self, order_id: u64) -> Option<&MboMsg> {
-        let (side, price) = self.orders_by_id.get(&order_id)?;
-        let levels = self.side_levels(*side);
-        let level = levels.get(price)?;
-        level.iter().find(|order| order.order_id == order_id)
+    pub fn order(&self, order_id: u64) -> Option<&Order> {
+        let order = self.locate(order_id)?;
+        Some(&self.slab.node(order.slot).order)
     }
 
-    pub fn queue_pos(&self, order_id: u64) -> Option<u32> {
-        let (side, price) = self.orders_by_id.get(&order_id)?;
-        let levels = self.side_levels(*side);
-        let level = levels.get(price)?;
+    /// Side and price of the level a tracked order rests at.
+    pub fn order_level(&self, order_id: u64) -> Option<(Side, i64)> {
+        let order = self.locate(order_id)?;
+        Some((order.side, order.price))
+    }
+
+    /// Size resting ahead of the order at its price, summed as wide as `Level::size`.
+    pub fn queue_pos(&self, order_id: u64) -> Option<u64> {
+        let order = self.locate(order_id)?;
+        let level = self.side_levels(order.side).get(&order.price)?;
         Some(
             level
-                .iter()
+                .iter(&self.slab)
                 .take_while(|order| order.order_id != order_id)
-                .fold(0, |acc, order| acc + order.size),
+                .map(|order| order.size as u64)
+                .sum(),
         )
     }
 
+    /// The top `level_count` levels per side, walking each side once.
     pub fn snapshot(&self, level_count: usize) -> Vec<BidAskPair> {
+        let mut bids = self.iter_bids_desc();
+        let mut asks = self.iter_asks_asc();
         (0..level_count)
-            .map(|i| {
+            .map(|_| {
                 let mut ba_pair = BidAskPair::default();
-                if let Some(bid) = self.bid_level(i) {
+                if let Some(bid) = bids.next() {
                     ba_pair.bid_px = bid.price;
                     ba_pair.bid_sz = bid.size;
                     ba_pair.bid_ct = bid.count;
                 }
-                if let Some(ask) = self.ask_level(i) {
+                if let Some(ask) = asks.next() {
                     ba_pair.ask_px = ask.price;
                     ba_pair.ask_sz = ask.size;
                     ba_pair.ask_ct = ask.count;
@@ -213,148 +1248,318 @@ impl Book {
             .collect()
     }
 
-    pub fn apply(&mut self, mbo: MboMsg) -> bool {
-        let action = mbo.action().unwrap();
+    /// Records that cannot be applied consistently (unknown action or side, duplicate
+    /// adds, oversized cancels) are rejected with a `BookError` rather than panicking,
+    /// since they can come off the wire.
+    pub fn apply(&mut self, mbo: MboMsg) -> Result<ApplyOutcome, BookError> {
+        let Ok(action) = mbo.action() else {
+            return Err(BookError::UnknownAction(mbo.action as u8));
+        };
+        if matches!(action, Action::Trade | Action::Fill | Action::None) {
+            return Ok(ApplyOutcome::Passthrough);
+        }
+        self.change(action, mbo)
+    }
+
+    /// Applies a top-of-book record to the implied side it quotes: an add or modify
+    /// replaces it and a cancel, or an undefined price, removes it. The outright
+    /// orders are left as they are.
+    pub fn apply_implied(&mut self, mbo: MboMsg) -> Result<ApplyOutcome, BookError> {
+        let Ok(action) = mbo.action() else {
+            return Err(BookError::UnknownAction(mbo.action as u8));
+        };
+        let level = match action {
+            Action::Add | Action::Modify if mbo.price != UNDEF_PRICE => Some(PriceLevel {
+                price: mbo.price,
+                size: mbo.size,
+                count: 1,
+            }),
+            Action::Add | Action::Modify | Action::Cancel => None,
+            _ => return Ok(ApplyOutcome::Passthrough),
+        };
+        if book_side(&mbo)? == Side::Bid {
+            self.implied_bid = level;
+        } else {
+            self.implied_ask = level;
+        }
+        self.ts_event = mbo.hd.ts_event;
+        self.bbo_epoch += 1;
+        Ok(ApplyOutcome::Updated)
+    }
+
+    /// Reduces the resting order by the fill's size like a cancel, removing it once
+    /// fully filled. See `FillHandling` for the venues this applies to.
+    pub fn apply_fill(&mut self, mbo: MboMsg) -> Result<ApplyOutcome, BookError> {
+        self.change(Action::Fill, mbo)
+    }
+
+    /// Applies a record that changes the book and bumps the BBO epoch when it may
+    /// have moved the touch.
+    fn change(&mut self, action: Action, mbo: MboMsg) -> Result<ApplyOutcome, BookError> {
+        let before = self.best_prices();
+        let ts_event = mbo.hd.ts_event;
+        let side = book_side(&mbo);
+        let price = mbo.price;
+        // A modify may move the order away from the touch
+        let moved_from = match action {
+            Action::Modify => self
+                .orders_by_id
+                .get(&mbo.order_id)
+                .map(|order| (order.side, order.price)),
+            _ => None,
+        };
         match action {
-            Action::Modify => self.modify(mbo),
-            Action::Trade | Action::Fill | Action::None => true,
-            Action::Cancel => self.cancel(mbo),
-            Action::Add => self.add(mbo),
-            Action::Clear => {
-                self.clear();
-                true
+            Action::Modify => self.modify(mbo)?,
+            Action::Cancel | Action::Fill => {
+                if !self.cancel(mbo)? {
+                    return Ok(ApplyOutcome::Passthrough);
+                }
             }
+            Action::Add => self.add(mbo)?,
+            Action::Clear => self.clear(),
+            Action::Trade | Action::None => return Ok(ApplyOutcome::Passthrough),
         }
+        if matches!(action, Action::Add | Action::Modify) {
+            self.enforce_depth_cap();
+        }
+        self.ts_event = ts_event;
+        let after = self.best_prices();
+        let at_touch = |side: Side, price: i64| match side {
+            Side::Bid => after.0 == Some(price),
+            Side::Ask => after.1 == Some(price),
+            Side::None => false,
+        };
+        if before != after
+            || side.is_ok_and(|side| at_touch(side, price))
+            || moved_from.is_some_and(|(side, price)| at_touch(side, price))
+        {
+            self.bbo_epoch += 1;
+        }
+        Ok(ApplyOutcome::Updated)
+    }
+
+    /// Bumped whenever the price or size at level 0 of either side may have changed,
+    /// so callers can skip work for updates deeper in the book.
+    pub fn bbo_epoch(&self) -> u64 {
+        self.bbo_epoch
     }
 
     fn clear(&mut self) {
         self.orders_by_id.clear();
         self.offers.clear();
         self.bids.clear();
+        self.slab.clear();
+        self.implied_bid = None;
+        self.implied_ask = None;
+        self.evicted.clear();
+        self.stale = false;
     }
 
-    fn add(&mut self, mbo: MboMsg) -> bool {
+    /// Evicts the deepest levels until the book is within its `DepthCap`.
+    fn enforce_depth_cap(&mut self) {
+        if let Some(max) = self.depth_cap.max_levels_per_side {
+            while self.bids.len() > max.max(1) {
+                self.evict_deepest(Side::Bid);
+            }
+            while self.offers.len() > max.max(1) {
+                self.evict_deepest(Side::Ask);
+            }
+        }
+        if let Some(max) = self.depth_cap.max_orders {
+            while self.slab.nodes.len() - self.slab.free.len() > max {
+                // Trim the longer side, keeping both best levels
+                let side = if self.bids.len() >= self.offers.len() {
+                    Side::Bid
+                } else {
+                    Side::Ask
+                };
+                if self.side_levels(side).len() <= 1 {
+                    break;
+                }
+                self.evict_deepest(side);
+            }
+        }
+    }
+
+    /// Drops the side's level furthest from the touch with its orders.
+    fn evict_deepest(&mut self, side: Side) {
+        let evicted = match side {
+            Side::Bid => self.bids.pop_first(),
+            _ => self.offers.pop_last(),
+        };
+        let Some((price, level)) = evicted else {
+            return;
+        };
+        for order in level.iter(&self.slab) {
+            let tracked = self
+                .orders_by_id
+                .get(&order.order_id)
+                .is_some_and(|order| order.side == side && order.price == price);
+            if tracked {
+                self.orders_by_id.remove(&order.order_id);
+                self.evicted.insert(order.order_id, order.size);
+            }
+            self.evicted_orders += 1;
+        }
+        level.release(&mut self.slab);
+        self.evicted_levels += 1;
+    }
+
+    fn add(&mut self, mbo: MboMsg) -> Result<(), BookError> {
         let price = mbo.price;
-        let side = mbo.side().unwrap();
+        let side = book_side(&mbo)?;
         if mbo.flags.is_tob() {
-            let levels: &mut BTreeMap<i64, Level> = self.side_levels_mut(side);
-            levels.clear();
+            let (levels, slab) = self.levels_and_slab(side);
+            for level in std::mem::take(levels).into_values() {
+                level.release(slab);
+            }
             // UNDEF_PRICE indicates the side's book should be cleared
             // and doesn't represent an order that should be added
             if mbo.price != UNDEF_PRICE {
-                levels.insert(price, VecDeque::from([mbo]));
+                self.push_order(side, price, Order::from(&mbo));
             }
         } else {
-            assert_ne!(price, UNDEF_PRICE);
-            assert!(
-                self.orders_by_id
-                    .insert(mbo.order_id, (side, price))
-                    .is_none()
-            );
-            let level: &mut Level = self.get_or_insert_level(side, price);
-            level.push_back(mbo);
+            if price == UNDEF_PRICE {
+                return Err(BookError::UndefinedPrice {
+                    order_id: mbo.order_id,
+                });
+            }
+            if self.orders_by_id.contains_key(&mbo.order_id) {
+                return Err(BookError::DuplicateOrder {
+                    order_id: mbo.order_id,
+                });
+            }
+            let order_id = mbo.order_id;
+            // Back within the kept depth, e.g. modified towards the touch
+            self.evicted.remove(&order_id);
+            let slot = self.push_order(side, price, Order::from(&mbo));
+            self.orders_by_id
+                .insert(order_id, OrderRef { side, price, slot });
         }
-        true
+        Ok(())
     }
 
-    fn cancel(&mut self, mbo: MboMsg) -> bool {
-        let side = mbo.side().unwrap();
-        // If level doesn't exist, ignore cancel
-        let Some(level) = self.side_levels_mut(side).get_mut(&mbo.price) else {
-            return false;
+    /// Returns false, leaving the book as it was, for an order the depth cap evicted.
+    fn cancel(&mut self, mbo: MboMsg) -> Result<bool, BookError> {
+        let side = book_side(&mbo)?;
+        let (order_id, price) = (mbo.order_id, mbo.price);
+        let tracked = self
+            .locate(mbo.order_id)
+            .filter(|order| order.side == side && order.price == mbo.price);
+        if tracked.is_none() && self.forgive_evicted(order_id, mbo.size) {
+            return Ok(false);
+        }
+        let (levels, slab) = self.levels_and_slab(side);
+        let Some(level) = levels.get_mut(&price) else {
+            return Err(BookError::UnknownLevel { order_id, price });
         };
-        // Find order within the level
-        let Some(order_idx) = level.iter().position(|o| o.order_id == mbo.order_id) else {
+        // Top-of-book records are not tracked by order id, so look for them in the level
+        let Some(slot) = tracked
+            .map(|order| order.slot)
+            .or_else(|| level.find(slab, mbo.order_id))
+        else {
+            return Err(BookError::UnknownOrder { order_id, price });
+        };
+        let existing_size = slab.node(slot).order.size;
+        if existing_size < mbo.size {
+            return Err(BookError::OversizedCancel {
+                order_id,
+                size: mbo.size,
+                resting: existing_size,
+            });
+        }
+        let remaining = existing_size - mbo.size;
+        level.resize(slab, slot, remaining);
+        if remaining == 0 {
+            self.unlink(side, mbo.price, slot);
+            self.orders_by_id.remove(&order_id);
+        }
+        Ok(true)
+    }
+
+    /// Takes `size` off an order the depth cap evicted, forgetting it once none is
+    /// left. False for orders that were never evicted.
+    fn forgive_evicted(&mut self, order_id: u64, size: u32) -> bool {
+        let Some(remaining) = self.evicted.get_mut(&order_id) else {
             return false;
         };
-        let existing_order = level.get_mut(order_idx).unwrap();
-        assert!(existing_order.size >= mbo.size);
-        existing_order.size -= mbo.size;
-        if existing_order.size == 0 {
-            level.remove(order_idx);
-            if level.is_empty() {
-                // Remove the now-empty level if it still exists
-                self.side_levels_mut(side).remove(&mbo.price);
-            }
-            self.orders_by_id.remove(&mbo.order_id);
+        *remaining = remaining.saturating_sub(size);
+        if *remaining == 0 {
+            self.evicted.remove(&order_id);
         }
+        self.forgiven += 1;
         true
     }
 
-    fn modify(&mut self, mbo: MboMsg) -> bool {
+    fn modify(&mut self, mbo: MboMsg) -> Result<(), BookError> {
         let order_id = mbo.order_id;
-        let new_side = mbo.side().unwrap();
+        let new_side = book_side(&mbo)?;
+        if mbo.price == UNDEF_PRICE && !mbo.flags.is_tob() {
+            return Err(BookError::UndefinedPrice { order_id });
+        }
         // If order not found, treat as add
-        let Some((prev_side, prev_price)) = self.orders_by_id.get(&order_id).cloned() else {
-            return self.add(mbo);
-        };
-        // Locate previous level and order; if missing, clean map and add fresh
-        let Some(prev_level) = self.side_levels_mut(prev_side).get_mut(&prev_price) else {
-            self.orders_by_id.remove(&order_id);
+        if !self.orders_by_id.contains_key(&order_id) {
             return self.add(mbo);
-        };
-        let Some(order_idx) = prev_level.iter().position(|o| o.order_id == order_id) else {
+        }
+        // If the order no longer rests where it was tracked, clean map and add fresh
+        let Some(prev) = self.locate(order_id) else {
             self.orders_by_id.remove(&order_id);
             return self.add(mbo);
         };
-        // Price changed → move; loses priority
-        if prev_price != mbo.price {
-            prev_level.remove(order_idx);
-            if prev_level.is_empty() {
-                // Remove using prev_side (not new_side)
-                self.side_levels_mut(prev_side).remove(&prev_price);
-            }
-            // Update map only after successful removal
-            self.orders_by_id.insert(order_id, (new_side, mbo.price));
-            let level = self.get_or_insert_level(new_side, mbo.price);
-            level.push_back(mbo);
-            return true;
-        }
-        // Same price:
-        // - Size increase loses priority (remove+push_back)
-        // - Size decrease/equal keeps priority (update in place)
-        let cur_size = prev_level[order_idx].size;
-        if cur_size < mbo.size {
-            prev_level.remove(order_idx);
-            // orders_by_id price unchanged
-            let level = self.get_or_insert_level(new_side, mbo.price);
-            level.push_back(mbo);
-        } else {
-            let existing_order = prev_level.get_mut(order_idx).unwrap();
-            existing_order.size = mbo.size;
-            // orders_by_id unchanged
+        let cur_size = self.slab.node(prev.slot).order.size;
+        // Price or side changed → move; loses priority
+        // Same price, size increase → loses priority (remove+push_back)
+        if prev.price != mbo.price || prev.side != new_side || cur_size < mbo.size {
+            self.unlink(prev.side, prev.price, prev.slot);
+            let price = mbo.price;
+            let slot = self.push_order(new_side, price, Order::from(&mbo));
+            self.orders_by_id.insert(
+                order_id,
+                OrderRef {
+                    side: new_side,
+                    price,
+                    slot,
+                },
+            );
+            return Ok(());
         }
-        true
-    }
-
-    fn get_or_insert_level(&mut self, side: Side, price: i64) -> &mut Level {
-        let levels = self.side_levels_mut(side);
-        levels.entry(price).or_default()
-    }
-
-    fn level_mut(&mut self, side: Side, price: i64) -> &mut Level {
-        let levels = self.side_levels_mut(side);
-        levels.get_mut(&price).unwrap()
+        // Same price, size decrease/equal keeps priority (update in place)
+        let (levels, slab) = self.levels_and_slab(prev.side);
+        if let Some(level) = levels.get_mut(&prev.price) {
+            level.resize(slab, prev.slot, mbo.size);
+        }
+        // orders_by_id unchanged
+        Ok(())
     }
 
-    fn remove_level(&mut self, side: Side, price: i64) {
-        self.side_levels_mut(side).remove(&price);
+    /// The tracked order `order_id`, if it still rests where the map says.
+    fn locate(&self, order_id: u64) -> Option<OrderRef> {
+        let order = *self.orders_by_id.get(&order_id)?;
+        self.slab.holds(order.slot, order_id).then_some(order)
     }
 
-    fn find_order(level: &VecDeque<MboMsg>, order_id: u64) -> usize {
-        level.iter().position(|o| o.order_id == order_id).unwrap()
+    /// Appends `order` to the back of its level, creating the level if needed.
+    fn push_order(&mut self, side: Side, price: i64, order: Order) -> Slot {
+        let (levels, slab) = self.levels_and_slab(side);
+        levels.entry(price).or_default().push_back(slab, order)
     }
 
-    fn remove_order(level: &mut VecDeque<MboMsg>, order_id: u64) {
-        if let Some(index) = level.iter().position(|o| o.order_id == order_id) {
-            level.remove(index);
+    /// Removes the order in `slot` from its level, dropping the level once empty.
+    fn unlink(&mut self, side: Side, price: i64, slot: Slot) {
+        let (levels, slab) = self.levels_and_slab(side);
+        let Some(level) = levels.get_mut(&price) else {
+            return;
+        };
+        level.remove(slab, slot);
+        if level.is_empty() {
+            levels.remove(&price);
         }
     }
 
-    fn side_levels_mut(&mut self, side: Side) -> &mut BTreeMap<i64, Level> {
+    fn levels_and_slab(&mut self, side: Side) -> (&mut BTreeMap<i64, Level>, &mut OrderSlab) {
         match side {
-            Side::Ask => &mut self.offers,
-            Side::Bid => &mut self.bids,
+            Side::Ask => (&mut self.offers, &mut self.slab),
+            Side::Bid => (&mut self.bids, &mut self.slab),
             Side::None => panic!("Invalid side None"),
         }
     }
@@ -368,22 +1573,217 @@ impl Book {
     }
 }
 
-impl PriceLevel {
-    fn new<'a>(price: i64, orders: impl Iterator<Item = &'a MboMsg>) -> Self {
-        orders.fold(
-            PriceLevel {
-                price,
-                size: 0,
-                count: 0,
-            },
-            |mut level, order| {
-                if !order.flags.is_tob() {
-                    level.count += 1;
-                }
-                level.size += order.size;
-                level
-            },
-        )
+/// The book side of a record, rejecting records without a valid Bid/Ask side.
+fn book_side(mbo: &MboMsg) -> Result<Side, BookError> {
+    match mbo.side() {
+        Ok(side @ (Side::Bid | Side::Ask)) => Ok(side),
+        _ => Err(BookError::InvalidSide {
+            order_id: mbo.order_id,
+            side: mbo.side as u8,
+        }),
+    }
+}
+
+impl Level {
+    fn aggregate(&self, price: i64) -> PriceLevel {
+        PriceLevel {
+            price,
+            size: self.size.min(u32::MAX as u64) as u32,
+            count: self.count,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// Orders in queue order.
+    fn iter<'a>(&self, slab: &'a OrderSlab) -> impl Iterator<Item = &'a Order> + use<'a> {
+        std::iter::successors(self.head.map(|slot| slab.node(slot)), |node| {
+            node.next.map(|slot| slab.node(slot))
+        })
+        .map(|node| &node.order)
+    }
+
+    /// Slot of `order_id` in this level, found by walking the queue.
+    fn find(&self, slab: &OrderSlab, order_id: u64) -> Option<Slot> {
+        let mut cursor = self.head;
+        while let Some(slot) = cursor {
+            let node = slab.node(slot);
+            if node.order.order_id == order_id {
+                return Some(slot);
+            }
+            cursor = node.next;
+        }
+        None
+    }
+
+    fn push_back(&mut self, slab: &mut OrderSlab, order: Order) -> Slot {
+        self.size += order.size as u64;
+        if !order.flags.is_tob() {
+            self.count += 1;
+        }
+        let slot = slab.insert(OrderNode {
+            order,
+            prev: self.tail,
+            next: None,
+        });
+        match self.tail {
+            Some(tail) => slab.node_mut(tail).next = Some(slot),
+            None => self.head = Some(slot),
+        }
+        self.tail = Some(slot);
+        slot
+    }
+
+    /// Sets the size of the order in `slot`, keeping its queue position.
+    fn resize(&mut self, slab: &mut OrderSlab, slot: Slot, size: u32) {
+        let order = &mut slab.node_mut(slot).order;
+        self.size = self.size - order.size as u64 + size as u64;
+        order.size = size;
+    }
+
+    fn remove(&mut self, slab: &mut OrderSlab, slot: Slot) -> Order {
+        let node = slab.remove(slot);
+        match node.prev {
+            Some(prev) => slab.node_mut(prev).next = node.next,
+            None => self.head = node.next,
+        }
+        match node.next {
+            Some(next) => slab.node_mut(next).prev = node.prev,
+            None => self.tail = node.prev,
+        }
+        self.size -= node.order.size as u64;
+        if !node.order.flags.is_tob() {
+            self.count -= 1;
+        }
+        node.order
+    }
+
+    /// Frees the slots of a level being dropped as a whole.
+    fn release(self, slab: &mut OrderSlab) {
+        let mut cursor = self.head;
+        while let Some(slot) = cursor {
+            cursor = slab.remove(slot).next;
+        }
+    }
+}
+
+impl OrderSlab {
+    fn insert(&mut self, node: OrderNode) -> Slot {
+        match self.free.pop() {
+            Some(slot) => {
+                self.recycled += 1;
+                self.nodes[slot as usize] = Some(node);
+                slot
+            }
+            None => {
+                self.nodes.push(Some(node));
+                (self.nodes.len() - 1) as Slot
+            }
+        }
+    }
+
+    /// Frees every slot, keeping them for the orders that rebuild the book.
+    fn clear(&mut self) {
+        self.free.clear();
+        // Reversed so the lowest slots are reused first
+        for (slot, node) in self.nodes.iter_mut().enumerate().rev() {
+            *node = None;
+            self.free.push(slot as Slot);
+        }
+    }
+
+    fn remove(&mut self, slot: Slot) -> OrderNode {
+        let node = self.nodes[slot as usize]
+            .take()
+            .expect("slot of a linked order");
+        self.free.push(slot);
+        node
+    }
+
+    fn node(&self, slot: Slot) -> &OrderNode {
+        self.nodes[slot as usize]
+            .as_ref()
+            .expect("slot of a linked order")
+    }
+
+    fn node_mut(&mut self, slot: Slot) -> &mut OrderNode {
+        self.nodes[slot as usize]
+            .as_mut()
+            .expect("slot of a linked order")
+    }
+
+    /// Whether `slot` holds the tracked order `order_id`.
+    fn holds(&self, slot: Slot, order_id: u64) -> bool {
+        self.nodes
+            .get(slot as usize)
+            .and_then(Option::as_ref)
+            .is_some_and(|node| node.order.order_id == order_id && !node.order.flags.is_tob())
+    }
+}
+
+impl From<&MboMsg> for Order {
+    fn from(mbo: &MboMsg) -> Self {
+        Self {
+            order_id: mbo.order_id,
+            ts_event: mbo.hd.ts_event,
+            size: mbo.size,
+            flags: mbo.flags,
+        }
+    }
+}
+
+impl Order {
+    /// The add record that rests this order at `price` on `side`.
+    pub fn to_add(&self, side: Side, price: i64) -> MboMsg {
+        MboMsg {
+            hd: RecordHeader::new::<MboMsg>(rtype::MBO, 0, 0, self.ts_event),
+            order_id: self.order_id,
+            price,
+            size: self.size,
+            flags: self.flags,
+            channel_id: 0,
+            action: Action::Add as u8 as _,
+            side: side as u8 as _,
+            ts_recv: self.ts_event,
+            ts_in_delta: 0,
+            sequence: 0,
+        }
+    }
+}
+
+impl PoolMetrics {
+    pub fn publish(&self, stats: PoolStats) {
+        self.allocated.store(stats.allocated, Ordering::Relaxed);
+        self.recycled.store(stats.recycled, Ordering::Relaxed);
+        self.live.store(stats.live, Ordering::Relaxed);
+        self.free.store(stats.free, Ordering::Relaxed);
+        self.evicted_levels
+            .store(stats.evicted_levels, Ordering::Relaxed);
+        self.evicted_orders
+            .store(stats.evicted_orders, Ordering::Relaxed);
+        self.forgiven.store(stats.forgiven, Ordering::Relaxed);
+        self.books.store(stats.books, Ordering::Relaxed);
+        self.memory_bytes
+            .store(stats.memory_bytes, Ordering::Relaxed);
+        self.removed_books
+            .store(stats.removed_books, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> PoolStats {
+        PoolStats {
+            allocated: self.allocated.load(Ordering::Relaxed),
+            recycled: self.recycled.load(Ordering::Relaxed),
+            live: self.live.load(Ordering::Relaxed),
+            free: self.free.load(Ordering::Relaxed),
+            evicted_levels: self.evicted_levels.load(Ordering::Relaxed),
+            evicted_orders: self.evicted_orders.load(Ordering::Relaxed),
+            forgiven: self.forgiven.load(Ordering::Relaxed),
+            books: self.books.load(Ordering::Relaxed),
+            memory_bytes: self.memory_bytes.load(Ordering::Relaxed),
+            removed_books: self.removed_books.load(Ordering::Relaxed),
+        }
     }
 }
 
@@ -398,3 +1798,388 @@ impl Display for PriceLevel {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use dbn::flags;
+
+    use super::*;
+
+    const INSTRUMENT: u32 = 7;
+    const GLBX: Publisher = Publisher::GlbxMdp3Glbx;
+
+    fn mbo(action: Action, side: Side, order_id: u64, price: i64, size: u32) -> MboMsg {
+        MboMsg {
+            hd: RecordHeader::new::<MboMsg>(rtype::MBO, GLBX as u16, INSTRUMENT, 1),
+            order_id,
+            price,
+            size,
+            flags: FlagSet::empty(),
+            channel_id: 0,
+            action: action as u8 as _,
+            side: side as u8 as _,
+            ts_recv: 1,
+            ts_in_delta: 0,
+            sequence: 0,
+        }
+    }
+
+    fn add(side: Side, order_id: u64, price: i64, size: u32) -> MboMsg {
+        mbo(Action::Add, side, order_id, price, size)
+    }
+
+    fn cancel(side: Side, order_id: u64, price: i64, size: u32) -> MboMsg {
+        mbo(Action::Cancel, side, order_id, price, size)
+    }
+
+    fn modify(side: Side, order_id: u64, price: i64, size: u32) -> MboMsg {
+        mbo(Action::Modify, side, order_id, price, size)
+    }
+
+    fn tob(side: Side, price: i64, size: u32) -> MboMsg {
+        MboMsg {
+            flags: FlagSet::new(flags::TOB),
+            ..add(side, 0, price, size)
+        }
+    }
+
+    fn at(mut mbo: MboMsg, ts_event: u64) -> MboMsg {
+        mbo.hd.ts_event = ts_event;
+        mbo
+    }
+
+    fn book(market: &Market) -> &Book {
+        market.book(INSTRUMENT, GLBX).expect("book of INSTRUMENT")
+    }
+
+    /// Queue of order ids and sizes at a bid price.
+    fn bid_queue(book: &Book, price: i64) -> Vec<(u64, u32)> {
+        book.iter_bid_orders()
+            .filter(|(px, _)| *px == price)
+            .map(|(_, order)| (order.order_id, order.size))
+            .collect()
+    }
+
+    fn level(level: Option<PriceLevel>) -> Option<(i64, u32, u32)> {
+        level.map(|level| (level.price, level.size, level.count))
+    }
+
+    #[test]
+    fn cancel_frees_a_slot_the_next_add_reuses() {
+        let mut market = Market::new();
+        for (order_id, size) in [(1, 10), (2, 20), (3, 30)] {
+            market.apply(add(Side::Bid, order_id, 100, size)).unwrap();
+        }
+        market.apply(cancel(Side::Bid, 2, 100, 20)).unwrap();
+        let stats = book(&market).pool_stats();
+        assert_eq!((stats.allocated, stats.live, stats.free), (3, 2, 1));
+        assert_eq!(book(&market).order(2), None);
+
+        market.apply(add(Side::Bid, 4, 100, 40)).unwrap();
+        let book = book(&market);
+        let stats = book.pool_stats();
+        assert_eq!((stats.allocated, stats.live, stats.recycled), (3, 3, 1));
+        assert_eq!(book.total_orders(), 3);
+        assert_eq!(bid_queue(book, 100), [(1, 10), (3, 30), (4, 40)]);
+        assert_eq!(book.order_level(4), Some((Side::Bid, 100)));
+        assert_eq!(level(book.bid_level(0)), Some((100, 80, 3)));
+    }
+
+    #[test]
+    fn partial_cancel_and_fill_reduce_the_order() {
+        let mut market = Market::new();
+        market.apply(add(Side::Ask, 1, 101, 10)).unwrap();
+        market.apply(add(Side::Ask, 2, 101, 5)).unwrap();
+        market.apply(cancel(Side::Ask, 1, 101, 4)).unwrap();
+        let mut restored = Book::new();
+        for mbo in book(&market).resting_orders() {
+            restored.restore_order(mbo).unwrap();
+        }
+        assert_eq!(restored.order(1).map(|order| order.size), Some(6));
+        assert_eq!(restored.queue_pos(2), Some(6));
+
+        let fill = mbo(Action::Fill, Side::Ask, 1, 101, 6);
+        // Fills only reduce the book when `FillHandling` says so
+        assert_eq!(restored.apply(fill.clone()), Ok(ApplyOutcome::Passthrough));
+        assert_eq!(restored.apply_fill(fill), Ok(ApplyOutcome::Updated));
+        assert_eq!(restored.order(1), None);
+        assert_eq!(restored.total_orders(), 1);
+        assert_eq!(level(restored.ask_level(0)), Some((101, 5, 1)));
+    }
+
+    #[test]
+    fn inconsistent_records_are_rejected() {
+        let mut market = Market::new();
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        assert_eq!(
+            market.apply(add(Side::Bid, 1, 99, 10)),
+            Err(BookError::DuplicateOrder { order_id: 1 })
+        );
+        assert_eq!(
+            market.apply(cancel(Side::Bid, 2, 98, 1)),
+            Err(BookError::UnknownLevel {
+                order_id: 2,
+                price: 98
+            })
+        );
+        assert_eq!(
+            market.apply(cancel(Side::Bid, 2, 100, 1)),
+            Err(BookError::UnknownOrder {
+                order_id: 2,
+                price: 100
+            })
+        );
+        assert_eq!(
+            market.apply(cancel(Side::Bid, 1, 100, 11)),
+            Err(BookError::OversizedCancel {
+                order_id: 1,
+                size: 11,
+                resting: 10
+            })
+        );
+        assert_eq!(bid_queue(book(&market), 100), [(1, 10)]);
+    }
+
+    #[test]
+    fn modify_keeps_priority_only_when_shrinking_in_place() {
+        let mut market = Market::new();
+        for order_id in 1..=3 {
+            market.apply(add(Side::Bid, order_id, 100, 10)).unwrap();
+        }
+        market.apply(modify(Side::Bid, 1, 100, 5)).unwrap();
+        assert_eq!(bid_queue(book(&market), 100), [(1, 5), (2, 10), (3, 10)]);
+
+        market.apply(modify(Side::Bid, 1, 100, 15)).unwrap();
+        assert_eq!(bid_queue(book(&market), 100), [(2, 10), (3, 10), (1, 15)]);
+
+        market.apply(modify(Side::Bid, 2, 99, 10)).unwrap();
+        market.apply(modify(Side::Bid, 2, 100, 10)).unwrap();
+        assert_eq!(bid_queue(book(&market), 100), [(3, 10), (1, 15), (2, 10)]);
+
+        market.apply(modify(Side::Bid, 3, 101, 10)).unwrap();
+        let book = book(&market);
+        assert_eq!(book.order_level(3), Some((Side::Bid, 101)));
+        assert_eq!(level(book.bid_level(0)), Some((101, 10, 1)));
+        assert_eq!(book.bid_level_count(), 2);
+        assert_eq!(book.total_orders(), 3);
+    }
+
+    #[test]
+    fn modify_of_unknown_order_adds_it() {
+        let mut market = Market::new();
+        market.apply(modify(Side::Ask, 9, 105, 3)).unwrap();
+        assert_eq!(book(&market).order_level(9), Some((Side::Ask, 105)));
+    }
+
+    #[test]
+    fn queue_pos_sums_the_sizes_ahead() {
+        let mut market = Market::new();
+        for (order_id, size) in [(1, u32::MAX), (2, u32::MAX), (3, 1)] {
+            market.apply(add(Side::Ask, order_id, 101, size)).unwrap();
+        }
+        let book = book(&market);
+        assert_eq!(book.queue_pos(1), Some(0));
+        assert_eq!(book.queue_pos(3), Some(2 * u32::MAX as u64));
+        assert_eq!(book.queue_pos(4), None);
+    }
+
+    #[test]
+    fn depth_cap_evicts_the_deepest_levels() {
+        let cap = DepthCap {
+            max_levels_per_side: Some(2),
+            max_orders: None,
+        };
+        let mut market = Market::new().with_depth_cap(cap);
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        market.apply(add(Side::Bid, 2, 99, 10)).unwrap();
+        market.apply(add(Side::Bid, 3, 98, 10)).unwrap();
+        market.apply(add(Side::Ask, 4, 101, 10)).unwrap();
+        market.apply(add(Side::Ask, 5, 103, 10)).unwrap();
+        market.apply(add(Side::Ask, 6, 102, 10)).unwrap();
+        let book = book(&market);
+        assert_eq!(book.bid_level_count(), 2);
+        assert_eq!(book.ask_level_count(), 2);
+        assert_eq!(book.order(3), None);
+        assert_eq!(book.order(5), None);
+        let stats = book.pool_stats();
+        assert_eq!((stats.evicted_levels, stats.evicted_orders), (2, 2));
+    }
+
+    #[test]
+    fn depth_cap_on_orders_trims_the_longer_side() {
+        let cap = DepthCap {
+            max_levels_per_side: None,
+            max_orders: Some(3),
+        };
+        let mut market = Market::new().with_depth_cap(cap);
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        market.apply(add(Side::Bid, 2, 99, 10)).unwrap();
+        market.apply(add(Side::Ask, 3, 101, 10)).unwrap();
+        market.apply(add(Side::Ask, 4, 101, 10)).unwrap();
+        let book = book(&market);
+        assert_eq!(book.total_orders(), 3);
+        assert_eq!(book.order(2), None);
+        assert_eq!(book.ask_level_count(), 1);
+    }
+
+    #[test]
+    fn records_for_evicted_orders_are_forgiven_until_they_are_gone() {
+        let cap = DepthCap {
+            max_levels_per_side: Some(1),
+            max_orders: None,
+        };
+        let mut market = Market::new().with_depth_cap(cap);
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        market.apply(add(Side::Bid, 2, 99, 10)).unwrap();
+        assert_eq!(
+            market.apply(cancel(Side::Bid, 2, 99, 4)),
+            Ok(ApplyOutcome::Passthrough)
+        );
+        assert_eq!(
+            market.apply(cancel(Side::Bid, 2, 99, 6)),
+            Ok(ApplyOutcome::Passthrough)
+        );
+        assert_eq!(book(&market).pool_stats().forgiven, 2);
+        // Fully cancelled, so another cancel is a real error
+        assert_eq!(
+            market.apply(cancel(Side::Bid, 2, 99, 1)),
+            Err(BookError::UnknownLevel {
+                order_id: 2,
+                price: 99
+            })
+        );
+        // As are orders at evicted prices that were never in the book
+        assert_eq!(
+            market.apply(cancel(Side::Bid, 3, 98, 1)),
+            Err(BookError::UnknownLevel {
+                order_id: 3,
+                price: 98
+            })
+        );
+        assert_eq!(level(book(&market).bid_level(0)), Some((100, 10, 1)));
+    }
+
+    #[test]
+    fn evicted_order_modified_to_the_touch_comes_back() {
+        let cap = DepthCap {
+            max_levels_per_side: Some(1),
+            max_orders: None,
+        };
+        let mut market = Market::new().with_depth_cap(cap);
+        market.apply(add(Side::Ask, 1, 101, 10)).unwrap();
+        market.apply(add(Side::Ask, 2, 102, 10)).unwrap();
+        market.apply(modify(Side::Ask, 2, 100, 10)).unwrap();
+        let book = book(&market);
+        assert_eq!(book.order_level(2), Some((Side::Ask, 100)));
+        assert_eq!(book.order(1), None);
+        assert_eq!(book.pool_stats().forgiven, 0);
+        // Order 1 is now the evicted one
+        assert_eq!(
+            market.apply(cancel(Side::Ask, 1, 101, 10)),
+            Ok(ApplyOutcome::Passthrough)
+        );
+        assert_eq!(
+            market.apply(cancel(Side::Ask, 2, 100, 10)),
+            Ok(ApplyOutcome::Updated)
+        );
+    }
+
+    #[test]
+    fn clear_forgets_evicted_orders() {
+        let cap = DepthCap {
+            max_levels_per_side: Some(1),
+            max_orders: None,
+        };
+        let mut market = Market::new().with_depth_cap(cap);
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        market.apply(add(Side::Bid, 2, 99, 10)).unwrap();
+        market
+            .apply(mbo(Action::Clear, Side::None, 0, 0, 0))
+            .unwrap();
+        assert_eq!(book(&market).total_orders(), 0);
+        assert!(market.apply(cancel(Side::Bid, 2, 99, 10)).is_err());
+    }
+
+    #[test]
+    fn top_of_book_record_replaces_the_side() {
+        let mut market = Market::new();
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        market.apply(add(Side::Bid, 2, 99, 10)).unwrap();
+        market.apply(add(Side::Ask, 3, 101, 10)).unwrap();
+        market.apply(tob(Side::Bid, 98, 7)).unwrap();
+        // Top-of-book records are not counted as orders
+        assert_eq!(level(book(&market).bid_level(0)), Some((98, 7, 0)));
+        assert_eq!(book(&market).bid_level_count(), 1);
+        assert_eq!(level(book(&market).ask_level(0)), Some((101, 10, 1)));
+
+        market.apply(tob(Side::Bid, UNDEF_PRICE, 0)).unwrap();
+        assert_eq!(book(&market).bid_level_count(), 0);
+        assert_eq!(book(&market).pool_stats().live, 1);
+    }
+
+    #[test]
+    fn implied_top_of_book_leaves_the_outright_orders() {
+        let policies = BookPolicies {
+            default: BookPolicy::Implied,
+            publishers: Vec::new(),
+        };
+        let mut market = Market::new().with_book_policies(policies);
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        market.apply(tob(Side::Bid, 101, 7)).unwrap();
+        let (bid, ask) = market.implied_bbo(INSTRUMENT);
+        assert_eq!((level(bid), level(ask)), (Some((101, 7, 1)), None));
+        assert_eq!(level(book(&market).bid_level(0)), Some((100, 10, 1)));
+
+        market.apply(tob(Side::Bid, UNDEF_PRICE, 0)).unwrap();
+        assert!(market.implied_bbo(INSTRUMENT).0.is_none());
+        assert_eq!(book(&market).total_orders(), 1);
+    }
+
+    #[test]
+    fn ignored_top_of_book_is_passed_through() {
+        let policies = BookPolicies {
+            default: BookPolicy::Replace,
+            publishers: vec![(GLBX, BookPolicy::Ignore)],
+        };
+        let mut market = Market::new().with_book_policies(policies);
+        market.apply(add(Side::Bid, 1, 100, 10)).unwrap();
+        assert_eq!(
+            market.apply(tob(Side::Bid, 101, 7)),
+            Ok(ApplyOutcome::Passthrough)
+        );
+        assert_eq!(level(book(&market).bid_level(0)), Some((100, 10, 1)));
+    }
+
+    #[test]
+    fn only_empty_books_go_idle() {
+        let mut market = Market::new();
+        market.apply(at(add(Side::Bid, 1, 100, 10), 10)).unwrap();
+        market.apply(at(add(Side::Bid, 2, 100, 10), 10)).unwrap();
+        assert!(market.idle_instruments(100, 50).is_empty());
+
+        market.apply(at(cancel(Side::Bid, 1, 100, 10), 20)).unwrap();
+        market.apply(at(cancel(Side::Bid, 2, 100, 10), 30)).unwrap();
+        assert!(book(&market).is_empty());
+        assert!(market.idle_instruments(79, 50).is_empty());
+        assert_eq!(market.idle_instruments(80, 50), [INSTRUMENT]);
+
+        // Records that leave the book as it was still count as activity
+        let mut trade = at(mbo(Action::Trade, Side::Bid, 0, 100, 1), 60);
+        trade.hd.instrument_id = INSTRUMENT;
+        market.apply(trade).unwrap();
+        assert!(market.idle_instruments(80, 50).is_empty());
+    }
+
+    #[test]
+    fn remove_instrument_drops_its_books() {
+        let mut market = Market::new();
+        market.apply(at(add(Side::Bid, 1, 100, 10), 5)).unwrap();
+        assert!(market.remove_instrument(INSTRUMENT));
+        assert!(!market.remove_instrument(INSTRUMENT));
+        assert!(market.is_empty());
+        assert_eq!(market.pool_stats().removed_books, 1);
+        // A later record starts a new book
+        market.apply(add(Side::Ask, 2, 101, 10)).unwrap();
+        assert_eq!(book(&market).total_orders(), 1);
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    io::{IsTerminal, stderr},
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use tracing::{error, info};
+
+/// Redraw at most this often; the bar is updated from the hot ingest loop.
+const UPDATE_EVERY: Duration = Duration::from_millis(200);
+/// Records between checks of whether a progress line is due and updates of the byte
+/// offset `/status` reports, so the hot loop does not read the clock or the offset per
+/// record.
+const LINE_CHECK_EVERY: u64 = 1024;
+
+/// When to draw the replay progress bar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Only when stderr is a terminal.
+    Auto,
+    Off,
+}
+
+impl ProgressMode {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "auto" => Some(Self::Auto),
+            "0" | "false" | "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// Depth of the book of the last record's instrument, summed over its publishers.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BookDepth {
+    pub instrument_id: u32,
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    pub orders: usize,
+}
+
+/// One `progress=` line. Byte fields, `percent` and `eta_secs` are null for live
+/// sources, which have no size.
+#[derive(Serialize)]
+struct ProgressLine {
+    records: u64,
+    bytes_read: Option<u64>,
+    total_bytes: Option<u64>,
+    percent: Option<f64>,
+    /// Records per second since the previous line.
+    msg_per_sec: f64,
+    /// At the byte rate since the previous line.
+    eta_secs: Option<f64>,
+    elapsed_secs: f64,
+    /// Null in sharded ingest, whose books live on the shard threads.
+    book: Option<BookDepth>,
+}
+
+/// How far ingest is, shared with the server for `/status`.
+#[derive(Debug, Default)]
+pub struct IngestStats {
+    /// Set when the first progress is reported.
+    started: OnceLock<Instant>,
+    records: AtomicU64,
+    bytes_read: AtomicU64,
+    /// 0 for live sources, which have no size.
+    total_bytes: AtomicU64,
+    finished: AtomicBool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct IngestMetrics {
+    pub records: u64,
+    /// Byte fields and `percent` are null for live sources.
+    pub bytes_read: Option<u64>,
+    pub total_bytes: Option<u64>,
+    pub percent: Option<f64>,
+    /// Average since ingest started.
+    pub msg_per_sec: f64,
+    pub elapsed_secs: f64,
+    /// The source ended or ingest was stopped; writers may still be draining.
+    pub finished: bool,
+}
+
+impl IngestStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> IngestMetrics {
+        let records = self.records.load(Ordering::Relaxed);
+        let total_bytes = Some(self.total_bytes.load(Ordering::Relaxed)).filter(|&t| t > 0);
+        let bytes_read = total_bytes.map(|_| self.bytes_read.load(Ordering::Relaxed));
+        let elapsed_secs = self
+            .started
+            .get()
+            .map_or(0.0, |started| started.elapsed().as_secs_f64());
+        IngestMetrics {
+            records,
+            bytes_read,
+            total_bytes,
+            percent: bytes_read
+                .zip(total_bytes)
+                .map(|(read, total)| read as f64 * 100.0 / total as f64),
+            msg_per_sec: if elapsed_secs > 0.0 {
+                records as f64 / elapsed_secs
+            } else {
+                0.0
+            },
+            elapsed_secs,
+            finished: self.finished.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Where the last progress line left off, for the rates of the next one.
+struct LineState {
+    interval: Duration,
+    at: Instant,
+    records: u64,
+    bytes_read: u64,
+}
+
+/// Progress of an ingest: a bar with ETA and record throughput for file replays, drawn
+/// on stderr so stdout logs stay machine-readable, and `progress=` JSON lines on stdout
+/// every `line_interval`. Position is the decoder's byte offset. Counts are also kept
+/// in `stats` for `/status`.
+pub struct ReplayProgress {
+    bar: Option<ProgressBar>,
+    stats: Arc<IngestStats>,
+    total_bytes: Option<u64>,
+    lines: Option<LineState>,
+    started: Instant,
+    last_update: Instant,
+}
+
+impl ReplayProgress {
+    /// `total_bytes` of `None` (live sources) disables the bar; `line_interval` of `None`
+    /// disables the progress lines.
+    pub fn new(
+        mode: ProgressMode,
+        total_bytes: Option<u64>,
+        line_interval: Option<Duration>,
+        stats: Arc<IngestStats>,
+    ) -> Self {
+        let enabled = mode == ProgressMode::Auto && stderr().is_terminal();
+        let bar = total_bytes.filter(|_| enabled).map(|total| {
+            let bar = ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stderr());
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{bar:40.cyan/blue} {percent:>3}% {binary_bytes}/{binary_total_bytes} {msg} eta {eta}",
+                )
+                .expect("valid progress template")
+                .progress_chars("=> "),
+            );
+            bar
+        });
+        let now = Instant::now();
+        let _ = stats.started.set(now);
+        stats
+            .total_bytes
+            .store(total_bytes.unwrap_or(0), Ordering::Relaxed);
+        Self {
+            bar,
+            stats,
+            total_bytes,
+            lines: line_interval.map(|interval| LineState {
+                interval,
+                at: now,
+                records: 0,
+                bytes_read: 0,
+            }),
+            started: now,
+            last_update: now,
+        }
+    }
+
+    /// Records the record count; `position` (the byte offset, `None` for live sources)
+    /// and `book` are only queried every `LINE_CHECK_EVERY` records or when a redraw is
+    /// due, since they cost a syscall or a lookup.
+    pub fn update(
+        &mut self,
+        records: u64,
+        position: impl FnOnce() -> Option<u64>,
+        book: impl FnOnce() -> Option<BookDepth>,
+    ) {
+        self.stats.records.store(records, Ordering::Relaxed);
+        let check = records.is_multiple_of(LINE_CHECK_EVERY);
+        let redraw = self.bar.is_some() && self.last_update.elapsed() >= UPDATE_EVERY;
+        let line = check
+            && self
+                .lines
+                .as_ref()
+                .is_some_and(|lines| lines.at.elapsed() >= lines.interval);
+        if !redraw && !check {
+            return;
+        }
+        let position = position();
+        if let Some(read) = position {
+            self.stats.bytes_read.store(read, Ordering::Relaxed);
+        }
+        if redraw && let Some(bar) = &self.bar {
+            self.last_update = Instant::now();
+            bar.set_position(position.unwrap_or(0));
+            let secs = self.started.elapsed().as_secs_f64();
+            let rate = if secs > 0.0 {
+                records as f64 / secs
+            } else {
+                0.0
+            };
+            bar.set_message(format!("{} records {:.0} rec/s", records, rate));
+        }
+        if line {
+            self.log_line(records, position, book());
+        }
+    }
+
+    fn log_line(&mut self, records: u64, bytes_read: Option<u64>, book: Option<BookDepth>) {
+        let Some(lines) = &mut self.lines else {
+            return;
+        };
+        let now = Instant::now();
+        let secs = now.duration_since(lines.at).as_secs_f64();
+        let per_sec = |delta: u64| if secs > 0.0 { delta as f64 / secs } else { 0.0 };
+        let total_bytes = self.total_bytes.filter(|_| bytes_read.is_some());
+        let eta_secs = bytes_read.zip(total_bytes).and_then(|(read, total)| {
+            let byte_rate = per_sec(read.saturating_sub(lines.bytes_read));
+            (byte_rate > 0.0).then(|| total.saturating_sub(read) as f64 / byte_rate)
+        });
+        let line = ProgressLine {
+            records,
+            bytes_read,
+            total_bytes,
+            percent: bytes_read
+                .zip(total_bytes)
+                .filter(|&(_, total)| total > 0)
+                .map(|(read, total)| read as f64 * 100.0 / total as f64),
+            msg_per_sec: per_sec(records.saturating_sub(lines.records)),
+            eta_secs,
+            elapsed_secs: now.duration_since(self.started).as_secs_f64(),
+            book,
+        };
+        *lines = LineState {
+            interval: lines.interval,
+            at: now,
+            records,
+            bytes_read: bytes_read.unwrap_or(0),
+        };
+        match serde_json::to_string(&line) {
+            Ok(json) => {
+                // Keep the line off the bar's row
+                if let Some(bar) = &self.bar {
+                    bar.suspend(|| info!(progress = %json));
+                } else {
+                    info!(progress = %json);
+                }
+            }
+            Err(e) => error!(error = %e, "progress line failed to serialize"),
+        }
+    }
+
+    /// Removes the bar so the completion logs print on a clean line.
+    pub fn finish(&self) {
+        self.stats.finished.store(true, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
@@ -0,0 +1,634 @@
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering, fence},
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use crossbeam_channel::{RecvError, RecvTimeoutError, TrySendError};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::snapshot::{SharedL3, SharedSnapshot};
+
+/// How ingest hands snapshots to a writer thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueueBackend {
+    /// A crossbeam bounded channel.
+    #[default]
+    Channel,
+    /// A pre-allocated single-producer single-consumer ring. Sending never takes a
+    /// lock, so the ingest thread's latency does not depend on the writer.
+    Ring,
+}
+
+/// What a sender does with an item its queue has no room for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    /// Wait for the writer to make room, slowing ingest to the writer's pace.
+    #[default]
+    Block,
+    /// Drop the item being sent.
+    DropNewest,
+    /// Drop the oldest queued item to make room. Channel queues only, since a ring's
+    /// producer cannot take items off it.
+    DropOldest,
+    /// Hold back the latest snapshot per instrument until there is room, replacing
+    /// the one held before. Items without an instrument are dropped.
+    Coalesce,
+}
+
+impl BackpressurePolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BackpressurePolicy::Block => "block",
+            BackpressurePolicy::DropNewest => "drop_newest",
+            BackpressurePolicy::DropOldest => "drop_oldest",
+            BackpressurePolicy::Coalesce => "coalesce",
+        }
+    }
+}
+
+/// Backend, size and backpressure policy of one writer queue.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QueueConfig {
+    pub backend: QueueBackend,
+    pub capacity: usize,
+    pub policy: BackpressurePolicy,
+}
+
+/// Items the `Coalesce` policy can replace with a newer one of the same key.
+pub trait Coalesce {
+    /// Items with the same key supersede each other; `None` never coalesces.
+    fn coalesce_key(&self) -> Option<u32>;
+}
+
+impl Coalesce for SharedSnapshot {
+    fn coalesce_key(&self) -> Option<u32> {
+        Some(self.instrument_id)
+    }
+}
+
+impl Coalesce for SharedL3 {
+    fn coalesce_key(&self) -> Option<u32> {
+        Some(self.instrument_id)
+    }
+}
+
+/// Counters of every queue ingest feeds, served under `/metrics`.
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    queues: Mutex<Vec<Arc<QueueCounters>>>,
+}
+
+/// What became of the items sent to one queue.
+#[derive(Debug)]
+pub struct QueueCounters {
+    name: String,
+    policy: BackpressurePolicy,
+    sent: AtomicU64,
+    /// Items the writer took off the queue.
+    received: AtomicU64,
+    /// Items dropped on arrival (`DropNewest`, or unkeyed under `Coalesce`).
+    dropped: AtomicU64,
+    /// Queued items dropped for newer ones (`DropOldest`).
+    evicted: AtomicU64,
+    /// Snapshots replaced by a newer one of their instrument (`Coalesce`).
+    coalesced: AtomicU64,
+    /// Time spent waiting for room (`Block`).
+    blocked_us: AtomicU64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueueMetrics {
+    pub name: String,
+    pub policy: BackpressurePolicy,
+    pub sent: u64,
+    /// Items waiting for the writer.
+    pub depth: u64,
+    pub dropped: u64,
+    pub evicted: u64,
+    pub coalesced: u64,
+    pub blocked_ms: u64,
+}
+
+impl QueueStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn register(&self, name: &str, policy: BackpressurePolicy) -> Arc<QueueCounters> {
+        let counters = Arc::new(QueueCounters {
+            name: name.to_owned(),
+            policy,
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            blocked_us: AtomicU64::new(0),
+        });
+        self.queues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(counters.clone());
+        counters
+    }
+
+    pub fn snapshot(&self) -> Vec<QueueMetrics> {
+        self.queues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|queue| queue.snapshot())
+            .collect()
+    }
+}
+
+impl QueueCounters {
+    /// Items lost to a full queue, as opposed to superseded by a newer snapshot.
+    pub fn lost(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed) + self.evicted.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self) -> QueueMetrics {
+        let sent = self.sent.load(Ordering::Relaxed);
+        // Evicted items left the queue without reaching the writer
+        let left = self.received.load(Ordering::Relaxed) + self.evicted.load(Ordering::Relaxed);
+        QueueMetrics {
+            name: self.name.clone(),
+            policy: self.policy,
+            sent,
+            depth: sent.saturating_sub(left),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            blocked_ms: self.blocked_us.load(Ordering::Relaxed) / 1_000,
+        }
+    }
+}
+
+/// A bounded queue from the ingest thread to the writer `name`, with its counters
+/// registered in `stats`.
+pub fn bounded<T: Coalesce>(
+    name: &str,
+    config: QueueConfig,
+    stats: &QueueStats,
+) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (inner, rx) = match config.backend {
+        QueueBackend::Channel => {
+            let (tx, rx) = crossbeam_channel::bounded(config.capacity);
+            (
+                SenderKind::Channel {
+                    tx,
+                    evict: rx.clone(),
+                },
+                ReceiverKind::Channel(rx),
+            )
+        }
+        QueueBackend::Ring => {
+            let (producer, consumer) = rtrb::RingBuffer::new(config.capacity);
+            let wake = Arc::new(Wake::default());
+            (
+                SenderKind::Ring(RingSender {
+                    producer,
+                    wake: wake.clone(),
+                }),
+                ReceiverKind::Ring(RingReceiver { consumer, wake }),
+            )
+        }
+    };
+    let counters = stats.register(name, config.policy);
+    let sender = QueueSender {
+        inner,
+        policy: config.policy,
+        counters: counters.clone(),
+        pending: HashMap::new(),
+        warned: false,
+    };
+    (
+        sender,
+        QueueReceiver {
+            inner: rx,
+            counters,
+        },
+    )
+}
+
+/// Sending side of a writer queue, applying its backpressure policy. Dropping it
+/// sends the snapshots `Coalesce` still holds back, then closes the queue.
+pub struct QueueSender<T: Coalesce> {
+    inner: SenderKind<T>,
+    policy: BackpressurePolicy,
+    counters: Arc<QueueCounters>,
+    /// Latest snapshot per instrument held back by `Coalesce`.
+    pending: HashMap<u32, T>,
+    /// Only the first loss is logged; later ones are counted.
+    warned: bool,
+}
+
+enum SenderKind<T> {
+    Channel {
+        tx: crossbeam_channel::Sender<T>,
+        /// Lets `DropOldest` take the oldest item off the queue.
+        evict: crossbeam_channel::Receiver<T>,
+    },
+    Ring(RingSender<T>),
+}
+
+pub struct QueueReceiver<T> {
+    inner: ReceiverKind<T>,
+    counters: Arc<QueueCounters>,
+}
+
+enum ReceiverKind<T> {
+    Channel(crossbeam_channel::Receiver<T>),
+    Ring(RingReceiver<T>),
+}
+
+pub struct RingSender<T> {
+    producer: rtrb::Producer<T>,
+    wake: Arc<Wake>,
+}
+
+pub struct RingReceiver<T> {
+    consumer: rtrb::Consumer<T>,
+    wake: Arc<Wake>,
+}
+
+/// Lets the producer wake a consumer parked on an empty ring, without touching a lock
+/// while the consumer is busy.
+#[derive(Default)]
+struct Wake {
+    /// Set by the consumer before it parks.
+    sleeping: AtomicBool,
+    consumer: Mutex<Option<Thread>>,
+    /// The producer is gone; the consumer drains what is left and stops.
+    closed: AtomicBool,
+}
+
+impl Wake {
+    fn notify(&self) {
+        // Orders the push (or close) before the check, against the consumer's
+        // store of `sleeping` before its emptiness check
+        fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::Relaxed)
+            && let Some(thread) = self
+                .consumer
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_ref()
+        {
+            thread.unpark();
+        }
+    }
+}
+
+impl<T: Coalesce> QueueSender<T> {
+    /// Queues `item` under the queue's backpressure policy. Fails only once the writer
+    /// is gone.
+    pub fn send(&mut self, item: T) -> Result<()> {
+        let item = if self.pending.is_empty() {
+            match self.try_send(item)? {
+                None => return Ok(()),
+                Some(item) => item,
+            }
+        } else {
+            // Held-back snapshots go first, so none is sent after a newer one of
+            // its instrument; whatever is still held means the queue is full
+            self.flush_pending()?;
+            if self.pending.is_empty() {
+                match self.try_send(item)? {
+                    None => return Ok(()),
+                    Some(item) => item,
+                }
+            } else {
+                item
+            }
+        };
+        match self.policy {
+            BackpressurePolicy::Block => self.send_blocking(item),
+            BackpressurePolicy::DropNewest => {
+                self.lose(false);
+                Ok(())
+            }
+            BackpressurePolicy::DropOldest => self.send_evicting(item),
+            BackpressurePolicy::Coalesce => {
+                match item.coalesce_key() {
+                    Some(key) => {
+                        if self.pending.insert(key, item).is_some() {
+                            self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    None => self.lose(false),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn counters(&self) -> &Arc<QueueCounters> {
+        &self.counters
+    }
+
+    /// Queues `item` if there is room, handing it back when the queue is full.
+    fn try_send(&mut self, item: T) -> Result<Option<T>> {
+        let result = match &mut self.inner {
+            SenderKind::Channel { tx, .. } => tx.try_send(item),
+            SenderKind::Ring(ring) => ring.try_send(item),
+        };
+        match result {
+            Ok(()) => {
+                self.counters.sent.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            Err(TrySendError::Full(item)) => Ok(Some(item)),
+            Err(TrySendError::Disconnected(_)) => {
+                Err(anyhow!("{} queue disconnected", self.counters.name))
+            }
+        }
+    }
+
+    fn send_blocking(&mut self, mut item: T) -> Result<()> {
+        let start = Instant::now();
+        let mut spins = 0u32;
+        // A ring cannot wake its producer, so the wait is a spin that backs off to
+        // short sleeps rather than a park
+        loop {
+            item = match self.try_send(item)? {
+                None => break,
+                Some(item) => item,
+            };
+            spins += 1;
+            if spins < 64 {
+                std::hint::spin_loop();
+            } else if spins < 128 {
+                thread::yield_now();
+            } else {
+                thread::sleep(Duration::from_micros(50));
+            }
+        }
+        self.counters
+            .blocked_us
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn send_evicting(&mut self, mut item: T) -> Result<()> {
+        loop {
+            let evicted = match &self.inner {
+                SenderKind::Channel { evict, .. } => evict.try_recv().is_ok(),
+                SenderKind::Ring(_) => {
+                    // Rejected by config validation; dropping is the closest alternative
+                    self.lose(false);
+                    return Ok(());
+                }
+            };
+            if evicted {
+                self.lose(true);
+            }
+            item = match self.try_send(item)? {
+                None => return Ok(()),
+                Some(item) => item,
+            };
+        }
+    }
+
+    /// Sends held-back snapshots while there is room.
+    fn flush_pending(&mut self) -> Result<()> {
+        let keys: Vec<u32> = self.pending.keys().copied().collect();
+        for key in keys {
+            let Some(item) = self.pending.remove(&key) else {
+                continue;
+            };
+            if let Some(item) = self.try_send(item)? {
+                self.pending.insert(key, item);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn lose(&mut self, evicted: bool) {
+        let counter = if evicted {
+            &self.counters.evicted
+        } else {
+            &self.counters.dropped
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if !self.warned {
+            self.warned = true;
+            warn!(
+                queue = %self.counters.name,
+                policy = self.policy.as_str(),
+                "queue full, dropping; further drops are counted in /metrics"
+            );
+        }
+    }
+}
+
+impl<T: Coalesce> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let pending: Vec<T> = self.pending.drain().map(|(_, item)| item).collect();
+        for item in pending {
+            // Fails only when the writer is gone, and then nothing can be sent
+            if self.send_blocking(item).is_err() {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// A mailbox keeping only the newest item per key, for a consumer that serves the
+/// latest state rather than every item. Putting never waits, however far behind the
+/// consumer is; replaced items are counted as `coalesced` under `name`.
+pub fn latest<T: Coalesce>(name: &str, stats: &QueueStats) -> (LatestSender<T>, LatestReceiver<T>) {
+    let counters = stats.register(name, BackpressurePolicy::Coalesce);
+    let mailbox = Arc::new(Mailbox {
+        state: Mutex::new(MailboxState {
+            items: Vec::new(),
+            index: HashMap::new(),
+            closed: false,
+        }),
+        ready: Condvar::new(),
+    });
+    (
+        LatestSender {
+            mailbox: mailbox.clone(),
+            counters: counters.clone(),
+        },
+        LatestReceiver { mailbox, counters },
+    )
+}
+
+/// Putting side of a [`latest`] mailbox. Dropping it lets the consumer take what is
+/// left and stop.
+pub struct LatestSender<T> {
+    mailbox: Arc<Mailbox<T>>,
+    counters: Arc<QueueCounters>,
+}
+
+pub struct LatestReceiver<T> {
+    mailbox: Arc<Mailbox<T>>,
+    counters: Arc<QueueCounters>,
+}
+
+struct Mailbox<T> {
+    state: Mutex<MailboxState<T>>,
+    ready: Condvar,
+}
+
+struct MailboxState<T> {
+    /// In order of each key's first put since the last take.
+    items: Vec<T>,
+    /// Position of each key's item in `items`.
+    index: HashMap<u32, usize>,
+    closed: bool,
+}
+
+impl<T> Mailbox<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, MailboxState<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: Coalesce> LatestSender<T> {
+    /// Replaces the item of the same key still waiting for the consumer, if any.
+    pub fn put(&mut self, item: T) {
+        let mut state = self.mailbox.lock();
+        let was_empty = state.items.is_empty();
+        let key = item.coalesce_key();
+        match key.and_then(|key| state.index.get(&key).copied()) {
+            Some(at) => {
+                state.items[at] = item;
+                self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                if let Some(key) = key {
+                    let at = state.items.len();
+                    state.index.insert(key, at);
+                }
+                state.items.push(item);
+                self.counters.sent.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        drop(state);
+        if was_empty {
+            self.mailbox.ready.notify_one();
+        }
+    }
+}
+
+impl<T> Drop for LatestSender<T> {
+    fn drop(&mut self) {
+        self.mailbox.lock().closed = true;
+        self.mailbox.ready.notify_one();
+    }
+}
+
+impl<T> LatestReceiver<T> {
+    /// Waits for items and takes all of them; `None` once the sender is gone and
+    /// everything was taken.
+    pub fn recv(&mut self) -> Option<Vec<T>> {
+        let mut state = self.mailbox.lock();
+        while state.items.is_empty() && !state.closed {
+            state = self
+                .mailbox
+                .ready
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        if state.items.is_empty() {
+            return None;
+        }
+        state.index.clear();
+        self.counters
+            .received
+            .fetch_add(state.items.len() as u64, Ordering::Relaxed);
+        Some(mem::take(&mut state.items))
+    }
+}
+
+impl<T> RingSender<T> {
+    fn try_send(&mut self, item: T) -> Result<(), TrySendError<T>> {
+        if self.producer.is_abandoned() {
+            return Err(TrySendError::Disconnected(item));
+        }
+        self.producer
+            .push(item)
+            .map_err(|rtrb::PushError::Full(item)| TrySendError::Full(item))?;
+        self.wake.notify();
+        Ok(())
+    }
+}
+
+impl<T> Drop for RingSender<T> {
+    fn drop(&mut self) {
+        self.wake.closed.store(true, Ordering::Release);
+        self.wake.notify();
+    }
+}
+
+impl<T> QueueReceiver<T> {
+    /// Waits for the next item; fails once the sender is gone and the queue is empty.
+    pub fn recv(&mut self) -> Result<T, RecvError> {
+        let item = match &mut self.inner {
+            ReceiverKind::Channel(rx) => rx.recv(),
+            ReceiverKind::Ring(ring) => ring.recv_until(None).map_err(|_| RecvError),
+        }?;
+        self.counters.received.fetch_add(1, Ordering::Relaxed);
+        Ok(item)
+    }
+
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let item = match &mut self.inner {
+            ReceiverKind::Channel(rx) => rx.recv_timeout(timeout),
+            ReceiverKind::Ring(ring) => ring.recv_until(Some(Instant::now() + timeout)),
+        }?;
+        self.counters.received.fetch_add(1, Ordering::Relaxed);
+        Ok(item)
+    }
+}
+
+impl<T> RingReceiver<T> {
+    fn recv_until(&mut self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            // Read before popping, so items pushed before the close are drained
+            let closed = self.wake.closed.load(Ordering::Acquire);
+            if let Ok(item) = self.consumer.pop() {
+                return Ok(item);
+            }
+            if closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if !left.is_zero() => Some(left),
+                    _ => return Err(RecvTimeoutError::Timeout),
+                },
+                None => None,
+            };
+            self.park(timeout);
+        }
+    }
+
+    fn park(&mut self, timeout: Option<Duration>) {
+        *self.wake.consumer.lock().unwrap_or_else(|e| e.into_inner()) = Some(thread::current());
+        self.wake.sleeping.store(true, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        // A push or close after this check sees `sleeping` and unparks
+        if self.consumer.is_empty() && !self.wake.closed.load(Ordering::Acquire) {
+            match timeout {
+                Some(timeout) => thread::park_timeout(timeout),
+                None => thread::park(),
+            }
+        }
+        self.wake.sleeping.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{path::PathBuf, time::Instant};
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    checkpoint::read_checkpoint,
+    ingest::{DbnFileSource, IngestSource},
+    order_book::{BookPolicies, DepthCap, FillHandling, Market},
+    snapshot::{SnapshotRecord, SymbolMap, build_snapshot_record},
+};
+
+/// The DBN input a market can be rewound against.
+///
+/// Stored snapshots only hold aggregated levels, so they cannot seed order-level state.
+/// The book at a timestamp is instead rebuilt from the input, starting at the checkpoint
+/// when it was taken from the same input before that timestamp, else at the first record.
+#[derive(Clone, Debug)]
+pub struct ReplaySource {
+    input_path: String,
+    checkpoint_path: Option<PathBuf>,
+    symbols: SymbolMap,
+    fills: FillHandling,
+    policies: BookPolicies,
+    depth_cap: DepthCap,
+}
+
+/// How a rewind rebuilt the market, as reported by `/book/at`.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Rewind {
+    pub ts: i64,
+    /// Whether replay started at the checkpoint rather than the start of the input.
+    pub from_checkpoint: bool,
+    /// Records read from the input, including those the checkpoint already covered.
+    pub records: u64,
+    /// Records applied on top of the starting state.
+    pub applied: u64,
+    /// ts_event of the last record replayed or covered by the checkpoint; 0 when none was.
+    pub last_ts_event: i64,
+    pub elapsed_ms: u64,
+}
+
+impl ReplaySource {
+    pub fn new(
+        input_path: impl Into<String>,
+        checkpoint_path: Option<PathBuf>,
+        symbols: SymbolMap,
+        fills: FillHandling,
+        policies: BookPolicies,
+        depth_cap: DepthCap,
+    ) -> Self {
+        Self {
+            input_path: input_path.into(),
+            checkpoint_path,
+            symbols,
+            fills,
+            policies,
+            depth_cap,
+        }
+    }
+
+    /// Rebuilds the book of `symbol` as of `ts`, `depth` levels deep. `None` when the
+    /// symbol is unknown to both the configured map and the input's metadata.
+    pub fn book_at(
+        &self,
+        symbol: &str,
+        ts: i64,
+        depth: usize,
+    ) -> Result<Option<(SnapshotRecord, Rewind)>> {
+        let mut symbols = self.symbols.clone();
+        symbols.extend_missing(DbnFileSource::open(&self.input_path)?.symbols());
+        let Some(instrument_id) = symbols.instrument_id(symbol) else {
+            return Ok(None);
+        };
+        let mut market = Market::new();
+        let rewind = market.rewind_to(self, ts)?;
+        let record = build_snapshot_record(&market, instrument_id, &symbols, ts, depth);
+        Ok(Some((record, rewind)))
+    }
+
+    /// The checkpointed market when it can seed a replay to `ts`, with its position.
+    fn checkpoint_before(&self, ts: i64) -> Option<(Market, u64, i64)> {
+        let path = self.checkpoint_path.as_ref()?;
+        if !path.exists() {
+            return None;
+        }
+        let checkpoint = match read_checkpoint(path) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                warn!(
+                    path = %path.display(),
+                    error = format!("{:#}", e),
+                    "replay ignores checkpoint"
+                );
+                return None;
+            }
+        };
+        let position = checkpoint.position;
+        (checkpoint.input_path == self.input_path && position.last_ts_event <= ts).then_some((
+            checkpoint.market,
+            position.records,
+            position.last_ts_event,
+        ))
+    }
+}
+
+impl Market {
+    /// Replaces this market with its state after every input record up to `ts`
+    /// (ts_event, inclusive), read from `source`. Replay stops at the first later
+    /// record, as ingest does for `TO_TS`.
+    pub fn rewind_to(&mut self, source: &ReplaySource, ts: i64) -> Result<Rewind> {
+        let start = Instant::now();
+        let mut input = DbnFileSource::open(&source.input_path)?;
+        let mut rewind = Rewind {
+            ts,
+            ..Rewind::default()
+        };
+        let mut market = match source.checkpoint_before(ts) {
+            Some((market, records, last_ts_event)) => {
+                skip_to_checkpoint(&mut input, records, last_ts_event).with_context(|| {
+                    format!("failed to replay {} from its checkpoint", source.input_path)
+                })?;
+                rewind.from_checkpoint = true;
+                rewind.records = records;
+                rewind.last_ts_event = last_ts_event;
+                market
+            }
+            None => Market::new(),
+        }
+        .with_fill_handling(source.fills.clone())
+        .with_book_policies(source.policies.clone())
+        .with_depth_cap(source.depth_cap);
+        loop {
+            let rec = match input.next_record() {
+                Ok(Some(rec)) => rec,
+                Ok(None) => break,
+                // Skipped during ingest as well
+                Err(_) => continue,
+            };
+            let ts_event = rec.hd.ts_event as i64;
+            if ts_event > ts {
+                break;
+            }
+            rewind.records += 1;
+            if market.apply(rec).is_ok() {
+                rewind.applied += 1;
+            }
+            rewind.last_ts_event = ts_event;
+        }
+        *self = market;
+        rewind.elapsed_ms = start.elapsed().as_millis() as u64;
+        info!(
+            path = %source.input_path,
+            ts,
+            from_checkpoint = rewind.from_checkpoint,
+            records = rewind.records,
+            applied = rewind.applied,
+            elapsed_ms = rewind.elapsed_ms,
+            "replay_rewind"
+        );
+        Ok(rewind)
+    }
+}
+
+/// Reads past the records a checkpoint covers, checking the last one matches it.
+fn skip_to_checkpoint(input: &mut DbnFileSource, records: u64, last_ts_event: i64) -> Result<()> {
+    let mut skipped = 0u64;
+    let mut last_seen = 0i64;
+    while skipped < records {
+        match input.next_record() {
+            Ok(Some(rec)) => {
+                skipped += 1;
+                last_seen = rec.hd.ts_event as i64;
+            }
+            Ok(None) => bail!(
+                "input ends after {} records but the checkpoint is at record {}",
+                skipped,
+                records
+            ),
+            Err(_) => {}
+        }
+    }
+    if skipped > 0 && last_seen != last_ts_event {
+        bail!(
+            "record {} has ts_event {}, checkpoint expects {}",
+            records,
+            last_seen,
+            last_ts_event
+        );
+    }
+    Ok(())
+}
@@ -1,65 +1,1612 @@
-use std::{net::SocketAddr, sync::Arc, thread};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result};
-use arc_swap::ArcSwapOption;
-use axum::{Json, Router, extract::State, http::StatusCode, response::IntoResponse, routing::get};
+use anyhow::{Context, Result, bail};
+use axum::{
+    Extension, Json, Router,
+    extract::{
+        Path, Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware,
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{get, post},
+};
+use futures_util::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::broadcast::{
+        self,
+        error::{RecvError, TryRecvError},
+    },
+    time::MissedTickBehavior,
+};
+use tracing::{error, info, info_span, warn};
 
-use crate::snapshot::SnapshotRecord;
+use crate::{
+    access_log::{RequestId, access_log},
+    analytics::{
+        Analytics, AnalyticsEvent,
+        bars::{BAR_HISTORY, BarStore},
+        rolling::RollingStats,
+    },
+    book_query::{BookQueries, OrderFilter, OrderView, QueuePosition, Unanswered},
+    compression::{CompressionConfig, StreamGuard, StreamStats, deflate_message, stream_body},
+    instruments::InstrumentRegistry,
+    latency::{PublishLatency, wall_clock_ns},
+    order_book::PoolMetrics,
+    progress::IngestStats,
+    queue::QueueStats,
+    replay::ReplaySource,
+    shutdown::Shutdown,
+    sim::{CancelOutcome, NewSimOrder, Simulator},
+    snapshot::{
+        BookDelta, DEFAULT_TOP_LEVELS, DepthChart, EncodedCache, FlowSignals, Notional,
+        PriceFormat, SharedSnapshot, Snapshot, SnapshotEncoding, SnapshotRecord, SnapshotRegistry,
+        SymbolMap, build_delta_record, build_l3_snapshot, snapshot_to_mbp_output,
+    },
+    storage::{
+        LevelQuery, SnapshotQuery, StorageStats, load_level_history, load_snapshot_at,
+        load_snapshots, load_snapshots_at, ping_database,
+    },
+    supervisor::{SinkHealth, SinkSwitches},
+    trades::{TAPE_LEN, TradeTape},
+    venue_stats::VenueStats,
+};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct ServerConfig {
     pub addr: SocketAddr,
+    /// Upper bound on SSE events per second per client; clients may ask for less.
+    pub sse_max_rate: f64,
+    /// Snapshots per second sent to WebSocket clients demoted to the conflated tier.
+    pub ws_conflated_rate: f64,
+    pub compression: CompressionConfig,
+}
+
+/// One pipeline's snapshots, served under `/<prefix>` or at the root when the prefix is empty.
+#[derive(Clone)]
+pub struct Namespace {
+    /// Route prefix such as `replay/2024-05-01`; surrounding slashes are ignored.
+    pub prefix: String,
+    pub registry: Arc<SnapshotRegistry>,
+    /// Every new snapshot from ingest, for streaming clients.
+    pub updates: broadcast::Sender<SharedSnapshot>,
+    /// Trade-through and locked/crossed analytics for `/analytics` and `/sse/events`.
+    pub analytics: Arc<Analytics>,
+    /// Simulated order entry under `/sim/orders`, when enabled for the pipeline.
+    pub simulator: Option<Arc<Simulator>>,
+    /// Recent executions for `/trades`.
+    pub trades: Arc<TradeTape>,
+    /// Latest imbalances and statistics for `/imbalance` and `/statistics`.
+    pub venue: Arc<VenueStats>,
+    /// Time bars for `/bars`.
+    pub bars: Arc<BarStore>,
+    /// Rolling book statistics for `/stats`, when this process runs the ingest loop.
+    pub stats: Option<Arc<RollingStats>>,
+    /// DBN input `/book/at` rebuilds past books from, when the pipeline reads one.
+    pub replay: Option<Arc<ReplaySource>>,
+    /// Order lookups against the live books for `/order` and `/queue_pos`, when the
+    /// pipeline builds books.
+    pub books: Option<Arc<BookQueries>>,
+    /// Instrument definitions for `/instruments` and per-instrument price decimals.
+    pub instruments: Arc<InstrumentRegistry>,
+    /// Prices of `format=mbp` responses, as in the pipeline's MBP output.
+    pub mbp_prices: PriceFormat,
+}
+
+/// Pipeline state the server reads from.
+#[derive(Clone)]
+pub struct ServerContext {
+    pub namespaces: Vec<Namespace>,
+    pub sinks: Arc<SinkHealth>,
+    /// Runtime sink toggles behind `/admin/sinks`.
+    pub switches: Arc<SinkSwitches>,
+    /// Postgres holding persisted snapshots, for historical queries.
+    pub db_url: Option<Arc<String>>,
+    /// Storage writer counters for `/metrics`, when this process runs the writers.
+    pub storage: Option<Arc<StorageStats>>,
+    /// Order slab usage for `/metrics`, when this process runs the ingest loop.
+    pub order_pool: Option<Arc<PoolMetrics>>,
+    /// Writer queue counters for `/metrics`, when this process runs the ingest loop.
+    pub queues: Option<Arc<QueueStats>>,
+    /// Snapshot publication latency for `/metrics`, when this process runs the ingest loop.
+    pub latency: Option<Arc<PublishLatency>>,
+    /// Ingest progress for `/status`, when this process runs the ingest loop.
+    pub ingest: Option<Arc<IngestStats>>,
+    /// Stops the server and closes WebSocket and SSE streams.
+    pub shutdown: Shutdown,
 }
 
 #[derive(Clone)]
 struct AppState {
-    latest: Arc<ArcSwapOption<SnapshotRecord>>,
+    registry: Arc<SnapshotRegistry>,
+    updates: broadcast::Sender<SharedSnapshot>,
+    analytics: Arc<Analytics>,
+    simulator: Option<Arc<Simulator>>,
+    trades: Arc<TradeTape>,
+    venue: Arc<VenueStats>,
+    bars: Arc<BarStore>,
+    stats: Option<Arc<RollingStats>>,
+    replay: Option<Arc<ReplaySource>>,
+    books: Option<Arc<BookQueries>>,
+    instruments: Arc<InstrumentRegistry>,
+    mbp_prices: PriceFormat,
+    streams: Arc<StreamStats>,
+    db_url: Option<Arc<String>>,
+    shutdown: Shutdown,
+    sse_max_rate: f64,
+    ws_conflated_rate: f64,
+    compression: CompressionConfig,
+}
+
+/// How long `/readyz` waits for Postgres to answer.
+const READY_DB_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Server-wide state behind `/healthz`, `/readyz`, `/status`, `/metrics` and `/admin/sinks`.
+#[derive(Clone)]
+struct StatusState {
+    /// Every namespace's snapshots, for the readiness check and the last snapshot.
+    registries: Vec<Arc<SnapshotRegistry>>,
+    db_url: Option<Arc<String>>,
+    ingest: Option<Arc<IngestStats>>,
+    sinks: Arc<SinkHealth>,
+    switches: Arc<SinkSwitches>,
+    streams: Arc<StreamStats>,
+    storage: Option<Arc<StorageStats>>,
+    order_pool: Option<Arc<PoolMetrics>>,
+    queues: Option<Arc<QueueStats>>,
+    latency: Option<Arc<PublishLatency>>,
+}
+
+/// Per-client filter sent by WebSocket clients, e.g. `{"symbols":["CLX5"],"depth":5}`.
+/// Missing fields mean no filtering.
+#[derive(Debug, Default, Deserialize)]
+struct Subscribe {
+    symbols: Option<Vec<String>>,
+    depth: Option<usize>,
+    /// Deflate large messages into binary frames.
+    #[serde(default)]
+    compress: bool,
+}
+
+impl Subscribe {
+    fn matches(&self, snapshot: &SharedSnapshot) -> bool {
+        self.symbols
+            .as_ref()
+            .is_none_or(|symbols| symbols.iter().any(|s| *s == snapshot.payload.symbol))
+    }
 }
 
 pub fn spawn_http_server(
-    state: Arc<ArcSwapOption<SnapshotRecord>>,
+    context: ServerContext,
     config: ServerConfig,
 ) -> thread::JoinHandle<Result<()>> {
-    thread::spawn(move || blocking_server(state, config))
+    thread::spawn(move || blocking_server(context, config))
 }
 
-fn blocking_server(latest: Arc<ArcSwapOption<SnapshotRecord>>, config: ServerConfig) -> Result<()> {
+fn blocking_server(context: ServerContext, config: ServerConfig) -> Result<()> {
+    let _span = info_span!("server", addr = %config.addr).entered();
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .context("failed to build tokio runtime for http server")?;
     runtime.block_on(async move {
-        let app_state = AppState { latest };
-        let router = Router::new()
-            .route("/healthz", get(health))
-            .route("/snapshot", get(snapshot))
-            .with_state(app_state);
+        let shutdown = context.shutdown.clone();
+        let router = build_router(context, &config)?;
 
         let listener = tokio::net::TcpListener::bind(config.addr)
             .await
             .with_context(|| format!("failed to bind server to {}", config.addr))?;
 
-        println!("server_ready addr={}", config.addr);
+        info!("server_ready");
 
-        axum::serve(listener, router)
-            .with_graceful_shutdown(async {
-                let _ = tokio::signal::ctrl_c().await;
-            })
-            .await
-            .context("http server terminated unexpectedly")
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(async move { shutdown.wait().await })
+        .await
+        .context("http server terminated unexpectedly")
     })
 }
 
-async fn health() -> impl IntoResponse {
-    StatusCode::OK
+fn build_router(context: ServerContext, config: &ServerConfig) -> Result<Router> {
+    let streams = Arc::new(StreamStats::new());
+    let prefixes: Vec<String> = context
+        .namespaces
+        .iter()
+        .map(|namespace| namespace.prefix.trim_matches('/').to_owned())
+        .collect();
+    let mut router = Router::new()
+        .route("/healthz", get(health))
+        .route("/readyz", get(ready))
+        .route("/status", get(status))
+        .route("/metrics", get(metrics))
+        .route("/admin/sinks", get(list_sinks).put(toggle_sink))
+        .with_state(StatusState {
+            registries: context
+                .namespaces
+                .iter()
+                .map(|namespace| namespace.registry.clone())
+                .collect(),
+            db_url: context.db_url.clone(),
+            ingest: context.ingest,
+            sinks: context.sinks,
+            switches: context.switches,
+            streams: streams.clone(),
+            storage: context.storage,
+            order_pool: context.order_pool,
+            queues: context.queues,
+            latency: context.latency,
+        })
+        .route(
+            "/namespaces",
+            get({
+                let prefixes = prefixes.clone();
+                move || async move { Json(prefixes) }
+            }),
+        );
+
+    let mut seen = HashSet::new();
+    for (namespace, prefix) in context.namespaces.into_iter().zip(prefixes) {
+        if !seen.insert(prefix.clone()) {
+            bail!("namespace {:?} is configured more than once", prefix);
+        }
+        let mut routes = Router::new()
+            .route("/snapshot", get(snapshot))
+            .route("/snapshot/:symbol", get(snapshot_by_symbol))
+            .route("/depthchart", get(depth_chart))
+            .route("/level", get(level_history))
+            .route("/snapshots", get(stored_snapshots))
+            .route("/snapshots/at", get(stored_snapshots_at))
+            .route("/book/at", get(book_at))
+            .route("/ws/snapshots", get(ws_snapshots))
+            .route("/sse/snapshot", get(sse_snapshot))
+            .route("/analytics", get(analytics_report))
+            .route("/analytics/latest", get(latest_signals))
+            .route("/sse/events", get(sse_events))
+            .route("/trades", get(recent_trades))
+            .route("/bars", get(recent_bars))
+            .route("/imbalance", get(latest_imbalances))
+            .route("/statistics", get(latest_statistics))
+            .route("/instruments", get(list_instruments));
+        if namespace.simulator.is_some() {
+            routes = routes
+                .route("/sim/orders", post(sim_submit))
+                .route("/sim/orders/:id", get(sim_order).delete(sim_cancel));
+        }
+        if namespace.stats.is_some() {
+            routes = routes.route("/stats", get(book_stats));
+        }
+        if namespace.books.is_some() {
+            routes = routes
+                .route("/order/:order_id", get(order_lookup))
+                .route("/queue_pos/:order_id", get(queue_pos_lookup))
+                .route("/l3", get(l3_snapshot));
+        }
+        let routes = routes.with_state(AppState {
+            registry: namespace.registry,
+            updates: namespace.updates,
+            analytics: namespace.analytics,
+            simulator: namespace.simulator,
+            trades: namespace.trades,
+            venue: namespace.venue,
+            bars: namespace.bars,
+            stats: namespace.stats,
+            replay: namespace.replay,
+            books: namespace.books,
+            instruments: namespace.instruments,
+            mbp_prices: namespace.mbp_prices,
+            streams: streams.clone(),
+            db_url: context.db_url.clone(),
+            shutdown: context.shutdown.clone(),
+            sse_max_rate: config.sse_max_rate,
+            ws_conflated_rate: config.ws_conflated_rate,
+            compression: config.compression,
+        });
+        router = if prefix.is_empty() {
+            router.merge(routes)
+        } else {
+            router.nest(&format!("/{}", prefix), routes)
+        };
+    }
+    Ok(router.layer(middleware::from_fn(access_log)))
+}
+
+async fn health(State(state): State<StatusState>) -> impl IntoResponse {
+    let status = if state.sinks.is_healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(serde_json::json!({ "sinks": state.sinks.snapshot() })),
+    )
+}
+
+/// Ready once every namespace has a snapshot to serve and, when snapshots are stored in
+/// Postgres, the database answers.
+async fn ready(State(state): State<StatusState>) -> impl IntoResponse {
+    let snapshots = state
+        .registries
+        .iter()
+        .all(|registry| registry.latest().is_some());
+    let database = match state.db_url.clone() {
+        Some(db_url) => {
+            let ping =
+                tokio::task::spawn_blocking(move || ping_database(&db_url, READY_DB_TIMEOUT)).await;
+            match ping {
+                Ok(Ok(())) => Some(true),
+                Ok(Err(e)) => {
+                    warn!(
+                        error = format!("{:#}", e),
+                        "readiness database check failed"
+                    );
+                    Some(false)
+                }
+                Err(_) => Some(false),
+            }
+        }
+        None => None,
+    };
+    let ready = snapshots && database != Some(false);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "ready": ready,
+            "snapshots": snapshots,
+            "database": database,
+        })),
+    )
+}
+
+async fn status(State(state): State<StatusState>) -> impl IntoResponse {
+    let last = state
+        .registries
+        .iter()
+        .filter_map(|registry| registry.latest())
+        .max_by_key(|snapshot| snapshot.ts_event);
+    let queues = state.queues.as_ref().map(|queues| queues.snapshot());
+    let storage = state.storage.as_ref().map(|storage| {
+        let storage = storage.snapshot();
+        // Event time the writers are behind the newest published snapshot
+        let lag_ms = last
+            .as_ref()
+            .zip(storage.newest_ts_event)
+            .map(|(last, stored)| (last.ts_event - stored).max(0) / 1_000_000);
+        serde_json::json!({
+            "written": storage.written,
+            "newest_ts_event": storage.newest_ts_event,
+            "lag_ms": lag_ms,
+            "queued": queues.as_ref().map(|queues| {
+                queues
+                    .iter()
+                    .filter(|queue| queue.name.starts_with("storage"))
+                    .map(|queue| queue.depth)
+                    .sum::<u64>()
+            }),
+        })
+    });
+    let drops = queues.as_ref().map(|queues| {
+        queues
+            .iter()
+            .map(|queue| (queue.name.clone(), queue.dropped + queue.evicted))
+            .collect::<BTreeMap<_, _>>()
+    });
+    Json(serde_json::json!({
+        "healthy": state.sinks.is_healthy(),
+        "ingest": state.ingest.as_ref().map(|ingest| ingest.snapshot()),
+        "last_snapshot": last.map(|snapshot| serde_json::json!({
+            "symbol": snapshot.payload.symbol,
+            "instrument_id": snapshot.instrument_id,
+            "ts_event": snapshot.ts_event,
+            "ts_recv": snapshot.ts_recv,
+            "age_ms": (snapshot.built_at > 0)
+                .then(|| (wall_clock_ns() - snapshot.built_at).max(0) / 1_000_000),
+        })),
+        "queues": queues.as_ref().map(|queues| {
+            queues
+                .iter()
+                .map(|queue| (queue.name.clone(), queue.depth))
+                .collect::<BTreeMap<_, _>>()
+        }),
+        "storage": storage,
+        "drops": drops,
+    }))
+}
+
+async fn metrics(State(state): State<StatusState>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "sinks": state.sinks.snapshot(),
+        "streams": state.streams.snapshot(),
+        "storage": state.storage.as_ref().map(|storage| storage.snapshot()),
+        "order_pool": state.order_pool.as_ref().map(|pool| pool.snapshot()),
+        "queues": state.queues.as_ref().map(|queues| queues.snapshot()),
+        "latency": state.latency.as_ref().map(|latency| latency.snapshot()),
+    }))
+}
+
+/// One sink's switch, as listed by `GET /admin/sinks` and sent to `PUT /admin/sinks`.
+/// Names are the ones sinks log under, e.g. `postgres`, `file:snapshots.jsonl` or `mbp`.
+#[derive(Debug, Deserialize, Serialize)]
+struct SinkToggle {
+    name: String,
+    enabled: bool,
+}
+
+async fn list_sinks(State(state): State<StatusState>) -> impl IntoResponse {
+    let sinks: Vec<SinkToggle> = state
+        .switches
+        .snapshot()
+        .into_iter()
+        .map(|(name, enabled)| SinkToggle { name, enabled })
+        .collect();
+    Json(sinks)
+}
+
+/// Takes effect before the sink's next batch; a batch already being written finishes.
+async fn toggle_sink(State(state): State<StatusState>, Json(toggle): Json<SinkToggle>) -> Response {
+    if !state.switches.set(&toggle.name, toggle.enabled) {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("no sink named {}", toggle.name),
+        )
+            .into_response();
+    }
+    info!(sink = %toggle.name, enabled = toggle.enabled, "sink toggled by admin");
+    Json(toggle).into_response()
+}
+
+/// Selection and shape of `/snapshot` and `/snapshot/:symbol` responses.
+#[derive(Debug, Default, Deserialize)]
+struct SnapshotParams {
+    symbol: Option<String>,
+    instrument_id: Option<u32>,
+    /// Levels per side to keep.
+    depth: Option<usize>,
+    #[serde(default)]
+    format: SnapshotFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SnapshotFormat {
+    /// The `Snapshot` as streamed and stored.
+    #[default]
+    Snapshot,
+    /// The `MbpOutput` shape of the MBP writer's JSON lines.
+    Mbp,
+}
+
+/// The latest snapshot across instruments, or of the instrument selected by `symbol`
+/// and/or `instrument_id` (both must match when both are given).
+async fn snapshot(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<SnapshotParams>,
+) -> Response {
+    let selected = match (params.instrument_id, params.symbol.as_deref()) {
+        (None, None) => {
+            return snapshot_response(state.registry.latest(), &params, &headers, &state);
+        }
+        (Some(instrument_id), symbol) => state
+            .registry
+            .get(instrument_id)
+            .filter(|snapshot| symbol.is_none_or(|symbol| snapshot.payload.symbol == symbol)),
+        (None, Some(symbol)) => state.registry.get_by_symbol(symbol),
+    };
+    match selected {
+        Some(snapshot) => snapshot_response(Some(snapshot), &params, &headers, &state),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn snapshot_by_symbol(
+    State(state): State<AppState>,
+    Path(symbol): Path<String>,
+    headers: HeaderMap,
+    Query(params): Query<SnapshotParams>,
+) -> Response {
+    match state.registry.get_by_symbol(&symbol) {
+        Some(snapshot) => snapshot_response(Some(snapshot), &params, &headers, &state),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthChartParams {
+    symbol: String,
+    /// As-of `ts_event` in nanoseconds; the live book when omitted.
+    ts: Option<i64>,
 }
 
-async fn snapshot(State(state): State<AppState>) -> impl IntoResponse {
-    match state.latest.load_full() {
-        Some(snapshot) => match snapshot.to_json() {
-            Ok(json) => Json(json).into_response(),
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+async fn depth_chart(
+    State(state): State<AppState>,
+    Query(params): Query<DepthChartParams>,
+) -> Response {
+    let live = state.registry.get_by_symbol(&params.symbol);
+    // The live book answers any time at or after its last update
+    let live = live.filter(|live| params.ts.is_none_or(|ts| ts >= live.ts_event));
+    if let Some(live) = live {
+        return Json(DepthChart::from_snapshot(&live.payload)).into_response();
+    }
+    let Some(ts) = params.ts else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(db_url) = state.db_url.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "historical depth requires the postgres sink",
+        )
+            .into_response();
+    };
+    let symbol = params.symbol;
+    let stored = tokio::task::spawn_blocking(move || load_snapshot_at(&db_url, &symbol, ts)).await;
+    match stored {
+        Ok(Ok(Some(snapshot))) => {
+            Json(DepthChart::from_snapshot(&snapshot.payload)).into_response()
+        }
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(e)) => {
+            error!(error = format!("{:#}", e), "depthchart query failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Renders a snapshot in the encoding the `Accept` header asks for; the MBP shape is
+/// JSON only.
+fn snapshot_response(
+    snapshot: Option<SharedSnapshot>,
+    params: &SnapshotParams,
+    headers: &HeaderMap,
+    state: &AppState,
+) -> Response {
+    let Some(snapshot) = snapshot else {
+        return StatusCode::NO_CONTENT.into_response();
+    };
+    // Full snapshots come from the shared record's cache; truncated ones are encoded
+    // per request
+    let truncated = params.depth.map(|depth| SnapshotRecord {
+        instrument_id: snapshot.instrument_id,
+        publisher_id: snapshot.publisher_id,
+        ts_event: snapshot.ts_event,
+        ts_recv: snapshot.ts_recv,
+        built_at: snapshot.built_at,
+        payload: snapshot.payload.truncated(depth),
+        cache: EncodedCache::default(),
+    });
+    let record = truncated.as_ref().unwrap_or(&snapshot);
+    let encoding = match params.format {
+        SnapshotFormat::Snapshot => SnapshotEncoding::negotiate(
+            headers
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok()),
+        ),
+        SnapshotFormat::Mbp => {
+            let prices = state
+                .instruments
+                .price_format(record.instrument_id, state.mbp_prices);
+            return Json(snapshot_to_mbp_output(record, prices)).into_response();
+        }
+    };
+    match record.encoded(encoding) {
+        Ok(body) => (
+            [
+                (header::CONTENT_TYPE, encoding.content_type()),
+                (header::VARY, "accept"),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct WsParams {
+    /// json, msgpack or protobuf; overrides `Accept`, which browsers cannot set on a
+    /// WebSocket.
+    encoding: Option<String>,
+}
+
+async fn ws_snapshots(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Query(params): Query<WsParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let encoding = match params.encoding.as_deref() {
+        Some(raw) => match SnapshotEncoding::parse(raw) {
+            Ok(encoding) => encoding,
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        },
+        None => SnapshotEncoding::negotiate(
+            headers
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok()),
+        ),
+    };
+    let session = WsSession {
+        updates: state.updates.subscribe(),
+        shutdown: state.shutdown.clone(),
+        guard: state.streams.register(&request_id.0, "ws"),
+        compress_min_bytes: state.compression.ws_min_bytes,
+        conflated_gap: Duration::from_secs_f64(1.0 / state.ws_conflated_rate),
+        filter: Subscribe::default(),
+        tier: Tier::Live,
+        encoding,
+        last_sent: HashMap::new(),
+    };
+    ws.on_upgrade(move |socket| session.run(socket))
+        .into_response()
+}
+
+/// Delivery tier of a WebSocket client, echoed in every message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Tier {
+    /// Keeping up: every update is sent as a delta against the last message.
+    Live,
+    /// Fell behind the broadcast buffer: the latest full snapshot per symbol is sent
+    /// at `conflated_gap` intervals until the client drains quickly again.
+    Conflated,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum WsPayload<'a> {
+    Snapshot(&'a Snapshot),
+    Delta(&'a BookDelta),
+}
+
+#[derive(Serialize)]
+struct WsMessage<'a> {
+    tier: Tier,
+    #[serde(flatten)]
+    payload: WsPayload<'a>,
+}
+
+/// An encoded WebSocket message.
+enum WsFrame {
+    Json(String),
+    Binary(Vec<u8>),
+}
+
+/// Conflated ticks in a row that must finish sending within half the gap before a
+/// client is promoted back to live deltas.
+const RECOVERY_TICKS: u32 = 5;
+
+struct WsSession {
+    updates: broadcast::Receiver<SharedSnapshot>,
+    shutdown: Shutdown,
+    guard: StreamGuard,
+    compress_min_bytes: usize,
+    conflated_gap: Duration,
+    filter: Subscribe,
+    tier: Tier,
+    encoding: SnapshotEncoding,
+    /// Last snapshot sent per symbol, after depth truncation; deltas are taken against it.
+    last_sent: HashMap<String, SnapshotRecord>,
+}
+
+impl WsSession {
+    async fn run(mut self, mut socket: WebSocket) {
+        let mut ticker: Option<tokio::time::Interval> = None;
+        let mut fast_ticks = 0u32;
+        loop {
+            tokio::select! {
+                update = self.updates.recv(), if self.tier == Tier::Live => {
+                    let snapshot = match update {
+                        Ok(snapshot) => snapshot,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(
+                                request_id = %self.guard.request_id(),
+                                skipped,
+                                "ws client lagged, switching to conflated"
+                            );
+                            self.tier = Tier::Conflated;
+                            let mut interval = tokio::time::interval(self.conflated_gap);
+                            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                            ticker = Some(interval);
+                            fast_ticks = 0;
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    if self.filter.matches(&snapshot) && self.send_update(&mut socket, &snapshot).await.is_err() {
+                        break;
+                    }
+                }
+                _ = tick(&mut ticker) => {
+                    let start = Instant::now();
+                    let Ok(closed) = self.send_conflated(&mut socket).await else {
+                        break;
+                    };
+                    if closed {
+                        break;
+                    }
+                    if start.elapsed() < self.conflated_gap / 2 {
+                        fast_ticks += 1;
+                    } else {
+                        fast_ticks = 0;
+                    }
+                    if fast_ticks >= RECOVERY_TICKS {
+                        info!(
+                            request_id = %self.guard.request_id(),
+                            "ws client caught up, switching to live"
+                        );
+                        self.tier = Tier::Live;
+                        ticker = None;
+                    }
+                }
+                _ = self.shutdown.wait() => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+                incoming = socket.recv() => {
+                    match incoming {
+                        Some(Ok(Message::Text(text))) => match serde_json::from_str::<Subscribe>(&text) {
+                            Ok(subscribe) => {
+                                self.filter = subscribe;
+                                // Depth may have changed, so restart from full snapshots
+                                self.last_sent.clear();
+                            }
+                            Err(e) => {
+                                let error = serde_json::json!({ "error": format!("invalid subscribe message: {}", e) });
+                                if socket.send(Message::Text(error.to_string())).await.is_err() {
+                                    break;
+                                }
+                            }
+                        },
+                        Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                        Some(Ok(_)) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends a delta against the last snapshot sent for the symbol, or the full
+    /// snapshot when there is none. Unchanged views are skipped.
+    async fn send_update(
+        &mut self,
+        socket: &mut WebSocket,
+        snapshot: &SnapshotRecord,
+    ) -> Result<(), axum::Error> {
+        let view = self.view(snapshot);
+        let frame = match self.last_sent.get(&view.payload.symbol) {
+            Some(prev) => {
+                let delta = build_delta_record(prev, &view);
+                if delta.payload.is_empty() {
+                    return Ok(());
+                }
+                self.frame(self.tier, WsPayload::Delta(&delta.payload), &view)
+            }
+            None => self.frame(self.tier, WsPayload::Snapshot(&view.payload), &view),
+        };
+        // A skipped frame leaves the next delta against what the client last got
+        match frame {
+            Ok(frame) => self.send_frame(socket, frame).await?,
+            Err(e) => {
+                error!(
+                    error = format!("{:#}", e),
+                    "websocket frame encoding failed"
+                );
+                return Ok(());
+            }
+        }
+        self.last_sent.insert(view.payload.symbol.clone(), view);
+        Ok(())
+    }
+
+    /// Drains everything queued and sends the newest full snapshot per symbol.
+    /// Returns true once the broadcast channel has closed.
+    async fn send_conflated(&mut self, socket: &mut WebSocket) -> Result<bool, axum::Error> {
+        let mut latest: HashMap<String, SharedSnapshot> = HashMap::new();
+        let closed = loop {
+            match self.updates.try_recv() {
+                Ok(snapshot) if self.filter.matches(&snapshot) => {
+                    latest.insert(snapshot.payload.symbol.clone(), snapshot);
+                }
+                Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Closed) => break true,
+            }
+        };
+        for snapshot in latest.into_values() {
+            let view = self.view(&snapshot);
+            match self.frame(Tier::Conflated, WsPayload::Snapshot(&view.payload), &view) {
+                Ok(frame) => self.send_frame(socket, frame).await?,
+                Err(e) => {
+                    error!(
+                        error = format!("{:#}", e),
+                        "websocket frame encoding failed"
+                    );
+                    continue;
+                }
+            }
+            self.last_sent.insert(view.payload.symbol.clone(), view);
+        }
+        Ok(closed)
+    }
+
+    fn view(&self, snapshot: &SnapshotRecord) -> SnapshotRecord {
+        match self.filter.depth {
+            Some(depth) => SnapshotRecord {
+                payload: snapshot.payload.truncated(depth),
+                ..snapshot.clone()
+            },
+            None => snapshot.clone(),
+        }
+    }
+
+    /// Encodes a message in the session's encoding. Protobuf has no delta or tier, so
+    /// protobuf clients get the `batonics.v1.Snapshot` of `view` every time.
+    fn frame(&self, tier: Tier, payload: WsPayload<'_>, view: &SnapshotRecord) -> Result<WsFrame> {
+        let message = WsMessage { tier, payload };
+        match self.encoding {
+            SnapshotEncoding::Json => Ok(WsFrame::Json(serde_json::to_string(&message)?)),
+            SnapshotEncoding::MessagePack => {
+                let mut out = Vec::new();
+                message.serialize(&mut rmp_serde::Serializer::new(&mut out).with_struct_map())?;
+                Ok(WsFrame::Binary(out))
+            }
+            SnapshotEncoding::Protobuf => {
+                let mut out = Vec::new();
+                self.encoding.encoder().encode(view, &mut out)?;
+                Ok(WsFrame::Binary(out))
+            }
+        }
+    }
+
+    /// Sends a frame, deflating JSON when the client asked for compression. Binary
+    /// encodings go out as they are, so a binary message always means one encoding.
+    async fn send_frame(&self, socket: &mut WebSocket, frame: WsFrame) -> Result<(), axum::Error> {
+        let (message, raw_len) = match frame {
+            WsFrame::Json(json) => {
+                let raw_len = json.len();
+                let message = if self.filter.compress && raw_len >= self.compress_min_bytes {
+                    match deflate_message(json.as_bytes()) {
+                        Ok(compressed) => Message::Binary(compressed),
+                        Err(_) => Message::Text(json),
+                    }
+                } else {
+                    Message::Text(json)
+                };
+                (message, raw_len)
+            }
+            WsFrame::Binary(data) => {
+                let raw_len = data.len();
+                (Message::Binary(data), raw_len)
+            }
+        };
+        let wire_len = match &message {
+            Message::Binary(data) => data.len(),
+            _ => raw_len,
+        };
+        socket.send(message).await?;
+        self.guard.counters().record(raw_len, wire_len);
+        Ok(())
+    }
+}
+
+async fn tick(ticker: &mut Option<tokio::time::Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SseParams {
+    symbol: Option<String>,
+    depth: Option<usize>,
+    max_rate: Option<f64>,
+}
+
+/// Streams snapshots as SSE events. Updates arriving faster than the client's rate
+/// are conflated so only the newest matching snapshot is sent after each gap.
+async fn sse_snapshot(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Query(params): Query<SseParams>,
+) -> Response {
+    let rate = params
+        .max_rate
+        .filter(|rate| *rate > 0.0)
+        .map_or(state.sse_max_rate, |rate| rate.min(state.sse_max_rate));
+    let min_gap = Duration::from_secs_f64(1.0 / rate);
+    let filter = Subscribe {
+        symbols: params.symbol.map(|symbol| vec![symbol]),
+        depth: params.depth,
+        compress: false,
+    };
+    let updates = state.updates.subscribe();
+    let shutdown = state.shutdown.clone();
+
+    let events = stream::unfold(
+        (updates, filter, None::<Instant>),
+        move |(mut updates, filter, last_sent)| async move {
+            loop {
+                let mut snapshot = next_matching(&mut updates, &filter).await?;
+                if let Some(last_sent) = last_sent {
+                    tokio::time::sleep_until((last_sent + min_gap).into()).await;
+                    // Conflate whatever arrived while throttled
+                    loop {
+                        match updates.try_recv() {
+                            Ok(newer) if filter.matches(&newer) => snapshot = newer,
+                            Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Closed) => break,
+                        }
+                    }
+                }
+                let payload = match filter.depth {
+                    Some(depth) => serde_json::to_string(&snapshot.payload.truncated(depth))
+                        .map_err(anyhow::Error::from),
+                    None => snapshot
+                        .encoded(SnapshotEncoding::Json)
+                        .map(|json| String::from_utf8_lossy(&json).into_owned()),
+                };
+                let payload = match payload {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        error!(error = format!("{:#}", e), "sse snapshot encoding failed");
+                        continue;
+                    }
+                };
+                // Lets EventSource clients spot gaps through `lastEventId`
+                let event = Event::default()
+                    .event("snapshot")
+                    .id(format!(
+                        "{}:{}",
+                        snapshot.payload.symbol, snapshot.payload.seq
+                    ))
+                    .data(payload);
+                return Some((
+                    Ok::<_, Infallible>(event),
+                    (updates, filter, Some(Instant::now())),
+                ));
+            }
         },
-        None => StatusCode::NO_CONTENT.into_response(),
+    )
+    // Ends the response so the server's graceful shutdown is not held open
+    .take_until(async move { shutdown.wait().await });
+
+    let gzip = state.compression.sse_gzip && accepts_gzip(&headers);
+    let guard = state.streams.register(&request_id.0, "sse");
+    let (mut parts, body) = Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+        .into_parts();
+    if gzip {
+        parts
+            .headers
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        parts
+            .headers
+            .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    Response::from_parts(parts, stream_body(body, gzip, guard))
+}
+
+async fn analytics_report(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.analytics.report())
+}
+
+#[derive(Debug, Deserialize)]
+struct LatestSignalsParams {
+    symbol: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LatestSignals<'a> {
+    symbol: &'a str,
+    ts_event: i64,
+    seq: u64,
+    #[serde(flatten)]
+    signals: Option<&'a FlowSignals>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notional: Option<&'a Notional>,
+}
+
+/// Flow signals and notional of each symbol's latest snapshot, sorted by symbol, or of
+/// one symbol. Empty when ingest runs without `FLOW_SIGNALS` and `CONTRACT_MULTIPLIER`.
+async fn latest_signals(
+    State(state): State<AppState>,
+    Query(params): Query<LatestSignalsParams>,
+) -> Response {
+    let mut snapshots = state.registry.all();
+    if let Some(symbol) = &params.symbol {
+        snapshots.retain(|snapshot| &snapshot.payload.symbol == symbol);
+    }
+    snapshots.sort_by(|a, b| a.payload.symbol.cmp(&b.payload.symbol));
+    let latest: Vec<LatestSignals> = snapshots
+        .iter()
+        .filter(|snapshot| {
+            snapshot.payload.signals.is_some() || snapshot.payload.notional.is_some()
+        })
+        .map(|snapshot| LatestSignals {
+            symbol: &snapshot.payload.symbol,
+            ts_event: snapshot.ts_event,
+            seq: snapshot.payload.seq,
+            signals: snapshot.payload.signals.as_ref(),
+            notional: snapshot.payload.notional.as_ref(),
+        })
+        .collect();
+    match (&params.symbol, latest.first()) {
+        (Some(symbol), None) => (
+            StatusCode::NOT_FOUND,
+            format!("no flow signals or notional for {}", symbol),
+        )
+            .into_response(),
+        (Some(_), Some(latest)) => Json(latest).into_response(),
+        (None, _) => Json(latest).into_response(),
+    }
+}
+
+/// Points returned by `/level` unless `limit` asks for fewer.
+const LEVEL_HISTORY_LIMIT: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+struct LevelParams {
+    symbol: String,
+    /// Fixed-point price of the level.
+    price: i64,
+    from: Option<i64>,
+    to: Option<i64>,
+    /// Only snapshots stored by this ingest run.
+    run: Option<i64>,
+    limit: Option<usize>,
+}
+
+/// How the size and order count at one price evolved, from the stored snapshots.
+async fn level_history(
+    State(state): State<AppState>,
+    Query(params): Query<LevelParams>,
+) -> Response {
+    let Some(db_url) = state.db_url.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "level history requires the postgres sink",
+        )
+            .into_response();
+    };
+    if let (Some(from), Some(to)) = (params.from, params.to)
+        && from > to
+    {
+        return (StatusCode::BAD_REQUEST, "from must not be after to").into_response();
+    }
+    let query = LevelQuery {
+        symbol: params.symbol,
+        price: params.price,
+        from_ts: params.from,
+        to_ts: params.to,
+        run_id: params.run,
+        limit: params
+            .limit
+            .unwrap_or(LEVEL_HISTORY_LIMIT)
+            .min(LEVEL_HISTORY_LIMIT),
+    };
+    let history = tokio::task::spawn_blocking(move || {
+        load_level_history(&db_url, &query).map(|points| (query, points))
+    })
+    .await;
+    match history {
+        Ok(Ok((query, points))) => Json(serde_json::json!({
+            "symbol": query.symbol,
+            "price": query.price,
+            "points": points,
+        }))
+        .into_response(),
+        Ok(Err(e)) => {
+            error!(error = format!("{:#}", e), "level history query failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Most snapshots one `/snapshots` request returns.
+const SNAPSHOT_HISTORY_LIMIT: usize = 1_000;
+
+#[derive(Debug, Deserialize)]
+struct SnapshotsParams {
+    symbol: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    /// Only snapshots stored by this ingest run.
+    run: Option<i64>,
+    limit: Option<usize>,
+}
+
+/// Stored snapshots of a symbol over a `ts_event` range, oldest first; 100 unless
+/// `limit` asks for more.
+async fn stored_snapshots(
+    State(state): State<AppState>,
+    Query(params): Query<SnapshotsParams>,
+) -> Response {
+    let Some(db_url) = state.db_url.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "historical snapshots require the postgres sink",
+        )
+            .into_response();
+    };
+    if let (Some(from), Some(to)) = (params.from, params.to)
+        && from > to
+    {
+        return (StatusCode::BAD_REQUEST, "from must not be after to").into_response();
+    }
+    let query = SnapshotQuery {
+        symbol: params.symbol,
+        from_ts: params.from,
+        to_ts: params.to,
+        run_id: params.run,
+        limit: params.limit.unwrap_or(100).min(SNAPSHOT_HISTORY_LIMIT),
+    };
+    let stored = tokio::task::spawn_blocking(move || {
+        load_snapshots(&db_url, &query).map(|snapshots| (query.symbol, snapshots))
+    })
+    .await;
+    match stored {
+        Ok(Ok((symbol, snapshots))) => Json(serde_json::json!({
+            "symbol": symbol,
+            "snapshots": snapshots.iter().map(|s| &s.payload).collect::<Vec<_>>(),
+        }))
+        .into_response(),
+        Ok(Err(e)) => {
+            error!(error = format!("{:#}", e), "snapshots query failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotsAtParams {
+    ts: i64,
+    symbol: Option<String>,
+}
+
+/// The book of every symbol, or of `symbol`, as last stored at or before `ts`.
+async fn stored_snapshots_at(
+    State(state): State<AppState>,
+    Query(params): Query<SnapshotsAtParams>,
+) -> Response {
+    let Some(db_url) = state.db_url.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "historical snapshots require the postgres sink",
+        )
+            .into_response();
+    };
+    let ts = params.ts;
+    let stored = tokio::task::spawn_blocking(move || {
+        load_snapshots_at(&db_url, ts, params.symbol.as_deref())
+    })
+    .await;
+    match stored {
+        Ok(Ok(snapshots)) => Json(serde_json::json!({
+            "ts": ts,
+            "snapshots": snapshots.iter().map(|s| &s.payload).collect::<Vec<_>>(),
+        }))
+        .into_response(),
+        Ok(Err(e)) => {
+            error!(error = format!("{:#}", e), "snapshots query failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BookAtParams {
+    symbol: String,
+    /// `ts_event` in nanoseconds; records up to and including it are applied.
+    ts: i64,
+    depth: Option<usize>,
+}
+
+/// The symbol's book as of `ts`, rebuilt by replaying the DBN input. Meant for
+/// debugging and research: each request re-reads the input from the checkpoint or
+/// its start, so it takes as long as ingesting that much of the file.
+async fn book_at(State(state): State<AppState>, Query(params): Query<BookAtParams>) -> Response {
+    let Some(replay) = state.replay.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "rewinding requires a DBN file input",
+        )
+            .into_response();
+    };
+    let depth = params.depth.unwrap_or(DEFAULT_TOP_LEVELS);
+    let rebuilt =
+        tokio::task::spawn_blocking(move || replay.book_at(&params.symbol, params.ts, depth)).await;
+    match rebuilt {
+        Ok(Ok(Some((snapshot, rewind)))) => Json(serde_json::json!({
+            "rewind": rewind,
+            "snapshot": snapshot.payload,
+        }))
+        .into_response(),
+        Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+        Ok(Err(e)) => {
+            error!(error = format!("{:#}", e), "book rewind failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeParams {
+    symbol: String,
+    limit: Option<usize>,
+}
+
+/// The symbol's latest trades, oldest first; 100 unless `limit` asks for more.
+async fn recent_trades(
+    State(state): State<AppState>,
+    Query(params): Query<TradeParams>,
+) -> Response {
+    let limit = params.limit.unwrap_or(100).min(TAPE_LEN);
+    let trades = state.trades.recent(&params.symbol, limit);
+    Json(
+        trades
+            .iter()
+            .map(|trade| trade.as_ref())
+            .collect::<Vec<_>>(),
+    )
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct VenueParams {
+    symbol: Option<String>,
+}
+
+/// The latest auction imbalance of each publisher of one symbol, or of every symbol.
+async fn latest_imbalances(
+    State(state): State<AppState>,
+    Query(params): Query<VenueParams>,
+) -> Response {
+    let imbalances = state.venue.imbalances(params.symbol.as_deref());
+    Json(
+        imbalances
+            .iter()
+            .map(|imbalance| imbalance.as_ref())
+            .collect::<Vec<_>>(),
+    )
+    .into_response()
+}
+
+/// The latest value of each venue statistic of one symbol, or of every symbol.
+async fn latest_statistics(
+    State(state): State<AppState>,
+    Query(params): Query<VenueParams>,
+) -> Response {
+    let statistics = state.venue.statistics(params.symbol.as_deref());
+    Json(
+        statistics
+            .iter()
+            .map(|statistic| statistic.as_ref())
+            .collect::<Vec<_>>(),
+    )
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct InstrumentParams {
+    symbol: Option<String>,
+}
+
+/// Every defined instrument by id, or those whose raw symbol is `symbol`. Empty
+/// without `INSTRUMENT_DEFS_PATH` or definitions in the feed.
+async fn list_instruments(
+    State(state): State<AppState>,
+    Query(params): Query<InstrumentParams>,
+) -> Response {
+    let mut instruments = state.instruments.all();
+    if let Some(symbol) = &params.symbol {
+        instruments.retain(|instrument| instrument.raw_symbol == *symbol);
+    }
+    Json(
+        instruments
+            .iter()
+            .map(|instrument| instrument.as_ref())
+            .collect::<Vec<_>>(),
+    )
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct BarParams {
+    symbol: String,
+    interval: String,
+    limit: Option<usize>,
+}
+
+/// The symbol's latest bars of one interval, oldest first, ending with the bar still
+/// being built (`"closed":false`); 100 unless `limit` asks for more.
+async fn recent_bars(State(state): State<AppState>, Query(params): Query<BarParams>) -> Response {
+    let intervals = state.bars.intervals();
+    if !intervals.iter().any(|i| i.label == params.interval) {
+        let known: Vec<&str> = intervals.iter().map(|i| i.label.as_str()).collect();
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("interval must be one of: {}", known.join(",")),
+        )
+            .into_response();
+    }
+    let limit = params.limit.unwrap_or(100).min(BAR_HISTORY + 1);
+    Json(state.bars.recent(&params.symbol, &params.interval, limit)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsParams {
+    symbol: Option<String>,
+}
+
+/// Rolling statistics of one symbol, or of every symbol sorted by symbol.
+async fn book_stats(State(state): State<AppState>, Query(params): Query<StatsParams>) -> Response {
+    let Some(stats) = state.stats else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match &params.symbol {
+        Some(symbol) => match stats.get(symbol) {
+            Some(row) => Json(row).into_response(),
+            None => (
+                StatusCode::NOT_FOUND,
+                format!("no snapshots for {}", symbol),
+            )
+                .into_response(),
+        },
+        None => {
+            let mut rows = stats.all();
+            rows.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+            Json(rows).into_response()
+        }
+    }
+}
+
+/// Enters a simulated order against the symbol's latest snapshot. Answers 201 with
+/// the order, including any fills taken on entry.
+async fn sim_submit(State(state): State<AppState>, Json(request): Json<NewSimOrder>) -> Response {
+    let Some(simulator) = state.simulator else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if request.size == 0 {
+        return (StatusCode::BAD_REQUEST, "size must be positive").into_response();
+    }
+    let Some(book) = state.registry.get_by_symbol(&request.symbol) else {
+        return (StatusCode::NOT_FOUND, "no book for symbol").into_response();
+    };
+    (StatusCode::CREATED, Json(simulator.submit(request, &book))).into_response()
+}
+
+async fn sim_order(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    match state.simulator.and_then(|simulator| simulator.get(id)) {
+        Some(order) => Json(order).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Cancels a resting simulated order. A filled or already cancelled order answers 409
+/// with its final state.
+async fn sim_cancel(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    let Some(simulator) = state.simulator else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match simulator.cancel(id) {
+        CancelOutcome::Cancelled(order) => Json(order).into_response(),
+        CancelOutcome::Closed(order) => (StatusCode::CONFLICT, Json(order)).into_response(),
+        CancelOutcome::NotFound => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OrderParams {
+    instrument_id: Option<u32>,
+    publisher_id: Option<u16>,
+}
+
+impl OrderParams {
+    fn filter(&self) -> OrderFilter {
+        OrderFilter {
+            instrument_id: self.instrument_id,
+            publisher_id: self.publisher_id,
+        }
+    }
+}
+
+/// A resting order in the live books. Order ids are only unique per venue, so
+/// `instrument_id` and `publisher_id` pick the book when several hold the id; without
+/// them the first book found answers.
+async fn order_lookup(
+    State(state): State<AppState>,
+    Path(order_id): Path<u64>,
+    Query(params): Query<OrderParams>,
+) -> Response {
+    let Some(books) = state.books else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let filter = params.filter();
+    let found = books
+        .ask(params.instrument_id, move |market| {
+            OrderView::find(market, order_id, filter)
+        })
+        .await;
+    book_answer(found)
+}
+
+/// Where a resting order stands in its level's queue: the size ahead of it at its
+/// price, along with the level's size and order count.
+async fn queue_pos_lookup(
+    State(state): State<AppState>,
+    Path(order_id): Path<u64>,
+    Query(params): Query<OrderParams>,
+) -> Response {
+    let Some(books) = state.books else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let filter = params.filter();
+    let found = books
+        .ask(params.instrument_id, move |market| {
+            QueuePosition::find(market, order_id, filter)
+        })
+        .await;
+    book_answer(found)
+}
+
+#[derive(Debug, Deserialize)]
+struct L3Params {
+    symbol: String,
+}
+
+/// Every resting order of the symbol's books, from the live books rather than a
+/// snapshot, stamped with the ts_event of the last record that changed them.
+async fn l3_snapshot(State(state): State<AppState>, Query(params): Query<L3Params>) -> Response {
+    let Some(books) = state.books else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // The registry knows which instrument a symbol is once it has a snapshot
+    let Some(instrument_id) = state
+        .registry
+        .get_by_symbol(&params.symbol)
+        .map(|snapshot| snapshot.instrument_id)
+    else {
+        return (StatusCode::NOT_FOUND, "no book for symbol").into_response();
+    };
+    let symbols = SymbolMap::new(params.symbol);
+    let found = books
+        .ask(Some(instrument_id), move |market| {
+            let ts_event = market
+                .books_by_pub(instrument_id)?
+                .iter()
+                .map(|(_, book)| book.ts_event())
+                .max()?;
+            build_l3_snapshot(market, instrument_id, &symbols, ts_event as i64)
+        })
+        .await;
+    book_answer(found)
+}
+
+fn book_answer<T: Serialize>(found: Result<Option<T>, Unanswered>) -> Response {
+    match found {
+        Ok(Some(answer)) => Json(answer).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(Unanswered::Closed) => {
+            (StatusCode::SERVICE_UNAVAILABLE, "ingest stopped").into_response()
+        }
+        Err(Unanswered::Busy) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "ingest did not answer in time",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EventParams {
+    symbol: Option<String>,
+}
+
+/// Streams analytics events (`trade_through`, `quote_rule`) as they are detected.
+/// Events are never conflated; a client that falls behind misses the overflow.
+async fn sse_events(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    headers: HeaderMap,
+    Query(params): Query<EventParams>,
+) -> Response {
+    let events = state.analytics.subscribe();
+    let shutdown = state.shutdown.clone();
+    let stream = stream::unfold((events, params.symbol), |(mut events, symbol)| async move {
+        let (event, payload) = loop {
+            match events.recv().await {
+                Ok(event) if symbol.as_ref().is_none_or(|s| s == event.symbol()) => {
+                    match serde_json::to_string(&event) {
+                        Ok(payload) => break (event, payload),
+                        Err(e) => error!(error = format!("{:#}", e), "sse event encoding failed"),
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        };
+        let name = match event {
+            AnalyticsEvent::TradeThrough(_) => "trade_through",
+            AnalyticsEvent::QuoteRule { .. } => "quote_rule",
+        };
+        Some((
+            Ok::<_, Infallible>(Event::default().event(name).data(payload)),
+            (events, symbol),
+        ))
+    })
+    .take_until(async move { shutdown.wait().await });
+
+    let gzip = state.compression.sse_gzip && accepts_gzip(&headers);
+    let guard = state.streams.register(&request_id.0, "sse");
+    let (mut parts, body) = Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+        .into_parts();
+    if gzip {
+        parts
+            .headers
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        parts
+            .headers
+            .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    Response::from_parts(parts, stream_body(body, gzip, guard))
+}
+
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let disabled = parts.any(|param| param.replace(' ', "") == "q=0");
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !disabled
+        })
+}
+
+async fn next_matching(
+    updates: &mut broadcast::Receiver<SharedSnapshot>,
+    filter: &Subscribe,
+) -> Option<SharedSnapshot> {
+    loop {
+        match updates.recv().await {
+            Ok(snapshot) if filter.matches(&snapshot) => return Some(snapshot),
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return None,
+        }
     }
 }
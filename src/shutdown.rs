@@ -0,0 +1,135 @@
+use std::{
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+};
+
+use anyhow::{Context, Result};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+/// Stop signal shared by every thread of a pipeline. Once triggered, ingest stops
+/// decoding and drops its queues, the writers drain and flush what was queued, and the
+/// HTTP server closes its streams and exits.
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    triggered: AtomicBool,
+    notify: watch::Sender<bool>,
+    /// Run once on trigger, e.g. to unblock a thread stuck in a socket read.
+    hooks: Mutex<Vec<Hook>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                triggered: AtomicBool::new(false),
+                notify: watch::Sender::new(false),
+                hooks: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Starts the shutdown. Later calls do nothing.
+    pub fn trigger(&self) {
+        if self.inner.triggered.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.inner.notify.send_replace(true);
+        let hooks = std::mem::take(&mut *self.inner.hooks.lock().expect("shutdown hooks poisoned"));
+        for hook in hooks {
+            hook();
+        }
+    }
+
+    /// Cheap enough to check once per record.
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once the shutdown has been triggered.
+    pub async fn wait(&self) {
+        let mut rx = self.inner.notify.subscribe();
+        // The sender lives in `self`, so this only fails if it is dropped mid-await
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Runs `hook` on trigger, or right away if the shutdown already started.
+    pub fn on_trigger(&self, hook: impl FnOnce() + Send + 'static) {
+        let mut hooks = self.inner.hooks.lock().expect("shutdown hooks poisoned");
+        if self.is_triggered() {
+            drop(hooks);
+            hook();
+        } else {
+            hooks.push(Box::new(hook));
+        }
+    }
+
+    /// Triggers on the first ctrl-c or SIGTERM. A second signal exits at once without
+    /// draining, for when a flush is stuck.
+    pub fn listen_for_signals(&self) -> Result<()> {
+        let shutdown = self.clone();
+        thread::Builder::new()
+            .name("shutdown-signals".to_owned())
+            .spawn(move || {
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        error!(error = %e, "failed to build signal runtime");
+                        return;
+                    }
+                };
+                runtime.block_on(async {
+                    let signal = next_signal().await;
+                    info!(signal, "shutdown signal, draining");
+                    shutdown.trigger();
+                    let signal = next_signal().await;
+                    warn!(signal, "shutdown signal again, exiting without draining");
+                    process::exit(130);
+                });
+            })
+            .context("failed to spawn shutdown signal thread")?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+async fn next_signal() -> &'static str {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!(error = %e, "failed to listen for SIGTERM");
+            let _ = tokio::signal::ctrl_c().await;
+            return "SIGINT";
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+async fn next_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "ctrl-c"
+}
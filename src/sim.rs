@@ -0,0 +1,306 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use dbn::{
+    enums::{Action, Side},
+    record::MboMsg,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    order_book::Market,
+    snapshot::{LevelEntry, SnapshotRecord},
+};
+
+/// Simulated limit orders matched against the replayed book, for testing execution
+/// logic without a venue. Orders never affect the replay: liquidity taken on entry is
+/// still there for the next order, and resting orders only fill from trades the feed
+/// reports at or through their price.
+///
+/// Queue position is modelled conservatively. A resting order starts behind all size
+/// already displayed at its price, trades at that price consume the queue ahead first,
+/// and cancels at the level shrink the queue ahead to at most the size left there.
+#[derive(Default)]
+pub struct Simulator {
+    state: Mutex<SimState>,
+    /// Open orders, so ingest can skip the lock when there are none.
+    open: AtomicUsize,
+}
+
+#[derive(Default)]
+struct SimState {
+    next_id: u64,
+    orders: BTreeMap<u64, SimOrder>,
+    /// Open order ids per instrument, oldest first.
+    open_by_instrument: HashMap<u32, Vec<u64>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimStatus {
+    Open,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Liquidity {
+    /// Filled on entry against displayed liquidity.
+    Taker,
+    /// Filled while resting.
+    Maker,
+}
+
+/// `POST /sim/orders` body. Prices are fixed-point like every price in the API.
+#[derive(Clone, Debug, Deserialize)]
+pub struct NewSimOrder {
+    pub symbol: String,
+    pub side: SimSide,
+    pub price: i64,
+    pub size: u32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SimFill {
+    pub ts_event: i64,
+    pub price: i64,
+    pub size: u32,
+    pub liquidity: Liquidity,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SimOrder {
+    pub id: u64,
+    pub symbol: String,
+    pub instrument_id: u32,
+    pub side: SimSide,
+    pub price: i64,
+    pub size: u32,
+    pub filled: u32,
+    pub status: SimStatus,
+    /// Displayed size estimated to be ahead of the order at its price.
+    pub queue_ahead: u32,
+    /// `ts_event` of the book the order was entered against.
+    pub entered_ts: i64,
+    pub fills: Vec<SimFill>,
+}
+
+pub enum CancelOutcome {
+    Cancelled(SimOrder),
+    /// Already filled or cancelled.
+    Closed(SimOrder),
+    NotFound,
+}
+
+impl SimOrder {
+    fn remaining(&self) -> u32 {
+        self.size - self.filled
+    }
+
+    fn is_open(&self) -> bool {
+        matches!(self.status, SimStatus::Open | SimStatus::PartiallyFilled)
+    }
+
+    fn fill(&mut self, ts_event: i64, price: i64, size: u32, liquidity: Liquidity) {
+        let size = size.min(self.remaining());
+        if size == 0 {
+            return;
+        }
+        self.filled += size;
+        self.status = if self.remaining() == 0 {
+            SimStatus::Filled
+        } else {
+            SimStatus::PartiallyFilled
+        };
+        self.fills.push(SimFill {
+            ts_event,
+            price,
+            size,
+            liquidity,
+        });
+    }
+
+    /// True when a price on the opposite side would trade with this order.
+    fn crosses(&self, price: i64) -> bool {
+        match self.side {
+            SimSide::Buy => price <= self.price,
+            SimSide::Sell => price >= self.price,
+        }
+    }
+
+    fn book_side(&self) -> Side {
+        match self.side {
+            SimSide::Buy => Side::Bid,
+            SimSide::Sell => Side::Ask,
+        }
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enters an order against `book`, the latest snapshot of its instrument. The
+    /// marketable part fills at once against the snapshot's levels; the rest rests.
+    pub fn submit(&self, request: NewSimOrder, book: &SnapshotRecord) -> SimOrder {
+        let mut state = self.state.lock().expect("simulator lock poisoned");
+        state.next_id += 1;
+        let mut order = SimOrder {
+            id: state.next_id,
+            symbol: request.symbol,
+            instrument_id: book.instrument_id,
+            side: request.side,
+            price: request.price,
+            size: request.size,
+            filled: 0,
+            status: SimStatus::Open,
+            queue_ahead: 0,
+            entered_ts: book.ts_event,
+            fills: Vec::new(),
+        };
+        let (same, opposite): (&[LevelEntry], &[LevelEntry]) = match order.side {
+            SimSide::Buy => (&book.payload.bids, &book.payload.asks),
+            SimSide::Sell => (&book.payload.asks, &book.payload.bids),
+        };
+        for level in opposite {
+            if !order.is_open() || !order.crosses(level.price) {
+                break;
+            }
+            order.fill(book.ts_event, level.price, level.size, Liquidity::Taker);
+        }
+        if order.is_open() {
+            order.queue_ahead = same
+                .iter()
+                .find(|level| level.price == order.price)
+                .map_or(0, |level| level.size);
+            state
+                .open_by_instrument
+                .entry(order.instrument_id)
+                .or_default()
+                .push(order.id);
+            self.open.fetch_add(1, Ordering::Relaxed);
+        }
+        state.orders.insert(order.id, order.clone());
+        order
+    }
+
+    pub fn get(&self, id: u64) -> Option<SimOrder> {
+        self.state
+            .lock()
+            .expect("simulator lock poisoned")
+            .orders
+            .get(&id)
+            .cloned()
+    }
+
+    pub fn cancel(&self, id: u64) -> CancelOutcome {
+        let mut state = self.state.lock().expect("simulator lock poisoned");
+        let Some(order) = state.orders.get_mut(&id) else {
+            return CancelOutcome::NotFound;
+        };
+        if !order.is_open() {
+            return CancelOutcome::Closed(order.clone());
+        }
+        order.status = SimStatus::Cancelled;
+        let order = order.clone();
+        state.close(order.instrument_id, id);
+        self.open.fetch_sub(1, Ordering::Relaxed);
+        CancelOutcome::Cancelled(order)
+    }
+
+    /// Advances resting orders of the record's instrument. Called by ingest after
+    /// `mbo` was applied to `market`.
+    pub fn on_record(&self, market: &Market, mbo: &MboMsg) {
+        if self.open.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let instrument_id = mbo.hd.instrument_id;
+        let mut state = self.state.lock().expect("simulator lock poisoned");
+        let Some(ids) = state.open_by_instrument.get(&instrument_id).cloned() else {
+            return;
+        };
+        let ts_event = mbo.hd.ts_event as i64;
+        let action = mbo.action();
+        let mut trade_left = mbo.size;
+        let (best_bid, best_ask) = market.aggregated_bbo(instrument_id);
+        let mut closed = Vec::new();
+        for id in ids {
+            let Some(order) = state.orders.get_mut(&id) else {
+                continue;
+            };
+            match action {
+                Ok(Action::Trade) if order.crosses(mbo.price) => {
+                    if mbo.price == order.price {
+                        // Displayed size ahead at our price trades first
+                        let ahead = order.queue_ahead.min(trade_left);
+                        order.queue_ahead -= ahead;
+                        trade_left -= ahead;
+                        let before = order.filled;
+                        order.fill(ts_event, order.price, trade_left, Liquidity::Maker);
+                        trade_left -= order.filled - before;
+                    } else {
+                        // Traded through our price: we would have been hit first
+                        order.fill(ts_event, order.price, order.remaining(), Liquidity::Maker);
+                    }
+                }
+                Ok(Action::Cancel | Action::Modify)
+                    if mbo.price == order.price
+                        && mbo.side().is_ok_and(|side| side == order.book_side()) =>
+                {
+                    let displayed = market
+                        .books_by_pub(instrument_id)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|(_, book)| book.level_size(order.book_side(), order.price))
+                        .sum::<u32>();
+                    order.queue_ahead = order.queue_ahead.min(displayed);
+                }
+                _ => {}
+            }
+            // The opposite touch moved onto our price: it would have traded with us
+            let opposite = match order.side {
+                SimSide::Buy => best_ask.as_ref(),
+                SimSide::Sell => best_bid.as_ref(),
+            };
+            if order.is_open()
+                && let Some(level) = opposite
+                && order.crosses(level.price)
+            {
+                order.fill(ts_event, order.price, level.size, Liquidity::Maker);
+            }
+            if !order.is_open() {
+                closed.push(id);
+            }
+        }
+        for id in closed {
+            state.close(instrument_id, id);
+            self.open.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl SimState {
+    fn close(&mut self, instrument_id: u32, id: u64) {
+        if let Some(ids) = self.open_by_instrument.get_mut(&instrument_id) {
+            ids.retain(|open| *open != id);
+            if ids.is_empty() {
+                self.open_by_instrument.remove(&instrument_id);
+            }
+        }
+    }
+}
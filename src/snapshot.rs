@@ -1,21 +1,37 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, OnceLock, RwLock},
+};
 
-use anyhow::Result;
-use serde::Serialize;
-use serde_json::Value;
+use anyhow::{Context, Result, anyhow};
+use arc_swap::ArcSwapOption;
+use bytes::Bytes;
+use dbn::FIXED_PRICE_SCALE;
+use prost::Message;
+use serde::{Deserialize, Serialize};
 
-use crate::order_book::{Book, Market, PriceLevel};
+use crate::{
+    grpc::snapshot_message,
+    latency::wall_clock_ns,
+    order_book::{
+        Book, BucketedDepth, ConsolidatedBook, DepthWithin, ExecutionCost, Market, Order,
+        PriceLevel,
+    },
+};
 
 pub const DEFAULT_TOP_LEVELS: usize = 10;
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LevelEntry {
     pub price: i64,
     pub size: u32,
     pub count: u32,
+    /// Size likely hidden behind an iceberg order at this level (`ICEBERG_DETECTION`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub est_hidden: Option<u32>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
 pub struct Bbo {
     pub best_bid: Option<LevelEntry>,
     pub best_ask: Option<LevelEntry>,
@@ -26,50 +42,756 @@ pub struct Snapshot {
     pub bbo: Bbo,
     pub symbol: String,
     pub ts_ns: i64,
+    /// Per-instrument snapshot number, from 1 with no gaps, stamped by ingest. Sinks
+    /// persist it so consumers can spot missing or duplicated snapshots; 0 means
+    /// unknown, e.g. rows stored before snapshots were numbered.
+    pub seq: u64,
     pub bids: Vec<LevelEntry>,
     pub asks: Vec<LevelEntry>,
     pub total_orders: usize,
     pub bid_levels: usize,
     pub ask_levels: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notional: Option<Notional>,
+    /// Present when flow signals are on (`FLOW_SIGNALS`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signals: Option<FlowSignals>,
+    /// Present when liquidity measures are on (`LIQUIDITY_SIZES`, `LIQUIDITY_BPS`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidity: Option<Liquidity>,
+    /// Present when depth buckets are on (`DEPTH_BUCKET_TICKS`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<BucketedDepth>,
+    /// Implied top of book of publishers whose top-of-book records carry implied
+    /// liquidity (`BOOK_POLICIES`), apart from the outright `bbo` and levels.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub implied: Option<Bbo>,
+    /// Set while the book may be missing updates after a sequence gap.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+/// Notional values derived from the contract multiplier, in the contract currency.
+/// Depth notional covers every level of the book, not only those the snapshot keeps.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Notional {
+    pub currency: String,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub bid_depth: f64,
+    pub ask_depth: f64,
+}
+
+/// Order-flow signals at a snapshot, computed by `analytics::flow::FlowTracker` from the
+/// consolidated BBO.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FlowSignals {
+    /// Order-flow imbalance (Cont, Kukanov and Stoikov) over the trailing `OFI_WINDOW_MS`
+    /// of `ts_event` time, in contracts: the sum over BBO changes of
+    /// `[Pb >= Pb'] qb - [Pb <= Pb'] qb' - [Pa <= Pa'] qa + [Pa >= Pa'] qa'`, where primes
+    /// mark the BBO before the change. Positive means buying pressure.
+    pub ofi: i64,
+    /// `(bid size - ask size) / (bid size + ask size)` at the BBO, from -1 to 1.
+    pub queue_imbalance: Option<f64>,
+    /// Size-weighted mid, `(ask px * bid size + bid px * ask size) / (bid size + ask size)`,
+    /// fixed-point like every price and rounded to the nearest unit.
+    pub microprice: Option<i64>,
+}
+
+/// What it costs to trade the configured sizes against the full book, and how much
+/// size rests near the mid. Measured on every level, not only those in the snapshot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Liquidity {
+    /// A buy of each of `LIQUIDITY_SIZES`, taking the asks.
+    pub buy: Vec<ExecutionCost>,
+    /// A sell of each of `LIQUIDITY_SIZES`, taking the bids.
+    pub sell: Vec<ExecutionCost>,
+    /// Size within each of `LIQUIDITY_BPS` of the mid; empty while a side is empty.
+    pub depth: Vec<DepthWithin>,
+}
+
+/// Order sizes and bands measured into `Snapshot::liquidity`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LiquiditySpec {
+    pub sizes: Vec<u64>,
+    pub bps: Vec<u32>,
+}
+
+impl LiquiditySpec {
+    pub fn for_book(&self, book: &Book) -> Liquidity {
+        Liquidity {
+            buy: self
+                .sizes
+                .iter()
+                .map(|&size| book.cost_to_buy(size))
+                .collect(),
+            sell: self
+                .sizes
+                .iter()
+                .map(|&size| book.cost_to_sell(size))
+                .collect(),
+            depth: self
+                .bps
+                .iter()
+                .filter_map(|&bps| book.depth_within_bps(bps))
+                .collect(),
+        }
+    }
+
+    pub fn for_consolidated(&self, book: &ConsolidatedBook) -> Liquidity {
+        Liquidity {
+            buy: self
+                .sizes
+                .iter()
+                .map(|&size| book.cost_to_buy(size))
+                .collect(),
+            sell: self
+                .sizes
+                .iter()
+                .map(|&size| book.cost_to_sell(size))
+                .collect(),
+            depth: self
+                .bps
+                .iter()
+                .filter_map(|&bps| book.depth_within_bps(bps))
+                .collect(),
+        }
+    }
+
+    /// For sources without a book, measured on the snapshot's own levels.
+    pub fn for_snapshot(&self, snapshot: &Snapshot) -> Liquidity {
+        let bids = || snapshot.bids.iter().map(|l| (l.price, l.size));
+        let asks = || snapshot.asks.iter().map(|l| (l.price, l.size));
+        Liquidity {
+            buy: self
+                .sizes
+                .iter()
+                .map(|&size| ExecutionCost::sweep(size, asks()))
+                .collect(),
+            sell: self
+                .sizes
+                .iter()
+                .map(|&size| ExecutionCost::sweep(size, bids()))
+                .collect(),
+            depth: self
+                .bps
+                .iter()
+                .filter_map(|&bps| DepthWithin::measure(bps, bids(), asks()))
+                .collect(),
+        }
+    }
+}
+
+/// Which book the levels of a market snapshot come from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SnapshotBook {
+    /// The instrument's first publisher, the only one in a single-venue feed.
+    #[default]
+    First,
+    /// Every publisher's depth merged by `Market::consolidated_book`.
+    Consolidated,
+}
+
+/// Which record timestamp drives interval cadence and bar boundaries. Snapshots and
+/// trades carry both either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// The venue's matching-engine time.
+    #[default]
+    Event,
+    /// When the record was received, as a capture or live session saw it.
+    Recv,
+}
+
+impl TimestampSource {
+    pub fn pick(self, ts_event: i64, ts_recv: i64) -> i64 {
+        match self {
+            TimestampSource::Event => ts_event,
+            TimestampSource::Recv => ts_recv,
+        }
+    }
+}
+
+/// Bucket width and count of `Snapshot::buckets`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BucketSpec {
+    pub tick_size: i64,
+    pub bucket_ticks: u32,
+    /// Buckets kept per side.
+    pub buckets: usize,
+}
+
+impl BucketSpec {
+    pub fn for_book(&self, book: &Book) -> BucketedDepth {
+        book.aggregate_by_ticks(self.tick_size, self.bucket_ticks, self.buckets)
+    }
+
+    pub fn for_consolidated(&self, book: &ConsolidatedBook) -> BucketedDepth {
+        book.aggregate_by_ticks(self.tick_size, self.bucket_ticks, self.buckets)
+    }
+
+    /// For sources without a book, bucketed from the snapshot's own levels.
+    pub fn for_snapshot(&self, snapshot: &Snapshot) -> BucketedDepth {
+        BucketedDepth::aggregate(
+            self.tick_size.saturating_mul(self.bucket_ticks as i64),
+            self.buckets,
+            snapshot.bids.iter().map(|l| (l.price, l.size, l.count)),
+            snapshot.asks.iter().map(|l| (l.price, l.size, l.count)),
+        )
+    }
+}
+
+/// Contract metadata needed to turn fixed-point prices into notional values.
+#[derive(Clone, Debug)]
+pub struct ContractMeta {
+    pub multiplier: f64,
+    pub currency: String,
 }
 
 #[derive(Clone, Debug)]
 pub struct SnapshotRecord {
     pub instrument_id: u32,
+    /// Publisher whose book the levels come from; 0 for consolidated books and for
+    /// sources and rows that do not say.
+    pub publisher_id: u16,
     pub ts_event: i64,
+    /// When the record behind the snapshot was received; `ts_event` for sources
+    /// without a receive time.
+    pub ts_recv: i64,
+    /// Wall clock, Unix nanoseconds, at which ingest built the snapshot; 0 for
+    /// snapshots loaded from storage.
+    pub built_at: i64,
     pub payload: Snapshot,
+    /// Encodings of `payload` made so far; see `encoded`.
+    pub cache: EncodedCache,
 }
 
 pub type SharedSnapshot = Arc<SnapshotRecord>;
 
+/// One slot per `SnapshotEncoding`, filled on first use. Clones start empty, as a
+/// cloned record is usually about to get a different payload.
+#[derive(Debug, Default)]
+pub struct EncodedCache([OnceLock<Option<Bytes>>; 3]);
+
+impl Clone for EncodedCache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// Level changes on one side between two snapshots. `removed` holds prices only.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SideDelta {
+    pub added: Vec<LevelEntry>,
+    pub changed: Vec<LevelEntry>,
+    pub removed: Vec<i64>,
+}
+
+/// Difference between consecutive snapshots of the same instrument. Applying it to
+/// the previous snapshot with `apply_to` reproduces the next one.
+#[derive(Clone, Debug, Serialize)]
+pub struct BookDelta {
+    pub symbol: String,
+    pub ts_ns: i64,
+    pub prev_ts_ns: i64,
+    pub seq: u64,
+    /// More than `seq - 1` when snapshots in between were conflated away.
+    pub prev_seq: u64,
+    /// Present only when the BBO changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bbo: Option<Bbo>,
+    /// The next snapshot's signals, which move with the OFI window even when the book
+    /// does not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signals: Option<FlowSignals>,
+    /// The next snapshot's liquidity, which can move with levels beyond the delta's depth.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidity: Option<Liquidity>,
+    /// The next snapshot's buckets, which also cover levels beyond the delta's depth.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<BucketedDepth>,
+    pub bids: SideDelta,
+    pub asks: SideDelta,
+    pub total_orders: usize,
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeltaRecord {
+    pub instrument_id: u32,
+    pub ts_event: i64,
+    pub payload: BookDelta,
+}
+
+/// Hands out `Snapshot::seq` numbers, one counter per instrument.
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotSequencer {
+    next: HashMap<u32, u64>,
+}
+
+impl SnapshotSequencer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continues from numbers saved with `last_issued`, e.g. in a checkpoint.
+    pub fn resume(last_issued: impl IntoIterator<Item = (u32, u64)>) -> Self {
+        Self {
+            next: last_issued.into_iter().collect(),
+        }
+    }
+
+    /// Numbers `record` as the next snapshot of its instrument.
+    pub fn stamp(&mut self, mut record: SnapshotRecord) -> SnapshotRecord {
+        let seq = self.next.entry(record.instrument_id).or_default();
+        *seq += 1;
+        record.payload.seq = *seq;
+        record
+    }
+
+    /// The last number issued per instrument, sorted by instrument.
+    pub fn last_issued(&self) -> Vec<(u32, u64)> {
+        let mut issued: Vec<(u32, u64)> = self.next.iter().map(|(id, seq)| (*id, *seq)).collect();
+        issued.sort_unstable();
+        issued
+    }
+}
+
+/// Resolves instrument ids to display symbols, falling back to a default symbol.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolMap {
+    symbols: HashMap<u32, String>,
+    default_symbol: String,
+}
+
+impl SymbolMap {
+    pub fn new(default_symbol: impl Into<String>) -> Self {
+        Self {
+            symbols: HashMap::new(),
+            default_symbol: default_symbol.into(),
+        }
+    }
+
+    pub fn insert(&mut self, instrument_id: u32, symbol: impl Into<String>) {
+        self.symbols.insert(instrument_id, symbol.into());
+    }
+
+    /// Adds mappings that are not already present, keeping explicit ones.
+    pub fn extend_missing(&mut self, symbols: impl IntoIterator<Item = (u32, String)>) {
+        for (instrument_id, symbol) in symbols {
+            self.symbols.entry(instrument_id).or_insert(symbol);
+        }
+    }
+
+    pub fn resolve(&self, instrument_id: u32) -> &str {
+        self.symbols
+            .get(&instrument_id)
+            .map(String::as_str)
+            .unwrap_or(&self.default_symbol)
+    }
+
+    /// Reverse lookup of an explicitly mapped symbol.
+    pub fn instrument_id(&self, symbol: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|(_, mapped)| mapped.as_str() == symbol)
+            .map(|(instrument_id, _)| *instrument_id)
+    }
+
+    pub fn default_symbol(&self) -> &str {
+        &self.default_symbol
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Latest snapshot per instrument, readable from the HTTP server while ingest updates it.
+#[derive(Default)]
+pub struct SnapshotRegistry {
+    by_instrument: RwLock<HashMap<u32, Arc<ArcSwapOption<SnapshotRecord>>>>,
+    latest: ArcSwapOption<SnapshotRecord>,
+}
+
+impl SnapshotRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn store(&self, snapshot: SharedSnapshot) {
+        let slot = self
+            .by_instrument
+            .read()
+            .expect("snapshot registry lock poisoned")
+            .get(&snapshot.instrument_id)
+            .cloned();
+        match slot {
+            Some(slot) => slot.store(Some(snapshot.clone())),
+            None => {
+                self.by_instrument
+                    .write()
+                    .expect("snapshot registry lock poisoned")
+                    .entry(snapshot.instrument_id)
+                    .or_default()
+                    .store(Some(snapshot.clone()));
+            }
+        }
+        self.latest.store(Some(snapshot));
+    }
+
+    /// Most recently stored snapshot across all instruments.
+    pub fn latest(&self) -> Option<SharedSnapshot> {
+        self.latest.load_full()
+    }
+
+    pub fn get(&self, instrument_id: u32) -> Option<SharedSnapshot> {
+        self.by_instrument
+            .read()
+            .expect("snapshot registry lock poisoned")
+            .get(&instrument_id)
+            .and_then(|slot| slot.load_full())
+    }
+
+    pub fn get_by_symbol(&self, symbol: &str) -> Option<SharedSnapshot> {
+        self.all()
+            .into_iter()
+            .find(|snapshot| snapshot.payload.symbol == symbol)
+    }
+
+    pub fn all(&self) -> Vec<SharedSnapshot> {
+        self.by_instrument
+            .read()
+            .expect("snapshot registry lock poisoned")
+            .values()
+            .filter_map(|slot| slot.load_full())
+            .collect()
+    }
+}
+
+impl Snapshot {
+    /// Copy of the snapshot keeping at most `depth` levels per side.
+    pub fn truncated(&self, depth: usize) -> Snapshot {
+        let mut snapshot = self.clone();
+        snapshot.bids.truncate(depth);
+        snapshot.asks.truncate(depth);
+        snapshot
+    }
+}
+
+/// Cumulative depth curve for charting. Each side is a list of `[price, cumulative_size]`
+/// pairs ordered from the touch outward, with prices in display units.
+#[derive(Clone, Debug, Serialize)]
+pub struct DepthChart {
+    pub symbol: String,
+    pub ts_ns: i64,
+    pub bids: Vec<(f64, u64)>,
+    pub asks: Vec<(f64, u64)>,
+}
+
+impl DepthChart {
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        Self {
+            symbol: snapshot.symbol.clone(),
+            ts_ns: snapshot.ts_ns,
+            bids: cumulative_depth(&snapshot.bids),
+            asks: cumulative_depth(&snapshot.asks),
+        }
+    }
+}
+
+fn cumulative_depth(levels: &[LevelEntry]) -> Vec<(f64, u64)> {
+    levels
+        .iter()
+        .scan(0u64, |total, level| {
+            *total += level.size as u64;
+            Some((level.price as f64 / FIXED_PRICE_SCALE as f64, *total))
+        })
+        .collect()
+}
+
 impl SnapshotRecord {
-    /// Lazily serialize to JSON only when needed (for DB write or HTTP response)
-    pub fn to_json(&self) -> Result<Value> {
-        Ok(serde_json::to_value(&self.payload)?)
+    /// The record as `encoding`'s `SnapshotEncoder::encode` writes it. Each encoding is
+    /// made once per record, so every reader of a `SharedSnapshot` shares the same bytes.
+    pub fn encoded(&self, encoding: SnapshotEncoding) -> Result<Bytes> {
+        self.cache.0[encoding as usize]
+            .get_or_init(|| {
+                let mut out = Vec::new();
+                encoding
+                    .encoder()
+                    .encode(self, &mut out)
+                    .ok()
+                    .map(|()| Bytes::from(out))
+            })
+            .clone()
+            .ok_or_else(|| anyhow!("failed to encode snapshot as {encoding:?}"))
     }
 
     pub fn to_json_string(&self) -> Result<String> {
         Ok(serde_json::to_string(&self.payload)?)
     }
+
+    pub fn with_notional(mut self, notional: Notional) -> Self {
+        self.payload.notional = Some(notional);
+        self
+    }
+
+    pub fn with_liquidity(mut self, liquidity: Liquidity) -> Self {
+        self.payload.liquidity = Some(liquidity);
+        self
+    }
+
+    pub fn with_buckets(mut self, buckets: BucketedDepth) -> Self {
+        self.payload.buckets = Some(buckets);
+        self
+    }
+
+    pub fn with_ts_recv(mut self, ts_recv: i64) -> Self {
+        self.ts_recv = ts_recv;
+        self
+    }
+}
+
+/// Wire encodings of a snapshot, chosen per sink (`SNAPSHOT_FILE_FORMAT`, `REDIS_FORMAT`)
+/// and per HTTP or WebSocket request through `Accept`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SnapshotEncoding {
+    /// The `Snapshot` JSON served since the start.
+    #[default]
+    Json,
+    /// The same fields as JSON, as a MessagePack map.
+    MessagePack,
+    /// The `batonics.v1.Snapshot` message of `snapshots.proto`, which leaves out the
+    /// BBO, signals and other derived fields.
+    Protobuf,
+}
+
+impl SnapshotEncoding {
+    /// Parses `json`, `msgpack` or `protobuf`.
+    pub fn parse(raw: &str) -> Result<Self> {
+        match raw {
+            "json" => Ok(SnapshotEncoding::Json),
+            "msgpack" => Ok(SnapshotEncoding::MessagePack),
+            "protobuf" => Ok(SnapshotEncoding::Protobuf),
+            _ => Err(anyhow!(
+                "unknown encoding {:?}, expected json, msgpack or protobuf",
+                raw
+            )),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            SnapshotEncoding::Json => "application/json",
+            SnapshotEncoding::MessagePack => "application/msgpack",
+            SnapshotEncoding::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    /// The encoding of a media type such as `application/x-msgpack`; parameters are
+    /// ignored.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        let essence = media_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" => Some(SnapshotEncoding::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(SnapshotEncoding::MessagePack)
+            }
+            "application/x-protobuf"
+            | "application/protobuf"
+            | "application/vnd.google.protobuf" => Some(SnapshotEncoding::Protobuf),
+            _ => None,
+        }
+    }
+
+    /// The first encoding an `Accept` header lists, JSON when it lists none (including
+    /// `*/*` and a missing header).
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        accept
+            .into_iter()
+            .flat_map(|accept| accept.split(','))
+            .find_map(Self::from_media_type)
+            .unwrap_or_default()
+    }
+
+    pub fn encoder(self) -> &'static dyn SnapshotEncoder {
+        match self {
+            SnapshotEncoding::Json => &JsonEncoder,
+            SnapshotEncoding::MessagePack => &MessagePackEncoder,
+            SnapshotEncoding::Protobuf => &ProtobufEncoder,
+        }
+    }
+}
+
+/// Turns snapshots into bytes for sinks and API responses.
+pub trait SnapshotEncoder: Send + Sync {
+    /// Appends `record` to `out` as one message.
+    fn encode(&self, record: &SnapshotRecord, out: &mut Vec<u8>) -> Result<()>;
+
+    /// Appends `record` to `out` so that a stream of them can be split again: JSON
+    /// lines, back-to-back MessagePack values or length-delimited protobuf.
+    fn encode_framed(&self, record: &SnapshotRecord, out: &mut Vec<u8>) -> Result<()> {
+        self.encode(record, out)
+    }
+}
+
+pub struct JsonEncoder;
+
+impl SnapshotEncoder for JsonEncoder {
+    fn encode(&self, record: &SnapshotRecord, out: &mut Vec<u8>) -> Result<()> {
+        serde_json::to_writer(out, &record.payload).context("failed to encode snapshot as JSON")
+    }
+
+    fn encode_framed(&self, record: &SnapshotRecord, out: &mut Vec<u8>) -> Result<()> {
+        self.encode(record, out)?;
+        out.push(b'\n');
+        Ok(())
+    }
+}
+
+pub struct MessagePackEncoder;
+
+impl SnapshotEncoder for MessagePackEncoder {
+    fn encode(&self, record: &SnapshotRecord, out: &mut Vec<u8>) -> Result<()> {
+        // Named fields keep the JSON shape, so clients decode both into the same types
+        record
+            .payload
+            .serialize(&mut rmp_serde::Serializer::new(out).with_struct_map())
+            .context("failed to encode snapshot as MessagePack")
+    }
+}
+
+pub struct ProtobufEncoder;
+
+impl SnapshotEncoder for ProtobufEncoder {
+    fn encode(&self, record: &SnapshotRecord, out: &mut Vec<u8>) -> Result<()> {
+        snapshot_message(record, 0)
+            .encode(out)
+            .context("failed to encode snapshot as protobuf")
+    }
+
+    fn encode_framed(&self, record: &SnapshotRecord, out: &mut Vec<u8>) -> Result<()> {
+        snapshot_message(record, 0)
+            .encode_length_delimited(out)
+            .context("failed to encode snapshot as protobuf")
+    }
+}
+
+impl ContractMeta {
+    /// Value of `size` contracts at the fixed-point `price`.
+    pub fn value(&self, price: i64, size: u64) -> f64 {
+        (price as f64 / FIXED_PRICE_SCALE as f64) * size as f64 * self.multiplier
+    }
+
+    /// BBO notional of `snapshot` and depth notional of the whole of `book`.
+    pub fn for_book(&self, snapshot: &Snapshot, book: &Book) -> Notional {
+        self.notional(
+            snapshot,
+            book.iter_bids_desc().map(|l| (l.price, l.size)),
+            book.iter_asks_asc().map(|l| (l.price, l.size)),
+        )
+    }
+
+    pub fn for_consolidated(&self, snapshot: &Snapshot, book: &ConsolidatedBook) -> Notional {
+        self.notional(
+            snapshot,
+            book.bids.iter().map(|l| (l.price, l.size)),
+            book.asks.iter().map(|l| (l.price, l.size)),
+        )
+    }
+
+    /// For sources without a book, over the snapshot's own levels.
+    pub fn for_snapshot(&self, snapshot: &Snapshot) -> Notional {
+        self.notional(
+            snapshot,
+            snapshot.bids.iter().map(|l| (l.price, l.size)),
+            snapshot.asks.iter().map(|l| (l.price, l.size)),
+        )
+    }
+
+    fn notional(
+        &self,
+        snapshot: &Snapshot,
+        bids: impl Iterator<Item = (i64, u32)>,
+        asks: impl Iterator<Item = (i64, u32)>,
+    ) -> Notional {
+        let level = |l: &LevelEntry| self.value(l.price, l.size as u64);
+        Notional {
+            currency: self.currency.clone(),
+            best_bid: snapshot.bbo.best_bid.as_ref().map(level),
+            best_ask: snapshot.bbo.best_ask.as_ref().map(level),
+            bid_depth: bids
+                .map(|(price, size)| self.value(price, size as u64))
+                .sum(),
+            ask_depth: asks
+                .map(|(price, size)| self.value(price, size as u64))
+                .sum(),
+        }
+    }
 }
 
 pub fn build_snapshot_record(
     market: &Market,
     instrument_id: u32,
-    symbol: &str,
+    symbols: &SymbolMap,
     ts_event: i64,
     depth: usize,
 ) -> SnapshotRecord {
-    build_snapshot_record_internal(market, instrument_id, symbol, ts_event, Some(depth))
+    build_snapshot_record_internal(
+        market,
+        instrument_id,
+        symbols.resolve(instrument_id),
+        ts_event,
+        Some(depth),
+    )
 }
 
 pub fn build_full_snapshot_record(
     market: &Market,
     instrument_id: u32,
-    symbol: &str,
+    symbols: &SymbolMap,
     ts_event: i64,
 ) -> SnapshotRecord {
-    build_snapshot_record_internal(market, instrument_id, symbol, ts_event, None)
+    build_snapshot_record_internal(
+        market,
+        instrument_id,
+        symbols.resolve(instrument_id),
+        ts_event,
+        None,
+    )
+}
+
+/// Like `build_snapshot_record`, with levels from `book`, the instrument's
+/// `Market::consolidated_book`, instead of the first publisher's book.
+pub fn build_consolidated_snapshot_record(
+    market: &Market,
+    instrument_id: u32,
+    symbols: &SymbolMap,
+    ts_event: i64,
+    depth: usize,
+    book: &ConsolidatedBook,
+) -> SnapshotRecord {
+    let summary = summarize_consolidated(book, depth);
+    SnapshotRecord {
+        instrument_id,
+        publisher_id: 0,
+        ts_event,
+        ts_recv: ts_event,
+        built_at: wall_clock_ns(),
+        payload: build_snapshot(
+            market,
+            instrument_id,
+            symbols.resolve(instrument_id).to_owned(),
+            ts_event,
+            summary,
+        ),
+        cache: EncodedCache::default(),
+    }
 }
 
 fn build_snapshot_record_internal(
@@ -79,31 +801,46 @@ fn build_snapshot_record_internal(
     ts_event: i64,
     depth: Option<usize>,
 ) -> SnapshotRecord {
-    let payload = build_snapshot(market, instrument_id, symbol.to_owned(), ts_event, depth);
+    let first = market
+        .books_by_pub(instrument_id)
+        .and_then(|books| books.first());
+    let summary = first
+        .map(|(_, book)| summarize_book(book, depth))
+        .unwrap_or_else(|| (Vec::new(), Vec::new(), 0, 0, 0));
+    let payload = build_snapshot(market, instrument_id, symbol.to_owned(), ts_event, summary);
     SnapshotRecord {
         instrument_id,
+        publisher_id: first.map_or(0, |(publisher, _)| *publisher as u16),
         ts_event,
+        ts_recv: ts_event,
+        built_at: wall_clock_ns(),
         payload,
+        cache: EncodedCache::default(),
     }
 }
 
+/// Levels of each side, then total orders and level counts of each side.
+type BookSummary = (Vec<LevelEntry>, Vec<LevelEntry>, usize, usize, usize);
+
 fn build_snapshot(
     market: &Market,
     instrument_id: u32,
     symbol: String,
     ts_event: i64,
-    depth: Option<usize>,
+    (book_bids, book_asks, total_orders, bid_levels, ask_levels): BookSummary,
 ) -> Snapshot {
     let (agg_bid, agg_ask) = market.aggregated_bbo(instrument_id);
-    let (book_bids, book_asks, total_orders, bid_levels, ask_levels) = market
-        .books_by_pub(instrument_id)
-        .and_then(|books| books.first())
-        .map(|(_, book)| summarize_book(book, depth))
-        .unwrap_or_else(|| (Vec::new(), Vec::new(), 0, 0, 0));
-
+    let implied = match market.implied_bbo(instrument_id) {
+        (None, None) => None,
+        (bid, ask) => Some(Bbo {
+            best_bid: bid.as_ref().map(to_level_entry),
+            best_ask: ask.as_ref().map(to_level_entry),
+        }),
+    };
     Snapshot {
         symbol,
         ts_ns: ts_event,
+        seq: 0,
         bbo: Bbo {
             best_bid: agg_bid.as_ref().map(to_level_entry),
             best_ask: agg_ask.as_ref().map(to_level_entry),
@@ -113,13 +850,16 @@ fn build_snapshot(
         total_orders,
         bid_levels,
         ask_levels,
+        notional: None,
+        signals: None,
+        liquidity: None,
+        buckets: None,
+        implied,
+        stale: market.is_stale(instrument_id),
     }
 }
 
-fn summarize_book(
-    book: &Book,
-    depth: Option<usize>,
-) -> (Vec<LevelEntry>, Vec<LevelEntry>, usize, usize, usize) {
+fn summarize_book(book: &Book, depth: Option<usize>) -> BookSummary {
     let bid_iter = book.iter_bids_desc().map(|lvl| to_level_entry(&lvl));
     let ask_iter = book.iter_asks_asc().map(|lvl| to_level_entry(&lvl));
 
@@ -141,82 +881,332 @@ fn summarize_book(
     )
 }
 
+fn summarize_consolidated(book: &ConsolidatedBook, depth: usize) -> BookSummary {
+    (
+        book.bids.iter().take(depth).map(to_level_entry).collect(),
+        book.asks.iter().take(depth).map(to_level_entry).collect(),
+        book.total_orders,
+        book.bids.len(),
+        book.asks.len(),
+    )
+}
+
+/// One resting order of an L3 snapshot.
+#[derive(Clone, Debug, Serialize)]
+pub struct L3Order {
+    pub order_id: u64,
+    pub price: i64,
+    pub size: u32,
+    /// Size resting ahead of the order at its price.
+    pub queue_pos: u32,
+    /// ts_event of the record that gave the order its place in the queue.
+    pub ts_event: u64,
+}
+
+/// Every resting order of one publisher's book: bids from the best price down, asks
+/// from the best price up, each level in queue order.
+#[derive(Clone, Debug, Serialize)]
+pub struct L3Book {
+    pub publisher_id: u16,
+    pub bids: Vec<L3Order>,
+    pub asks: Vec<L3Order>,
+}
+
+/// An instrument's books order by order, for consumers that need more than the
+/// aggregated levels of `Snapshot`.
+#[derive(Clone, Debug, Serialize)]
+pub struct L3Snapshot {
+    pub symbol: String,
+    pub instrument_id: u32,
+    pub ts_ns: i64,
+    pub books: Vec<L3Book>,
+}
+
+pub type SharedL3 = Arc<L3Snapshot>;
+
+/// Every resting order of the instrument's books, one `L3Book` per publisher. `None`
+/// when the market has no book for the instrument.
+pub fn build_l3_snapshot(
+    market: &Market,
+    instrument_id: u32,
+    symbols: &SymbolMap,
+    ts_event: i64,
+) -> Option<L3Snapshot> {
+    let books = market.books_by_pub(instrument_id)?;
+    Some(L3Snapshot {
+        symbol: symbols.resolve(instrument_id).to_owned(),
+        instrument_id,
+        ts_ns: ts_event,
+        books: books
+            .iter()
+            .map(|(publisher, book)| L3Book {
+                publisher_id: *publisher as u16,
+                bids: l3_orders(book.iter_bid_orders()),
+                asks: l3_orders(book.iter_ask_orders()),
+            })
+            .collect(),
+    })
+}
+
+/// Numbers each order's place in its level from orders given level by level.
+fn l3_orders<'a>(orders: impl Iterator<Item = (i64, &'a Order)>) -> Vec<L3Order> {
+    let mut level: Option<i64> = None;
+    let mut ahead = 0u32;
+    orders
+        .map(|(price, order)| {
+            if level != Some(price) {
+                level = Some(price);
+                ahead = 0;
+            }
+            let entry = L3Order {
+                order_id: order.order_id,
+                price,
+                size: order.size,
+                queue_pos: ahead,
+                ts_event: order.ts_event,
+            };
+            ahead = ahead.saturating_add(order.size);
+            entry
+        })
+        .collect()
+}
+
+pub fn build_delta_record(prev: &SnapshotRecord, next: &SnapshotRecord) -> DeltaRecord {
+    let payload = BookDelta {
+        symbol: next.payload.symbol.clone(),
+        ts_ns: next.payload.ts_ns,
+        prev_ts_ns: prev.payload.ts_ns,
+        seq: next.payload.seq,
+        prev_seq: prev.payload.seq,
+        bbo: (prev.payload.bbo != next.payload.bbo).then(|| next.payload.bbo.clone()),
+        signals: next.payload.signals.clone(),
+        liquidity: next.payload.liquidity.clone(),
+        buckets: next.payload.buckets.clone(),
+        bids: diff_side(&prev.payload.bids, &next.payload.bids),
+        asks: diff_side(&prev.payload.asks, &next.payload.asks),
+        total_orders: next.payload.total_orders,
+        bid_levels: next.payload.bid_levels,
+        ask_levels: next.payload.ask_levels,
+    };
+    DeltaRecord {
+        instrument_id: next.instrument_id,
+        ts_event: next.ts_event,
+        payload,
+    }
+}
+
+fn diff_side(prev: &[LevelEntry], next: &[LevelEntry]) -> SideDelta {
+    let prev_by_px: HashMap<i64, &LevelEntry> = prev.iter().map(|l| (l.price, l)).collect();
+    let mut delta = SideDelta::default();
+    for level in next {
+        match prev_by_px.get(&level.price) {
+            None => delta.added.push(level.clone()),
+            Some(old) if *old != level => delta.changed.push(level.clone()),
+            Some(_) => {}
+        }
+    }
+    let next_prices: HashSet<i64> = next.iter().map(|l| l.price).collect();
+    delta.removed = prev
+        .iter()
+        .filter(|l| !next_prices.contains(&l.price))
+        .map(|l| l.price)
+        .collect();
+    delta
+}
+
+impl BookDelta {
+    pub fn is_empty(&self) -> bool {
+        self.bbo.is_none() && self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    pub fn apply_to(&self, snapshot: &mut Snapshot) {
+        apply_side(&mut snapshot.bids, &self.bids, |a, b| b.cmp(&a));
+        apply_side(&mut snapshot.asks, &self.asks, |a, b| a.cmp(&b));
+        if let Some(bbo) = &self.bbo {
+            snapshot.bbo = bbo.clone();
+        }
+        snapshot.ts_ns = self.ts_ns;
+        snapshot.seq = self.seq;
+        snapshot.signals = self.signals.clone();
+        snapshot.liquidity = self.liquidity.clone();
+        snapshot.buckets = self.buckets.clone();
+        snapshot.total_orders = self.total_orders;
+        snapshot.bid_levels = self.bid_levels;
+        snapshot.ask_levels = self.ask_levels;
+    }
+}
+
+impl SideDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn apply_side(
+    levels: &mut Vec<LevelEntry>,
+    delta: &SideDelta,
+    order: impl Fn(i64, i64) -> std::cmp::Ordering,
+) {
+    levels.retain(|l| !delta.removed.contains(&l.price));
+    for changed in &delta.changed {
+        if let Some(level) = levels.iter_mut().find(|l| l.price == changed.price) {
+            *level = changed.clone();
+        }
+    }
+    levels.extend(delta.added.iter().cloned());
+    levels.sort_by(|a, b| order(a.price, b.price));
+}
+
 fn to_level_entry(level: &PriceLevel) -> LevelEntry {
     LevelEntry {
         price: level.price,
         size: level.size,
         count: level.count,
+        est_hidden: None,
     }
 }
 
 // MBP output format structures
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct MbpLevel {
     pub count: u32,
     pub price: String,
     pub size: u32,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct MbpBboSide {
     pub count: u32,
     pub price: String,
     pub size: u32,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct MbpBbo {
     pub ask: Option<MbpBboSide>,
     pub bid: Option<MbpBboSide>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct MbpLevels {
     pub asks: Vec<MbpLevel>,
     pub bids: Vec<MbpLevel>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct MbpStats {
     pub ask_levels: usize,
     pub bid_levels: usize,
     pub total_orders: usize,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct MbpOutput {
     pub bbo: MbpBbo,
     pub levels: MbpLevels,
     pub info: MbpStats,
     pub symbol: String,
     pub timestamp: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notional: Option<Notional>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signals: Option<FlowSignals>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liquidity: Option<Liquidity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buckets: Option<BucketedDepth>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub implied: Option<MbpBbo>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    /// `Snapshot::seq`; missing in files written before snapshots were numbered.
+    #[serde(default)]
+    pub seq: u64,
 }
 
-fn level_to_mbp(e: &LevelEntry) -> MbpLevel {
-    MbpLevel {
-        count: e.count,
-        price: e.price.to_string(),
-        size: e.size,
+/// How the MBP output writes prices (`MBP_PRICES`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PriceFormat {
+    /// Fixed-point integers in units of 1e-9, as DBN carries them.
+    Raw,
+    /// Decimals with at least `decimals` fractional digits, and more for a price off
+    /// the tick so nothing is rounded away. Always written with a decimal point, which
+    /// tells them apart from raw prices.
+    Decimal { decimals: u32 },
+}
+
+impl PriceFormat {
+    /// Decimal prices with as many fractional digits as the fixed-point `tick_size`.
+    pub fn for_tick(tick_size: i64) -> Self {
+        let mut fraction = tick_size % FIXED_PRICE_SCALE;
+        let mut decimals = 9;
+        if fraction == 0 {
+            decimals = 0;
+        }
+        while fraction != 0 && fraction % 10 == 0 {
+            fraction /= 10;
+            decimals -= 1;
+        }
+        Self::Decimal { decimals }
+    }
+
+    pub fn format(self, price: i64) -> String {
+        let Self::Decimal { decimals } = self else {
+            return price.to_string();
+        };
+        let units = price / FIXED_PRICE_SCALE;
+        let mut fraction = format!("{:09}", (price % FIXED_PRICE_SCALE).unsigned_abs());
+        let digits = fraction
+            .trim_end_matches('0')
+            .len()
+            .max(decimals as usize)
+            .max(1);
+        fraction.truncate(digits);
+        let sign = if price < 0 && units == 0 { "-" } else { "" };
+        format!("{}{}.{}", sign, units, fraction)
     }
 }
 
-fn level_to_mbp_bbo(e: &LevelEntry) -> MbpBboSide {
-    MbpBboSide {
-        count: e.count,
-        price: e.price.to_string(),
-        size: e.size,
+/// Reads a price written in either `PriceFormat`.
+pub fn parse_mbp_price(raw: &str) -> Result<i64> {
+    let invalid = || anyhow!("invalid MBP price {:?}", raw);
+    let Some((units, fraction)) = raw.split_once('.') else {
+        return raw.parse().map_err(|_| invalid());
+    };
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
     }
+    let negative = units.starts_with('-');
+    let units: i64 = units.parse().map_err(|_| invalid())?;
+    let nanos: i64 = format!("{:0<9}", fraction).parse().map_err(|_| invalid())?;
+    let magnitude = units
+        .unsigned_abs()
+        .checked_mul(FIXED_PRICE_SCALE as u64)
+        .and_then(|scaled| scaled.checked_add(nanos as u64))
+        .and_then(|total| i64::try_from(total).ok())
+        .ok_or_else(invalid)?;
+    Ok(if negative { -magnitude } else { magnitude })
 }
 
-pub fn snapshot_to_mbp_output(rec: &SnapshotRecord) -> MbpOutput {
+pub fn snapshot_to_mbp_output(rec: &SnapshotRecord, prices: PriceFormat) -> MbpOutput {
+    let level = |e: &LevelEntry| MbpLevel {
+        count: e.count,
+        price: prices.format(e.price),
+        size: e.size,
+    };
+    let bbo_side = |e: &LevelEntry| MbpBboSide {
+        count: e.count,
+        price: prices.format(e.price),
+        size: e.size,
+    };
     MbpOutput {
         bbo: MbpBbo {
-            ask: rec.payload.bbo.best_ask.as_ref().map(level_to_mbp_bbo),
-            bid: rec.payload.bbo.best_bid.as_ref().map(level_to_mbp_bbo),
+            ask: rec.payload.bbo.best_ask.as_ref().map(bbo_side),
+            bid: rec.payload.bbo.best_bid.as_ref().map(bbo_side),
         },
         levels: MbpLevels {
-            asks: rec.payload.asks.iter().map(level_to_mbp).collect(),
-            bids: rec.payload.bids.iter().map(level_to_mbp).collect(),
+            asks: rec.payload.asks.iter().map(level).collect(),
+            bids: rec.payload.bids.iter().map(level).collect(),
         },
         info: MbpStats {
             ask_levels: rec.payload.ask_levels,
@@ -225,5 +1215,79 @@ pub fn snapshot_to_mbp_output(rec: &SnapshotRecord) -> MbpOutput {
         },
         symbol: rec.payload.symbol.clone(),
         timestamp: rec.payload.ts_ns.to_string(),
+        notional: rec.payload.notional.clone(),
+        signals: rec.payload.signals.clone(),
+        liquidity: rec.payload.liquidity.clone(),
+        buckets: rec.payload.buckets.clone(),
+        implied: rec.payload.implied.as_ref().map(|implied| MbpBbo {
+            ask: implied.best_ask.as_ref().map(bbo_side),
+            bid: implied.best_bid.as_ref().map(bbo_side),
+        }),
+        stale: rec.payload.stale,
+        seq: rec.payload.seq,
     }
 }
+
+/// Rebuilds a snapshot from a line of MBP output, e.g. a previous run's `final_mbp.json`.
+pub fn mbp_output_to_snapshot(mbp: &MbpOutput, instrument_id: u32) -> Result<SnapshotRecord> {
+    let ts_ns: i64 = mbp
+        .timestamp
+        .parse()
+        .with_context(|| format!("invalid MBP timestamp {:?}", mbp.timestamp))?;
+    let level = |count: u32, price: &str, size: u32| -> Result<LevelEntry> {
+        Ok(LevelEntry {
+            price: parse_mbp_price(price)?,
+            size,
+            count,
+            est_hidden: None,
+        })
+    };
+    let levels = |levels: &[MbpLevel]| -> Result<Vec<LevelEntry>> {
+        levels
+            .iter()
+            .map(|l| level(l.count, &l.price, l.size))
+            .collect()
+    };
+    let bbo_side = |side: &Option<MbpBboSide>| -> Result<Option<LevelEntry>> {
+        side.as_ref()
+            .map(|s| level(s.count, &s.price, s.size))
+            .transpose()
+    };
+    Ok(SnapshotRecord {
+        instrument_id,
+        publisher_id: 0,
+        ts_event: ts_ns,
+        ts_recv: ts_ns,
+        built_at: wall_clock_ns(),
+        payload: Snapshot {
+            bbo: Bbo {
+                best_bid: bbo_side(&mbp.bbo.bid)?,
+                best_ask: bbo_side(&mbp.bbo.ask)?,
+            },
+            symbol: mbp.symbol.clone(),
+            ts_ns,
+            seq: mbp.seq,
+            bids: levels(&mbp.levels.bids)?,
+            asks: levels(&mbp.levels.asks)?,
+            total_orders: mbp.info.total_orders,
+            bid_levels: mbp.info.bid_levels,
+            ask_levels: mbp.info.ask_levels,
+            notional: mbp.notional.clone(),
+            signals: mbp.signals.clone(),
+            liquidity: mbp.liquidity.clone(),
+            buckets: mbp.buckets.clone(),
+            implied: mbp
+                .implied
+                .as_ref()
+                .map(|implied| -> Result<Bbo> {
+                    Ok(Bbo {
+                        best_bid: bbo_side(&implied.bid)?,
+                        best_ask: bbo_side(&implied.ask)?,
+                    })
+                })
+                .transpose()?,
+            stale: mbp.stale,
+        },
+        cache: EncodedCache::default(),
+    })
+}
@@ -1,21 +1,67 @@
 use std::{
-    io::Write,
-    sync::Arc,
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File, OpenOptions},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering},
+    },
     thread,
     time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, anyhow};
-use crossbeam_channel::{Receiver, RecvTimeoutError};
+use arrow_array::{
+    ArrayRef, ArrowPrimitiveType, Int64Array, ListArray, RecordBatch, StringArray, UInt32Array,
+    UInt64Array,
+    types::{Int64Type, UInt32Type},
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use crossbeam_channel::RecvTimeoutError;
+use parquet::{
+    arrow::ArrowWriter, basic::Compression as ParquetCompression,
+    file::properties::WriterProperties,
+};
 use postgres::error::SqlState;
-use postgres::{Client, Config, NoTls};
+use postgres::{
+    Client, Config, NoTls,
+    fallible_iterator::FallibleIterator,
+    types::{Json, ToSql, Type},
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::{
+    runtime::Runtime,
+    sync::{mpsc, oneshot},
+};
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tracing::{error, info, warn};
 
-use crate::snapshot::SharedSnapshot;
+use crate::{
+    analytics::{
+        bars::{Bar, SharedBar},
+        rolling::{BookStats, SharedStats},
+    },
+    codec::{Codec, Encoder},
+    latency::{PublishLatency, wall_clock_ns},
+    queue::{self, Coalesce, QueueConfig, QueueCounters, QueueReceiver, QueueSender, QueueStats},
+    snapshot::{
+        Bbo, EncodedCache, LevelEntry, SharedSnapshot, Snapshot, SnapshotEncoder, SnapshotEncoding,
+        SnapshotRecord,
+    },
+    supervisor::{RestartPolicy, SinkHealth, SinkSwitches, spawn_supervised},
+    trades::{SharedTrade, TradeRecord},
+    venue_stats::{ImbalanceRecord, SharedImbalance, SharedStatistic, StatisticRecord},
+};
 
 const TABLE_DDL: &str = r#"
 CREATE TABLE IF NOT EXISTS orderbook_snapshots (
     id BIGSERIAL PRIMARY KEY,
     symbol VARCHAR(50) NOT NULL,
+    instrument_id BIGINT NOT NULL DEFAULT 0,
     ts_event BIGINT NOT NULL,
     best_bid_price BIGINT NOT NULL,
     best_bid_size INTEGER NOT NULL,
@@ -28,87 +74,3551 @@ CREATE TABLE IF NOT EXISTS orderbook_snapshots (
     total_orders INTEGER NOT NULL,
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
 );
+ALTER TABLE orderbook_snapshots
+    ADD COLUMN IF NOT EXISTS instrument_id BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE orderbook_snapshots
+    ADD COLUMN IF NOT EXISTS levels JSONB;
+ALTER TABLE orderbook_snapshots
+    ADD COLUMN IF NOT EXISTS seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE orderbook_snapshots
+    ADD COLUMN IF NOT EXISTS run_id BIGINT;
+ALTER TABLE orderbook_snapshots
+    ADD COLUMN IF NOT EXISTS ts_recv BIGINT;
 CREATE INDEX IF NOT EXISTS idx_orderbook_snapshots_ts
     ON orderbook_snapshots (ts_event);
 CREATE INDEX IF NOT EXISTS idx_orderbook_snapshots_symbol
     ON orderbook_snapshots (symbol, ts_event DESC);
 "#;
 
-#[derive(Clone, Debug)]
-pub struct StorageConfig {
-    pub db_url: Arc<String>,
-    pub batch_size: usize,
-    pub flush_interval: Duration,
+/// The newest snapshot of each book, kept up to date with the history when
+/// `SNAPSHOT_LATEST_TABLE` is on.
+const LATEST_DDL: &str = r#"
+CREATE TABLE IF NOT EXISTS orderbook_latest (
+    symbol VARCHAR(50) NOT NULL,
+    publisher_id INTEGER NOT NULL,
+    instrument_id BIGINT NOT NULL,
+    ts_event BIGINT NOT NULL,
+    ts_recv BIGINT NOT NULL,
+    seq BIGINT NOT NULL,
+    best_bid_price BIGINT NOT NULL,
+    best_bid_size INTEGER NOT NULL,
+    best_bid_count INTEGER NOT NULL,
+    best_ask_price BIGINT NOT NULL,
+    best_ask_size INTEGER NOT NULL,
+    best_ask_count INTEGER NOT NULL,
+    bid_levels INTEGER NOT NULL,
+    ask_levels INTEGER NOT NULL,
+    total_orders INTEGER NOT NULL,
+    levels JSONB NOT NULL,
+    run_id BIGINT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (symbol, publisher_id)
+);
+"#;
+
+const TRADES_DDL: &str = r#"
+CREATE TABLE IF NOT EXISTS trades (
+    id BIGSERIAL PRIMARY KEY,
+    symbol VARCHAR(50) NOT NULL,
+    instrument_id BIGINT NOT NULL,
+    publisher_id INTEGER NOT NULL,
+    ts_event BIGINT NOT NULL,
+    price BIGINT NOT NULL,
+    size INTEGER NOT NULL,
+    side CHAR(1) NOT NULL,
+    -- Ids above i64::MAX are stored as their two's-complement bit pattern
+    order_id BIGINT NOT NULL,
+    sequence BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+ALTER TABLE trades
+    ADD COLUMN IF NOT EXISTS ts_recv BIGINT;
+CREATE INDEX IF NOT EXISTS idx_trades_symbol
+    ON trades (symbol, ts_event DESC);
+"#;
+
+const BARS_DDL: &str = r#"
+CREATE TABLE IF NOT EXISTS bars (
+    id BIGSERIAL PRIMARY KEY,
+    symbol VARCHAR(50) NOT NULL,
+    instrument_id BIGINT NOT NULL,
+    interval VARCHAR(16) NOT NULL,
+    start_ts BIGINT NOT NULL,
+    end_ts BIGINT NOT NULL,
+    open BIGINT NOT NULL,
+    high BIGINT NOT NULL,
+    low BIGINT NOT NULL,
+    close BIGINT NOT NULL,
+    volume BIGINT NOT NULL,
+    trades BIGINT NOT NULL,
+    vwap BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_bars_symbol
+    ON bars (symbol, interval, start_ts DESC);
+"#;
+
+const BOOK_STATS_DDL: &str = r#"
+CREATE TABLE IF NOT EXISTS book_stats (
+    id BIGSERIAL PRIMARY KEY,
+    symbol VARCHAR(50) NOT NULL,
+    instrument_id BIGINT NOT NULL,
+    ts_event BIGINT NOT NULL,
+    window_ms BIGINT NOT NULL,
+    snapshots BIGINT NOT NULL,
+    avg_spread BIGINT,
+    avg_bid_depth DOUBLE PRECISION NOT NULL,
+    avg_ask_depth DOUBLE PRECISION NOT NULL,
+    realized_vol DOUBLE PRECISION,
+    updates_per_sec DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_book_stats_symbol
+    ON book_stats (symbol, ts_event DESC);
+"#;
+
+const IMBALANCES_DDL: &str = r#"
+CREATE TABLE IF NOT EXISTS imbalances (
+    id BIGSERIAL PRIMARY KEY,
+    symbol VARCHAR(50) NOT NULL,
+    instrument_id BIGINT NOT NULL,
+    publisher_id INTEGER NOT NULL,
+    ts_event BIGINT NOT NULL,
+    ts_recv BIGINT NOT NULL,
+    ref_price BIGINT,
+    auction_time BIGINT,
+    cont_book_clr_price BIGINT,
+    auct_interest_clr_price BIGINT,
+    paired_qty BIGINT NOT NULL,
+    total_imbalance_qty BIGINT NOT NULL,
+    side CHAR(1) NOT NULL,
+    auction_type CHAR(1) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_imbalances_symbol
+    ON imbalances (symbol, ts_event DESC);
+"#;
+
+const STATISTICS_DDL: &str = r#"
+CREATE TABLE IF NOT EXISTS statistics (
+    id BIGSERIAL PRIMARY KEY,
+    symbol VARCHAR(50) NOT NULL,
+    instrument_id BIGINT NOT NULL,
+    publisher_id INTEGER NOT NULL,
+    ts_event BIGINT NOT NULL,
+    ts_recv BIGINT NOT NULL,
+    ts_ref BIGINT,
+    stat_type INTEGER NOT NULL,
+    stat VARCHAR(32) NOT NULL,
+    price BIGINT,
+    quantity BIGINT,
+    sequence BIGINT NOT NULL,
+    stat_flags SMALLINT NOT NULL,
+    deleted BOOLEAN NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS idx_statistics_symbol
+    ON statistics (symbol, stat_type, ts_event DESC);
+"#;
+
+const RUNS_DDL: &str = r#"
+CREATE TABLE IF NOT EXISTS ingest_runs (
+    id BIGSERIAL PRIMARY KEY,
+    input_path TEXT NOT NULL,
+    symbol VARCHAR(50) NOT NULL,
+    status VARCHAR(16) NOT NULL,
+    processed BIGINT,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS idx_ingest_runs_input
+    ON ingest_runs (input_path, symbol);
+"#;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+    /// Stopped early by a shutdown signal after draining; does not block a re-ingest.
+    Interrupted,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Running => "running",
+            RunStatus::Completed => "completed",
+            RunStatus::Failed => "failed",
+            RunStatus::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// What drives periodic flushes of the writer buffer.
+#[derive(Clone, Copy, Debug)]
+pub enum FlushSchedule {
+    /// Flush every `flush_interval` of wall-clock time.
+    WallClock,
+    /// Flush whenever snapshot `ts_event` crosses a `bucket_ns` boundary, so paced or
+    /// simulated replays flush in step with data time.
+    DataTime { bucket_ns: i64 },
+}
+
+/// Layout of `orderbook_snapshots` as a TimescaleDB hypertable partitioned on `ts_event`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimescaleConfig {
+    /// Width of each chunk in `ts_event` nanoseconds.
+    pub chunk_interval_ns: i64,
+    /// Compress chunks whose data is older than this many nanoseconds; `None` leaves
+    /// compression as configured on the server.
+    pub compress_after_ns: Option<i64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StorageConfig {
+    pub db_url: Arc<String>,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub flush_schedule: FlushSchedule,
+    pub sinks: Vec<SinkKind>,
+    pub parquet_row_group_size: usize,
+    /// Compression of `file:` sinks.
+    pub file_codec: Codec,
+    /// How `file:` sinks encode snapshots.
+    pub file_encoding: SnapshotEncoding,
+    /// Column compression of `parquet:` sinks; `None` keeps Snappy.
+    pub parquet_codec: Option<Codec>,
+    pub timescale: Option<TimescaleConfig>,
+    /// Number of writer shards, each with its own sinks and batch buffer.
+    pub writers: usize,
+    /// How each Postgres sink spreads its COPYs over connections.
+    pub pipeline: CopyPipeline,
+    /// How Postgres sinks retry a lost connection.
+    pub retry: RetryPolicy,
+    /// Where Postgres sinks spill batches they fail to copy; `None` fails the writer.
+    pub spill_dir: Option<PathBuf>,
+    /// `ingest_runs` row stamped on every stored snapshot; `None` for unrecorded runs.
+    pub run_id: Option<i64>,
+    /// Postgres sinks also upsert the newest snapshot of each book into `orderbook_latest`.
+    pub latest_table: bool,
+    /// Keys, channel and encoding of `redis:` sinks.
+    pub redis: RedisOptions,
+    /// Write-ahead journal every queued snapshot is appended to first.
+    pub journal: Option<Arc<Journal>>,
+    /// Where written snapshots record how long they took to get stored.
+    pub latency: Option<Arc<PublishLatency>>,
+}
+
+impl StorageConfig {
+    pub fn new(db_url: Arc<String>, batch_size: usize, flush_interval: Duration) -> Self {
+        Self {
+            db_url,
+            batch_size: batch_size.max(1),
+            flush_interval,
+            flush_schedule: FlushSchedule::WallClock,
+            sinks: vec![SinkKind::Postgres],
+            parquet_row_group_size: 100_000,
+            file_codec: Codec::NONE,
+            file_encoding: SnapshotEncoding::Json,
+            parquet_codec: None,
+            timescale: None,
+            writers: 1,
+            pipeline: CopyPipeline {
+                connections: 1,
+                in_flight: 1,
+            },
+            retry: RetryPolicy::once(),
+            spill_dir: None,
+            run_id: None,
+            latest_table: false,
+            redis: RedisOptions::default(),
+            journal: None,
+            latency: None,
+        }
+    }
+
+    pub fn with_latency(mut self, latency: Arc<PublishLatency>) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    pub fn with_run_id(mut self, run_id: Option<i64>) -> Self {
+        self.run_id = run_id;
+        self
+    }
+
+    pub fn with_latest_table(mut self, latest_table: bool) -> Self {
+        self.latest_table = latest_table;
+        self
+    }
+
+    pub fn with_redis(mut self, redis: RedisOptions) -> Self {
+        self.redis = redis;
+        self
+    }
+
+    pub fn with_timescale(mut self, timescale: Option<TimescaleConfig>) -> Self {
+        self.timescale = timescale;
+        self
+    }
+
+    pub fn with_sinks(mut self, sinks: Vec<SinkKind>) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    pub fn with_parquet_row_group_size(mut self, rows: usize) -> Self {
+        self.parquet_row_group_size = rows.max(1);
+        self
+    }
+
+    pub fn with_file_codec(mut self, codec: Codec) -> Self {
+        self.file_codec = codec;
+        self
+    }
+
+    pub fn with_file_encoding(mut self, encoding: SnapshotEncoding) -> Self {
+        self.file_encoding = encoding;
+        self
+    }
+
+    pub fn with_parquet_codec(mut self, codec: Option<Codec>) -> Self {
+        self.parquet_codec = codec;
+        self
+    }
+
+    pub fn with_flush_schedule(mut self, flush_schedule: FlushSchedule) -> Self {
+        self.flush_schedule = flush_schedule;
+        self
+    }
+
+    pub fn with_writers(mut self, writers: usize) -> Self {
+        self.writers = writers.max(1);
+        self
+    }
+
+    pub fn with_pipeline(mut self, pipeline: CopyPipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_spill_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.spill_dir = dir;
+        self
+    }
+
+    pub fn with_journal(mut self, journal: Option<Arc<Journal>>) -> Self {
+        self.journal = journal;
+        self
+    }
+}
+
+/// Tracks the flush cadence for `FlushSchedule`. In data-time mode the wall-clock
+/// interval only applies as an idle timeout when no snapshots arrive.
+struct FlushScheduler {
+    schedule: FlushSchedule,
+    interval: Duration,
+    current_bucket: Option<i64>,
+}
+
+impl FlushScheduler {
+    fn new(schedule: FlushSchedule, interval: Duration) -> Self {
+        Self {
+            schedule,
+            interval,
+            current_bucket: None,
+        }
+    }
+
+    /// Returns true when `ts_event` falls in a later bucket than the buffered data.
+    fn starts_new_bucket(&mut self, ts_event: i64) -> bool {
+        let FlushSchedule::DataTime { bucket_ns } = self.schedule else {
+            return false;
+        };
+        let bucket = ts_event.div_euclid(bucket_ns);
+        let crossed = self.current_bucket.is_some_and(|current| current != bucket);
+        self.current_bucket = Some(bucket);
+        crossed
+    }
+
+    fn interval_due(&self, last_flush: Instant) -> bool {
+        matches!(self.schedule, FlushSchedule::WallClock) && last_flush.elapsed() >= self.interval
+    }
+}
+
+/// What ingest hands to the storage writers.
+#[derive(Clone, Debug)]
+pub enum StorageItem {
+    Snapshot(SharedSnapshot),
+    Trade(SharedTrade),
+    /// A closed time bar, stored only when bar storage is enabled.
+    Bar(SharedBar),
+    /// Rolling book statistics at the end of a persist interval (`STATS_PERSIST_SECS`).
+    Stats(SharedStats),
+    /// An auction imbalance from the feed's Imbalance records.
+    Imbalance(SharedImbalance),
+    /// A venue statistic from the feed's Statistics records.
+    Statistic(SharedStatistic),
+}
+
+impl StorageItem {
+    fn ts_event(&self) -> i64 {
+        match self {
+            StorageItem::Snapshot(snapshot) => snapshot.ts_event,
+            StorageItem::Trade(trade) => trade.ts_event,
+            StorageItem::Bar(bar) => bar.start_ts,
+            StorageItem::Stats(stats) => stats.ts_event,
+            StorageItem::Imbalance(imbalance) => imbalance.ts_event,
+            StorageItem::Statistic(statistic) => statistic.ts_event,
+        }
+    }
+}
+
+impl Coalesce for StorageItem {
+    fn coalesce_key(&self) -> Option<u32> {
+        match self {
+            StorageItem::Snapshot(snapshot) => snapshot.coalesce_key(),
+            StorageItem::Trade(_)
+            | StorageItem::Bar(_)
+            | StorageItem::Stats(_)
+            | StorageItem::Imbalance(_)
+            | StorageItem::Statistic(_) => None,
+        }
+    }
+}
+
+/// A destination for persisted snapshots. The writer loop owns batching and flush
+/// cadence; sinks only see whole batches.
+pub trait SnapshotSink: Send {
+    /// Short label used in logs.
+    fn name(&self) -> String;
+
+    /// Called by the writer loop before each batch, between batches.
+    fn before_batch(&mut self) {}
+
+    /// True for sinks that record `PublishLatency::stored` themselves, once a batch is
+    /// durably written rather than when `write_batch` returns.
+    fn records_stored_latency(&self) -> bool {
+        false
+    }
+
+    /// Persists one batch. Sinks may buffer internally until `flush`.
+    fn write_batch(&mut self, batch: &[SharedSnapshot]) -> Result<()>;
+
+    /// Persists executed trades; sinks without a trade table ignore them.
+    fn write_trades(&mut self, _trades: &[SharedTrade]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Persists closed time bars; sinks without a bars table ignore them.
+    fn write_bars(&mut self, _bars: &[SharedBar]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Persists rolling book statistics; sinks without a book_stats table ignore them.
+    fn write_stats(&mut self, _stats: &[SharedStats]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Persists auction imbalances; sinks without an imbalances table ignore them.
+    fn write_imbalances(&mut self, _imbalances: &[SharedImbalance]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Persists venue statistics; sinks without a statistics table ignore them.
+    fn write_statistics(&mut self, _statistics: &[SharedStatistic]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Pushes any internally buffered rows to the backend.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once after the last batch when ingest ends.
+    fn close(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+/// Which sinks the writer fans out to, parsed from `SNAPSHOT_SINKS`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SinkKind {
+    Postgres,
+    /// Snapshots appended to `path`, as JSON lines unless `SNAPSHOT_FILE_FORMAT` says
+    /// otherwise.
+    JsonFile {
+        path: String,
+    },
+    /// Parquet files partitioned by symbol and hour under `dir`.
+    Parquet {
+        dir: String,
+    },
+    /// Latest snapshot per symbol SET in, and PUBLISHed on, the Redis server at `url`.
+    Redis {
+        url: String,
+    },
+}
+
+impl SinkKind {
+    /// Parses a comma-separated list such as
+    /// `postgres,file:snapshots.jsonl,parquet:snapshots,redis:redis://localhost:6379`.
+    pub fn parse_list(raw: &str) -> Result<Vec<SinkKind>> {
+        let kinds = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                None if entry == "postgres" => Ok(SinkKind::Postgres),
+                Some(("file", path)) if !path.is_empty() => Ok(SinkKind::JsonFile {
+                    path: path.to_owned(),
+                }),
+                Some(("parquet", dir)) if !dir.is_empty() => Ok(SinkKind::Parquet {
+                    dir: dir.to_owned(),
+                }),
+                Some(("redis", url)) if !url.is_empty() => Ok(SinkKind::Redis {
+                    url: url.to_owned(),
+                }),
+                _ => Err(anyhow!(
+                    "unknown sink {:?}, expected postgres, file:<path>, parquet:<dir> or redis:<url>",
+                    entry
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if kinds.is_empty() {
+            return Err(anyhow!("at least one sink must be configured"));
+        }
+        Ok(kinds)
+    }
+}
+
+/// Opens the configured sinks and registers them with `switches`. A shard of a sharded
+/// writer passes the shared `bulk` load so the Postgres table is prepared once for all
+/// shards.
+fn open_sinks(
+    config: &StorageConfig,
+    bulk: Option<&BulkLoad>,
+    stats: &StorageStats,
+    switches: &Arc<SinkSwitches>,
+) -> Result<Box<dyn SnapshotSink>> {
+    let recovery = Recovery {
+        retry: config.retry,
+        retries: stats.retries.clone(),
+        spill: stats.spill.clone(),
+    };
+    let sinks = config
+        .sinks
+        .iter()
+        .map(|kind| -> Result<Box<dyn SnapshotSink>> {
+            match (kind, bulk) {
+                (SinkKind::Postgres, None) => Ok(Box::new(PostgresSink::connect(
+                    config.db_url.clone(),
+                    config.timescale,
+                    config.run_id,
+                    config.latest_table,
+                    config.pipeline,
+                    recovery.clone(),
+                    config.latency.clone(),
+                )?)),
+                (SinkKind::Postgres, Some(bulk)) => Ok(Box::new(PostgresSink::connect_shard(
+                    config.db_url.clone(),
+                    bulk,
+                    config.run_id,
+                    config.latest_table,
+                    config.pipeline,
+                    recovery.clone(),
+                    config.latency.clone(),
+                )?)),
+                (SinkKind::JsonFile { path }, _) => Ok(Box::new(JsonFileSink::create(
+                    path,
+                    config.file_codec,
+                    config.file_encoding,
+                )?)),
+                (SinkKind::Parquet { dir }, _) => Ok(Box::new(ParquetSink::create(
+                    dir,
+                    config.parquet_row_group_size,
+                    parquet_compression(config.parquet_codec)?,
+                )?)),
+                (SinkKind::Redis { url }, _) => {
+                    Ok(Box::new(RedisSink::connect(url, config.redis.clone())?))
+                }
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Box::new(FanoutSink::new(sinks, switches.clone())))
+}
+
+/// Writes every batch to each enabled inner sink in order. The first failure aborts the
+/// batch, so sinks earlier in the list may hold rows the later ones are missing.
+/// Switches are read in `before_batch`, so a sink toggled through `/admin/sinks` sees
+/// whole batches only; disabled sinks are still flushed and closed.
+pub struct FanoutSink {
+    sinks: Vec<Box<dyn SnapshotSink>>,
+    switches: Arc<SinkSwitches>,
+    enabled: Vec<bool>,
+}
+
+impl FanoutSink {
+    pub fn new(sinks: Vec<Box<dyn SnapshotSink>>, switches: Arc<SinkSwitches>) -> Self {
+        for sink in &sinks {
+            switches.register(&sink.name());
+        }
+        let enabled = sinks
+            .iter()
+            .map(|sink| switches.is_enabled(&sink.name()))
+            .collect();
+        Self {
+            sinks,
+            switches,
+            enabled,
+        }
+    }
+
+    fn enabled_sinks(&mut self) -> impl Iterator<Item = &mut Box<dyn SnapshotSink>> {
+        self.sinks
+            .iter_mut()
+            .zip(&self.enabled)
+            .filter(|(_, enabled)| **enabled)
+            .map(|(sink, _)| sink)
+    }
+}
+
+impl SnapshotSink for FanoutSink {
+    fn name(&self) -> String {
+        self.sinks
+            .iter()
+            .map(|sink| sink.name())
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+
+    fn before_batch(&mut self) {
+        for (sink, enabled) in self.sinks.iter_mut().zip(&mut self.enabled) {
+            let name = sink.name();
+            let now = self.switches.is_enabled(&name);
+            if now != *enabled {
+                info!(sink = %name, enabled = now, "sink toggled");
+                *enabled = now;
+            }
+            if now {
+                sink.before_batch();
+            }
+        }
+    }
+
+    fn records_stored_latency(&self) -> bool {
+        self.sinks
+            .iter()
+            .zip(&self.enabled)
+            .any(|(sink, enabled)| *enabled && sink.records_stored_latency())
+    }
+
+    fn write_batch(&mut self, batch: &[SharedSnapshot]) -> Result<()> {
+        for sink in self.enabled_sinks() {
+            let name = sink.name();
+            sink.write_batch(batch)
+                .with_context(|| format!("sink {} failed to write batch", name))?;
+        }
+        Ok(())
+    }
+
+    fn write_trades(&mut self, trades: &[SharedTrade]) -> Result<()> {
+        for sink in self.enabled_sinks() {
+            let name = sink.name();
+            sink.write_trades(trades)
+                .with_context(|| format!("sink {} failed to write trades", name))?;
+        }
+        Ok(())
+    }
+
+    fn write_bars(&mut self, bars: &[SharedBar]) -> Result<()> {
+        for sink in self.enabled_sinks() {
+            let name = sink.name();
+            sink.write_bars(bars)
+                .with_context(|| format!("sink {} failed to write bars", name))?;
+        }
+        Ok(())
+    }
+
+    fn write_stats(&mut self, stats: &[SharedStats]) -> Result<()> {
+        for sink in self.enabled_sinks() {
+            let name = sink.name();
+            sink.write_stats(stats)
+                .with_context(|| format!("sink {} failed to write book stats", name))?;
+        }
+        Ok(())
+    }
+
+    fn write_imbalances(&mut self, imbalances: &[SharedImbalance]) -> Result<()> {
+        for sink in self.enabled_sinks() {
+            let name = sink.name();
+            sink.write_imbalances(imbalances)
+                .with_context(|| format!("sink {} failed to write imbalances", name))?;
+        }
+        Ok(())
+    }
+
+    fn write_statistics(&mut self, statistics: &[SharedStatistic]) -> Result<()> {
+        for sink in self.enabled_sinks() {
+            let name = sink.name();
+            sink.write_statistics(statistics)
+                .with_context(|| format!("sink {} failed to write statistics", name))?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for sink in &mut self.sinks {
+            let name = sink.name();
+            sink.flush()
+                .with_context(|| format!("sink {} failed to flush", name))?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        for sink in &mut self.sinks {
+            let name = sink.name();
+            sink.close()
+                .with_context(|| format!("sink {} failed to close", name))?;
+        }
+        Ok(())
+    }
+}
+
+/// The `orderbook_snapshots`, `trades` and `bars` tables, loaded with COPY over
+/// tokio-postgres on the sink's own runtime. Rows are split by instrument across
+/// `connections` lanes, each copying its batches in order on a connection of its own,
+/// so the lanes load in parallel while every instrument's rows commit in the order they
+/// were written. The writer goes on buffering while up to `pipeline` batches are in
+/// flight, each in its own transaction; a failed batch is reported by the next write,
+/// `flush` or `close`. Indexes are dropped while the sink is open and rebuilt on
+/// `close`, unless the sink is one shard of a bulk load whose indexes are rebuilt once
+/// every shard has finished.
+pub struct PostgresSink {
+    db_url: Arc<String>,
+    runtime: Runtime,
+    lanes: Vec<mpsc::UnboundedSender<LaneJob>>,
+    /// Completion of every batch handed to a lane, oldest first.
+    in_flight: VecDeque<oneshot::Receiver<Result<()>>>,
+    pipeline: usize,
+    failed_flushes: Arc<AtomicUsize>,
+    owns_indexes: bool,
+    run_id: Option<i64>,
+    /// Keep `orderbook_latest` up to date with every batch.
+    latest: bool,
+    /// Lanes record `PublishLatency::stored` on commit.
+    records_latency: bool,
+}
+
+/// A batch for a lane and where to report how it went.
+type LaneJob = (CopyBatch, oneshot::Sender<Result<()>>);
+
+/// What a Postgres sink does about failed connects and COPYs.
+#[derive(Clone, Debug)]
+pub struct Recovery {
+    pub retry: RetryPolicy,
+    pub retries: Arc<RetryStats>,
+    /// Takes batches that still fail after the last retry; `None` fails the sink.
+    pub spill: Option<Arc<Spill>>,
+}
+
+/// How Postgres sinks retry a lost connection: up to `max_attempts` tries, the first
+/// included, with exponentially growing jittered delays, giving up early once the next
+/// try would start more than `deadline` after the first failure. Other errors fail at once.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub deadline: Duration,
+}
+
+impl RetryPolicy {
+    /// Reconnects and retries once, straight away.
+    pub fn once() -> Self {
+        Self {
+            max_attempts: 2,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            deadline: Duration::MAX,
+        }
+    }
+
+    /// Delay before the try after the `failures`th failure, or `None` to give up. The
+    /// delay doubles from `initial_backoff` up to `max_backoff`, then is cut by a random
+    /// fraction in [0, 1/2) of itself, so writers that lost the same server do not
+    /// reconnect in step.
+    fn delay(&self, failures: u32, since_first_failure: Duration) -> Option<Duration> {
+        if failures >= self.max_attempts {
+            return None;
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << failures.saturating_sub(1).min(20))
+            .min(self.max_backoff);
+        let delay = backoff.mul_f64(1.0 - rand::random_range(0.0..0.5));
+        (since_first_failure.saturating_add(delay) <= self.deadline).then_some(delay)
+    }
+}
+
+/// Retry counters of every Postgres sink, served under `/metrics`.
+#[derive(Debug, Default)]
+pub struct RetryStats {
+    /// Tries after a failure, whether or not they succeeded.
+    retries: AtomicU64,
+    /// Connections reopened after one was lost.
+    reconnects: AtomicU64,
+    /// Connects and batches given up on after the last try.
+    exhausted: AtomicU64,
+}
+
+impl Recovery {
+    /// Sleeps before retrying after the `failures`th failure, or returns `e` when
+    /// [`StorageError`] deems it not retryable or the policy is used up.
+    async fn back_off(
+        &self,
+        e: anyhow::Error,
+        what: &str,
+        failures: u32,
+        first_failure: Instant,
+    ) -> Result<()> {
+        let kind = StorageError::classify(&e);
+        if !kind.is_retryable() {
+            return Err(e);
+        }
+        let Some(delay) = self.retry.delay(failures, first_failure.elapsed()) else {
+            self.retries.exhausted.fetch_add(1, Ordering::Relaxed);
+            error!(
+                what,
+                attempts = failures,
+                error = format!("{:#}", e),
+                "giving up"
+            );
+            return Err(e.context(format!("gave up on {} after {} attempts", what, failures)));
+        };
+        self.retries.retries.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            what,
+            ?kind,
+            attempt = failures + 1,
+            delay_ms = delay.as_millis() as u64,
+            "retrying"
+        );
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    /// Opens a connection, retrying while the server cannot be reached.
+    async fn connect(&self, db_url: &str) -> Result<tokio_postgres::Client> {
+        let first_failure = Instant::now();
+        let mut failures = 0;
+        loop {
+            match connect_async(db_url).await {
+                Ok(client) => return Ok(client),
+                Err(e) => {
+                    failures += 1;
+                    self.back_off(e, "connect", failures, first_failure).await?;
+                }
+            }
+        }
+    }
+}
+
+/// How a Postgres sink spreads its COPYs.
+#[derive(Clone, Copy, Debug)]
+pub struct CopyPipeline {
+    /// Connections copying in parallel, with instruments hashed across them.
+    pub connections: usize,
+    /// Batches handed to the connections before the writer waits for the oldest.
+    pub in_flight: usize,
+}
+
+impl PostgresSink {
+    pub fn connect(
+        db_url: Arc<String>,
+        timescale: Option<TimescaleConfig>,
+        run_id: Option<i64>,
+        latest: bool,
+        pipeline: CopyPipeline,
+        recovery: Recovery,
+        latency: Option<Arc<PublishLatency>>,
+    ) -> Result<Self> {
+        // Only prepares the table; batches use the sink's async connections
+        prepare_bulk_load(&db_url, timescale)?;
+        Self::open(db_url, run_id, latest, pipeline, recovery, latency, true)
+    }
+
+    /// Connects one shard of `bulk`, preparing the table first if no shard has yet.
+    fn connect_shard(
+        db_url: Arc<String>,
+        bulk: &BulkLoad,
+        run_id: Option<i64>,
+        latest: bool,
+        pipeline: CopyPipeline,
+        recovery: Recovery,
+        latency: Option<Arc<PublishLatency>>,
+    ) -> Result<Self> {
+        bulk.prepare()?;
+        Self::open(db_url, run_id, latest, pipeline, recovery, latency, false)
+    }
+
+    /// Starts the runtime and opens every lane's connection, so a bad URL fails here
+    /// rather than on the first batch. With `latency`, lanes record `stored` as their
+    /// snapshot batches commit.
+    fn open(
+        db_url: Arc<String>,
+        run_id: Option<i64>,
+        latest: bool,
+        pipeline: CopyPipeline,
+        recovery: Recovery,
+        latency: Option<Arc<PublishLatency>>,
+        owns_indexes: bool,
+    ) -> Result<Self> {
+        let connections = pipeline.connections.max(1);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(connections)
+            .thread_name("postgres-copy")
+            .enable_all()
+            .build()
+            .context("failed to start the postgres writer runtime")?;
+        let failed_flushes = Arc::new(AtomicUsize::new(0));
+        let recovery = Arc::new(recovery);
+        let mut lanes = Vec::with_capacity(connections);
+        for _ in 0..connections {
+            let client = runtime.block_on(recovery.connect(&db_url))?;
+            let (tx, rx) = mpsc::unbounded_channel();
+            runtime.spawn(run_lane(
+                db_url.clone(),
+                client,
+                failed_flushes.clone(),
+                recovery.clone(),
+                latency.clone(),
+                rx,
+            ));
+            lanes.push(tx);
+        }
+        info!(connections, "postgres connections opened");
+        Ok(Self {
+            db_url,
+            runtime,
+            lanes,
+            in_flight: VecDeque::with_capacity(pipeline.in_flight),
+            pipeline: pipeline.in_flight.max(1),
+            records_latency: latency.is_some(),
+            failed_flushes,
+            owns_indexes,
+            run_id,
+            latest,
+        })
+    }
+
+    /// Hands each lane its share of `batch` once fewer than `pipeline` batches are in
+    /// flight.
+    fn submit(&mut self, batch: CopyBatch) -> Result<()> {
+        self.reap_finished()?;
+        for (lane, part) in batch.split(self.lanes.len()) {
+            while self.in_flight.len() >= self.pipeline {
+                self.wait_oldest()?;
+            }
+            let (done, finished) = oneshot::channel();
+            self.lanes[lane]
+                .send((part, done))
+                .map_err(|_| anyhow!("postgres copy lane {} stopped", lane))?;
+            self.in_flight.push_back(finished);
+        }
+        Ok(())
+    }
+
+    /// Surfaces failures of finished batches before more are queued behind them.
+    fn reap_finished(&mut self) -> Result<()> {
+        while let Some(finished) = self.in_flight.front_mut() {
+            match finished.try_recv() {
+                Err(oneshot::error::TryRecvError::Empty) => break,
+                result => {
+                    self.in_flight.pop_front();
+                    result.map_err(|_| anyhow!("postgres copy lane stopped"))??;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_oldest(&mut self) -> Result<()> {
+        let Some(finished) = self.in_flight.pop_front() else {
+            return Ok(());
+        };
+        self.runtime
+            .block_on(finished)
+            .map_err(|_| anyhow!("postgres copy lane stopped"))?
+    }
+
+    /// Waits for every batch in flight, returning the first failure.
+    fn wait_all(&mut self) -> Result<()> {
+        while !self.in_flight.is_empty() {
+            self.wait_oldest()?;
+        }
+        Ok(())
+    }
+}
+
+/// Rows of one COPY, owned so the copy can run on the sink's runtime.
+enum CopyBatch {
+    Snapshots {
+        snapshots: Vec<SharedSnapshot>,
+        run_id: Option<i64>,
+        /// Also bring `orderbook_latest` up to date.
+        latest: bool,
+    },
+    Trades(Vec<SharedTrade>),
+    Bars(Vec<SharedBar>),
+    Stats(Vec<SharedStats>),
+    Imbalances(Vec<SharedImbalance>),
+    Statistics(Vec<SharedStatistic>),
+}
+
+impl CopyBatch {
+    fn len(&self) -> usize {
+        match self {
+            CopyBatch::Snapshots { snapshots, .. } => snapshots.len(),
+            CopyBatch::Trades(trades) => trades.len(),
+            CopyBatch::Bars(bars) => bars.len(),
+            CopyBatch::Stats(stats) => stats.len(),
+            CopyBatch::Imbalances(imbalances) => imbalances.len(),
+            CopyBatch::Statistics(statistics) => statistics.len(),
+        }
+    }
+
+    /// Table the rows go to, as named in spill files.
+    fn table(&self) -> &'static str {
+        match self {
+            CopyBatch::Snapshots { .. } => "orderbook_snapshots",
+            CopyBatch::Trades(_) => "trades",
+            CopyBatch::Bars(_) => "bars",
+            CopyBatch::Stats(_) => "book_stats",
+            CopyBatch::Imbalances(_) => "imbalances",
+            CopyBatch::Statistics(_) => "statistics",
+        }
+    }
+
+    fn spill_rows(&self) -> Vec<SpillRow> {
+        match self {
+            CopyBatch::Snapshots {
+                snapshots,
+                run_id,
+                latest,
+            } => snapshots
+                .iter()
+                .map(|snapshot| {
+                    SpillRow::OrderbookSnapshots(SpilledSnapshot::new(snapshot, *run_id, *latest))
+                })
+                .collect(),
+            CopyBatch::Trades(trades) => trades
+                .iter()
+                .map(|trade| SpillRow::Trades(TradeRecord::clone(trade)))
+                .collect(),
+            CopyBatch::Bars(bars) => bars
+                .iter()
+                .map(|bar| SpillRow::Bars(Bar::clone(bar)))
+                .collect(),
+            CopyBatch::Stats(stats) => stats
+                .iter()
+                .map(|row| SpillRow::BookStats(BookStats::clone(row)))
+                .collect(),
+            CopyBatch::Imbalances(imbalances) => imbalances
+                .iter()
+                .map(|row| SpillRow::Imbalances(ImbalanceRecord::clone(row)))
+                .collect(),
+            CopyBatch::Statistics(statistics) => statistics
+                .iter()
+                .map(|row| SpillRow::Statistics(StatisticRecord::clone(row)))
+                .collect(),
+        }
+    }
+
+    /// Splits the rows into one batch per lane by `instrument_id`, keeping their order.
+    /// Lanes without rows get no batch.
+    fn split(self, lanes: usize) -> Vec<(usize, CopyBatch)> {
+        if lanes == 1 {
+            return vec![(0, self)];
+        }
+        match self {
+            CopyBatch::Snapshots {
+                snapshots,
+                run_id,
+                latest,
+            } => split_rows(snapshots, lanes, |s| s.instrument_id)
+                .map(|(lane, snapshots)| {
+                    let batch = CopyBatch::Snapshots {
+                        snapshots,
+                        run_id,
+                        latest,
+                    };
+                    (lane, batch)
+                })
+                .collect(),
+            CopyBatch::Trades(trades) => split_rows(trades, lanes, |t| t.instrument_id)
+                .map(|(lane, trades)| (lane, CopyBatch::Trades(trades)))
+                .collect(),
+            CopyBatch::Bars(bars) => split_rows(bars, lanes, |b| b.instrument_id)
+                .map(|(lane, bars)| (lane, CopyBatch::Bars(bars)))
+                .collect(),
+            CopyBatch::Stats(stats) => split_rows(stats, lanes, |s| s.instrument_id)
+                .map(|(lane, stats)| (lane, CopyBatch::Stats(stats)))
+                .collect(),
+            CopyBatch::Imbalances(imbalances) => split_rows(imbalances, lanes, |i| i.instrument_id)
+                .map(|(lane, imbalances)| (lane, CopyBatch::Imbalances(imbalances)))
+                .collect(),
+            CopyBatch::Statistics(statistics) => split_rows(statistics, lanes, |s| s.instrument_id)
+                .map(|(lane, statistics)| (lane, CopyBatch::Statistics(statistics)))
+                .collect(),
+        }
+    }
+
+    async fn copy(&self, client: &mut tokio_postgres::Client) -> Result<()> {
+        match self {
+            CopyBatch::Snapshots {
+                snapshots,
+                run_id,
+                latest,
+            } => flush_copy(client, snapshots, *run_id, *latest).await,
+            CopyBatch::Trades(trades) => flush_trades(client, trades).await,
+            CopyBatch::Bars(bars) => flush_bars(client, bars).await,
+            CopyBatch::Stats(stats) => flush_stats(client, stats).await,
+            CopyBatch::Imbalances(imbalances) => flush_imbalances(client, imbalances).await,
+            CopyBatch::Statistics(statistics) => flush_statistics(client, statistics).await,
+        }
+    }
+}
+
+fn split_rows<T>(
+    rows: Vec<Arc<T>>,
+    lanes: usize,
+    instrument_id: impl Fn(&T) -> u32,
+) -> impl Iterator<Item = (usize, Vec<Arc<T>>)> {
+    let mut parts = vec![Vec::new(); lanes];
+    for row in rows {
+        parts[instrument_id(&row) as usize % lanes].push(row);
+    }
+    parts
+        .into_iter()
+        .enumerate()
+        .filter(|(_, part)| !part.is_empty())
+}
+
+/// Opens an async connection, driving it on the current runtime until it closes.
+async fn connect_async(db_url: &str) -> Result<tokio_postgres::Client> {
+    let (client, connection) = tokio_postgres::connect(db_url, NoTls).await.map_err(|e| {
+        error!(error = %e, "failed to connect to postgres");
+        anyhow!(e).context(format!("failed to connect to postgres using {}", db_url))
+    })?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            warn!(error = %e, "postgres connection closed");
+        }
+    });
+    Ok(client)
+}
+
+/// Copies one lane's batches in the order they arrive until the sink is dropped. With
+/// a spill dir a batch that still fails after the last retry is written there and
+/// counts as done. Snapshots of a committed batch count as stored in `latency`.
+async fn run_lane(
+    db_url: Arc<String>,
+    mut client: tokio_postgres::Client,
+    failed_flushes: Arc<AtomicUsize>,
+    recovery: Arc<Recovery>,
+    latency: Option<Arc<PublishLatency>>,
+    mut jobs: mpsc::UnboundedReceiver<LaneJob>,
+) {
+    while let Some((batch, done)) = jobs.recv().await {
+        let mut result =
+            copy_with_retry(&db_url, &mut client, &failed_flushes, &recovery, &batch).await;
+        if let (Ok(()), Some(latency), CopyBatch::Snapshots { snapshots, .. }) =
+            (&result, &latency, &batch)
+        {
+            record_stored(latency, snapshots);
+        }
+        if let (Err(e), Some(spill)) = (&result, &recovery.spill) {
+            result = match spill.write(&batch) {
+                Ok(path) => {
+                    error!(
+                        path = %path.display(),
+                        rows = batch.len(),
+                        error = format!("{:#}", e),
+                        "batch spilled"
+                    );
+                    Ok(())
+                }
+                Err(spill_error) => Err(anyhow!(
+                    "{:#}; spilling the batch failed too: {:#}",
+                    e,
+                    spill_error
+                )),
+            };
+        }
+        // The sink stops waiting for batches once one has failed
+        let _ = done.send(result);
+    }
+}
+
+/// Runs one COPY, reconnecting and retrying as `recovery` allows while the connection
+/// is lost.
+async fn copy_with_retry(
+    db_url: &str,
+    client: &mut tokio_postgres::Client,
+    failed_flushes: &AtomicUsize,
+    recovery: &Recovery,
+    batch: &CopyBatch,
+) -> Result<()> {
+    let first_failure = Instant::now();
+    let mut failures = 0;
+    loop {
+        let result = async {
+            // Closed by the server while the lane was idle, or lost by the last try
+            if failures > 0 || client.is_closed() {
+                *client = connect_async(db_url).await?;
+                recovery.retries.reconnects.fetch_add(1, Ordering::Relaxed);
+                info!("reconnected");
+            }
+            batch.copy(client).await
+        }
+        .await;
+        let Err(e) = result else {
+            if failures > 0 {
+                info!(
+                    size = batch.len(),
+                    attempts = failures + 1,
+                    "retry flush succeeded"
+                );
+            }
+            return Ok(());
+        };
+        let attempt = failed_flushes.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            attempt,
+            error = %e,
+            buffer_size = batch.len(),
+            "flush failed"
+        );
+        failures += 1;
+        recovery
+            .back_off(e, "flush", failures, first_failure)
+            .await?;
+    }
+}
+
+/// Where Postgres sinks write the batches they could not copy (`SPILL_DIR`), one NDJSON
+/// file per batch, for `replay-spill` to load later. File names start with the wall
+/// clock, so listing them in name order replays each instrument's rows in order.
+#[derive(Debug)]
+pub struct Spill {
+    dir: PathBuf,
+    /// Tells apart files spilled within the same nanosecond.
+    next: AtomicU64,
+    batches: AtomicU64,
+    rows: AtomicU64,
+}
+
+impl Spill {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            next: AtomicU64::new(0),
+            batches: AtomicU64::new(0),
+            rows: AtomicU64::new(0),
+        }
+    }
+
+    /// Writes `batch` under a temporary name and renames it into place, so
+    /// `replay-spill` never reads a partial file.
+    fn write(&self, batch: &CopyBatch) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create spill dir {}", self.dir.display()))?;
+        let name = format!(
+            "{:020}-{:06}-{}.jsonl",
+            wall_clock_ns(),
+            self.next.fetch_add(1, Ordering::Relaxed),
+            batch.table()
+        );
+        let path = self.dir.join(name);
+        let partial = path.with_extension("jsonl.partial");
+        let file = File::create(&partial)
+            .with_context(|| format!("failed to create spill file {}", partial.display()))?;
+        let mut writer = BufWriter::new(file);
+        for row in batch.spill_rows() {
+            serde_json::to_writer(&mut writer, &row).context("failed to serialize spilled row")?;
+            writer.write_all(b"\n")?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("failed to write spill file {}", partial.display()))?;
+        fs::rename(&partial, &path)
+            .with_context(|| format!("failed to move spill file to {}", path.display()))?;
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.rows.fetch_add(batch.len() as u64, Ordering::Relaxed);
+        Ok(path)
+    }
+}
+
+/// One line of a spill file: a row of the table named by `table`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "table", rename_all = "snake_case")]
+enum SpillRow {
+    OrderbookSnapshots(SpilledSnapshot),
+    Trades(TradeRecord),
+    Bars(Bar),
+    BookStats(BookStats),
+    Imbalances(ImbalanceRecord),
+    Statistics(StatisticRecord),
+}
+
+/// What `orderbook_snapshots` stores of a snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+struct SpilledSnapshot {
+    symbol: String,
+    instrument_id: u32,
+    ts_event: i64,
+    ts_recv: i64,
+    seq: u64,
+    run_id: Option<i64>,
+    #[serde(default)]
+    publisher_id: u16,
+    /// Whether the sink also kept `orderbook_latest` up to date.
+    #[serde(default)]
+    latest: bool,
+    best_bid: Option<LevelEntry>,
+    best_ask: Option<LevelEntry>,
+    bids: Vec<LevelEntry>,
+    asks: Vec<LevelEntry>,
+    total_orders: usize,
+    bid_levels: usize,
+    ask_levels: usize,
+}
+
+impl SpilledSnapshot {
+    fn new(snapshot: &SnapshotRecord, run_id: Option<i64>, latest: bool) -> Self {
+        let payload = &snapshot.payload;
+        Self {
+            symbol: payload.symbol.clone(),
+            instrument_id: snapshot.instrument_id,
+            ts_event: snapshot.ts_event,
+            ts_recv: snapshot.ts_recv,
+            seq: payload.seq,
+            run_id,
+            publisher_id: snapshot.publisher_id,
+            latest,
+            best_bid: payload.bbo.best_bid.clone(),
+            best_ask: payload.bbo.best_ask.clone(),
+            bids: payload.bids.clone(),
+            asks: payload.asks.clone(),
+            total_orders: payload.total_orders,
+            bid_levels: payload.bid_levels,
+            ask_levels: payload.ask_levels,
+        }
+    }
+
+    fn into_record(self) -> SnapshotRecord {
+        SnapshotRecord {
+            instrument_id: self.instrument_id,
+            publisher_id: self.publisher_id,
+            ts_event: self.ts_event,
+            ts_recv: self.ts_recv,
+            built_at: 0,
+            payload: Snapshot {
+                symbol: self.symbol,
+                ts_ns: self.ts_event,
+                seq: self.seq,
+                bids: self.bids,
+                asks: self.asks,
+                bbo: Bbo {
+                    best_bid: self.best_bid,
+                    best_ask: self.best_ask,
+                },
+                total_orders: self.total_orders,
+                bid_levels: self.bid_levels,
+                ask_levels: self.ask_levels,
+                notional: None,
+                signals: None,
+                liquidity: None,
+                buckets: None,
+                implied: None,
+                stale: false,
+            },
+            cache: EncodedCache::default(),
+        }
+    }
+}
+
+/// What `replay_spill` loaded.
+#[derive(Debug, Default)]
+pub struct SpillReplay {
+    pub files: usize,
+    pub rows: usize,
+}
+
+/// Copies the spill files in `dir` into Postgres in name order, deleting each once its
+/// rows are committed. The first file that fails stops the replay and is kept with
+/// every later one, so the replay can be run again once the cause is fixed.
+pub fn replay_spill(db_url: &str, dir: &Path) -> Result<SpillReplay> {
+    let mut files = fs::read_dir(dir)
+        .with_context(|| format!("failed to read spill dir {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("failed to read spill dir {}", dir.display()))?;
+    files.retain(|path| path.extension().is_some_and(|ext| ext == "jsonl"));
+    files.sort();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed to start the replay runtime")?;
+    runtime.block_on(async {
+        let mut client = connect_async(db_url).await?;
+        let mut replay = SpillReplay::default();
+        for path in files {
+            let batches = read_spill_file(&path)?;
+            for batch in &batches {
+                batch
+                    .copy(&mut client)
+                    .await
+                    .with_context(|| format!("failed to replay {}", path.display()))?;
+            }
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove replayed {}", path.display()))?;
+            let rows = batches.iter().map(CopyBatch::len).sum::<usize>();
+            info!(file = %path.display(), rows, "spill file replayed");
+            replay.files += 1;
+            replay.rows += rows;
+        }
+        Ok(replay)
+    })
+}
+
+/// Reads a spill file back into batches, one per table present.
+fn read_spill_file(path: &Path) -> Result<Vec<CopyBatch>> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("failed to read spill file {}", path.display()))?;
+    let mut snapshots = Vec::new();
+    let (mut run_id, mut latest) = (None, false);
+    let (mut trades, mut bars, mut stats) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut imbalances, mut statistics) = (Vec::new(), Vec::new());
+    for (idx, line) in raw.lines().enumerate().filter(|(_, line)| !line.is_empty()) {
+        let row: SpillRow = serde_json::from_str(line)
+            .with_context(|| format!("{} line {} is not a spilled row", path.display(), idx + 1))?;
+        match row {
+            SpillRow::OrderbookSnapshots(snapshot) => {
+                // A spilled batch belongs to a single run
+                (run_id, latest) = (snapshot.run_id, snapshot.latest);
+                snapshots.push(Arc::new(snapshot.into_record()));
+            }
+            SpillRow::Trades(trade) => trades.push(Arc::new(trade)),
+            SpillRow::Bars(bar) => bars.push(Arc::new(bar)),
+            SpillRow::BookStats(row) => stats.push(Arc::new(row)),
+            SpillRow::Imbalances(row) => imbalances.push(Arc::new(row)),
+            SpillRow::Statistics(row) => statistics.push(Arc::new(row)),
+        }
+    }
+    let batches = [
+        CopyBatch::Snapshots {
+            snapshots,
+            run_id,
+            latest,
+        },
+        CopyBatch::Trades(trades),
+        CopyBatch::Bars(bars),
+        CopyBatch::Stats(stats),
+        CopyBatch::Imbalances(imbalances),
+        CopyBatch::Statistics(statistics),
+    ];
+    Ok(batches
+        .into_iter()
+        .filter(|batch| batch.len() > 0)
+        .collect())
+}
+
+/// Write-ahead journal of the snapshots handed to the storage writers (`JOURNAL_DIR`).
+/// Each snapshot is appended as a spill-file line before it is queued, so a crash
+/// between the queue and a committed COPY leaves it on disk for `replay_journal`.
+/// The journal rolls over to a new segment every `segment_rows` lines; a closed segment
+/// is deleted once every writer shard has flushed past its last line.
+///
+/// Lines reach the OS before the snapshot is queued, which survives a crash of the
+/// process; segments are synced to disk when they close.
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    segment_rows: u64,
+    run_id: Option<i64>,
+    latest: bool,
+    shards: Vec<JournalShard>,
+    segments: Mutex<JournalSegments>,
+}
+
+/// Progress of one writer shard through the journal, counted in snapshots.
+#[derive(Debug, Default)]
+struct JournalShard {
+    /// Appended for this shard by ingest.
+    sent: AtomicU64,
+    /// Taken off the queue by the writer.
+    received: AtomicU64,
+    /// Received and then flushed by the writer, so stored or spilled.
+    durable: AtomicU64,
+    /// A writer died holding unflushed snapshots, so `durable` stops advancing and the
+    /// segments it left behind wait for the next startup's replay.
+    lost: AtomicBool,
+}
+
+#[derive(Debug)]
+struct JournalSegments {
+    next: u64,
+    /// Opened by the first append after the previous segment closed.
+    open: Option<OpenSegment>,
+    /// Closed segments in order, with each shard's `sent` at their last line.
+    closed: VecDeque<(PathBuf, Vec<u64>)>,
+}
+
+#[derive(Debug)]
+struct OpenSegment {
+    path: PathBuf,
+    file: File,
+    rows: u64,
+}
+
+impl Journal {
+    /// Starts the journal of a run stamped with `run_id`, for `writers` shards.
+    pub fn create(
+        dir: PathBuf,
+        segment_rows: u64,
+        writers: usize,
+        run_id: Option<i64>,
+        latest: bool,
+    ) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create journal dir {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            segment_rows: segment_rows.max(1),
+            run_id,
+            latest,
+            shards: (0..writers.max(1))
+                .map(|_| JournalShard::default())
+                .collect(),
+            segments: Mutex::new(JournalSegments {
+                next: 0,
+                open: None,
+                closed: VecDeque::new(),
+            }),
+        })
+    }
+
+    /// Appends `snapshot`, bound for writer `shard`, to the open segment.
+    fn append(&self, shard: usize, snapshot: &SnapshotRecord) -> Result<()> {
+        let row =
+            SpillRow::OrderbookSnapshots(SpilledSnapshot::new(snapshot, self.run_id, self.latest));
+        let mut line = serde_json::to_vec(&row).context("failed to serialize journal row")?;
+        line.push(b'\n');
+        let mut segments = self.segments.lock().expect("journal lock poisoned");
+        let open = match &mut segments.open {
+            Some(open) => open,
+            None => {
+                let open = OpenSegment::create(&self.dir, segments.next)?;
+                segments.next += 1;
+                segments.open.insert(open)
+            }
+        };
+        open.file
+            .write_all(&line)
+            .with_context(|| format!("failed to append to {}", open.path.display()))?;
+        open.rows += 1;
+        self.shards[shard].sent.fetch_add(1, Ordering::Relaxed);
+        if open.rows >= self.segment_rows {
+            self.close_segment(&mut segments)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs and closes the open segment, if any.
+    fn close_segment(&self, segments: &mut JournalSegments) -> Result<()> {
+        let Some(closed) = segments.open.take() else {
+            return Ok(());
+        };
+        closed
+            .file
+            .sync_all()
+            .with_context(|| format!("failed to sync {}", closed.path.display()))?;
+        let sent: Vec<u64> = self
+            .shards
+            .iter()
+            .map(|shard| shard.sent.load(Ordering::Relaxed))
+            .collect();
+        segments.closed.push_back((closed.path, sent));
+        Ok(())
+    }
+
+    /// Called as a writer (re)starts. Anything the previous writer received but never
+    /// flushed went down with it.
+    fn writer_started(&self, shard: usize) {
+        let shard = &self.shards[shard];
+        if shard.received.load(Ordering::Relaxed) != shard.durable.load(Ordering::Relaxed) {
+            shard.lost.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn received(&self, shard: usize) {
+        self.shards[shard].received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the writer has received all of its part of the oldest closed segment it
+    /// has not yet flushed.
+    fn wants_flush(&self, shard: usize) -> bool {
+        let state = &self.shards[shard];
+        if state.lost.load(Ordering::Relaxed) {
+            return false;
+        }
+        let segments = self.segments.lock().expect("journal lock poisoned");
+        let durable = state.durable.load(Ordering::Relaxed);
+        segments
+            .closed
+            .iter()
+            .map(|(_, sent)| sent[shard])
+            .find(|sent| *sent > durable)
+            .is_some_and(|sent| state.received.load(Ordering::Relaxed) >= sent)
+    }
+
+    /// Records that the writer's sinks hold everything it has received, and deletes the
+    /// closed segments no shard still needs.
+    fn flushed(&self, shard: usize) -> Result<()> {
+        let shard = &self.shards[shard];
+        if !shard.lost.load(Ordering::Relaxed) {
+            shard
+                .durable
+                .store(shard.received.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        let mut segments = self.segments.lock().expect("journal lock poisoned");
+        self.release(&mut segments)
+    }
+
+    fn release(&self, segments: &mut JournalSegments) -> Result<()> {
+        while let Some((path, sent)) = segments.closed.front() {
+            let stored = self
+                .shards
+                .iter()
+                .zip(sent)
+                .all(|(shard, sent)| shard.durable.load(Ordering::Relaxed) >= *sent);
+            if !stored {
+                break;
+            }
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove journal {}", path.display()))?;
+            segments.closed.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Closes the open segment once the writers have finished and deletes what they
+    /// stored. Returns the segments left for the next startup to replay.
+    pub fn finish(&self) -> Result<usize> {
+        let mut segments = self.segments.lock().expect("journal lock poisoned");
+        self.close_segment(&mut segments)?;
+        self.release(&mut segments)?;
+        Ok(segments.closed.len())
+    }
+}
+
+impl OpenSegment {
+    /// Segment names start with the wall clock, so listing them in name order replays
+    /// each instrument's rows in order across runs.
+    fn create(dir: &Path, n: u64) -> Result<Self> {
+        let path = dir.join(format!("{:020}-{:06}.jsonl", wall_clock_ns(), n));
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to create journal {}", path.display()))?;
+        Ok(Self {
+            path,
+            file,
+            rows: 0,
+        })
+    }
+}
+
+/// What `replay_journal` found in the journal of earlier runs.
+#[derive(Debug, Default)]
+pub struct JournalReplay {
+    pub files: usize,
+    /// Rows copied because Postgres was missing them.
+    pub rows: usize,
+    /// Rows skipped because Postgres already held them.
+    pub duplicates: usize,
+}
+
+/// Copies the journal segments in `dir` left by earlier runs into Postgres in name
+/// order, skipping snapshots already stored under the same `(instrument_id, ts_event,
+/// seq)`, and deletes each segment once its rows are committed. A segment cut short by
+/// a crash loses only its torn last line. Replaying again after a failure is safe.
+pub fn replay_journal(
+    db_url: &str,
+    dir: &Path,
+    timescale: Option<TimescaleConfig>,
+) -> Result<JournalReplay> {
+    let mut files = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(|| format!("failed to read journal dir {}", dir.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read journal dir {}", dir.display()));
+        }
+    };
+    files.retain(|path| path.extension().is_some_and(|ext| ext == "jsonl"));
+    files.sort();
+    let mut replay = JournalReplay::default();
+    if files.is_empty() {
+        return Ok(replay);
+    }
+    init_database(db_url, timescale.as_ref())?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed to start the replay runtime")?;
+    runtime.block_on(async {
+        let mut client = connect_async(db_url).await?;
+        for path in files {
+            let (snapshots, run_id, latest) = read_journal_segment(&path)?;
+            let stored = stored_snapshot_keys(&client, &snapshots)
+                .await
+                .with_context(|| format!("failed to look up rows of {}", path.display()))?;
+            let total = snapshots.len();
+            let snapshots: Vec<SharedSnapshot> = snapshots
+                .into_iter()
+                .filter(|snapshot| !stored.contains(&snapshot_key(snapshot)))
+                .collect();
+            let rows = snapshots.len();
+            if rows > 0 {
+                CopyBatch::Snapshots {
+                    snapshots,
+                    run_id,
+                    latest,
+                }
+                .copy(&mut client)
+                .await
+                .with_context(|| format!("failed to replay {}", path.display()))?;
+            }
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove replayed {}", path.display()))?;
+            info!(file = %path.display(), rows, duplicates = total - rows, "journal segment replayed");
+            replay.files += 1;
+            replay.rows += rows;
+            replay.duplicates += total - rows;
+        }
+        Ok(replay)
+    })
+}
+
+/// Reads a journal segment back, with the run and `orderbook_latest` setting it was
+/// written under.
+fn read_journal_segment(path: &Path) -> Result<(Vec<SharedSnapshot>, Option<i64>, bool)> {
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("failed to read journal {}", path.display()))?;
+    let mut snapshots = Vec::new();
+    let (mut run_id, mut latest) = (None, false);
+    let mut lines = raw.split('\n').enumerate().peekable();
+    while let Some((idx, line)) = lines.next() {
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<SpillRow>(line) {
+            Ok(SpillRow::OrderbookSnapshots(snapshot)) => {
+                (run_id, latest) = (snapshot.run_id, snapshot.latest);
+                snapshots.push(Arc::new(snapshot.into_record()));
+            }
+            Ok(_) => {
+                return Err(anyhow!(
+                    "{} line {} is not a snapshot row",
+                    path.display(),
+                    idx + 1
+                ));
+            }
+            // Only the line being appended when the process died can be torn
+            Err(_) if lines.peek().is_none() => {
+                warn!(file = %path.display(), line = idx + 1, "skipping torn journal line");
+            }
+            Err(e) => Err(e).with_context(|| {
+                format!("{} line {} is not a journal row", path.display(), idx + 1)
+            })?,
+        }
+    }
+    Ok((snapshots, run_id, latest))
+}
+
+/// How the journal replay tells a stored snapshot apart.
+fn snapshot_key(snapshot: &SnapshotRecord) -> (i64, i64, i64) {
+    (
+        snapshot.instrument_id as i64,
+        snapshot.ts_event,
+        snapshot.payload.seq as i64,
+    )
+}
+
+/// Keys of the stored snapshots among the instruments and `ts_event` range of `snapshots`.
+async fn stored_snapshot_keys(
+    client: &tokio_postgres::Client,
+    snapshots: &[SharedSnapshot],
+) -> Result<HashSet<(i64, i64, i64)>> {
+    let Some(from) = snapshots.iter().map(|s| s.ts_event).min() else {
+        return Ok(HashSet::new());
+    };
+    let to = snapshots.iter().map(|s| s.ts_event).max().unwrap_or(from);
+    let mut instruments: Vec<i64> = snapshots.iter().map(|s| s.instrument_id as i64).collect();
+    instruments.sort_unstable();
+    instruments.dedup();
+    let rows = client
+        .query(
+            "SELECT instrument_id, ts_event, seq FROM orderbook_snapshots \
+             WHERE instrument_id = ANY($1) AND ts_event BETWEEN $2 AND $3",
+            &[&instruments, &from, &to],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect())
+}
+
+/// Creates the database and schema if missing and drops the indexes for bulk loading.
+fn prepare_bulk_load(db_url: &str, timescale: Option<TimescaleConfig>) -> Result<()> {
+    info!(db_url, "preparing bulk load");
+
+    // Ensure database exists
+    if let Err(e) = ensure_database(db_url) {
+        error!(error = %e, "failed to ensure database");
+        return Err(e);
+    }
+    info!("database ensured");
+
+    // Connect to database
+    let mut client = match Client::connect(db_url, NoTls) {
+        Ok(c) => {
+            info!("connected to postgres");
+            c
+        }
+        Err(e) => {
+            error!(error = %e, "failed to connect to postgres");
+            return Err(
+                anyhow!(e).context(format!("failed to connect to postgres using {}", &db_url))
+            );
+        }
+    };
+
+    // Ensure schema
+    if let Err(e) = ensure_schema(&mut client, timescale.as_ref()) {
+        error!(error = %e, "failed to ensure schema");
+        return Err(e);
+    }
+
+    // Drop indexes for bulk load
+    info!("dropping indexes for bulk load");
+    if let Err(e) = drop_indexes(&mut client) {
+        error!(error = %e, "failed to drop indexes");
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+/// Reconnects and rebuilds the indexes dropped by `prepare_bulk_load`.
+fn finish_bulk_load(db_url: &str) -> Result<()> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    recreate_indexes(&mut client).inspect_err(|e| {
+        error!(error = %e, "failed to recreate indexes");
+    })
+}
+
+/// Postgres state shared by the shards of a sharded writer. The first shard to open
+/// prepares the table and drops its indexes; `spawn_writers` rebuilds them after the
+/// last shard finishes, so no shard loads into a table that is being re-indexed.
+struct BulkLoad {
+    db_url: Arc<String>,
+    timescale: Option<TimescaleConfig>,
+    prepared: Mutex<bool>,
+}
+
+impl BulkLoad {
+    fn prepare(&self) -> Result<()> {
+        let mut prepared = self.prepared.lock().expect("bulk load lock poisoned");
+        if !*prepared {
+            prepare_bulk_load(&self.db_url, self.timescale)?;
+            *prepared = true;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<()> {
+        if !*self.prepared.lock().expect("bulk load lock poisoned") {
+            return Ok(());
+        }
+        finish_bulk_load(&self.db_url)
+    }
+}
+
+impl SnapshotSink for PostgresSink {
+    fn name(&self) -> String {
+        String::from("postgres")
+    }
+
+    fn records_stored_latency(&self) -> bool {
+        self.records_latency
+    }
+
+    fn write_batch(&mut self, batch: &[SharedSnapshot]) -> Result<()> {
+        self.submit(CopyBatch::Snapshots {
+            snapshots: batch.to_vec(),
+            run_id: self.run_id,
+            latest: self.latest,
+        })
+    }
+
+    fn write_trades(&mut self, trades: &[SharedTrade]) -> Result<()> {
+        self.submit(CopyBatch::Trades(trades.to_vec()))
+    }
+
+    fn write_bars(&mut self, bars: &[SharedBar]) -> Result<()> {
+        self.submit(CopyBatch::Bars(bars.to_vec()))
+    }
+
+    fn write_stats(&mut self, stats: &[SharedStats]) -> Result<()> {
+        self.submit(CopyBatch::Stats(stats.to_vec()))
+    }
+
+    fn write_imbalances(&mut self, imbalances: &[SharedImbalance]) -> Result<()> {
+        self.submit(CopyBatch::Imbalances(imbalances.to_vec()))
+    }
+
+    fn write_statistics(&mut self, statistics: &[SharedStatistic]) -> Result<()> {
+        self.submit(CopyBatch::Statistics(statistics.to_vec()))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.wait_all()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.wait_all()?;
+        if !self.owns_indexes {
+            return Ok(());
+        }
+        info!(
+            failed_flushes = self.failed_flushes.load(Ordering::Relaxed),
+            "recreating indexes"
+        );
+        finish_bulk_load(&self.db_url)
+    }
+}
+
+/// Appends each snapshot as one JSON line, in the same shape served by `/snapshot`, or
+/// as one framed message of another `SnapshotEncoding`. With a codec each run appends
+/// its own compressed frame, ended on `close`.
+pub struct JsonFileSink {
+    path: String,
+    writer: Encoder<BufWriter<File>>,
+    encoder: &'static dyn SnapshotEncoder,
+    buf: Vec<u8>,
+}
+
+impl JsonFileSink {
+    pub fn create(path: &str, codec: Codec, encoding: SnapshotEncoding) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open snapshot file {}", path))?;
+        Ok(Self {
+            path: path.to_owned(),
+            writer: codec
+                .encoder(BufWriter::new(file))
+                .with_context(|| format!("failed to start {} encoder for {}", codec, path))?,
+            encoder: encoding.encoder(),
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl SnapshotSink for JsonFileSink {
+    fn name(&self) -> String {
+        format!("file:{}", self.path)
+    }
+
+    fn write_batch(&mut self, batch: &[SharedSnapshot]) -> Result<()> {
+        for snapshot in batch {
+            self.buf.clear();
+            self.encoder.encode_framed(snapshot, &mut self.buf)?;
+            self.writer
+                .write_all(&self.buf)
+                .with_context(|| format!("failed to write snapshot to {}", self.path))?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .with_context(|| format!("failed to flush {}", self.path))
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.writer
+            .try_finish()
+            .with_context(|| format!("failed to finish {}", self.path))
+    }
+}
+
+/// What `redis:` sinks write, and where.
+#[derive(Clone, Debug)]
+pub struct RedisOptions {
+    /// Each symbol's latest snapshot is SET under this prefix followed by the symbol.
+    pub key_prefix: String,
+    /// Channel each batch's latest snapshots are PUBLISHed on.
+    pub channel: String,
+    pub encoding: SnapshotEncoding,
+}
+
+impl Default for RedisOptions {
+    fn default() -> Self {
+        Self {
+            key_prefix: "batonics:snapshot:".to_owned(),
+            channel: "batonics:snapshots".to_owned(),
+            encoding: SnapshotEncoding::Json,
+        }
+    }
+}
+
+/// Keeps the newest snapshot of each symbol in Redis for consumers that poll it, and
+/// PUBLISHes it on a channel for those that subscribe. Only the last snapshot of a
+/// symbol in each batch is sent; values are the JSON served by `/snapshot` unless
+/// `encoding` says otherwise. Symbols
+/// always map to the same writer shard, so a key never goes back in time.
+pub struct RedisSink {
+    url: String,
+    options: RedisOptions,
+    connection: redis::Connection,
+}
+
+impl RedisSink {
+    pub fn connect(url: &str, options: RedisOptions) -> Result<Self> {
+        let connection = redis::Client::open(url)
+            .and_then(|client| client.get_connection())
+            .with_context(|| format!("failed to connect to redis at {}", url))?;
+        Ok(Self {
+            url: url.to_owned(),
+            options,
+            connection,
+        })
+    }
+}
+
+impl SnapshotSink for RedisSink {
+    fn name(&self) -> String {
+        format!("redis:{}", self.url)
+    }
+
+    fn write_batch(&mut self, batch: &[SharedSnapshot]) -> Result<()> {
+        let mut latest: HashMap<&str, &SnapshotRecord> = HashMap::new();
+        for snapshot in batch {
+            latest.insert(&snapshot.payload.symbol, snapshot);
+        }
+        if latest.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        for (symbol, snapshot) in latest {
+            // Shares the encode with API readers of the same snapshot
+            let value = snapshot.encoded(self.options.encoding)?;
+            pipe.set(format!("{}{}", self.options.key_prefix, symbol), &value[..])
+                .ignore()
+                .publish(&self.options.channel, &value[..])
+                .ignore();
+        }
+        pipe.query::<()>(&mut self.connection)
+            .with_context(|| format!("failed to write snapshots to redis at {}", self.url))
+    }
+}
+
+const NS_PER_HOUR: i64 = 3_600_000_000_000;
+
+/// Parquet column compression for an optional codec; unset keeps Snappy.
+fn parquet_compression(codec: Option<Codec>) -> Result<ParquetCompression> {
+    codec.map_or(Ok(ParquetCompression::SNAPPY), |codec| codec.parquet())
+}
+
+/// Writes snapshots to Hive-partitioned Parquet files under `root`, laid out as
+/// `symbol=<symbol>/date=<YYYY-MM-DD>/hour=<HH>/part-<n>.parquet` by `ts_event` (UTC).
+/// A file is finalized when its symbol moves to a later hour or on `close`; files of
+/// a crashed run are missing their footer and should be discarded.
+pub struct ParquetSink {
+    root: PathBuf,
+    row_group_size: usize,
+    compression: ParquetCompression,
+    schema: SchemaRef,
+    open: HashMap<String, ParquetPartition>,
+    files_written: usize,
+}
+
+struct ParquetPartition {
+    hour: i64,
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+}
+
+impl ParquetSink {
+    pub fn create(
+        root: &str,
+        row_group_size: usize,
+        compression: ParquetCompression,
+    ) -> Result<Self> {
+        fs::create_dir_all(root)
+            .with_context(|| format!("failed to create parquet directory {}", root))?;
+        Ok(Self {
+            root: PathBuf::from(root),
+            row_group_size: row_group_size.max(1),
+            compression,
+            schema: Arc::new(parquet_schema()),
+            open: HashMap::new(),
+            files_written: 0,
+        })
+    }
+
+    fn writer_for(&mut self, symbol: &str, hour: i64) -> Result<&mut ArrowWriter<File>> {
+        if self.open.get(symbol).is_some_and(|open| open.hour != hour)
+            && let Some(previous) = self.open.remove(symbol)
+        {
+            self.finish_partition(previous)?;
+        }
+        if !self.open.contains_key(symbol) {
+            let partition = self.open_partition(symbol, hour)?;
+            self.open.insert(symbol.to_owned(), partition);
+        }
+        Ok(&mut self
+            .open
+            .get_mut(symbol)
+            .expect("partition opened above")
+            .writer)
+    }
+
+    fn open_partition(&self, symbol: &str, hour: i64) -> Result<ParquetPartition> {
+        let start = OffsetDateTime::from_unix_timestamp_nanos(hour as i128 * NS_PER_HOUR as i128)
+            .with_context(|| format!("ts_event hour {} is out of range", hour))?;
+        let dir = self
+            .root
+            .join(format!("symbol={}", symbol.replace(['/', '\\'], "_")))
+            .join(format!(
+                "date={:04}-{:02}-{:02}",
+                start.year(),
+                start.month() as u8,
+                start.day()
+            ))
+            .join(format!("hour={:02}", start.hour()));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create parquet partition {}", dir.display()))?;
+        // Never overwrite files from earlier runs or restarts
+        let path = (0..)
+            .map(|n| dir.join(format!("part-{:05}.parquet", n)))
+            .find(|path| !path.exists())
+            .expect("unbounded part numbers");
+        let file =
+            File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+        let props = WriterProperties::builder()
+            .set_compression(self.compression)
+            .set_max_row_group_row_count(Some(self.row_group_size))
+            .build();
+        let writer = ArrowWriter::try_new(file, self.schema.clone(), Some(props))
+            .with_context(|| format!("failed to start parquet writer for {}", path.display()))?;
+        Ok(ParquetPartition { hour, path, writer })
+    }
+
+    fn finish_partition(&mut self, partition: ParquetPartition) -> Result<()> {
+        let metadata = partition
+            .writer
+            .close()
+            .with_context(|| format!("failed to finalize {}", partition.path.display()))?;
+        self.files_written += 1;
+        info!(
+            path = %partition.path.display(),
+            rows = metadata.file_metadata().num_rows(),
+            "parquet partition written"
+        );
+        Ok(())
+    }
+}
+
+impl SnapshotSink for ParquetSink {
+    fn name(&self) -> String {
+        format!("parquet:{}", self.root.display())
+    }
+
+    fn write_batch(&mut self, batch: &[SharedSnapshot]) -> Result<()> {
+        // Group by partition, keeping first-seen order so hours rotate forward
+        let mut order: Vec<(String, i64)> = Vec::new();
+        let mut groups: HashMap<(String, i64), Vec<&SnapshotRecord>> = HashMap::new();
+        for snapshot in batch {
+            let key = (
+                snapshot.payload.symbol.clone(),
+                snapshot.ts_event.div_euclid(NS_PER_HOUR),
+            );
+            groups
+                .entry(key.clone())
+                .or_insert_with(|| {
+                    order.push(key);
+                    Vec::new()
+                })
+                .push(snapshot);
+        }
+        for key in order {
+            let rows = &groups[&key];
+            let record_batch = snapshots_to_record_batch(self.schema.clone(), rows)?;
+            let (symbol, hour) = key;
+            self.writer_for(&symbol, hour)?
+                .write(&record_batch)
+                .with_context(|| format!("failed to write parquet rows for {}", symbol))?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        let open: Vec<ParquetPartition> = self.open.drain().map(|(_, p)| p).collect();
+        for partition in open {
+            self.finish_partition(partition)?;
+        }
+        info!(files_written = self.files_written, "parquet sink closed");
+        Ok(())
+    }
+}
+
+fn parquet_schema() -> Schema {
+    let level_list = |name: &str, item: DataType| {
+        Field::new_list(name, Field::new_list_field(item, true), false)
+    };
+    Schema::new(vec![
+        Field::new("symbol", DataType::Utf8, false),
+        Field::new("instrument_id", DataType::UInt32, false),
+        Field::new("ts_event", DataType::Int64, false),
+        Field::new("best_bid_price", DataType::Int64, true),
+        Field::new("best_bid_size", DataType::UInt32, true),
+        Field::new("best_bid_count", DataType::UInt32, true),
+        Field::new("best_ask_price", DataType::Int64, true),
+        Field::new("best_ask_size", DataType::UInt32, true),
+        Field::new("best_ask_count", DataType::UInt32, true),
+        Field::new("bid_levels", DataType::UInt64, false),
+        Field::new("ask_levels", DataType::UInt64, false),
+        Field::new("total_orders", DataType::UInt64, false),
+        level_list("bid_prices", DataType::Int64),
+        level_list("bid_sizes", DataType::UInt32),
+        level_list("bid_counts", DataType::UInt32),
+        level_list("ask_prices", DataType::Int64),
+        level_list("ask_sizes", DataType::UInt32),
+        level_list("ask_counts", DataType::UInt32),
+        Field::new("seq", DataType::UInt64, false),
+        Field::new("ts_recv", DataType::Int64, false),
+    ])
+}
+
+fn snapshots_to_record_batch(schema: SchemaRef, rows: &[&SnapshotRecord]) -> Result<RecordBatch> {
+    let best_bids: Vec<Option<&LevelEntry>> = rows
+        .iter()
+        .map(|row| row.payload.bbo.best_bid.as_ref())
+        .collect();
+    let best_asks: Vec<Option<&LevelEntry>> = rows
+        .iter()
+        .map(|row| row.payload.bbo.best_ask.as_ref())
+        .collect();
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            rows.iter().map(|row| row.payload.symbol.as_str()),
+        )),
+        Arc::new(UInt32Array::from_iter_values(
+            rows.iter().map(|row| row.instrument_id),
+        )),
+        Arc::new(Int64Array::from_iter_values(
+            rows.iter().map(|row| row.ts_event),
+        )),
+        Arc::new(Int64Array::from_iter(
+            best_bids.iter().map(|level| level.map(|l| l.price)),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            best_bids.iter().map(|level| level.map(|l| l.size)),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            best_bids.iter().map(|level| level.map(|l| l.count)),
+        )),
+        Arc::new(Int64Array::from_iter(
+            best_asks.iter().map(|level| level.map(|l| l.price)),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            best_asks.iter().map(|level| level.map(|l| l.size)),
+        )),
+        Arc::new(UInt32Array::from_iter(
+            best_asks.iter().map(|level| level.map(|l| l.count)),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.payload.bid_levels as u64),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.payload.ask_levels as u64),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.payload.total_orders as u64),
+        )),
+        level_list::<Int64Type>(rows, |s| &s.bids, |l| l.price),
+        level_list::<UInt32Type>(rows, |s| &s.bids, |l| l.size),
+        level_list::<UInt32Type>(rows, |s| &s.bids, |l| l.count),
+        level_list::<Int64Type>(rows, |s| &s.asks, |l| l.price),
+        level_list::<UInt32Type>(rows, |s| &s.asks, |l| l.size),
+        level_list::<UInt32Type>(rows, |s| &s.asks, |l| l.count),
+        Arc::new(UInt64Array::from_iter_values(
+            rows.iter().map(|row| row.payload.seq),
+        )),
+        Arc::new(Int64Array::from_iter_values(
+            rows.iter().map(|row| row.ts_recv),
+        )),
+    ];
+    RecordBatch::try_new(schema, columns).context("failed to build parquet record batch")
+}
+
+fn level_list<T: ArrowPrimitiveType>(
+    rows: &[&SnapshotRecord],
+    side: impl Fn(&Snapshot) -> &[LevelEntry],
+    value: impl Fn(&LevelEntry) -> T::Native,
+) -> ArrayRef {
+    Arc::new(ListArray::from_iter_primitive::<T, _, _>(rows.iter().map(
+        |row| {
+            Some(
+                side(&row.payload)
+                    .iter()
+                    .map(|level| Some(value(level)))
+                    .collect::<Vec<_>>(),
+            )
+        },
+    )))
+}
+
+/// Sending side of the storage writer queues. A symbol always maps to the same shard,
+/// so its snapshots are written in order.
+pub struct StorageSender {
+    shards: Vec<QueueSender<StorageItem>>,
+    journal: Option<Arc<Journal>>,
+}
+
+impl StorageSender {
+    pub fn for_symbol(&mut self, symbol: &str) -> &mut QueueSender<StorageItem> {
+        let shard = shard_of(symbol, self.shards.len());
+        &mut self.shards[shard]
+    }
+
+    /// Queues a snapshot for its symbol's writer, journaling it first when a journal
+    /// is configured.
+    pub fn send_snapshot(&mut self, snapshot: SharedSnapshot) -> Result<()> {
+        let shard = shard_of(&snapshot.payload.symbol, self.shards.len());
+        if let Some(journal) = &self.journal {
+            journal.append(shard, &snapshot)?;
+        }
+        self.shards[shard].send(StorageItem::Snapshot(snapshot))
+    }
+
+    /// Counters of every shard's queue, which outlive the sender.
+    pub fn counters(&self) -> Vec<Arc<QueueCounters>> {
+        self.shards.iter().map(|tx| tx.counters().clone()).collect()
+    }
+}
+
+fn shard_of(symbol: &str, shards: usize) -> usize {
+    if shards == 1 {
+        return 0;
+    }
+    // DefaultHasher::new uses fixed keys, so the mapping is stable across runs
+    let mut hasher = DefaultHasher::new();
+    symbol.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// Write counters of every storage writer shard, served under `/metrics`.
+#[derive(Debug)]
+pub struct StorageStats {
+    started: Instant,
+    shards: Vec<ShardStats>,
+    retries: Arc<RetryStats>,
+    spill: Option<Arc<Spill>>,
+}
+
+#[derive(Debug, Default)]
+struct ShardStats {
+    written: AtomicU64,
+    trades: AtomicU64,
+    bars: AtomicU64,
+    batches: AtomicU64,
+    write_us: AtomicU64,
+    /// Newest `ts_event` among the written snapshots; 0 before the first.
+    newest_ts_event: AtomicI64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StorageMetrics {
+    pub writers: usize,
+    pub written: u64,
+    pub trades: u64,
+    pub bars: u64,
+    pub batches: u64,
+    pub write_ms: u64,
+    pub snapshots_per_sec: f64,
+    /// Newest `ts_event` written by any shard; null before the first write.
+    pub newest_ts_event: Option<i64>,
+    /// Postgres connects and batches tried again, connections reopened, and connects or
+    /// batches given up on after the last try.
+    pub retries: u64,
+    pub reconnects: u64,
+    pub retries_exhausted: u64,
+    /// Batches and rows written to `SPILL_DIR` instead of Postgres.
+    pub spilled_batches: u64,
+    pub spilled_rows: u64,
+    pub shards: Vec<ShardMetrics>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ShardMetrics {
+    pub shard: usize,
+    pub written: u64,
+    pub trades: u64,
+    pub bars: u64,
+    pub batches: u64,
+    /// Time spent inside sink writes.
+    pub write_ms: u64,
+    pub snapshots_per_sec: f64,
+    pub newest_ts_event: Option<i64>,
+}
+
+impl StorageStats {
+    pub fn new(shards: usize, spill: Option<Arc<Spill>>) -> Self {
+        Self {
+            started: Instant::now(),
+            shards: (0..shards.max(1)).map(|_| ShardStats::default()).collect(),
+            retries: Arc::default(),
+            spill,
+        }
+    }
+
+    pub fn snapshot(&self) -> StorageMetrics {
+        let elapsed = self.started.elapsed().as_secs_f64().max(f64::EPSILON);
+        let shards: Vec<ShardMetrics> = self
+            .shards
+            .iter()
+            .enumerate()
+            .map(|(shard, stats)| {
+                let written = stats.written.load(Ordering::Relaxed);
+                ShardMetrics {
+                    shard,
+                    written,
+                    trades: stats.trades.load(Ordering::Relaxed),
+                    bars: stats.bars.load(Ordering::Relaxed),
+                    batches: stats.batches.load(Ordering::Relaxed),
+                    write_ms: stats.write_us.load(Ordering::Relaxed) / 1_000,
+                    snapshots_per_sec: written as f64 / elapsed,
+                    newest_ts_event: Some(stats.newest_ts_event.load(Ordering::Relaxed))
+                        .filter(|&ts| ts > 0),
+                }
+            })
+            .collect();
+        let written = shards.iter().map(|shard| shard.written).sum::<u64>();
+        StorageMetrics {
+            writers: shards.len(),
+            written,
+            trades: shards.iter().map(|shard| shard.trades).sum(),
+            bars: shards.iter().map(|shard| shard.bars).sum(),
+            batches: shards.iter().map(|shard| shard.batches).sum(),
+            write_ms: shards.iter().map(|shard| shard.write_ms).sum(),
+            snapshots_per_sec: written as f64 / elapsed,
+            newest_ts_event: shards
+                .iter()
+                .filter_map(|shard| shard.newest_ts_event)
+                .max(),
+            retries: self.retries.retries.load(Ordering::Relaxed),
+            reconnects: self.retries.reconnects.load(Ordering::Relaxed),
+            retries_exhausted: self.retries.exhausted.load(Ordering::Relaxed),
+            spilled_batches: self
+                .spill
+                .as_ref()
+                .map_or(0, |spill| spill.batches.load(Ordering::Relaxed)),
+            spilled_rows: self
+                .spill
+                .as_ref()
+                .map_or(0, |spill| spill.rows.load(Ordering::Relaxed)),
+            shards,
+        }
+    }
+}
+
+/// Handles to the running storage writers.
+pub struct StorageWriters {
+    /// Dropping it lets the writers drain their queues and finish.
+    pub sender: StorageSender,
+    pub stats: Arc<StorageStats>,
+    /// Finishes once every shard has finished, with the first shard's error.
+    pub handle: thread::JoinHandle<Result<()>>,
+}
+
+/// Spawns `config.writers` snapshot writers under the supervisor, each draining its own
+/// `queue`, with its counters in `queues`. A restarted writer reopens its sinks and keeps draining
+/// its queue, so snapshots still queued are not lost. A single writer reports its
+/// health as `storage`, shards as `storage-0`, `storage-1`, ...
+pub fn spawn_writers(
+    config: StorageConfig,
+    queue: QueueConfig,
+    queues: &QueueStats,
+    health: Arc<SinkHealth>,
+    switches: Arc<SinkSwitches>,
+    policy: RestartPolicy,
+) -> StorageWriters {
+    let spill = config
+        .spill_dir
+        .clone()
+        .map(|dir| Arc::new(Spill::new(dir)));
+    let stats = Arc::new(StorageStats::new(config.writers, spill));
+    let journal = config.journal.clone();
+    if config.writers == 1 {
+        let (tx, mut rx) = queue::bounded("storage", queue, queues);
+        let shard_stats = stats.clone();
+        let handle = spawn_supervised("storage", health, policy, move |_| {
+            let sink = open_sinks(&config, None, &shard_stats, &switches)?;
+            writer_loop(&config, 0, &shard_stats.shards[0], &mut rx, sink)
+        });
+        return StorageWriters {
+            sender: StorageSender {
+                shards: vec![tx],
+                journal: journal.clone(),
+            },
+            stats,
+            handle,
+        };
+    }
+
+    let config = Arc::new(config);
+    let bulk = Arc::new(BulkLoad {
+        db_url: config.db_url.clone(),
+        timescale: config.timescale,
+        prepared: Mutex::new(false),
+    });
+    let mut shards = Vec::with_capacity(config.writers);
+    let mut handles = Vec::with_capacity(config.writers);
+    for shard in 0..config.writers {
+        let (tx, mut rx) = queue::bounded(&format!("storage-{}", shard), queue, queues);
+        let (config, bulk, stats, switches) = (
+            config.clone(),
+            bulk.clone(),
+            stats.clone(),
+            switches.clone(),
+        );
+        handles.push(spawn_supervised(
+            &format!("storage-{}", shard),
+            health.clone(),
+            policy,
+            move |_| {
+                let sink = open_sinks(&config, Some(&bulk), &stats, &switches)?;
+                writer_loop(&config, shard, &stats.shards[shard], &mut rx, sink)
+            },
+        ));
+        shards.push(tx);
+    }
+    info!(shards = config.writers, "storage writers started");
+
+    let handle = thread::spawn(move || {
+        let mut result = Ok(());
+        for handle in handles {
+            let shard_result = handle.join().expect("storage writer shard panicked");
+            if result.is_ok() {
+                result = shard_result;
+            }
+        }
+        // Rebuild even after a shard failed; the other shards' rows are in the table
+        let indexes = bulk.finish();
+        result.and(indexes)
+    });
+    StorageWriters {
+        sender: StorageSender { shards, journal },
+        stats,
+        handle,
+    }
+}
+
+/// Creates the database and tables if missing. With `timescale` set the snapshot table
+/// is converted to (or reconfigured as) a hypertable; `None` leaves an existing
+/// hypertable untouched.
+pub fn init_database(db_url: &str, timescale: Option<&TimescaleConfig>) -> Result<()> {
+    ensure_database(db_url)?;
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    ensure_schema(&mut client, timescale)
+}
+
+/// Checks that Postgres accepts connections and answers a query within `timeout`.
+pub fn ping_database(db_url: &str, timeout: Duration) -> Result<()> {
+    let mut client = db_url
+        .parse::<Config>()
+        .context("invalid postgres url")?
+        .connect_timeout(timeout)
+        .connect(NoTls)
+        .context("failed to connect to postgres")?;
+    client
+        .simple_query("SELECT 1")
+        .context("postgres did not answer")?;
+    Ok(())
+}
+
+/// How much history retention keeps in each table. Ages are measured back from the
+/// newest row rather than the wall clock, so replays of old sessions are pruned like
+/// live data.
+#[derive(Clone, Copy, Debug)]
+pub struct RetentionPolicy {
+    /// Rows older than the newest row by more than this go.
+    pub max_age_ns: Option<i64>,
+    /// Rows beyond the newest this many go; rows sharing the oldest kept timestamp stay.
+    pub max_rows: Option<u64>,
+}
+
+/// Tables retention prunes and the column their age is measured on.
+const RETENTION_TABLES: [(&str, &str); 6] = [
+    ("orderbook_snapshots", "ts_event"),
+    ("trades", "ts_event"),
+    ("bars", "start_ts"),
+    ("book_stats", "ts_event"),
+    ("imbalances", "ts_event"),
+    ("statistics", "ts_event"),
+];
+
+/// What one table lost to a prune.
+#[derive(Clone, Debug, Serialize)]
+pub struct TablePrune {
+    pub table: &'static str,
+    /// Rows older than this were removed; `None` when the policy kept everything.
+    pub cutoff: Option<i64>,
+    /// Hypertable chunks or range partitions dropped whole.
+    pub dropped_partitions: u64,
+    pub deleted_rows: u64,
+}
+
+/// Removes what `policy` no longer keeps from every table. Timescale chunks and native
+/// range partitions lying wholly below the cutoff are dropped, which frees their space
+/// at once; the rows left over are deleted.
+pub fn prune(db_url: &str, policy: &RetentionPolicy) -> Result<Vec<TablePrune>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let timescale = client
+        .query_opt(
+            "SELECT 1 FROM pg_extension WHERE extname = 'timescaledb'",
+            &[],
+        )
+        .context("failed to look up the timescaledb extension")?
+        .is_some();
+    RETENTION_TABLES
+        .iter()
+        .map(|&(table, column)| {
+            let pruned = prune_table(&mut client, table, column, policy, timescale)
+                .with_context(|| format!("failed to prune {}", table))?;
+            if pruned.dropped_partitions > 0 || pruned.deleted_rows > 0 {
+                info!(
+                    table,
+                    cutoff = pruned.cutoff,
+                    dropped_partitions = pruned.dropped_partitions,
+                    deleted_rows = pruned.deleted_rows,
+                    "pruned"
+                );
+            }
+            Ok(pruned)
+        })
+        .collect()
+}
+
+fn prune_table(
+    client: &mut Client,
+    table: &'static str,
+    column: &str,
+    policy: &RetentionPolicy,
+    timescale: bool,
+) -> Result<TablePrune> {
+    let mut pruned = TablePrune {
+        table,
+        cutoff: retention_cutoff(client, table, column, policy)?,
+        dropped_partitions: 0,
+        deleted_rows: 0,
+    };
+    let Some(cutoff) = pruned.cutoff else {
+        return Ok(pruned);
+    };
+    let hypertable = timescale
+        && client
+            .query_opt(
+                "SELECT 1 FROM timescaledb_information.hypertables WHERE hypertable_name = $1",
+                &[&table],
+            )
+            .context("failed to look up hypertables")?
+            .is_some();
+    if hypertable {
+        pruned.dropped_partitions = client
+            .query(
+                &format!("SELECT drop_chunks('{}', older_than => $1::BIGINT)", table),
+                &[&cutoff],
+            )
+            .context("failed to drop chunks")?
+            .len() as u64;
+    } else {
+        pruned.dropped_partitions = drop_partitions(client, table, column, cutoff)?;
+    }
+    pruned.deleted_rows = client
+        .execute(
+            &format!("DELETE FROM {} WHERE {} < $1", table, column),
+            &[&cutoff],
+        )
+        .context("failed to delete rows")?;
+    Ok(pruned)
+}
+
+/// The oldest timestamp `policy` keeps in `table`, or `None` to keep every row.
+fn retention_cutoff(
+    client: &mut Client,
+    table: &str,
+    column: &str,
+    policy: &RetentionPolicy,
+) -> Result<Option<i64>> {
+    let mut cutoff = None;
+    if let Some(max_age_ns) = policy.max_age_ns {
+        let newest: Option<i64> = client
+            .query_one(&format!("SELECT MAX({}) FROM {}", column, table), &[])
+            .context("failed to find the newest row")?
+            .get(0);
+        cutoff = newest.map(|newest| newest.saturating_sub(max_age_ns));
+    }
+    if let Some(max_rows) = policy.max_rows {
+        let oldest_kept: Option<i64> = client
+            .query_opt(
+                &format!(
+                    "SELECT {column} FROM {table} ORDER BY {column} DESC OFFSET $1 LIMIT 1",
+                    column = column,
+                    table = table
+                ),
+                &[&(max_rows.saturating_sub(1).min(i64::MAX as u64) as i64)],
+            )
+            .context("failed to find the oldest row to keep")?
+            .map(|row| row.get(0));
+        cutoff = cutoff.max(oldest_kept);
+    }
+    Ok(cutoff)
+}
+
+/// Drops the partitions of a table range-partitioned on `column` whose upper bound is
+/// at or below `cutoff`. Tables that are not partitioned that way have none to drop.
+fn drop_partitions(client: &mut Client, table: &str, column: &str, cutoff: i64) -> Result<u64> {
+    let partitioned = client
+        .query_opt(
+            "SELECT pg_get_partkeydef(c.oid) FROM pg_partitioned_table p \
+             JOIN pg_class c ON c.oid = p.partrelid WHERE c.oid = to_regclass($1)",
+            &[&table],
+        )
+        .context("failed to look up table partitioning")?
+        .map(|row| row.get::<_, String>(0));
+    if partitioned.as_deref() != Some(format!("RANGE ({})", column).as_str()) {
+        return Ok(0);
+    }
+    let partitions = client
+        .query(
+            "SELECT c.oid::regclass::text, pg_get_expr(c.relpartbound, c.oid) \
+             FROM pg_inherits i JOIN pg_class c ON c.oid = i.inhrelid \
+             WHERE i.inhparent = to_regclass($1)",
+            &[&table],
+        )
+        .context("failed to list partitions")?;
+    let mut dropped = 0;
+    for row in partitions {
+        let (name, bound): (String, String) = (row.get(0), row.get(1));
+        // FOR VALUES FROM (...) TO (...); MAXVALUE and DEFAULT partitions never drop
+        let upper = bound
+            .split_once(" TO (")
+            .and_then(|(_, upper)| upper.strip_suffix(')'))
+            .and_then(|upper| upper.trim_matches('\'').parse::<i64>().ok());
+        if upper.is_some_and(|upper| upper <= cutoff) {
+            client
+                .batch_execute(&format!("DROP TABLE {}", name))
+                .with_context(|| format!("failed to drop partition {}", name))?;
+            dropped += 1;
+        }
+    }
+    Ok(dropped)
+}
+
+/// The thread pruning the tables every `interval` while ingest runs. Dropping it stops
+/// the thread without waiting.
+pub struct Retention {
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Retention {
+    /// Prunes once right away, then every `interval` until `finish`. Failed prunes are
+    /// logged and tried again at the next interval.
+    pub fn start(db_url: Arc<String>, policy: RetentionPolicy, interval: Duration) -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = thread::Builder::new()
+            .name("retention".to_owned())
+            .spawn(move || {
+                let tick = interval.min(Duration::from_millis(250));
+                let mut next = Instant::now();
+                while !stopped.load(Ordering::Relaxed) {
+                    if Instant::now() >= next {
+                        if let Err(e) = prune(&db_url, &policy) {
+                            warn!(error = format!("{:#}", e), "retention prune failed");
+                        }
+                        next = Instant::now() + interval;
+                    }
+                    thread::sleep(tick);
+                }
+            })
+            .context("failed to start the retention thread")?;
+        info!(
+            max_age_ns = policy.max_age_ns,
+            max_rows = policy.max_rows,
+            interval_ms = interval.as_millis() as u64,
+            "retention enabled"
+        );
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stops pruning, waiting for a prune in progress.
+    pub fn finish(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            error!("retention thread panicked");
+        }
+    }
+}
+
+impl Drop for Retention {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Registers a new ingest run, refusing when the same input/symbol already completed
+/// unless `force` is set. An advisory lock serializes concurrent starts of the same input.
+pub fn begin_ingest_run(db_url: &str, input_path: &str, symbol: &str, force: bool) -> Result<i64> {
+    init_database(db_url, None)?;
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let mut txn = client
+        .transaction()
+        .context("failed to start ingest_runs transaction")?;
+    let lock_key = format!("{}\u{1f}{}", input_path, symbol);
+    txn.execute("SELECT pg_advisory_xact_lock(hashtext($1))", &[&lock_key])
+        .context("failed to lock ingest_runs for input")?;
+
+    let previous = txn
+        .query_opt(
+            "SELECT id, completed_at::TEXT FROM ingest_runs \
+             WHERE input_path = $1 AND symbol = $2 AND status = $3 \
+             ORDER BY id DESC LIMIT 1",
+            &[&input_path, &symbol, &RunStatus::Completed.as_str()],
+        )
+        .context("failed to query ingest_runs")?;
+    if let Some(row) = previous {
+        let id: i64 = row.get(0);
+        let completed_at: Option<String> = row.get(1);
+        if !force {
+            return Err(anyhow!(
+                "input {} (symbol {}) was already ingested by run {} completed at {}; pass --force to ingest again",
+                input_path,
+                symbol,
+                id,
+                completed_at.as_deref().unwrap_or("unknown")
+            ));
+        }
+        info!(
+            input_path,
+            previous_run = id,
+            "forcing re-ingest of a completed input"
+        );
+    }
+
+    let row = txn
+        .query_one(
+            "INSERT INTO ingest_runs (input_path, symbol, status) VALUES ($1, $2, $3) RETURNING id",
+            &[&input_path, &symbol, &RunStatus::Running.as_str()],
+        )
+        .context("failed to insert ingest_runs row")?;
+    txn.commit().context("failed to commit ingest_runs row")?;
+    Ok(row.get(0))
+}
+
+pub fn finish_ingest_run(
+    db_url: &str,
+    run_id: i64,
+    status: RunStatus,
+    processed: u64,
+) -> Result<()> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    client
+        .execute(
+            "UPDATE ingest_runs SET status = $2, processed = $3, completed_at = NOW() WHERE id = $1",
+            &[&run_id, &status.as_str(), &(processed as i64)],
+        )
+        .with_context(|| format!("failed to update ingest run {}", run_id))?;
+    Ok(())
+}
+
+/// One row of `ingest_runs`.
+#[derive(Clone, Debug, Serialize)]
+pub struct IngestRun {
+    pub id: i64,
+    pub input_path: String,
+    pub symbol: String,
+    pub status: String,
+    pub processed: Option<i64>,
+    pub started_at: String,
+    pub completed_at: Option<String>,
+    /// Wall time from start to completion; `None` while the run is unfinished.
+    pub duration_ms: Option<i64>,
+}
+
+pub fn load_ingest_run(db_url: &str, run_id: i64) -> Result<Option<IngestRun>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let row = client
+        .query_opt(
+            "SELECT id, input_path, symbol, status, processed, started_at::TEXT, completed_at::TEXT, \
+             (EXTRACT(EPOCH FROM completed_at - started_at) * 1000)::BIGINT \
+             FROM ingest_runs WHERE id = $1",
+            &[&run_id],
+        )
+        .with_context(|| format!("failed to load ingest run {}", run_id))?;
+    Ok(row.map(|row| IngestRun {
+        id: row.get(0),
+        input_path: row.get(1),
+        symbol: row.get(2),
+        status: row.get(3),
+        processed: row.get(4),
+        started_at: row.get(5),
+        completed_at: row.get(6),
+        duration_ms: row.get(7),
+    }))
+}
+
+/// Streams the snapshots stored by `run_id` to `f`, ordered by symbol and `seq`, and
+/// returns how many there were. Only rows written since snapshots carried their run
+/// are found.
+pub fn for_each_run_snapshot(
+    db_url: &str,
+    run_id: i64,
+    mut f: impl FnMut(SnapshotRecord),
+) -> Result<u64> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let mut rows = client
+        .query_raw(
+            format!(
+                "SELECT {} FROM orderbook_snapshots WHERE run_id = $1 ORDER BY symbol, seq, id",
+                SNAPSHOT_COLUMNS
+            )
+            .as_str(),
+            [run_id],
+        )
+        .with_context(|| format!("failed to query snapshots of run {}", run_id))?;
+    let mut count = 0u64;
+    while let Some(row) = rows
+        .next()
+        .with_context(|| format!("failed to read snapshots of run {}", run_id))?
+    {
+        f(snapshot_from_row(&row));
+        count += 1;
+    }
+    Ok(count)
+}
+
+const SNAPSHOT_COLUMNS: &str = "symbol, instrument_id, ts_event, \
+    best_bid_price, best_bid_size, best_bid_count, \
+    best_ask_price, best_ask_size, best_ask_count, \
+    bid_levels, ask_levels, total_orders, levels, seq, ts_recv";
+
+/// Loads the most recent persisted snapshot per symbol. Rows written before full depth
+/// was stored carry only the best level on each side.
+pub fn load_latest_snapshots(db_url: &str) -> Result<Vec<SnapshotRecord>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let rows = client
+        .query(
+            &format!(
+                "SELECT DISTINCT ON (symbol) {} FROM orderbook_snapshots \
+                 ORDER BY symbol, ts_event DESC, id DESC",
+                SNAPSHOT_COLUMNS
+            ),
+            &[],
+        )
+        .context("failed to load latest snapshots per symbol")?;
+    Ok(rows.iter().map(snapshot_from_row).collect())
+}
+
+/// Loads the last persisted snapshot of `symbol` at or before `ts_event`.
+pub fn load_snapshot_at(
+    db_url: &str,
+    symbol: &str,
+    ts_event: i64,
+) -> Result<Option<SnapshotRecord>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let row = client
+        .query_opt(
+            &format!(
+                "SELECT {} FROM orderbook_snapshots \
+                 WHERE symbol = $1 AND ts_event <= $2 \
+                 ORDER BY ts_event DESC, id DESC LIMIT 1",
+                SNAPSHOT_COLUMNS
+            ),
+            &[&symbol, &ts_event],
+        )
+        .with_context(|| format!("failed to load snapshot of {} at {}", symbol, ts_event))?;
+    Ok(row.as_ref().map(snapshot_from_row))
+}
+
+/// Loads the last persisted snapshot at or before `ts_event` of every symbol, or only of
+/// `symbol` when given, ordered by symbol.
+pub fn load_snapshots_at(
+    db_url: &str,
+    ts_event: i64,
+    symbol: Option<&str>,
+) -> Result<Vec<SnapshotRecord>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let rows = client
+        .query(
+            &format!(
+                "SELECT DISTINCT ON (symbol) {} FROM orderbook_snapshots \
+                 WHERE ts_event <= $1 AND ($2::TEXT IS NULL OR symbol = $2) \
+                 ORDER BY symbol, ts_event DESC, id DESC",
+                SNAPSHOT_COLUMNS
+            ),
+            &[&ts_event, &symbol],
+        )
+        .with_context(|| format!("failed to load snapshots at {}", ts_event))?;
+    Ok(rows.iter().map(snapshot_from_row).collect())
+}
+
+/// Which stored snapshots `load_snapshots` returns, over an inclusive `ts_event` range.
+#[derive(Clone, Debug)]
+pub struct SnapshotQuery {
+    pub symbol: String,
+    pub from_ts: Option<i64>,
+    pub to_ts: Option<i64>,
+    /// Only snapshots stored by this `ingest_runs` row.
+    pub run_id: Option<i64>,
+    pub limit: usize,
+}
+
+/// Loads the first `query.limit` stored snapshots of `query.symbol` in the range,
+/// oldest first.
+pub fn load_snapshots(db_url: &str, query: &SnapshotQuery) -> Result<Vec<SnapshotRecord>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let rows = client
+        .query(
+            &format!(
+                "SELECT {} FROM orderbook_snapshots \
+                 WHERE symbol = $1 AND ts_event >= $2 AND ts_event <= $3 \
+                 AND ($4::BIGINT IS NULL OR run_id = $4) \
+                 ORDER BY ts_event, id LIMIT $5",
+                SNAPSHOT_COLUMNS
+            ),
+            &[
+                &query.symbol,
+                &query.from_ts.unwrap_or(i64::MIN),
+                &query.to_ts.unwrap_or(i64::MAX),
+                &query.run_id,
+                &(query.limit as i64),
+            ],
+        )
+        .with_context(|| format!("failed to load snapshots of {}", query.symbol))?;
+    Ok(rows.iter().map(snapshot_from_row).collect())
+}
+
+/// Which price level `load_level_history` follows, over an inclusive `ts_event` range.
+#[derive(Clone, Debug)]
+pub struct LevelQuery {
+    pub symbol: String,
+    pub price: i64,
+    pub from_ts: Option<i64>,
+    pub to_ts: Option<i64>,
+    /// Only snapshots stored by this `ingest_runs` row.
+    pub run_id: Option<i64>,
+    pub limit: usize,
+}
+
+/// The state of a price level from `ts_event` until the next point.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LevelPoint {
+    pub ts_event: i64,
+    pub seq: u64,
+    /// `bid` or `ask`; `None` while nothing rests at the price.
+    pub side: Option<&'static str>,
+    pub size: u32,
+    pub count: u32,
+}
+
+/// Replays the stored snapshots of `query.symbol` and returns a point each time the
+/// level at `query.price` changes, oldest first and at most `query.limit`. Snapshots
+/// only store the levels up to their depth, so those in which the price lies beyond
+/// the deepest stored bid or ask say nothing about it and are skipped.
+pub fn load_level_history(db_url: &str, query: &LevelQuery) -> Result<Vec<LevelPoint>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let from_ts = query.from_ts.unwrap_or(i64::MIN);
+    let to_ts = query.to_ts.unwrap_or(i64::MAX);
+    let params: [&(dyn ToSql + Sync); 4] = [&query.symbol, &from_ts, &to_ts, &query.run_id];
+    let mut rows = client
+        .query_raw(
+            format!(
+                "SELECT {} FROM orderbook_snapshots \
+                 WHERE symbol = $1 AND ts_event >= $2 AND ts_event <= $3 \
+                 AND ($4::BIGINT IS NULL OR run_id = $4) \
+                 ORDER BY ts_event, id",
+                SNAPSHOT_COLUMNS
+            )
+            .as_str(),
+            params,
+        )
+        .with_context(|| format!("failed to query level history of {}", query.symbol))?;
+    let mut points: Vec<LevelPoint> = Vec::new();
+    while points.len() < query.limit
+        && let Some(row) = rows
+            .next()
+            .with_context(|| format!("failed to read level history of {}", query.symbol))?
+    {
+        let snapshot = snapshot_from_row(&row);
+        let payload = &snapshot.payload;
+        let find = |levels: &[LevelEntry]| levels.iter().find(|l| l.price == query.price).cloned();
+        let (side, level) = match (find(&payload.bids), find(&payload.asks)) {
+            (Some(level), _) => (Some("bid"), level),
+            (None, Some(level)) => (Some("ask"), level),
+            (None, None) => {
+                // Between the deepest stored bid and ask nothing rests at the price
+                let deepest_bid = payload.bids.last().map_or(i64::MIN, |l| l.price);
+                let deepest_ask = payload.asks.last().map_or(i64::MAX, |l| l.price);
+                if !(deepest_bid..=deepest_ask).contains(&query.price) {
+                    continue;
+                }
+                (
+                    None,
+                    LevelEntry {
+                        price: query.price,
+                        size: 0,
+                        count: 0,
+                        est_hidden: None,
+                    },
+                )
+            }
+        };
+        if points.last().is_some_and(|last| {
+            last.side == side && last.size == level.size && last.count == level.count
+        }) {
+            continue;
+        }
+        points.push(LevelPoint {
+            ts_event: snapshot.ts_event,
+            seq: payload.seq,
+            side,
+            size: level.size,
+            count: level.count,
+        });
+    }
+    Ok(points)
+}
+
+/// Loads up to `per_symbol` of the latest persisted trades of each symbol, oldest first.
+pub fn load_recent_trades(db_url: &str, per_symbol: usize) -> Result<Vec<TradeRecord>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let rows = client
+        .query(
+            "SELECT symbol, instrument_id, publisher_id, ts_event, price, size, side, order_id, sequence, ts_recv \
+             FROM (SELECT *, row_number() OVER (PARTITION BY symbol ORDER BY ts_event DESC, id DESC) AS rn \
+                   FROM trades) recent \
+             WHERE rn <= $1 ORDER BY symbol, ts_event, id",
+            &[&(per_symbol as i64)],
+        )
+        .context("failed to load recent trades")?;
+    Ok(rows
+        .iter()
+        .map(|row| TradeRecord {
+            symbol: row.get(0),
+            instrument_id: row.get::<_, i64>(1) as u32,
+            publisher_id: row.get::<_, i32>(2) as u16,
+            ts_event: row.get(3),
+            price: row.get(4),
+            size: row.get::<_, i32>(5) as u32,
+            side: row.get::<_, String>(6).chars().next().unwrap_or('N'),
+            order_id: row.get::<_, i64>(7) as u64,
+            sequence: row.get::<_, i64>(8) as u32,
+            // Trades stored before receive times were kept fall back to ts_event
+            ts_recv: row.get::<_, Option<i64>>(9).unwrap_or(row.get(3)),
+        })
+        .collect())
 }
 
-impl StorageConfig {
-    pub fn new(db_url: Arc<String>, batch_size: usize, flush_interval: Duration) -> Self {
-        Self {
-            db_url,
-            batch_size: batch_size.max(1),
-            flush_interval,
-        }
-    }
+/// Loads up to `per_key` of the latest stored bars of each symbol and interval, oldest
+/// first.
+pub fn load_recent_bars(db_url: &str, per_key: usize) -> Result<Vec<Bar>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let rows = client
+        .query(
+            "SELECT symbol, instrument_id, interval, start_ts, end_ts, open, high, low, close, volume, trades, vwap \
+             FROM (SELECT *, row_number() OVER (PARTITION BY symbol, interval ORDER BY start_ts DESC, id DESC) AS rn \
+                   FROM bars) recent \
+             WHERE rn <= $1 ORDER BY symbol, interval, start_ts, id",
+            &[&(per_key as i64)],
+        )
+        .context("failed to load recent bars")?;
+    Ok(rows
+        .iter()
+        .map(|row| Bar {
+            symbol: row.get(0),
+            instrument_id: row.get::<_, i64>(1) as u32,
+            interval: row.get(2),
+            start_ts: row.get(3),
+            end_ts: row.get(4),
+            open: row.get(5),
+            high: row.get(6),
+            low: row.get(7),
+            close: row.get(8),
+            volume: row.get::<_, i64>(9) as u64,
+            trades: row.get::<_, i64>(10) as u64,
+            vwap: row.get(11),
+            closed: true,
+        })
+        .collect())
 }
 
-pub fn spawn_writer(
-    config: StorageConfig,
-    rx: Receiver<SharedSnapshot>,
-) -> thread::JoinHandle<Result<()>> {
-    thread::spawn(move || writer_loop(config, rx))
+/// Loads the latest persisted imbalance of each symbol and publisher.
+pub fn load_latest_imbalances(db_url: &str) -> Result<Vec<ImbalanceRecord>> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    let rows = client
+        .query(
+            "SELECT DISTINCT ON (symbol, publisher_id) symbol, instrument_id, publisher_id, ts_event, ts_recv, \
+                    ref_price, auction_time, cont_book_clr_price, auct_interest_clr_price, paired_qty, \
+                    total_imbalance_qty, side, auction_type \
+             FROM imbalances ORDER BY symbol, publisher_id, ts_event DESC, id DESC",
+            &[],
+        )
+        .context("failed to load latest imbalances")?;
+    Ok(rows
+        .iter()
+        .map(|row| ImbalanceRecord {
+            symbol: row.get(0),
+            instrument_id: row.get::<_, i64>(1) as u32,
+            publisher_id: row.get::<_, i32>(2) as u16,
+            ts_event: row.get(3),
+            ts_recv: row.get(4),
+            ref_price: row.get(5),
+            auction_time: row.get(6),
+            cont_book_clr_price: row.get(7),
+            auct_interest_clr_price: row.get(8),
+            paired_qty: row.get::<_, i64>(9) as u32,
+            total_imbalance_qty: row.get::<_, i64>(10) as u32,
+            side: row.get::<_, String>(11).chars().next().unwrap_or('N'),
+            auction_type: row.get::<_, String>(12).chars().next().unwrap_or(' '),
+        })
+        .collect())
 }
 
-pub fn init_database(db_url: &str) -> Result<()> {
-    ensure_database(db_url)?;
+/// Loads the latest persisted value of each symbol's statistics, per stat type and
+/// publisher. Statistics whose latest row is a deletion are left out.
+pub fn load_latest_statistics(db_url: &str) -> Result<Vec<StatisticRecord>> {
     let mut client = Client::connect(db_url, NoTls)
         .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
-    ensure_schema(&mut client)
+    let rows = client
+        .query(
+            "SELECT * FROM (SELECT DISTINCT ON (symbol, stat_type, publisher_id) symbol, instrument_id, \
+                    publisher_id, ts_event, ts_recv, ts_ref, stat_type, stat, price, quantity, sequence, \
+                    stat_flags, deleted \
+                 FROM statistics ORDER BY symbol, stat_type, publisher_id, ts_event DESC, id DESC) latest \
+             WHERE NOT deleted",
+            &[],
+        )
+        .context("failed to load latest statistics")?;
+    Ok(rows
+        .iter()
+        .map(|row| StatisticRecord {
+            symbol: row.get(0),
+            instrument_id: row.get::<_, i64>(1) as u32,
+            publisher_id: row.get::<_, i32>(2) as u16,
+            ts_event: row.get(3),
+            ts_recv: row.get(4),
+            ts_ref: row.get(5),
+            stat_type: row.get::<_, i32>(6) as u16,
+            stat: row.get(7),
+            price: row.get(8),
+            quantity: row.get(9),
+            sequence: row.get::<_, i64>(10) as u32,
+            stat_flags: row.get::<_, i16>(11) as u8,
+            deleted: row.get(12),
+        })
+        .collect())
 }
 
-fn writer_loop(config: StorageConfig, rx: Receiver<SharedSnapshot>) -> Result<()> {
-    println!("storage_writer starting db_url={}", config.db_url);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
 
-    // Ensure database exists
-    if let Err(e) = ensure_database(config.db_url.as_ref()) {
-        eprintln!("storage_writer failed to ensure database: {}", e);
-        return Err(e);
+/// Rows of `orderbook_snapshots` to export. Bounds are inclusive `ts_event` nanoseconds.
+#[derive(Clone, Debug)]
+pub struct ExportRequest {
+    pub symbol: Option<String>,
+    pub from_ts: Option<i64>,
+    pub to_ts: Option<i64>,
+    pub format: ExportFormat,
+    /// CSV stream compression, or Parquet column compression (unset = Snappy).
+    pub codec: Option<Codec>,
+    pub output: PathBuf,
+}
+
+/// Streams matching snapshots from Postgres into `request.output`, calling `progress`
+/// with the running row count. CSV is the table as stored (depth as a JSON column);
+/// Parquet uses the same columns as the Parquet sink. Returns the number of rows written.
+pub fn export_snapshots(
+    db_url: &str,
+    request: &ExportRequest,
+    mut progress: impl FnMut(u64),
+) -> Result<u64> {
+    let mut client = Client::connect(db_url, NoTls)
+        .with_context(|| format!("failed to connect to postgres using {}", db_url))?;
+    // Bounds are inlined because COPY does not take parameters
+    let mut filters = Vec::new();
+    if let Some(symbol) = &request.symbol {
+        filters.push(format!("symbol = '{}'", symbol.replace('\'', "''")));
+    }
+    if let Some(from_ts) = request.from_ts {
+        filters.push(format!("ts_event >= {}", from_ts));
     }
-    println!("storage_writer database ensured");
+    if let Some(to_ts) = request.to_ts {
+        filters.push(format!("ts_event <= {}", to_ts));
+    }
+    let where_clause = if filters.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", filters.join(" AND "))
+    };
+    let query = format!(
+        "SELECT {} FROM orderbook_snapshots {} ORDER BY ts_event, id",
+        SNAPSHOT_COLUMNS, where_clause
+    );
+    let file = File::create(&request.output)
+        .with_context(|| format!("failed to create {}", request.output.display()))?;
 
-    // Connect to database
-    let mut client = match Client::connect(&config.db_url, NoTls) {
-        Ok(c) => {
-            println!("storage_writer connected to postgres");
-            c
+    match request.format {
+        ExportFormat::Csv => export_csv(
+            &mut client,
+            &query,
+            file,
+            request.codec.unwrap_or(Codec::NONE),
+            progress,
+        ),
+        ExportFormat::Parquet => {
+            let schema = Arc::new(parquet_schema());
+            let props = WriterProperties::builder()
+                .set_compression(parquet_compression(request.codec)?)
+                .build();
+            let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(props))
+                .context("failed to start parquet export writer")?;
+            let mut rows = client
+                .query_raw(query.as_str(), std::iter::empty::<i64>())
+                .context("failed to query snapshots for export")?;
+            let mut pending: Vec<SnapshotRecord> = Vec::with_capacity(EXPORT_BATCH_ROWS);
+            let mut written = 0u64;
+            while let Some(row) = rows.next().context("failed to read exported row")? {
+                pending.push(snapshot_from_row(&row));
+                if pending.len() == EXPORT_BATCH_ROWS {
+                    written += write_export_batch(&mut writer, &schema, &mut pending)?;
+                    progress(written);
+                }
+            }
+            written += write_export_batch(&mut writer, &schema, &mut pending)?;
+            progress(written);
+            writer
+                .close()
+                .context("failed to finalize parquet export")?;
+            Ok(written)
         }
-        Err(e) => {
-            eprintln!("storage_writer failed to connect to postgres: {}", e);
-            return Err(anyhow!(e).context(format!(
-                "failed to connect to postgres using {}",
-                &config.db_url
-            )));
+    }
+}
+
+const EXPORT_BATCH_ROWS: usize = 50_000;
+
+fn export_csv(
+    client: &mut Client,
+    query: &str,
+    file: File,
+    codec: Codec,
+    mut progress: impl FnMut(u64),
+) -> Result<u64> {
+    let mut reader = client
+        .copy_out(format!("COPY ({}) TO STDOUT WITH (FORMAT csv, HEADER)", query).as_str())
+        .context("failed to start COPY export")?;
+    let mut writer = codec
+        .encoder(BufWriter::new(file))
+        .context("failed to start CSV export encoder")?;
+    let mut buf = vec![0u8; 256 * 1024];
+    // Depth is compact JSON, so every newline ends a row
+    let mut lines = 0u64;
+    let mut reported = 0u64;
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .context("failed to read COPY export")?;
+        if n == 0 {
+            break;
         }
-    };
+        lines += buf[..n].iter().filter(|b| **b == b'\n').count() as u64;
+        writer
+            .write_all(&buf[..n])
+            .context("failed to write CSV export")?;
+        if lines - reported >= EXPORT_BATCH_ROWS as u64 {
+            reported = lines;
+            progress(lines.saturating_sub(1));
+        }
+    }
+    writer.try_finish().context("failed to flush CSV export")?;
+    let rows = lines.saturating_sub(1);
+    progress(rows);
+    Ok(rows)
+}
 
-    // Ensure schema
-    if let Err(e) = ensure_schema(&mut client) {
-        eprintln!("storage_writer failed to ensure schema: {}", e);
-        return Err(e);
+fn write_export_batch(
+    writer: &mut ArrowWriter<File>,
+    schema: &SchemaRef,
+    pending: &mut Vec<SnapshotRecord>,
+) -> Result<u64> {
+    if pending.is_empty() {
+        return Ok(0);
     }
-    println!("storage_writer schema ensured");
+    let rows: Vec<&SnapshotRecord> = pending.iter().collect();
+    let batch = snapshots_to_record_batch(schema.clone(), &rows)?;
+    writer
+        .write(&batch)
+        .context("failed to write parquet export batch")?;
+    let written = pending.len() as u64;
+    pending.clear();
+    Ok(written)
+}
 
-    // Drop indexes for bulk load
-    println!("storage_writer dropping indexes for bulk load");
-    if let Err(e) = drop_indexes(&mut client) {
-        eprintln!("storage_writer failed to drop indexes: {}", e);
-        return Err(e);
+#[derive(Deserialize)]
+struct StoredLevels {
+    bids: Vec<LevelEntry>,
+    asks: Vec<LevelEntry>,
+}
+
+fn snapshot_from_row(row: &postgres::Row) -> SnapshotRecord {
+    let best_bid = level_from_columns(row.get(3), row.get(4), row.get(5));
+    let best_ask = level_from_columns(row.get(6), row.get(7), row.get(8));
+    let ts_event: i64 = row.get(2);
+    let instrument_id: i64 = row.get(1);
+    let bid_levels: i32 = row.get(9);
+    let ask_levels: i32 = row.get(10);
+    let total_orders: i32 = row.get(11);
+    let seq: i64 = row.get(13);
+    // Rows stored before receive times were kept fall back to ts_event
+    let ts_recv: i64 = row.get::<_, Option<i64>>(14).unwrap_or(ts_event);
+    let levels: Option<StoredLevels> = row
+        .get::<_, Option<serde_json::Value>>(12)
+        .and_then(|value| serde_json::from_value(value).ok());
+    let (bids, asks) = match levels {
+        Some(levels) => (levels.bids, levels.asks),
+        None => (
+            best_bid.iter().cloned().collect(),
+            best_ask.iter().cloned().collect(),
+        ),
+    };
+    SnapshotRecord {
+        instrument_id: instrument_id as u32,
+        publisher_id: 0,
+        ts_event,
+        ts_recv,
+        built_at: 0,
+        payload: Snapshot {
+            symbol: row.get(0),
+            ts_ns: ts_event,
+            seq: seq as u64,
+            bids,
+            asks,
+            bbo: Bbo { best_bid, best_ask },
+            total_orders: total_orders as usize,
+            bid_levels: bid_levels as usize,
+            ask_levels: ask_levels as usize,
+            notional: None,
+            signals: None,
+            liquidity: None,
+            buckets: None,
+            implied: None,
+            // Staleness is not persisted
+            stale: false,
+        },
+        cache: EncodedCache::default(),
+    }
+}
+
+// The writer stores missing sides as zeros
+fn level_from_columns(price: i64, size: i32, count: i32) -> Option<LevelEntry> {
+    (size > 0).then_some(LevelEntry {
+        price,
+        size: size as u32,
+        count: count as u32,
+        est_hidden: None,
+    })
+}
+
+fn writer_loop(
+    config: &StorageConfig,
+    shard: usize,
+    stats: &ShardStats,
+    rx: &mut QueueReceiver<StorageItem>,
+    mut sink: Box<dyn SnapshotSink>,
+) -> Result<()> {
+    info!(sinks = %sink.name(), "storage writer started");
+    let journal = config.journal.as_deref();
+    if let Some(journal) = journal {
+        journal.writer_started(shard);
     }
-    println!("storage_writer indexes dropped");
+    let mut writer = BatchWriter {
+        stats,
+        latency: config.latency.as_deref(),
+        total_written: 0,
+        total_trades: 0,
+        total_bars: 0,
+    };
 
-    let mut buffer: Vec<SharedSnapshot> = Vec::with_capacity(config.batch_size);
+    let mut buffer = Buffer::with_capacity(config.batch_size);
+    let mut scheduler = FlushScheduler::new(config.flush_schedule, config.flush_interval);
     let mut last_flush = Instant::now();
-    let mut total_written = 0usize;
-    let mut failed_flushes = 0usize;
 
     loop {
         let recv_result = if buffer.is_empty() {
@@ -119,158 +3629,330 @@ fn writer_loop(config: StorageConfig, rx: Receiver<SharedSnapshot>) -> Result<()
         };
 
         match recv_result {
-            Ok(snapshot) => {
-                buffer.push(snapshot);
+            Ok(item) => {
+                if let (Some(journal), StorageItem::Snapshot(_)) = (journal, &item) {
+                    journal.received(shard);
+                }
+                if scheduler.starts_new_bucket(item.ts_event()) && !buffer.is_empty() {
+                    writer.write(sink.as_mut(), &mut buffer, "data bucket")?;
+                    last_flush = Instant::now();
+                }
+                buffer.push(item);
                 if buffer.len() >= config.batch_size {
-                    match flush_copy(&mut client, &mut buffer) {
-                        Ok(_) => {
-                            total_written += buffer.len();
-                            println!(
-                                "storage_writer flushed batch size={} total={}",
-                                buffer.len(),
-                                total_written
-                            );
-                            buffer.clear();
-                            last_flush = Instant::now();
-                        }
-                        Err(e) => {
-                            failed_flushes += 1;
-                            eprintln!(
-                                "storage_writer flush failed attempt={} error={} buffer_size={}",
-                                failed_flushes,
-                                e,
-                                buffer.len()
-                            );
-                            // Try to reconnect if connection lost
-                            if is_connection_error(&e) {
-                                println!("storage_writer attempting reconnect...");
-                                match Client::connect(&config.db_url, NoTls) {
-                                    Ok(new_client) => {
-                                        client = new_client;
-                                        println!("storage_writer reconnected successfully");
-                                        // Retry flush once
-                                        if let Err(e2) = flush_copy(&mut client, &mut buffer) {
-                                            eprintln!("storage_writer retry flush failed: {}", e2);
-                                            return Err(e2);
-                                        } else {
-                                            total_written += buffer.len();
-                                            println!(
-                                                "storage_writer retry flush succeeded size={}",
-                                                buffer.len()
-                                            );
-                                            buffer.clear();
-                                            last_flush = Instant::now();
-                                        }
-                                    }
-                                    Err(e2) => {
-                                        eprintln!("storage_writer reconnect failed: {}", e2);
-                                        return Err(
-                                            anyhow!(e2).context("failed to reconnect to postgres")
-                                        );
-                                    }
-                                }
-                            } else {
-                                return Err(e);
-                            }
-                        }
-                    }
+                    writer.write(sink.as_mut(), &mut buffer, "batch")?;
+                    last_flush = Instant::now();
                 }
             }
             Err(RecvTimeoutError::Timeout) => {
                 if !buffer.is_empty() {
-                    match flush_copy(&mut client, &mut buffer) {
-                        Ok(_) => {
-                            total_written += buffer.len();
-                            println!(
-                                "storage_writer flushed timeout batch size={} total={}",
-                                buffer.len(),
-                                total_written
-                            );
-                            buffer.clear();
-                            last_flush = Instant::now();
-                        }
-                        Err(e) => {
-                            eprintln!("storage_writer timeout flush failed: {}", e);
-                            return Err(e);
-                        }
-                    }
+                    writer.write(sink.as_mut(), &mut buffer, "timeout batch")?;
+                    last_flush = Instant::now();
+                }
+                sink.flush()?;
+                if let Some(journal) = journal {
+                    journal.flushed(shard)?;
                 }
             }
             Err(RecvTimeoutError::Disconnected) => {
-                println!(
-                    "storage_writer channel disconnected, flushing remaining buffer_size={}",
-                    buffer.len()
+                info!(
+                    buffer_size = buffer.len(),
+                    "channel disconnected, flushing remaining"
                 );
                 if !buffer.is_empty() {
-                    match flush_copy(&mut client, &mut buffer) {
-                        Ok(_) => {
-                            total_written += buffer.len();
-                            println!("storage_writer final flush succeeded size={}", buffer.len());
-                            buffer.clear();
-                        }
-                        Err(e) => {
-                            eprintln!("storage_writer final flush failed: {}", e);
-                            return Err(e);
-                        }
-                    }
+                    writer.write(sink.as_mut(), &mut buffer, "final batch")?;
                 }
                 break;
             }
         }
 
-        if !buffer.is_empty() && last_flush.elapsed() >= config.flush_interval {
-            match flush_copy(&mut client, &mut buffer) {
-                Ok(_) => {
-                    total_written += buffer.len();
-                    buffer.clear();
-                    last_flush = Instant::now();
-                }
-                Err(e) => {
-                    eprintln!("storage_writer interval flush failed: {}", e);
-                    return Err(e);
-                }
-            }
+        if !buffer.is_empty() && scheduler.interval_due(last_flush) {
+            writer.write(sink.as_mut(), &mut buffer, "interval batch")?;
+            last_flush = Instant::now();
+        }
+        // Flush early once a closed journal segment is all queued to the sinks, so it
+        // can be deleted without waiting for the queue to go idle
+        if let Some(journal) = journal
+            && buffer.is_empty()
+            && journal.wants_flush(shard)
+        {
+            sink.flush()?;
+            journal.flushed(shard)?;
         }
     }
 
-    println!(
-        "storage_writer recreating indexes after {} snapshots (failed_flushes={})",
-        total_written, failed_flushes
+    info!(
+        snapshots = writer.total_written,
+        trades = writer.total_trades,
+        bars = writer.total_bars,
+        "closing sinks"
     );
-    if let Err(e) = recreate_indexes(&mut client) {
-        eprintln!("storage_writer failed to recreate indexes: {}", e);
-        return Err(e);
+    sink.close()?;
+    if let Some(journal) = journal {
+        journal.flushed(shard)?;
     }
-    println!("storage_writer indexes recreated successfully");
-
     Ok(())
 }
 
-fn is_connection_error(e: &anyhow::Error) -> bool {
-    e.to_string().contains("connection")
-        || e.to_string().contains("Connection")
-        || e.to_string().contains("broken pipe")
-        || e.to_string().contains("reset by peer")
+/// Snapshots, trades, bars, book stats and venue records waiting for the next batch
+/// write.
+struct Buffer {
+    snapshots: Vec<SharedSnapshot>,
+    trades: Vec<SharedTrade>,
+    bars: Vec<SharedBar>,
+    stats: Vec<SharedStats>,
+    imbalances: Vec<SharedImbalance>,
+    statistics: Vec<SharedStatistic>,
+}
+
+impl Buffer {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            snapshots: Vec::with_capacity(capacity),
+            trades: Vec::new(),
+            bars: Vec::new(),
+            stats: Vec::new(),
+            imbalances: Vec::new(),
+            statistics: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, item: StorageItem) {
+        match item {
+            StorageItem::Snapshot(snapshot) => self.snapshots.push(snapshot),
+            StorageItem::Trade(trade) => self.trades.push(trade),
+            StorageItem::Bar(bar) => self.bars.push(bar),
+            StorageItem::Stats(stats) => self.stats.push(stats),
+            StorageItem::Imbalance(imbalance) => self.imbalances.push(imbalance),
+            StorageItem::Statistic(statistic) => self.statistics.push(statistic),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.snapshots.len()
+            + self.trades.len()
+            + self.bars.len()
+            + self.stats.len()
+            + self.imbalances.len()
+            + self.statistics.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn write_to(&self, sink: &mut dyn SnapshotSink) -> Result<()> {
+        if !self.trades.is_empty() {
+            sink.write_trades(&self.trades)?;
+        }
+        if !self.bars.is_empty() {
+            sink.write_bars(&self.bars)?;
+        }
+        if !self.stats.is_empty() {
+            sink.write_stats(&self.stats)?;
+        }
+        if !self.imbalances.is_empty() {
+            sink.write_imbalances(&self.imbalances)?;
+        }
+        if !self.statistics.is_empty() {
+            sink.write_statistics(&self.statistics)?;
+        }
+        if !self.snapshots.is_empty() {
+            sink.write_batch(&self.snapshots)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes buffered batches to a sink, logging and counting each one.
+struct BatchWriter<'a> {
+    stats: &'a ShardStats,
+    latency: Option<&'a PublishLatency>,
+    total_written: usize,
+    total_trades: usize,
+    total_bars: usize,
+}
+
+impl BatchWriter<'_> {
+    fn write(
+        &mut self,
+        sink: &mut dyn SnapshotSink,
+        buffer: &mut Buffer,
+        reason: &str,
+    ) -> Result<()> {
+        let start = Instant::now();
+        sink.before_batch();
+        if let Err(e) = buffer.write_to(sink) {
+            error!(reason, error = format!("{:#}", e), "write failed");
+            return Err(e);
+        }
+        self.stats
+            .write_us
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        self.stats
+            .written
+            .fetch_add(buffer.snapshots.len() as u64, Ordering::Relaxed);
+        self.stats
+            .trades
+            .fetch_add(buffer.trades.len() as u64, Ordering::Relaxed);
+        self.stats
+            .bars
+            .fetch_add(buffer.bars.len() as u64, Ordering::Relaxed);
+        self.stats.batches.fetch_add(1, Ordering::Relaxed);
+        if let Some(newest) = buffer.snapshots.iter().map(|s| s.ts_event).max() {
+            self.stats
+                .newest_ts_event
+                .fetch_max(newest, Ordering::Relaxed);
+        }
+        // Sinks that commit asynchronously record it themselves once committed
+        if let Some(latency) = self.latency
+            && !sink.records_stored_latency()
+        {
+            record_stored(latency, &buffer.snapshots);
+        }
+        self.total_written += buffer.snapshots.len();
+        self.total_trades += buffer.trades.len();
+        self.total_bars += buffer.bars.len();
+        info!(
+            reason,
+            size = buffer.snapshots.len(),
+            trades = buffer.trades.len(),
+            total = self.total_written,
+            "flushed"
+        );
+        buffer.snapshots.clear();
+        buffer.trades.clear();
+        buffer.bars.clear();
+        buffer.stats.clear();
+        buffer.imbalances.clear();
+        buffer.statistics.clear();
+        Ok(())
+    }
+}
+
+fn record_stored(latency: &PublishLatency, snapshots: &[SharedSnapshot]) {
+    for snapshot in snapshots {
+        if let Some(ns) = latency.since_origin(snapshot) {
+            latency.stored.record(ns);
+        }
+    }
+}
+
+/// What a failed storage operation says about the server, judged from the driver's
+/// error kinds and SQLSTATE codes rather than message text, which varies with the
+/// server's locale and the driver version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageError {
+    /// The connection was closed, reset or could not be opened, or the server reported
+    /// a `connection_exception` (class 08).
+    Connection,
+    /// The server is shutting down, restarting or not yet accepting connections
+    /// (`admin_shutdown`, `crash_shutdown`, `cannot_connect_now`).
+    Unavailable,
+    /// The operation itself failed on a working connection, e.g. a missing table, bad
+    /// credentials or a rejected row; trying it again fails the same way.
+    Rejected,
+}
+
+impl StorageError {
+    /// Classifies `e` by the first Postgres or I/O error in its chain. Errors of neither
+    /// kind, such as a failed spill file, count as `Rejected`.
+    pub fn classify(e: &anyhow::Error) -> Self {
+        for cause in e.chain() {
+            if let Some(pg) = cause.downcast_ref::<tokio_postgres::Error>() {
+                return Self::of_postgres(pg);
+            }
+            if cause.is::<std::io::Error>() {
+                return StorageError::Connection;
+            }
+        }
+        StorageError::Rejected
+    }
+
+    fn of_postgres(e: &tokio_postgres::Error) -> Self {
+        if e.is_closed() {
+            return StorageError::Connection;
+        }
+        match e.code() {
+            Some(code) if code.code().starts_with("08") => StorageError::Connection,
+            Some(code)
+                if *code == SqlState::ADMIN_SHUTDOWN
+                    || *code == SqlState::CRASH_SHUTDOWN
+                    || *code == SqlState::CANNOT_CONNECT_NOW =>
+            {
+                StorageError::Unavailable
+            }
+            Some(_) => StorageError::Rejected,
+            // No SQLSTATE: an I/O source means the socket failed; config and protocol
+            // errors stay the same on a new connection
+            None if std::error::Error::source(e)
+                .is_some_and(|source| source.is::<std::io::Error>()) =>
+            {
+                StorageError::Connection
+            }
+            None => StorageError::Rejected,
+        }
+    }
+
+    /// Whether trying again on a new connection can succeed.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, StorageError::Connection | StorageError::Unavailable)
+    }
+}
+
+/// Column types of `SNAPSHOT_COPY`, in order.
+const SNAPSHOT_COPY_TYPES: [Type; 16] = [
+    Type::VARCHAR,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT4,
+    Type::INT4,
+    Type::INT8,
+    Type::INT4,
+    Type::INT4,
+    Type::INT4,
+    Type::INT4,
+    Type::INT4,
+    Type::JSONB,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+];
+
+const SNAPSHOT_COPY: &str = "COPY orderbook_snapshots (symbol, instrument_id, ts_event, best_bid_price, best_bid_size, best_bid_count, best_ask_price, best_ask_size, best_ask_count, bid_levels, ask_levels, total_orders, levels, seq, run_id, ts_recv) FROM STDIN WITH (FORMAT binary)";
+
+#[derive(Debug, Serialize)]
+struct LevelsRef<'a> {
+    bids: &'a [LevelEntry],
+    asks: &'a [LevelEntry],
 }
 
-fn flush_copy(client: &mut Client, buffer: &mut Vec<SharedSnapshot>) -> Result<()> {
+async fn flush_copy(
+    client: &mut tokio_postgres::Client,
+    buffer: &[SharedSnapshot],
+    run_id: Option<i64>,
+    latest: bool,
+) -> Result<()> {
     if buffer.is_empty() {
         return Ok(());
     }
 
     let batch_size = buffer.len();
 
-    let mut txn = client.transaction().with_context(|| {
+    let txn = client.transaction().await.with_context(|| {
         format!(
             "failed to start COPY transaction for {} snapshots",
             batch_size
         )
     })?;
 
-    let copy_stmt = "COPY orderbook_snapshots (symbol, ts_event, best_bid_price, best_bid_size, best_bid_count, best_ask_price, best_ask_size, best_ask_count, bid_levels, ask_levels, total_orders) FROM STDIN WITH (FORMAT csv)";
-    let mut writer = txn
-        .copy_in(copy_stmt)
+    let writer = txn
+        .copy_in(SNAPSHOT_COPY)
+        .await
         .with_context(|| format!("failed to start COPY for {} snapshots", batch_size))?;
+    // Binary COPY skips formatting and server-side parsing of every column
+    let writer = BinaryCopyInWriter::new(writer, &SNAPSHOT_COPY_TYPES);
+    let mut writer = pin!(writer);
 
     for (idx, snapshot) in buffer.iter().enumerate() {
         let payload = &snapshot.payload;
@@ -291,44 +3973,476 @@ fn flush_copy(client: &mut Client, buffer: &mut Vec<SharedSnapshot>) -> Result<(
             .map(|a| (a.price, a.size as i32, a.count as i32))
             .unwrap_or((0, 0, 0));
 
-        let row = format!(
-            "{},{},{},{},{},{},{},{},{},{},{}\n",
-            escape_csv(&payload.symbol),
-            snapshot.ts_event,
-            best_bid_price,
-            best_bid_size,
-            best_bid_count,
-            best_ask_price,
-            best_ask_size,
-            best_ask_count,
-            payload.bid_levels,
-            payload.ask_levels,
-            payload.total_orders
-        );
+        let levels = Json(LevelsRef {
+            bids: &payload.bids,
+            asks: &payload.asks,
+        });
 
-        writer.write_all(row.as_bytes()).with_context(|| {
-            format!(
-                "failed to write COPY row idx={} instrument_id={} ts={} size={}",
-                idx,
-                snapshot.instrument_id,
-                snapshot.ts_event,
-                row.len()
-            )
-        })?;
+        writer
+            .as_mut()
+            .write(&[
+                &payload.symbol,
+                &(snapshot.instrument_id as i64),
+                &snapshot.ts_event,
+                &best_bid_price,
+                &best_bid_size,
+                &best_bid_count,
+                &best_ask_price,
+                &best_ask_size,
+                &best_ask_count,
+                &(payload.bid_levels as i32),
+                &(payload.ask_levels as i32),
+                &(payload.total_orders as i32),
+                &levels,
+                &(payload.seq as i64),
+                &run_id,
+                &snapshot.ts_recv,
+            ])
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to write COPY row idx={} instrument_id={} ts={}",
+                    idx, snapshot.instrument_id, snapshot.ts_event
+                )
+            })?;
     }
 
     writer
+        .as_mut()
         .finish()
+        .await
         .with_context(|| format!("failed to finish COPY for {} snapshots", batch_size))?;
+    if latest {
+        upsert_latest(&txn, buffer, run_id).await?;
+    }
     txn.commit()
+        .await
         .with_context(|| format!("failed to commit COPY batch of {} snapshots", batch_size))?;
 
     Ok(())
 }
 
-fn escape_csv(s: &str) -> String {
-    // CSV escape: wrap in quotes and double internal quotes
-    format!("\"{}\"", s.replace('"', "\"\""))
+/// A row of `orderbook_latest`, passed to `LATEST_UPSERT` as JSON.
+#[derive(Debug, Serialize)]
+struct LatestRow<'a> {
+    symbol: &'a str,
+    publisher_id: u16,
+    instrument_id: u32,
+    ts_event: i64,
+    ts_recv: i64,
+    seq: u64,
+    best_bid_price: i64,
+    best_bid_size: u32,
+    best_bid_count: u32,
+    best_ask_price: i64,
+    best_ask_size: u32,
+    best_ask_count: u32,
+    bid_levels: usize,
+    ask_levels: usize,
+    total_orders: usize,
+    levels: LevelsRef<'a>,
+    run_id: Option<i64>,
+}
+
+/// Upserts every row in one statement. A stored row is only replaced by one at least as
+/// new, so replayed spill files cannot move the latest state backwards.
+const LATEST_UPSERT: &str = "INSERT INTO orderbook_latest (symbol, publisher_id, instrument_id, ts_event, ts_recv, seq, best_bid_price, best_bid_size, best_bid_count, best_ask_price, best_ask_size, best_ask_count, bid_levels, ask_levels, total_orders, levels, run_id) \
+    SELECT symbol, publisher_id, instrument_id, ts_event, ts_recv, seq, best_bid_price, best_bid_size, best_bid_count, best_ask_price, best_ask_size, best_ask_count, bid_levels, ask_levels, total_orders, levels, run_id \
+    FROM jsonb_to_recordset($1::jsonb) AS r(symbol VARCHAR, publisher_id INTEGER, instrument_id BIGINT, ts_event BIGINT, ts_recv BIGINT, seq BIGINT, best_bid_price BIGINT, best_bid_size INTEGER, best_bid_count INTEGER, best_ask_price BIGINT, best_ask_size INTEGER, best_ask_count INTEGER, bid_levels INTEGER, ask_levels INTEGER, total_orders INTEGER, levels JSONB, run_id BIGINT) \
+    ON CONFLICT (symbol, publisher_id) DO UPDATE SET instrument_id = EXCLUDED.instrument_id, ts_event = EXCLUDED.ts_event, ts_recv = EXCLUDED.ts_recv, seq = EXCLUDED.seq, \
+    best_bid_price = EXCLUDED.best_bid_price, best_bid_size = EXCLUDED.best_bid_size, best_bid_count = EXCLUDED.best_bid_count, \
+    best_ask_price = EXCLUDED.best_ask_price, best_ask_size = EXCLUDED.best_ask_size, best_ask_count = EXCLUDED.best_ask_count, \
+    bid_levels = EXCLUDED.bid_levels, ask_levels = EXCLUDED.ask_levels, total_orders = EXCLUDED.total_orders, levels = EXCLUDED.levels, run_id = EXCLUDED.run_id, updated_at = NOW() \
+    WHERE orderbook_latest.ts_event <= EXCLUDED.ts_event";
+
+/// Brings `orderbook_latest` up to the newest snapshot of each book in `buffer`, in
+/// the transaction that copies them into the history.
+async fn upsert_latest(
+    txn: &tokio_postgres::Transaction<'_>,
+    buffer: &[SharedSnapshot],
+    run_id: Option<i64>,
+) -> Result<()> {
+    // One row per key: a statement may not update the same row twice
+    let mut newest: HashMap<(&str, u16), &SnapshotRecord> = HashMap::new();
+    for snapshot in buffer {
+        let key = (snapshot.payload.symbol.as_str(), snapshot.publisher_id);
+        let kept = newest.entry(key).or_insert(snapshot);
+        if snapshot.ts_event >= kept.ts_event {
+            *kept = snapshot;
+        }
+    }
+    let rows: Vec<LatestRow> = newest
+        .into_values()
+        .map(|snapshot| {
+            let payload = &snapshot.payload;
+            let bid = payload.bbo.best_bid.as_ref();
+            let ask = payload.bbo.best_ask.as_ref();
+            LatestRow {
+                symbol: &payload.symbol,
+                publisher_id: snapshot.publisher_id,
+                instrument_id: snapshot.instrument_id,
+                ts_event: snapshot.ts_event,
+                ts_recv: snapshot.ts_recv,
+                seq: payload.seq,
+                best_bid_price: bid.map_or(0, |b| b.price),
+                best_bid_size: bid.map_or(0, |b| b.size),
+                best_bid_count: bid.map_or(0, |b| b.count),
+                best_ask_price: ask.map_or(0, |a| a.price),
+                best_ask_size: ask.map_or(0, |a| a.size),
+                best_ask_count: ask.map_or(0, |a| a.count),
+                bid_levels: payload.bid_levels,
+                ask_levels: payload.ask_levels,
+                total_orders: payload.total_orders,
+                levels: LevelsRef {
+                    bids: &payload.bids,
+                    asks: &payload.asks,
+                },
+                run_id,
+            }
+        })
+        .collect();
+    txn.execute(LATEST_UPSERT, &[&Json(&rows)])
+        .await
+        .with_context(|| format!("failed to upsert {} rows of orderbook_latest", rows.len()))?;
+    Ok(())
+}
+
+/// Column types of `TRADES_COPY`, in order.
+const TRADES_COPY_TYPES: [Type; 10] = [
+    Type::VARCHAR,
+    Type::INT8,
+    Type::INT4,
+    Type::INT8,
+    Type::INT8,
+    Type::INT4,
+    Type::BPCHAR,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+];
+
+const TRADES_COPY: &str = "COPY trades (symbol, instrument_id, publisher_id, ts_event, price, size, side, order_id, sequence, ts_recv) FROM STDIN WITH (FORMAT binary)";
+
+async fn flush_trades(client: &mut tokio_postgres::Client, trades: &[SharedTrade]) -> Result<()> {
+    let txn = client.transaction().await.with_context(|| {
+        format!(
+            "failed to start COPY transaction for {} trades",
+            trades.len()
+        )
+    })?;
+    let writer = txn
+        .copy_in(TRADES_COPY)
+        .await
+        .with_context(|| format!("failed to start COPY for {} trades", trades.len()))?;
+    let writer = BinaryCopyInWriter::new(writer, &TRADES_COPY_TYPES);
+    let mut writer = pin!(writer);
+    for trade in trades {
+        writer
+            .as_mut()
+            .write(&[
+                &trade.symbol,
+                &(trade.instrument_id as i64),
+                &(trade.publisher_id as i32),
+                &trade.ts_event,
+                &trade.price,
+                &(trade.size as i32),
+                &trade.side.to_string(),
+                &(trade.order_id as i64),
+                &(trade.sequence as i64),
+                &trade.ts_recv,
+            ])
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to write COPY trade instrument_id={} ts={}",
+                    trade.instrument_id, trade.ts_event
+                )
+            })?;
+    }
+    writer
+        .as_mut()
+        .finish()
+        .await
+        .with_context(|| format!("failed to finish COPY for {} trades", trades.len()))?;
+    txn.commit()
+        .await
+        .with_context(|| format!("failed to commit COPY batch of {} trades", trades.len()))?;
+    Ok(())
+}
+
+/// Column types of `BARS_COPY`, in order.
+const BARS_COPY_TYPES: [Type; 12] = [
+    Type::VARCHAR,
+    Type::INT8,
+    Type::VARCHAR,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+];
+
+const BARS_COPY: &str = "COPY bars (symbol, instrument_id, interval, start_ts, end_ts, open, high, low, close, volume, trades, vwap) FROM STDIN WITH (FORMAT binary)";
+
+async fn flush_bars(client: &mut tokio_postgres::Client, bars: &[SharedBar]) -> Result<()> {
+    let txn = client
+        .transaction()
+        .await
+        .with_context(|| format!("failed to start COPY transaction for {} bars", bars.len()))?;
+    let writer = txn
+        .copy_in(BARS_COPY)
+        .await
+        .with_context(|| format!("failed to start COPY for {} bars", bars.len()))?;
+    let writer = BinaryCopyInWriter::new(writer, &BARS_COPY_TYPES);
+    let mut writer = pin!(writer);
+    for bar in bars {
+        writer
+            .as_mut()
+            .write(&[
+                &bar.symbol,
+                &(bar.instrument_id as i64),
+                &bar.interval,
+                &bar.start_ts,
+                &bar.end_ts,
+                &bar.open,
+                &bar.high,
+                &bar.low,
+                &bar.close,
+                &(bar.volume as i64),
+                &(bar.trades as i64),
+                &bar.vwap,
+            ])
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to write COPY bar symbol={} start={}",
+                    bar.symbol, bar.start_ts
+                )
+            })?;
+    }
+    writer
+        .as_mut()
+        .finish()
+        .await
+        .with_context(|| format!("failed to finish COPY for {} bars", bars.len()))?;
+    txn.commit()
+        .await
+        .with_context(|| format!("failed to commit COPY batch of {} bars", bars.len()))?;
+    Ok(())
+}
+
+const BOOK_STATS_COPY: &str = "COPY book_stats (symbol, instrument_id, ts_event, window_ms, snapshots, avg_spread, avg_bid_depth, avg_ask_depth, realized_vol, updates_per_sec) FROM STDIN WITH (FORMAT binary)";
+
+const BOOK_STATS_COPY_TYPES: [Type; 10] = [
+    Type::VARCHAR,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::FLOAT8,
+    Type::FLOAT8,
+    Type::FLOAT8,
+    Type::FLOAT8,
+];
+
+async fn flush_stats(client: &mut tokio_postgres::Client, stats: &[SharedStats]) -> Result<()> {
+    let txn = client.transaction().await.with_context(|| {
+        format!(
+            "failed to start COPY transaction for {} book stats",
+            stats.len()
+        )
+    })?;
+    let writer = txn
+        .copy_in(BOOK_STATS_COPY)
+        .await
+        .with_context(|| format!("failed to start COPY for {} book stats", stats.len()))?;
+    let writer = BinaryCopyInWriter::new(writer, &BOOK_STATS_COPY_TYPES);
+    let mut writer = pin!(writer);
+    for row in stats {
+        writer
+            .as_mut()
+            .write(&[
+                &row.symbol,
+                &(row.instrument_id as i64),
+                &row.ts_event,
+                &row.window_ms,
+                &(row.snapshots as i64),
+                &row.avg_spread,
+                &row.avg_bid_depth,
+                &row.avg_ask_depth,
+                &row.realized_vol,
+                &row.updates_per_sec,
+            ])
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to write COPY book stats symbol={} ts={}",
+                    row.symbol, row.ts_event
+                )
+            })?;
+    }
+    writer
+        .as_mut()
+        .finish()
+        .await
+        .with_context(|| format!("failed to finish COPY for {} book stats", stats.len()))?;
+    txn.commit()
+        .await
+        .with_context(|| format!("failed to commit COPY batch of {} book stats", stats.len()))?;
+    Ok(())
+}
+
+const IMBALANCES_COPY: &str = "COPY imbalances (symbol, instrument_id, publisher_id, ts_event, ts_recv, ref_price, auction_time, cont_book_clr_price, auct_interest_clr_price, paired_qty, total_imbalance_qty, side, auction_type) FROM STDIN WITH (FORMAT binary)";
+
+const IMBALANCES_COPY_TYPES: [Type; 13] = [
+    Type::VARCHAR,
+    Type::INT8,
+    Type::INT4,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::BPCHAR,
+    Type::BPCHAR,
+];
+
+async fn flush_imbalances(
+    client: &mut tokio_postgres::Client,
+    imbalances: &[SharedImbalance],
+) -> Result<()> {
+    let txn = client.transaction().await.with_context(|| {
+        format!(
+            "failed to start COPY transaction for {} imbalances",
+            imbalances.len()
+        )
+    })?;
+    let writer = txn
+        .copy_in(IMBALANCES_COPY)
+        .await
+        .with_context(|| format!("failed to start COPY for {} imbalances", imbalances.len()))?;
+    let writer = BinaryCopyInWriter::new(writer, &IMBALANCES_COPY_TYPES);
+    let mut writer = pin!(writer);
+    for row in imbalances {
+        writer
+            .as_mut()
+            .write(&[
+                &row.symbol,
+                &(row.instrument_id as i64),
+                &(row.publisher_id as i32),
+                &row.ts_event,
+                &row.ts_recv,
+                &row.ref_price,
+                &row.auction_time,
+                &row.cont_book_clr_price,
+                &row.auct_interest_clr_price,
+                &(row.paired_qty as i64),
+                &(row.total_imbalance_qty as i64),
+                &row.side.to_string(),
+                &row.auction_type.to_string(),
+            ])
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to write COPY imbalance symbol={} ts={}",
+                    row.symbol, row.ts_event
+                )
+            })?;
+    }
+    writer
+        .as_mut()
+        .finish()
+        .await
+        .with_context(|| format!("failed to finish COPY for {} imbalances", imbalances.len()))?;
+    txn.commit().await.with_context(|| {
+        format!(
+            "failed to commit COPY batch of {} imbalances",
+            imbalances.len()
+        )
+    })?;
+    Ok(())
+}
+
+const STATISTICS_COPY: &str = "COPY statistics (symbol, instrument_id, publisher_id, ts_event, ts_recv, ts_ref, stat_type, stat, price, quantity, sequence, stat_flags, deleted) FROM STDIN WITH (FORMAT binary)";
+
+const STATISTICS_COPY_TYPES: [Type; 13] = [
+    Type::VARCHAR,
+    Type::INT8,
+    Type::INT4,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT4,
+    Type::VARCHAR,
+    Type::INT8,
+    Type::INT8,
+    Type::INT8,
+    Type::INT2,
+    Type::BOOL,
+];
+
+async fn flush_statistics(
+    client: &mut tokio_postgres::Client,
+    statistics: &[SharedStatistic],
+) -> Result<()> {
+    let txn = client.transaction().await.with_context(|| {
+        format!(
+            "failed to start COPY transaction for {} statistics",
+            statistics.len()
+        )
+    })?;
+    let writer = txn
+        .copy_in(STATISTICS_COPY)
+        .await
+        .with_context(|| format!("failed to start COPY for {} statistics", statistics.len()))?;
+    let writer = BinaryCopyInWriter::new(writer, &STATISTICS_COPY_TYPES);
+    let mut writer = pin!(writer);
+    for row in statistics {
+        writer
+            .as_mut()
+            .write(&[
+                &row.symbol,
+                &(row.instrument_id as i64),
+                &(row.publisher_id as i32),
+                &row.ts_event,
+                &row.ts_recv,
+                &row.ts_ref,
+                &(row.stat_type as i32),
+                &row.stat,
+                &row.price,
+                &row.quantity,
+                &(row.sequence as i64),
+                &(row.stat_flags as i16),
+                &row.deleted,
+            ])
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to write COPY statistic symbol={} ts={}",
+                    row.symbol, row.ts_event
+                )
+            })?;
+    }
+    writer
+        .as_mut()
+        .finish()
+        .await
+        .with_context(|| format!("failed to finish COPY for {} statistics", statistics.len()))?;
+    txn.commit().await.with_context(|| {
+        format!(
+            "failed to commit COPY batch of {} statistics",
+            statistics.len()
+        )
+    })?;
+    Ok(())
 }
 
 fn drop_indexes(client: &mut Client) -> Result<()> {
@@ -339,12 +4453,12 @@ DROP INDEX IF EXISTS idx_orderbook_snapshots_symbol;
     client
         .batch_execute(drop_sql)
         .context("failed to drop indexes (ts, symbol)")?;
-    println!("storage_writer dropped 2 indexes successfully");
+    info!(count = 2, "indexes dropped");
     Ok(())
 }
 
 fn recreate_indexes(client: &mut Client) -> Result<()> {
-    println!("storage_writer recreating indexes (this may take time)...");
+    info!("recreating indexes, this may take time");
     let start = Instant::now();
 
     let create_sql = r#"
@@ -358,23 +4472,133 @@ CREATE INDEX IF NOT EXISTS idx_orderbook_snapshots_symbol
         .context("failed to recreate indexes (ts, symbol)")?;
 
     let elapsed = start.elapsed();
-    println!(
-        "storage_writer recreated 2 indexes successfully in {:.2}s",
-        elapsed.as_secs_f64()
-    );
+    info!(count = 2, secs = elapsed.as_secs_f64(), "indexes recreated");
     Ok(())
 }
 
-fn ensure_schema(client: &mut Client) -> Result<()> {
+fn ensure_schema(client: &mut Client, timescale: Option<&TimescaleConfig>) -> Result<()> {
     client.batch_execute(TABLE_DDL).context(
         "failed to ensure orderbook_snapshots schema (CREATE TABLE and CREATE INDEX commands)",
     )?;
-    println!("storage_writer schema ensured (table and indexes exist)");
+    if let Some(timescale) = timescale {
+        ensure_hypertable(client, timescale)?;
+    }
+    client
+        .batch_execute(LATEST_DDL)
+        .context("failed to ensure orderbook_latest schema")?;
+    client
+        .batch_execute(TRADES_DDL)
+        .context("failed to ensure trades schema")?;
+    client
+        .batch_execute(BARS_DDL)
+        .context("failed to ensure bars schema")?;
+    client
+        .batch_execute(BOOK_STATS_DDL)
+        .context("failed to ensure book_stats schema")?;
+    client
+        .batch_execute(IMBALANCES_DDL)
+        .context("failed to ensure imbalances schema")?;
+    client
+        .batch_execute(STATISTICS_DDL)
+        .context("failed to ensure statistics schema")?;
+    client
+        .batch_execute(RUNS_DDL)
+        .context("failed to ensure ingest_runs schema")?;
+    info!("schema ensured, table and indexes exist");
+    Ok(())
+}
+
+/// Converts `orderbook_snapshots` to a hypertable on first use, then applies the chunk
+/// interval and compression policy so config changes take effect on later runs. New
+/// intervals only affect chunks created afterwards.
+fn ensure_hypertable(client: &mut Client, timescale: &TimescaleConfig) -> Result<()> {
+    client
+        .batch_execute("CREATE EXTENSION IF NOT EXISTS timescaledb")
+        .context("failed to enable the timescaledb extension (is TimescaleDB installed?)")?;
+    let existing = client
+        .query_opt(
+            "SELECT compression_enabled FROM timescaledb_information.hypertables \
+             WHERE hypertable_name = 'orderbook_snapshots'",
+            &[],
+        )
+        .context("failed to look up orderbook_snapshots in timescaledb_information")?;
+
+    let compression_enabled = match existing {
+        Some(row) => {
+            client
+                .execute(
+                    "SELECT set_chunk_time_interval('orderbook_snapshots', $1::BIGINT)",
+                    &[&timescale.chunk_interval_ns],
+                )
+                .context("failed to set hypertable chunk interval")?;
+            row.get::<_, bool>(0)
+        }
+        None => {
+            info!(
+                chunk_interval_ns = timescale.chunk_interval_ns,
+                "converting orderbook_snapshots to a hypertable"
+            );
+            let mut txn = client
+                .transaction()
+                .context("failed to start hypertable conversion")?;
+            // Unique constraints on a hypertable must include the partitioning column
+            txn.batch_execute(
+                r#"
+ALTER TABLE orderbook_snapshots DROP CONSTRAINT IF EXISTS orderbook_snapshots_pkey;
+ALTER TABLE orderbook_snapshots ADD PRIMARY KEY (id, ts_event);
+"#,
+            )
+            .context("failed to rebuild orderbook_snapshots primary key on (id, ts_event)")?;
+            txn.execute(
+                "SELECT create_hypertable('orderbook_snapshots', 'ts_event', \
+                 chunk_time_interval => $1::BIGINT, create_default_indexes => FALSE, \
+                 migrate_data => TRUE)",
+                &[&timescale.chunk_interval_ns],
+            )
+            .context("failed to create orderbook_snapshots hypertable")?;
+            txn.commit()
+                .context("failed to commit hypertable conversion")?;
+            false
+        }
+    };
+
+    let Some(compress_after_ns) = timescale.compress_after_ns else {
+        return Ok(());
+    };
+    // Policies on integer time need a "now" in the same unit as ts_event
+    client
+        .batch_execute(
+            r#"
+CREATE OR REPLACE FUNCTION orderbook_snapshots_now() RETURNS BIGINT
+    LANGUAGE SQL STABLE AS $$ SELECT (EXTRACT(EPOCH FROM NOW()) * 1000000000)::BIGINT $$;
+SELECT set_integer_now_func('orderbook_snapshots', 'orderbook_snapshots_now', replace_if_exists => TRUE);
+"#,
+        )
+        .context("failed to set integer now function for orderbook_snapshots")?;
+    if !compression_enabled {
+        client
+            .batch_execute(
+                "ALTER TABLE orderbook_snapshots SET (timescaledb.compress, \
+                 timescaledb.compress_segmentby = 'symbol', \
+                 timescaledb.compress_orderby = 'ts_event DESC')",
+            )
+            .context("failed to enable compression on orderbook_snapshots")?;
+    }
+    client
+        .batch_execute("SELECT remove_compression_policy('orderbook_snapshots', if_exists => TRUE)")
+        .context("failed to remove previous compression policy")?;
+    client
+        .execute(
+            "SELECT add_compression_policy('orderbook_snapshots', compress_after => $1::BIGINT)",
+            &[&compress_after_ns],
+        )
+        .context("failed to add compression policy")?;
+    info!(compress_after_ns, "hypertable compression policy set");
     Ok(())
 }
 
 fn ensure_database(db_url: &str) -> Result<()> {
-    println!("storage_writer ensuring database exists");
+    info!("ensuring database exists");
 
     let base_config: Config = db_url
         .parse()
@@ -384,21 +4608,15 @@ fn ensure_database(db_url: &str) -> Result<()> {
         .map(|s| s.to_owned())
         .unwrap_or_else(|| String::from("postgres"));
 
-    println!("storage_writer target_db={}", target_db);
+    info!(target_db, "target database");
 
     match base_config.clone().connect(NoTls) {
         Ok(mut client) => {
-            println!(
-                "storage_writer database {} already exists, validating...",
-                target_db
-            );
+            info!(target_db, "database exists, validating");
             client
                 .simple_query("SELECT 1")
                 .context("failed to validate postgres connectivity with SELECT 1")?;
-            println!(
-                "storage_writer database {} validated successfully",
-                target_db
-            );
+            info!(target_db, "database validated");
             return Ok(());
         }
         Err(err) => {
@@ -407,18 +4625,12 @@ fn ensure_database(db_url: &str) -> Result<()> {
                 .map(|db_err| db_err.code() == &SqlState::INVALID_CATALOG_NAME)
                 .unwrap_or(false);
             if !missing_db {
-                eprintln!(
-                    "storage_writer connection error (not missing database): {}",
-                    err
-                );
+                error!(error = %err, "connection error, not a missing database");
                 return Err(
                     anyhow!(err).context(format!("failed to connect to postgres using {}", db_url))
                 );
             }
-            println!(
-                "storage_writer database {} does not exist, creating...",
-                target_db
-            );
+            info!(target_db, "database does not exist, creating");
         }
     }
 
@@ -427,7 +4639,7 @@ fn ensure_database(db_url: &str) -> Result<()> {
     } else {
         "postgres"
     };
-    println!("storage_writer connecting to admin_db={}", admin_db);
+    info!(admin_db, "connecting to admin database");
 
     let mut admin_config = base_config.clone();
     admin_config.dbname(admin_db);
@@ -438,11 +4650,11 @@ fn ensure_database(db_url: &str) -> Result<()> {
         )
     })?;
 
-    println!("storage_writer creating database {}", target_db);
+    info!(target_db, "creating database");
     let create_sql = format!("CREATE DATABASE {}", escape_ident(&target_db));
     match admin_client.simple_query(&create_sql) {
         Ok(_) => {
-            println!("storage_writer created database {} successfully", target_db);
+            info!(target_db, "database created");
         }
         Err(err) => {
             let duplicate = err
@@ -450,26 +4662,17 @@ fn ensure_database(db_url: &str) -> Result<()> {
                 .map(|db_err| db_err.code() == &SqlState::DUPLICATE_DATABASE)
                 .unwrap_or(false);
             if !duplicate {
-                eprintln!(
-                    "storage_writer failed to create database {}: {}",
-                    target_db, err
-                );
+                error!(target_db, error = %err, "failed to create database");
                 return Err(
                     anyhow!(err).context(format!("failed to create target database {}", target_db))
                 );
             }
-            println!(
-                "storage_writer database {} already exists (concurrent creation)",
-                target_db
-            );
+            info!(target_db, "database already exists, created concurrently");
         }
     }
     drop(admin_client);
 
-    println!(
-        "storage_writer connecting to newly created database {}",
-        target_db
-    );
+    info!(target_db, "connecting to the new database");
     base_config.clone().connect(NoTls).with_context(|| {
         format!(
             "failed to connect to newly created database {} using {}",
@@ -477,7 +4680,7 @@ fn ensure_database(db_url: &str) -> Result<()> {
         )
     })?;
 
-    println!("storage_writer database {} ready", target_db);
+    info!(target_db, "database ready");
     Ok(())
 }
 
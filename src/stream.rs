@@ -0,0 +1,561 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    io::{BufWriter, ErrorKind, Write},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use bytes::{BufMut, BytesMut};
+use dbn::record::MboMsg as DbnMboMsg;
+use prost::Message;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{Instrument, error, info, info_span, warn};
+
+use crate::{
+    config::StreamConfig,
+    dbn_compat::DbnReader,
+    proto::{Header, MboBatch, MboMsg, SubscribeRequest},
+    shutdown::Shutdown,
+};
+
+const MAX_BATCH_BYTES: usize = 512 * 1024; // 512KB max batch size
+const MAX_SUBSCRIBE_BYTES: usize = 64 * 1024;
+
+/// How fast frames are written to each client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pace {
+    /// As fast as the socket accepts them.
+    Unlimited,
+    /// Following the original `ts_event` spacing, sped up by this factor (1.0 = realtime).
+    Speed(f64),
+}
+
+impl Pace {
+    /// Parses `unlimited`, `realtime` or a multiplier such as `10x` or `0.5x`.
+    pub fn parse(raw: &str) -> Result<Self> {
+        let speed = match raw.trim() {
+            "unlimited" => return Ok(Pace::Unlimited),
+            "realtime" => 1.0,
+            other => other
+                .strip_suffix('x')
+                .and_then(|v| v.parse::<f64>().ok())
+                .with_context(|| {
+                    format!(
+                        "--tcp-pace/TCP_PACE must be realtime, unlimited or a multiplier like 10x, got {}",
+                        other
+                    )
+                })?,
+        };
+        if !(speed.is_finite() && speed > 0.0) {
+            bail!(
+                "--tcp-pace/TCP_PACE multiplier must be positive, got {}",
+                raw
+            );
+        }
+        Ok(Pace::Speed(speed))
+    }
+}
+
+impl fmt::Display for Pace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pace::Unlimited => f.write_str("unlimited"),
+            Pace::Speed(speed) => write!(f, "{}x", speed),
+        }
+    }
+}
+
+/// Schedules frames against the data clock: a frame is due once the wall time since
+/// the first frame reaches the `ts_event` distance from it divided by the speed.
+struct Pacer {
+    speed: f64,
+    anchor: Option<(u64, tokio::time::Instant)>,
+}
+
+impl Pacer {
+    fn new(speed: f64) -> Self {
+        Self {
+            speed,
+            anchor: None,
+        }
+    }
+
+    /// Starts a new timeline, e.g. when a looped replay wraps around.
+    fn reset(&mut self) {
+        self.anchor = None;
+    }
+
+    async fn wait_for(&mut self, ts_event: u64) {
+        let (base_ts, base_instant) = *self
+            .anchor
+            .get_or_insert_with(|| (ts_event, tokio::time::Instant::now()));
+        let data_elapsed_ns = ts_event.saturating_sub(base_ts) as f64 / self.speed;
+        let due = base_instant + Duration::from_nanos(data_elapsed_ns as u64);
+        tokio::time::sleep_until(due).await;
+    }
+}
+
+/// `ts_event` of the first message in a length-prefixed frame.
+fn frame_ts_event(frame: &[u8]) -> Option<u64> {
+    let batch = MboBatch::decode(&frame[4..]).ok()?;
+    batch.msgs.first()?.hd.as_ref().map(|hd| hd.ts_event)
+}
+
+/// What a client asked to receive. Without a `SubscribeRequest` the whole file is sent.
+#[derive(Clone, Debug, Default)]
+struct Subscription {
+    instrument_ids: HashSet<u32>,
+    start_sequence: u64,
+}
+
+impl Subscription {
+    fn from_request(request: SubscribeRequest) -> Self {
+        Self {
+            instrument_ids: request.instrument_ids.into_iter().collect(),
+            start_sequence: request.start_sequence,
+        }
+    }
+
+    /// True when frames can be forwarded untouched.
+    fn is_everything(&self) -> bool {
+        self.instrument_ids.is_empty() && self.start_sequence == 0
+    }
+
+    fn matches(&self, msg: &MboMsg) -> bool {
+        msg.sequence >= self.start_sequence
+            && (self.instrument_ids.is_empty()
+                || msg
+                    .hd
+                    .as_ref()
+                    .is_some_and(|hd| self.instrument_ids.contains(&hd.instrument_id)))
+    }
+
+    /// Re-encodes a length-prefixed frame with only the matching messages, returning the
+    /// new frame and its message count, or `None` when nothing matched.
+    fn filter_frame(&self, frame: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+        let mut batch =
+            MboBatch::decode(&frame[4..]).context("failed to decode pre-encoded batch")?;
+        batch.msgs.retain(|msg| self.matches(msg));
+        if batch.msgs.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((encode_batch(&batch)?, batch.msgs.len())))
+    }
+}
+
+/// Waits up to `timeout` for the client's `SubscribeRequest`. Clients that send
+/// nothing get the whole file, so older clients keep working.
+async fn read_subscription(socket: &mut TcpStream, timeout: Duration) -> Result<Subscription> {
+    let mut len_buf = [0u8; 4];
+    match tokio::time::timeout(timeout, socket.read_exact(&mut len_buf)).await {
+        Err(_) => return Ok(Subscription::default()),
+        Ok(result) => result.context("failed to read subscribe request length")?,
+    };
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_SUBSCRIBE_BYTES {
+        bail!(
+            "subscribe request length {} exceeds max {}",
+            len,
+            MAX_SUBSCRIBE_BYTES
+        );
+    }
+    let mut payload = vec![0u8; len];
+    socket
+        .read_exact(&mut payload)
+        .await
+        .context("failed to read subscribe request")?;
+    let request =
+        SubscribeRequest::decode(payload.as_slice()).context("invalid subscribe request")?;
+    Ok(Subscription::from_request(request))
+}
+
+/// Pre-encodes the input if asked, then serves every client until `shutdown` fires.
+/// Everything logs in a `tcp_streamer` span, each client in a `client` span under it.
+pub async fn run(config: StreamConfig, shutdown: Shutdown) -> Result<()> {
+    let span = info_span!("tcp_streamer", bind = %config.bind_addr);
+    serve(config, shutdown).instrument(span).await
+}
+
+async fn serve(config: StreamConfig, shutdown: Shutdown) -> Result<()> {
+    info!(
+        batch_size = config.batch_size,
+        loop_replay = config.loop_replay,
+        input = %config.input_path,
+        encoded = %config.encoded_path,
+        preencode = config.preencode,
+        pace = %config.pace,
+        "tcp_streamer starting"
+    );
+
+    if config.preencode {
+        info!(
+            input = %config.input_path,
+            encoded = %config.encoded_path,
+            "preencoding DBN file"
+        );
+        let start = Instant::now();
+        let stats = preencode_to_file(&config.input_path, &config.encoded_path, config.batch_size)?;
+        let elapsed = start.elapsed();
+        let total_msgs = stats.batches * config.batch_size;
+        info!(
+            batches = stats.batches,
+            approx_msgs = total_msgs,
+            mb = stats.bytes as f64 / (1024.0 * 1024.0),
+            secs = elapsed.as_secs_f64(),
+            "preencoded"
+        );
+    } else {
+        let metadata = fs::metadata(&config.encoded_path).with_context(|| {
+            format!(
+                "encoded file {} not found (set PREENCODE=1 to rebuild)",
+                config.encoded_path
+            )
+        })?;
+        info!(
+            encoded = %config.encoded_path,
+            mb = metadata.len() as f64 / (1024.0 * 1024.0),
+            "using existing encoded file"
+        );
+    }
+
+    // Accept connections
+    let listener = TcpListener::bind(&config.bind_addr)
+        .await
+        .with_context(|| format!("failed to bind TCP listener to {}", config.bind_addr))?;
+
+    info!("tcp_streamer listening");
+
+    let mut client_id = 0u64;
+    loop {
+        tokio::select! {
+            accept_result = listener.accept() => {
+                match accept_result {
+                    Ok((socket, addr)) => {
+                        let cfg = config.clone();
+                        let id = client_id;
+                        client_id += 1;
+                        let span = info_span!("client", id, %addr);
+                        tokio::spawn(handle_client(socket, cfg).instrument(span));
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "accept error");
+                    }
+                }
+            }
+            _ = shutdown.wait() => {
+                info!("tcp_streamer shutting down");
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct PreencodeStats {
+    batches: usize,
+    bytes: u64,
+}
+
+fn preencode_to_file(
+    input_path: &str,
+    encoded_path: &str,
+    batch_size: usize,
+) -> Result<PreencodeStats> {
+    let mut reader = DbnReader::open(input_path)?;
+    let file = fs::File::create(encoded_path)
+        .with_context(|| format!("failed to create encoded output {}", encoded_path))?;
+    let mut writer = BufWriter::with_capacity(8 * 1024 * 1024, file);
+    let mut batch_msgs = Vec::with_capacity(batch_size);
+    let mut batches_written = 0usize;
+
+    loop {
+        match reader.next_record::<DbnMboMsg>() {
+            Ok(Some(dbn_msg)) => {
+                let proto_msg = convert_to_proto(&dbn_msg);
+                batch_msgs.push(proto_msg);
+
+                if batch_msgs.len() >= batch_size {
+                    let batch = MboBatch {
+                        msgs: batch_msgs.clone(),
+                    };
+                    let encoded = encode_batch(&batch)?;
+                    writer.write_all(&encoded)?;
+                    batch_msgs.clear();
+                    batches_written += 1;
+                }
+            }
+            Ok(None) => {
+                if !batch_msgs.is_empty() {
+                    let batch = MboBatch {
+                        msgs: batch_msgs.clone(),
+                    };
+                    let encoded = encode_batch(&batch)?;
+                    writer.write_all(&encoded)?;
+                    batch_msgs.clear();
+                    batches_written += 1;
+                }
+                break;
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+    }
+
+    writer.flush()?;
+    drop(writer);
+
+    let bytes = fs::metadata(encoded_path)
+        .with_context(|| format!("failed to stat encoded output {}", encoded_path))?
+        .len();
+
+    Ok(PreencodeStats {
+        batches: batches_written,
+        bytes,
+    })
+}
+
+// Convert DBN MboMsg to protobuf MboMsg
+fn convert_to_proto(dbn_msg: &DbnMboMsg) -> MboMsg {
+    let hd = &dbn_msg.hd;
+
+    MboMsg {
+        hd: Some(Header {
+            ts_event: hd.ts_event,
+            rtype: hd.rtype as u32,
+            publisher_id: hd.publisher_id as u32,
+            instrument_id: hd.instrument_id,
+        }),
+        action: action_to_string(dbn_msg.action as u8 as char),
+        side: side_to_string(dbn_msg.side as u8 as char),
+        price: dbn_msg.price as u64,
+        size: dbn_msg.size,
+        channel_id: dbn_msg.channel_id as u32,
+        order_id: dbn_msg.order_id,
+        flags: dbn_msg.flags.raw() as u32,
+        ts_in_delta: dbn_msg.ts_in_delta as u64,
+        sequence: dbn_msg.sequence as u64,
+        ts_recv: dbn_msg.ts_recv,
+    }
+}
+
+#[inline]
+fn action_to_string(action: char) -> String {
+    match action {
+        'A' => "A".to_string(),
+        'M' => "M".to_string(),
+        'D' => "D".to_string(),
+        'C' => "C".to_string(),
+        'T' => "T".to_string(),
+        _ => action.to_string(),
+    }
+}
+
+#[inline]
+fn side_to_string(side: char) -> String {
+    match side {
+        'A' => "A".to_string(),
+        'B' => "B".to_string(),
+        _ => side.to_string(),
+    }
+}
+
+// Encode a batch with length prefix: [u32 length][protobuf bytes]
+fn encode_batch(batch: &MboBatch) -> Result<Vec<u8>> {
+    let encoded_len = batch.encoded_len();
+    if encoded_len > MAX_BATCH_BYTES {
+        bail!(
+            "batch encoded length {} exceeds max {} bytes",
+            encoded_len,
+            MAX_BATCH_BYTES
+        );
+    }
+    let mut buf = BytesMut::with_capacity(encoded_len + 4);
+
+    // Write 4-byte length prefix (big-endian u32)
+    buf.put_u32(encoded_len as u32);
+
+    // Write protobuf-encoded batch
+    batch.encode(&mut buf)?;
+
+    Ok(buf.to_vec())
+}
+
+async fn handle_client(mut socket: TcpStream, config: StreamConfig) {
+    info!("client_connected");
+    if let Err(e) = socket.set_nodelay(true) {
+        warn!(error = %e, "failed to enable TCP_NODELAY");
+    }
+    let subscription = match read_subscription(&mut socket, config.handshake_timeout).await {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            warn!(error = format!("{:#}", e), "client rejected");
+            return;
+        }
+    };
+    if !subscription.is_everything() {
+        info!(
+            instruments = ?subscription.instrument_ids,
+            start_sequence = subscription.start_sequence,
+            "client_subscribed"
+        );
+    }
+
+    let start = Instant::now();
+    let mut total_msgs_sent = 0u64;
+    let mut total_batches_sent = 0u64;
+    let mut total_bytes_sent = 0u64;
+    let mut last_report = Instant::now();
+    let mut pacer = match config.pace {
+        Pace::Unlimited => None,
+        Pace::Speed(speed) => Some(Pacer::new(speed)),
+    };
+
+    'replay: loop {
+        if let Some(pacer) = pacer.as_mut() {
+            pacer.reset();
+        }
+        let mut file = match tokio::fs::File::open(&config.encoded_path).await {
+            Ok(file) => file,
+            Err(e) => {
+                error!(
+                    encoded = %config.encoded_path,
+                    error = %e,
+                    "failed to open encoded file"
+                );
+                break;
+            }
+        };
+
+        loop {
+            let mut len_buf = [0u8; 4];
+            if let Err(e) = file.read_exact(&mut len_buf).await {
+                if e.kind() == ErrorKind::UnexpectedEof {
+                    break;
+                } else {
+                    error!(error = %e, "read length error");
+                    break 'replay;
+                }
+            }
+
+            let frame_len = u32::from_be_bytes(len_buf) as usize;
+            if frame_len > MAX_BATCH_BYTES {
+                error!(frame_len, max = MAX_BATCH_BYTES, "frame length exceeds max");
+                break 'replay;
+            }
+
+            let mut frame = vec![0u8; frame_len + 4];
+            frame[..4].copy_from_slice(&len_buf);
+
+            if let Err(e) = file.read_exact(&mut frame[4..]).await {
+                if e.kind() == ErrorKind::UnexpectedEof {
+                    error!("truncated frame");
+                } else {
+                    error!(error = %e, "read payload error");
+                }
+                break 'replay;
+            }
+
+            let (frame, msgs) = if subscription.is_everything() {
+                (frame, config.batch_size)
+            } else {
+                match subscription.filter_frame(&frame) {
+                    Ok(Some(filtered)) => filtered,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!(error = format!("{:#}", e), "failed to filter frame");
+                        break 'replay;
+                    }
+                }
+            };
+
+            // Batches are paced as a unit; a smaller TCP_BATCH_SIZE gives a finer profile
+            if let Some(pacer) = pacer.as_mut()
+                && let Some(ts_event) = frame_ts_event(&frame)
+            {
+                pacer.wait_for(ts_event).await;
+            }
+
+            if let Err(e) = socket.write_all(&frame).await {
+                info!(
+                    batches = total_batches_sent,
+                    msgs = total_msgs_sent,
+                    error = %e,
+                    "client_disconnected"
+                );
+                break 'replay;
+            }
+
+            total_batches_sent += 1;
+            total_msgs_sent += msgs as u64;
+            total_bytes_sent += frame.len() as u64;
+
+            if last_report.elapsed() >= Duration::from_secs(1) {
+                let elapsed = start.elapsed().as_secs_f64();
+                let msg_rate = if elapsed > 0.0 {
+                    total_msgs_sent as f64 / elapsed
+                } else {
+                    0.0
+                };
+                let batch_rate = if elapsed > 0.0 {
+                    total_batches_sent as f64 / elapsed
+                } else {
+                    0.0
+                };
+                let throughput_mbps = if elapsed > 0.0 {
+                    (total_bytes_sent as f64 / elapsed) / (1024.0 * 1024.0)
+                } else {
+                    0.0
+                };
+                info!(
+                    msgs = total_msgs_sent,
+                    batches = total_batches_sent,
+                    msg_rate,
+                    batch_rate,
+                    throughput_mbps,
+                    "client progress"
+                );
+                last_report = Instant::now();
+            }
+        }
+
+        // Finished one complete replay
+        if !config.loop_replay {
+            info!(
+                batches = total_batches_sent,
+                msgs = total_msgs_sent,
+                "client finished"
+            );
+            break 'replay;
+        }
+
+        info!("replaying from start");
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let msg_rate = if elapsed > 0.0 {
+        total_msgs_sent as f64 / elapsed
+    } else {
+        0.0
+    };
+    let throughput_mbps = if elapsed > 0.0 {
+        (total_bytes_sent as f64 / elapsed) / (1024.0 * 1024.0)
+    } else {
+        0.0
+    };
+    info!(
+        msgs = total_msgs_sent,
+        batches = total_batches_sent,
+        bytes = total_bytes_sent,
+        duration_secs = elapsed,
+        msg_rate,
+        throughput_mbps,
+        "client_stats"
+    );
+}
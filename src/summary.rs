@@ -0,0 +1,145 @@
+use std::{collections::BTreeMap, fs, path::Path, process::ExitCode};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::{
+    order_book::BookErrorCounts,
+    supervisor::{SinkState, SinkStatus},
+    validate::ValidationReport,
+};
+
+/// Process exit codes for orchestration. When several apply the most severe wins, in
+/// the order sink failure, error, interrupted, data quality, partial.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitStatus {
+    /// Everything ingested and persisted.
+    Success,
+    /// Snapshots were dropped because a writer queue stayed full.
+    Partial,
+    /// The input had records that could not be decoded or broke the sequence check, or
+    /// its books diverged from the reference data they were validated against.
+    DataQuality,
+    /// A sink failed, so persisted output is incomplete.
+    SinkFailure,
+    /// The run stopped on an error before finishing, e.g. bad configuration.
+    Error,
+    /// A shutdown signal stopped ingest early; everything read before it was persisted.
+    Interrupted,
+}
+
+impl ExitStatus {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::Error => 1,
+            ExitStatus::Partial => 2,
+            ExitStatus::SinkFailure => 3,
+            ExitStatus::DataQuality => 4,
+            ExitStatus::Interrupted => 5,
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status.exit_code())
+    }
+}
+
+/// Final machine-readable record of a run, printed as one `run_summary=` line and
+/// optionally written to a file.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub status: Option<ExitStatus>,
+    pub exit_code: u8,
+    pub source: String,
+    pub processed: u64,
+    pub emitted: u64,
+    pub skipped: u64,
+    pub out_of_window: u64,
+    pub sampled_out: u64,
+    pub decode_errors: u64,
+    /// Records skipped as undecodable by this build (see `DbnReader`); not an error.
+    pub unsupported_records: u64,
+    pub sequence_gaps: u64,
+    pub dropped_storage: u64,
+    pub dropped_mbp: u64,
+    /// Storage rows written to SPILL_DIR instead of Postgres.
+    pub spilled_storage: u64,
+    /// Records the books rejected, by reason; their total is part of `skipped`.
+    pub book_errors: BookErrorCounts,
+    /// Set when books were cross-checked against reference MBP data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation: Option<ValidationReport>,
+    pub sinks: BTreeMap<String, SinkStatus>,
+    pub duration_ms: u128,
+    /// Set when a shutdown signal stopped ingest before the source ended.
+    pub interrupted: bool,
+    pub error: Option<String>,
+}
+
+impl RunSummary {
+    /// Derives the status from the sink states, error and counters. A failed sink wins
+    /// over the error it caused so orchestration sees why the run stopped.
+    pub fn finish(&mut self) -> ExitStatus {
+        let sink_failed = self
+            .sinks
+            .values()
+            .any(|status| status.state == SinkState::Failed);
+        let status = if sink_failed {
+            ExitStatus::SinkFailure
+        } else if self.error.is_some() {
+            ExitStatus::Error
+        } else if self.interrupted {
+            ExitStatus::Interrupted
+        } else if self.decode_errors > 0
+            || self.sequence_gaps > 0
+            || self
+                .validation
+                .as_ref()
+                .is_some_and(|validation| validation.divergences > 0)
+        {
+            ExitStatus::DataQuality
+        } else if self.dropped_storage > 0 || self.dropped_mbp > 0 || self.spilled_storage > 0 {
+            ExitStatus::Partial
+        } else {
+            ExitStatus::Success
+        };
+        self.status = Some(status);
+        self.exit_code = status.exit_code();
+        status
+    }
+
+    /// Prints the summary line and writes it to `path` if given. A failed file write is
+    /// logged rather than returned so it never masks the run's own status.
+    pub fn emit(&self, path: Option<&Path>) {
+        let json = match serde_json::to_string(self) {
+            Ok(json) => json,
+            Err(e) => {
+                error!(error = %e, "run summary failed to serialize");
+                return;
+            }
+        };
+        info!(run_summary = %json);
+        if let Some(path) = path
+            && let Err(e) = write_summary(path, &json)
+        {
+            error!(
+                path = %path.display(),
+                error = format!("{:#}", e),
+                "run summary failed to write"
+            );
+        }
+    }
+}
+
+/// Writes via a temporary file so readers never see a partial summary.
+fn write_summary(path: &Path, json: &str) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, format!("{}\n", json))
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to rename to {}", path.display()))
+}
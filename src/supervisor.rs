@@ -0,0 +1,205 @@
+use std::{
+    collections::BTreeMap,
+    panic::{AssertUnwindSafe, catch_unwind},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use tracing::{error, info_span, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkState {
+    Running,
+    Restarting,
+    Failed,
+    Finished,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SinkStatus {
+    pub state: SinkState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Live status of every supervised sink, shared with `/healthz` and `/metrics`.
+#[derive(Debug, Default)]
+pub struct SinkHealth {
+    sinks: Mutex<BTreeMap<String, SinkStatus>>,
+}
+
+impl SinkHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, SinkStatus> {
+        self.sinks
+            .lock()
+            .expect("sink health lock poisoned")
+            .clone()
+    }
+
+    /// True while no sink has permanently failed.
+    pub fn is_healthy(&self) -> bool {
+        self.sinks
+            .lock()
+            .expect("sink health lock poisoned")
+            .values()
+            .all(|status| status.state != SinkState::Failed)
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut SinkStatus)) {
+        let mut sinks = self.sinks.lock().expect("sink health lock poisoned");
+        let status = sinks.entry(name.to_owned()).or_insert(SinkStatus {
+            state: SinkState::Running,
+            restarts: 0,
+            last_error: None,
+        });
+        f(status);
+    }
+}
+
+/// Runtime on/off switches for sinks, listed and set through `/admin/sinks`. Sinks
+/// register under their name when they open and start enabled; a disabled sink keeps
+/// running but is handed no data until it is enabled again.
+#[derive(Debug, Default)]
+pub struct SinkSwitches {
+    sinks: Mutex<BTreeMap<String, bool>>,
+}
+
+impl SinkSwitches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `name` as enabled; a sink reopened by a restart keeps its switch.
+    pub fn register(&self, name: &str) {
+        self.sinks
+            .lock()
+            .expect("sink switches lock poisoned")
+            .entry(name.to_owned())
+            .or_insert(true);
+    }
+
+    /// Unregistered names count as enabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.sinks
+            .lock()
+            .expect("sink switches lock poisoned")
+            .get(name)
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// Sets a registered sink's switch; false when no sink has that name.
+    pub fn set(&self, name: &str, enabled: bool) -> bool {
+        match self
+            .sinks
+            .lock()
+            .expect("sink switches lock poisoned")
+            .get_mut(name)
+        {
+            Some(switch) => {
+                *switch = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<String, bool> {
+        self.sinks
+            .lock()
+            .expect("sink switches lock poisoned")
+            .clone()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    pub max_restarts: u32,
+    pub backoff: Duration,
+}
+
+impl RestartPolicy {
+    pub fn never() -> Self {
+        Self {
+            max_restarts: 0,
+            backoff: Duration::ZERO,
+        }
+    }
+}
+
+/// Runs `run` on a dedicated thread, containing panics and errors. Failures are
+/// published to `health` as soon as they happen and the sink is restarted up to
+/// `policy.max_restarts` times. `run` receives the attempt number (0 for the first run)
+/// and should keep its input queue in the closure so queued snapshots survive restarts.
+pub fn spawn_supervised<F>(
+    name: &str,
+    health: Arc<SinkHealth>,
+    policy: RestartPolicy,
+    mut run: F,
+) -> thread::JoinHandle<Result<()>>
+where
+    F: FnMut(u32) -> Result<()> + Send + 'static,
+{
+    let name = name.to_owned();
+    health.update(&name, |status| status.state = SinkState::Running);
+    thread::spawn(move || {
+        let _span = info_span!("sink", name = %name).entered();
+        let mut attempt = 0u32;
+        loop {
+            let outcome = match catch_unwind(AssertUnwindSafe(|| run(attempt))) {
+                Ok(result) => result,
+                Err(panic) => Err(anyhow!("panicked: {}", panic_message(&panic))),
+            };
+            let err = match outcome {
+                Ok(()) => {
+                    health.update(&name, |status| status.state = SinkState::Finished);
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+
+            let message = format!("{:#}", err);
+            if attempt >= policy.max_restarts {
+                error!(error = %message, "sink failed permanently");
+                health.update(&name, |status| {
+                    status.state = SinkState::Failed;
+                    status.last_error = Some(message);
+                });
+                return Err(err);
+            }
+
+            attempt += 1;
+            warn!(
+                attempt,
+                max_restarts = policy.max_restarts,
+                error = %message,
+                "sink failed, restarting"
+            );
+            health.update(&name, |status| {
+                status.state = SinkState::Restarting;
+                status.restarts = attempt;
+                status.last_error = Some(message);
+            });
+            thread::sleep(policy.backoff);
+            health.update(&name, |status| status.state = SinkState::Running);
+        }
+    })
+}
+
+fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        String::from("unknown panic payload")
+    }
+}
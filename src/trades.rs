@@ -0,0 +1,98 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use dbn::{
+    enums::{Action, Side},
+    record::MboMsg,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::SymbolMap;
+
+/// Trades kept per symbol for `/trades`.
+pub const TAPE_LEN: usize = 10_000;
+
+/// One execution from the feed. Only `Trade` records are captured: a `Fill` describes
+/// the resting side of an execution already reported by its trade, so capturing both
+/// would count it twice.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TradeRecord {
+    pub symbol: String,
+    pub instrument_id: u32,
+    pub publisher_id: u16,
+    pub ts_event: i64,
+    pub ts_recv: i64,
+    pub price: i64,
+    pub size: u32,
+    /// Aggressor side: `B` buy, `A` sell, `N` unknown.
+    pub side: char,
+    pub order_id: u64,
+    pub sequence: u32,
+}
+
+pub type SharedTrade = Arc<TradeRecord>;
+
+impl TradeRecord {
+    /// The trade reported by `mbo`, or None for any other action.
+    pub fn from_mbo(mbo: &MboMsg, symbols: &SymbolMap) -> Option<Self> {
+        if !matches!(mbo.action(), Ok(Action::Trade)) {
+            return None;
+        }
+        Some(Self {
+            symbol: symbols.resolve(mbo.hd.instrument_id).to_owned(),
+            instrument_id: mbo.hd.instrument_id,
+            publisher_id: mbo.hd.publisher_id,
+            ts_event: mbo.hd.ts_event as i64,
+            ts_recv: mbo.ts_recv as i64,
+            price: mbo.price,
+            size: mbo.size,
+            side: mbo.side().unwrap_or(Side::None) as u8 as char,
+            order_id: mbo.order_id,
+            sequence: mbo.sequence,
+        })
+    }
+}
+
+/// The most recent trades per symbol, shared between ingest and the server.
+pub struct TradeTape {
+    capacity: usize,
+    trades: Mutex<HashMap<String, VecDeque<SharedTrade>>>,
+}
+
+impl Default for TradeTape {
+    fn default() -> Self {
+        Self::new(TAPE_LEN)
+    }
+}
+
+impl TradeTape {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            trades: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, trade: SharedTrade) {
+        let mut trades = self.trades.lock().expect("trade tape lock poisoned");
+        let tape = trades.entry(trade.symbol.clone()).or_default();
+        if tape.len() == self.capacity {
+            tape.pop_front();
+        }
+        tape.push_back(trade);
+    }
+
+    /// Up to `limit` of the symbol's latest trades, oldest first.
+    pub fn recent(&self, symbol: &str, limit: usize) -> Vec<SharedTrade> {
+        let trades = self.trades.lock().expect("trade tape lock poisoned");
+        let Some(tape) = trades.get(symbol) else {
+            return Vec::new();
+        };
+        tape.iter()
+            .skip(tape.len().saturating_sub(limit))
+            .cloned()
+            .collect()
+    }
+}
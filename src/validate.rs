@@ -0,0 +1,261 @@
+//! Cross-checks the books built from MBO data against Databento's MBP-1 or MBP-10
+//! records of the same session.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Result, bail};
+use dbn::{
+    FIXED_PRICE_SCALE, Publisher, Schema, UNDEF_PRICE,
+    record::{BidAskPair, MboMsg, Mbp1Msg, Mbp10Msg},
+};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    dbn_compat::DbnReader,
+    order_book::{Market, PriceLevel},
+    snapshot::LevelEntry,
+};
+
+/// Where to find the reference records and how much to log.
+#[derive(Clone, Debug)]
+pub struct ValidateConfig {
+    pub path: String,
+    /// Divergences logged one per line; the rest are only counted.
+    pub max_reports: u64,
+}
+
+/// Result of a validation run, part of the run summary.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ValidationReport {
+    pub reference: String,
+    /// Reference records read.
+    pub reference_records: u64,
+    /// Book states compared.
+    pub checked: u64,
+    pub divergences: u64,
+    pub first: Option<Divergence>,
+    pub last: Option<Divergence>,
+}
+
+/// A book whose top of book differs from the reference.
+#[derive(Clone, Debug, Serialize)]
+pub struct Divergence {
+    pub ts_recv: u64,
+    pub ts_event: u64,
+    pub instrument_id: u32,
+    pub publisher_id: u16,
+    pub expected: Top,
+    pub actual: Top,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Top {
+    pub bid: Option<LevelEntry>,
+    pub ask: Option<LevelEntry>,
+}
+
+/// Book as (instrument_id, publisher_id).
+type BookKey = (u32, u16);
+
+/// Top of book of one reference record.
+struct Reference {
+    key: BookKey,
+    ts_recv: u64,
+    ts_event: u64,
+    top: Top,
+}
+
+/// Compares books after every `ts_recv`, once all MBO records received at that time
+/// are applied. Databento stamps MBP records with the `ts_recv` of the MBO event that
+/// produced them, so the reference is read up to the same time. Comparing between
+/// timestamps rather than after each event tolerates several events sharing one.
+pub struct BboValidator {
+    reader: DbnReader,
+    schema: Schema,
+    max_reports: u64,
+    /// First reference record past the time compared last.
+    next: Option<Reference>,
+    expected: HashMap<BookKey, Reference>,
+    /// Books changed by MBO or reference records since the last comparison.
+    touched: HashSet<BookKey>,
+    /// `ts_recv` of the records applied since the last comparison.
+    ts_recv: u64,
+    report: ValidationReport,
+}
+
+impl BboValidator {
+    pub fn open(config: &ValidateConfig) -> Result<Self> {
+        let reader = DbnReader::open(&config.path)?;
+        let schema = match reader.metadata().and_then(|metadata| metadata.schema) {
+            Some(schema @ (Schema::Mbp1 | Schema::Mbp10)) => schema,
+            Some(schema) => bail!(
+                "{} holds {} records; validation needs mbp-1 or mbp-10",
+                config.path,
+                schema
+            ),
+            None => bail!(
+                "{} declares no schema; validation needs mbp-1 or mbp-10",
+                config.path
+            ),
+        };
+        info!(reference = %config.path, schema = %schema, "validate");
+        Ok(Self {
+            reader,
+            schema,
+            max_reports: config.max_reports,
+            next: None,
+            expected: HashMap::new(),
+            touched: HashSet::new(),
+            ts_recv: 0,
+            report: ValidationReport {
+                reference: config.path.clone(),
+                ..ValidationReport::default()
+            },
+        })
+    }
+
+    /// Call before applying `rec`: a new `ts_recv` first compares the books as the
+    /// previous one left them.
+    pub fn before_apply(&mut self, market: &Market, rec: &MboMsg) -> Result<()> {
+        if rec.ts_recv != self.ts_recv {
+            self.compare(market)?;
+            self.ts_recv = rec.ts_recv;
+        }
+        self.touched
+            .insert((rec.hd.instrument_id, rec.hd.publisher_id));
+        Ok(())
+    }
+
+    /// Compares the books the last records left and logs the totals.
+    pub fn finish(mut self, market: &Market) -> Result<ValidationReport> {
+        self.compare(market)?;
+        let report = self.report;
+        info!(
+            reference = %report.reference,
+            reference_records = report.reference_records,
+            checked = report.checked,
+            divergences = report.divergences,
+            "validate_complete"
+        );
+        Ok(report)
+    }
+
+    fn compare(&mut self, market: &Market) -> Result<()> {
+        loop {
+            if self.next.is_none() {
+                self.next = self.read()?;
+            }
+            match self.next.take() {
+                Some(reference) if reference.ts_recv <= self.ts_recv => {
+                    self.touched.insert(reference.key);
+                    self.expected.insert(reference.key, reference);
+                }
+                next => {
+                    self.next = next;
+                    break;
+                }
+            }
+        }
+        for key in self.touched.drain() {
+            let Some(expected) = self.expected.get(&key) else {
+                continue;
+            };
+            // Instruments the MBO input never had, e.g. filtered out, are not compared
+            let Some(book) = Publisher::try_from(key.1)
+                .ok()
+                .and_then(|publisher| market.book(key.0, publisher))
+            else {
+                continue;
+            };
+            let (bid, ask) = book.bbo();
+            let entry = |level: PriceLevel| LevelEntry {
+                price: level.price,
+                size: level.size,
+                count: level.count,
+                est_hidden: None,
+            };
+            let actual = Top {
+                bid: bid.map(entry),
+                ask: ask.map(entry),
+            };
+            self.report.checked += 1;
+            if actual == expected.top {
+                continue;
+            }
+            let divergence = Divergence {
+                ts_recv: self.ts_recv,
+                ts_event: expected.ts_event,
+                instrument_id: key.0,
+                publisher_id: key.1,
+                expected: expected.top.clone(),
+                actual,
+            };
+            self.report.divergences += 1;
+            if self.report.divergences <= self.max_reports {
+                warn!(
+                    ts_recv = divergence.ts_recv,
+                    ts_event = divergence.ts_event,
+                    instrument_id = divergence.instrument_id,
+                    publisher_id = divergence.publisher_id,
+                    expected_bid = %level(&divergence.expected.bid),
+                    expected_ask = %level(&divergence.expected.ask),
+                    actual_bid = %level(&divergence.actual.bid),
+                    actual_ask = %level(&divergence.actual.ask),
+                    "validate_divergence"
+                );
+            }
+            self.report.first.get_or_insert_with(|| divergence.clone());
+            self.report.last = Some(divergence);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<Option<Reference>> {
+        let (hd, ts_recv, top) = match self.schema {
+            Schema::Mbp1 => match self.reader.next_record::<Mbp1Msg>()? {
+                Some(msg) => (msg.hd, msg.ts_recv, top(&msg.levels[0])),
+                None => return Ok(None),
+            },
+            _ => match self.reader.next_record::<Mbp10Msg>()? {
+                Some(msg) => (msg.hd, msg.ts_recv, top(&msg.levels[0])),
+                None => return Ok(None),
+            },
+        };
+        self.report.reference_records += 1;
+        Ok(Some(Reference {
+            key: (hd.instrument_id, hd.publisher_id),
+            ts_recv,
+            ts_event: hd.ts_event,
+            top,
+        }))
+    }
+}
+
+fn top(pair: &BidAskPair) -> Top {
+    let side = |price: i64, size: u32, count: u32| {
+        (price != UNDEF_PRICE && size > 0).then_some(LevelEntry {
+            price,
+            size,
+            count,
+            est_hidden: None,
+        })
+    };
+    Top {
+        bid: side(pair.bid_px, pair.bid_sz, pair.bid_ct),
+        ask: side(pair.ask_px, pair.ask_sz, pair.ask_ct),
+    }
+}
+
+/// `price x size (count)` in display units, or `-` for an empty side.
+fn level(level: &Option<LevelEntry>) -> String {
+    match level {
+        Some(level) => format!(
+            "{}x{}({})",
+            level.price as f64 / FIXED_PRICE_SCALE as f64,
+            level.size,
+            level.count
+        ),
+        None => "-".to_owned(),
+    }
+}
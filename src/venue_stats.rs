@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use dbn::{
+    UNDEF_PRICE, UNDEF_STAT_QUANTITY, UNDEF_TIMESTAMP,
+    enums::{StatType, StatUpdateAction},
+    record::{ImbalanceMsg, StatMsg},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::SymbolMap;
+
+/// A venue record other than MBO that DBN input carries alongside the book, kept for
+/// the imbalance and statistics handlers rather than skipped.
+#[derive(Clone, Debug)]
+pub enum VenueMsg {
+    Imbalance(ImbalanceMsg),
+    Stat(StatMsg),
+}
+
+impl VenueMsg {
+    pub fn instrument_id(&self) -> u32 {
+        match self {
+            VenueMsg::Imbalance(msg) => msg.hd.instrument_id,
+            VenueMsg::Stat(msg) => msg.hd.instrument_id,
+        }
+    }
+
+    pub fn ts_event(&self) -> i64 {
+        match self {
+            VenueMsg::Imbalance(msg) => msg.hd.ts_event as i64,
+            VenueMsg::Stat(msg) => msg.hd.ts_event as i64,
+        }
+    }
+}
+
+/// An auction imbalance from the feed's Imbalance records. Prices are fixed-point and
+/// None when the venue leaves them undefined.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImbalanceRecord {
+    pub symbol: String,
+    pub instrument_id: u32,
+    pub publisher_id: u16,
+    pub ts_event: i64,
+    pub ts_recv: i64,
+    /// Price the paired and imbalance quantities are calculated at.
+    pub ref_price: Option<i64>,
+    /// Time of the auction, ns since the epoch.
+    pub auction_time: Option<i64>,
+    /// Price that would clear the continuous book and the auction orders.
+    pub cont_book_clr_price: Option<i64>,
+    /// Price that would clear the auction orders alone.
+    pub auct_interest_clr_price: Option<i64>,
+    pub paired_qty: u32,
+    pub total_imbalance_qty: u32,
+    /// Side of `total_imbalance_qty`: `B` buy, `A` sell, `N` none.
+    pub side: char,
+    /// Venue-specific auction type code.
+    pub auction_type: char,
+}
+
+pub type SharedImbalance = Arc<ImbalanceRecord>;
+
+impl ImbalanceRecord {
+    pub fn from_msg(msg: &ImbalanceMsg, symbols: &SymbolMap) -> Self {
+        Self {
+            symbol: symbols.resolve(msg.hd.instrument_id).to_owned(),
+            instrument_id: msg.hd.instrument_id,
+            publisher_id: msg.hd.publisher_id,
+            ts_event: msg.hd.ts_event as i64,
+            ts_recv: msg.ts_recv as i64,
+            ref_price: defined_price(msg.ref_price),
+            auction_time: defined_ts(msg.auction_time),
+            cont_book_clr_price: defined_price(msg.cont_book_clr_price),
+            auct_interest_clr_price: defined_price(msg.auct_interest_clr_price),
+            paired_qty: msg.paired_qty,
+            total_imbalance_qty: msg.total_imbalance_qty,
+            side: c_char_or(msg.side, 'N'),
+            auction_type: c_char_or(msg.auction_type, ' '),
+        }
+    }
+}
+
+/// One statistic from the feed's Statistics records, such as a settlement price or
+/// the open interest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatisticRecord {
+    pub symbol: String,
+    pub instrument_id: u32,
+    pub publisher_id: u16,
+    pub ts_event: i64,
+    pub ts_recv: i64,
+    /// The time the statistic refers to, e.g. the trading date of a settlement.
+    pub ts_ref: Option<i64>,
+    /// DBN's `StatType` code.
+    pub stat_type: u16,
+    /// Its name, e.g. `settlement_price`; `unknown` for codes this build does not know.
+    pub stat: String,
+    pub price: Option<i64>,
+    pub quantity: Option<i64>,
+    pub sequence: u32,
+    /// Venue-specific flags, e.g. whether a settlement is final.
+    pub stat_flags: u8,
+    /// The venue withdrew the statistic rather than publishing it.
+    pub deleted: bool,
+}
+
+pub type SharedStatistic = Arc<StatisticRecord>;
+
+impl StatisticRecord {
+    pub fn from_msg(msg: &StatMsg, symbols: &SymbolMap) -> Self {
+        Self {
+            symbol: symbols.resolve(msg.hd.instrument_id).to_owned(),
+            instrument_id: msg.hd.instrument_id,
+            publisher_id: msg.hd.publisher_id,
+            ts_event: msg.hd.ts_event as i64,
+            ts_recv: msg.ts_recv as i64,
+            ts_ref: defined_ts(msg.ts_ref),
+            stat_type: msg.stat_type,
+            stat: msg.stat_type().map_or("unknown", stat_name).to_owned(),
+            price: defined_price(msg.price),
+            quantity: (msg.quantity != UNDEF_STAT_QUANTITY).then_some(msg.quantity),
+            sequence: msg.sequence,
+            stat_flags: msg.stat_flags,
+            deleted: matches!(msg.update_action(), Ok(StatUpdateAction::Delete)),
+        }
+    }
+}
+
+/// An imbalance or statistic with its symbol resolved, as ingest publishes it.
+#[derive(Clone, Debug)]
+pub enum VenueRecord {
+    Imbalance(SharedImbalance),
+    Statistic(SharedStatistic),
+}
+
+impl VenueRecord {
+    pub fn from_msg(msg: &VenueMsg, symbols: &SymbolMap) -> Self {
+        match msg {
+            VenueMsg::Imbalance(msg) => {
+                VenueRecord::Imbalance(Arc::new(ImbalanceRecord::from_msg(msg, symbols)))
+            }
+            VenueMsg::Stat(msg) => {
+                VenueRecord::Statistic(Arc::new(StatisticRecord::from_msg(msg, symbols)))
+            }
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        match self {
+            VenueRecord::Imbalance(imbalance) => &imbalance.symbol,
+            VenueRecord::Statistic(statistic) => &statistic.symbol,
+        }
+    }
+}
+
+/// The latest imbalance of each symbol and publisher, and the latest value of each of
+/// their statistics, shared between ingest and the server.
+#[derive(Default)]
+pub struct VenueStats {
+    /// By `(symbol, publisher_id)`.
+    imbalances: Mutex<BTreeMap<(String, u16), SharedImbalance>>,
+    /// By `(symbol, stat_type, publisher_id)`.
+    statistics: Mutex<BTreeMap<(String, u16, u16), SharedStatistic>>,
+}
+
+impl VenueStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, record: &VenueRecord) {
+        match record {
+            VenueRecord::Imbalance(imbalance) => {
+                let key = (imbalance.symbol.clone(), imbalance.publisher_id);
+                self.imbalances
+                    .lock()
+                    .expect("venue stats lock poisoned")
+                    .insert(key, imbalance.clone());
+            }
+            VenueRecord::Statistic(statistic) => {
+                let mut statistics = self.statistics.lock().expect("venue stats lock poisoned");
+                let key = (
+                    statistic.symbol.clone(),
+                    statistic.stat_type,
+                    statistic.publisher_id,
+                );
+                // A deleted statistic no longer has a value
+                if statistic.deleted {
+                    statistics.remove(&key);
+                } else {
+                    statistics.insert(key, statistic.clone());
+                }
+            }
+        }
+    }
+
+    /// The latest imbalance per publisher of `symbol`, or of every symbol, sorted by
+    /// symbol and publisher.
+    pub fn imbalances(&self, symbol: Option<&str>) -> Vec<SharedImbalance> {
+        self.imbalances
+            .lock()
+            .expect("venue stats lock poisoned")
+            .values()
+            .filter(|row| symbol.is_none_or(|symbol| row.symbol == symbol))
+            .cloned()
+            .collect()
+    }
+
+    /// The latest value of each statistic of `symbol`, or of every symbol, sorted by
+    /// symbol, stat type and publisher.
+    pub fn statistics(&self, symbol: Option<&str>) -> Vec<SharedStatistic> {
+        self.statistics
+            .lock()
+            .expect("venue stats lock poisoned")
+            .values()
+            .filter(|row| symbol.is_none_or(|symbol| row.symbol == symbol))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Snake-case name of a statistic type, as stored and served.
+pub fn stat_name(stat_type: StatType) -> &'static str {
+    match stat_type {
+        StatType::OpeningPrice => "opening_price",
+        StatType::IndicativeOpeningPrice => "indicative_opening_price",
+        StatType::SettlementPrice => "settlement_price",
+        StatType::TradingSessionLowPrice => "trading_session_low_price",
+        StatType::TradingSessionHighPrice => "trading_session_high_price",
+        StatType::ClearedVolume => "cleared_volume",
+        StatType::LowestOffer => "lowest_offer",
+        StatType::HighestBid => "highest_bid",
+        StatType::OpenInterest => "open_interest",
+        StatType::FixingPrice => "fixing_price",
+        StatType::ClosePrice => "close_price",
+        StatType::NetChange => "net_change",
+        StatType::Vwap => "vwap",
+        StatType::Volatility => "volatility",
+        StatType::Delta => "delta",
+        StatType::UncrossingPrice => "uncrossing_price",
+        _ => "unknown",
+    }
+}
+
+fn defined_price(price: i64) -> Option<i64> {
+    (price != UNDEF_PRICE).then_some(price)
+}
+
+fn defined_ts(ts: u64) -> Option<i64> {
+    (ts != UNDEF_TIMESTAMP && ts != 0).then_some(ts as i64)
+}
+
+fn c_char_or(c: std::os::raw::c_char, default: char) -> char {
+    match c as u8 {
+        0 => default,
+        byte => byte as char,
+    }
+}